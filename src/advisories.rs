@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+
+use crate::pending::PendingUpdate;
+
+/// How urgent a security fix is, as rated by the distribution.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdvisorySeverity {
+    /// The advisory has no rating, e.g. updates from an apt security pocket.
+    #[default]
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl AdvisorySeverity {
+    /// Maps the ratings used by arch-audit, dnf and zypper.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::advisories::AdvisorySeverity;
+    ///
+    /// assert_eq!(AdvisorySeverity::parse("Important"), AdvisorySeverity::High);
+    /// assert_eq!(AdvisorySeverity::parse("moderate"), AdvisorySeverity::Medium);
+    /// assert_eq!(AdvisorySeverity::parse("None"), AdvisorySeverity::Unknown);
+    /// ```
+    pub fn parse(rating: &str) -> Self {
+        match rating.trim().to_lowercase().as_str() {
+            "low" => Self::Low,
+            "medium" | "moderate" => Self::Medium,
+            "high" | "important" => Self::High,
+            "critical" => Self::Critical,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Unknown => "Unrated",
+            Self::Low => "Low",
+            Self::Medium => "Medium",
+            Self::High => "High",
+            Self::Critical => "Critical",
+        }
+    }
+
+    /// Returns `true` if the fix should be installed without waiting.
+    pub fn is_urgent(&self) -> bool {
+        *self >= Self::High
+    }
+}
+
+/// A security issue fixed by a pending update.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Advisory {
+    /// Advisory or CVE identifiers, if the tool reports them.
+    #[serde(default)]
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub severity: AdvisorySeverity,
+}
+
+impl Advisory {
+    fn new(ids: Vec<String>, severity: AdvisorySeverity) -> Self {
+        Self { ids, severity }
+    }
+
+    /// Combines two advisories for the same package, keeping the higher severity.
+    fn merge(&mut self, other: Advisory) {
+        self.severity = self.severity.max(other.severity);
+        for id in other.ids {
+            if !self.ids.contains(&id) {
+                self.ids.push(id);
+            }
+        }
+    }
+}
+
+/// Parses the output of a package manager's advisory command into
+/// advisories keyed by package name.
+///
+/// Sources without advisory data yield nothing.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::advisories::{AdvisorySeverity, parse_advisories};
+///
+/// let found = parse_advisories("paru", "openssl|High|CVE-2024-0727,CVE-2024-2511\n");
+/// assert_eq!(found[0].0, "openssl");
+/// assert_eq!(found[0].1.severity, AdvisorySeverity::High);
+/// assert_eq!(found[0].1.ids.len(), 2);
+/// ```
+pub fn parse_advisories(source: &str, output: &str) -> Vec<(String, Advisory)> {
+    let lines = output.lines().map(str::trim).filter(|l| !l.is_empty());
+
+    match source {
+        // `arch-audit --upgradable --format '%n|%s|%c'`
+        "paru" => lines
+            .filter_map(|line| {
+                let mut cols = line.split('|').map(str::trim);
+                let name = cols.next().filter(|n| !n.is_empty())?;
+                let severity = AdvisorySeverity::parse(cols.next()?);
+                let ids = cols
+                    .next()
+                    .map(|ids| {
+                        ids.split(',')
+                            .map(str::trim)
+                            .filter(|id| !id.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Some((name.to_string(), Advisory::new(ids, severity)))
+            })
+            .collect(),
+        // dnf 4 prints `ID Severity/Sec. NEVRA`, dnf 5 `ID security Severity NEVRA Issued`
+        "dnf" => lines
+            .filter_map(|line| {
+                let cols: Vec<&str> = line.split_whitespace().collect();
+                let (severity, nevra) = match cols.as_slice() {
+                    [_, rating, nevra, ..] if rating.ends_with("/Sec.") => {
+                        (rating.trim_end_matches("/Sec."), nevra)
+                    }
+                    [_, kind, rating, nevra, ..] if *kind == "security" => (*rating, nevra),
+                    _ => return None,
+                };
+                Some((
+                    nevra_name(nevra)?.to_string(),
+                    Advisory::new(vec![cols[0].to_string()], AdvisorySeverity::parse(severity)),
+                ))
+            })
+            .collect(),
+        // `Repository | Name | Category | Severity | Interactive | Status | Summary`;
+        // patches name the packages they fix only in their summary
+        "zypper" => lines
+            .filter_map(|line| {
+                let cols: Vec<&str> = line.split('|').map(str::trim).collect();
+                if cols.len() < 7 || cols[2] != "security" {
+                    return None;
+                }
+                let advisory =
+                    Advisory::new(vec![cols[1].to_string()], AdvisorySeverity::parse(cols[3]));
+                let (_, packages) = cols[6].split_once(" for ")?;
+                Some(
+                    packages
+                        .split([',', ' '])
+                        .filter(|name| !name.is_empty() && *name != "and")
+                        .map(|name| (name.to_string(), advisory.clone()))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .flatten()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the name of an rpm package from its `name-[epoch:]version-release.arch`.
+fn nevra_name(nevra: &str) -> Option<&str> {
+    let (rest, _arch) = nevra.rsplit_once('.')?;
+    let (rest, _release) = rest.rsplit_once('-')?;
+    let (name, _version) = rest.rsplit_once('-')?;
+    Some(name)
+}
+
+/// Attaches the `advisories` of `source` to its pending updates.
+pub fn annotate(pending: &mut [PendingUpdate], source: &str, advisories: Vec<(String, Advisory)>) {
+    for (package, advisory) in advisories {
+        for update in pending
+            .iter_mut()
+            .filter(|update| update.source == source && update.package == package)
+        {
+            match &mut update.advisory {
+                Some(existing) => existing.merge(advisory.clone()),
+                None => update.advisory = Some(advisory.clone()),
+            }
+        }
+    }
+}
+
+/// How many updates fix security issues, and the most severe of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SecuritySummary {
+    pub count: usize,
+    pub highest: Option<AdvisorySeverity>,
+}
+
+impl SecuritySummary {
+    pub fn of<'a>(updates: impl IntoIterator<Item = &'a PendingUpdate>) -> Self {
+        updates
+            .into_iter()
+            .filter_map(|update| update.advisory.as_ref())
+            .fold(Self::default(), |summary, advisory| Self {
+                count: summary.count + 1,
+                highest: summary.highest.max(Some(advisory.severity)),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pending::parse_check_output;
+
+    #[test]
+    fn test_parse_dnf_advisories() {
+        let output = "FEDORA-2024-1a2b3c Important/Sec. openssl-libs-1:3.1.4-3.fc40.x86_64\n\
+                      FEDORA-2024-4d5e6f bugfix         glibc-2.39-8.fc40.x86_64\n\
+                      FEDORA-2024-7a8b9c security Critical kernel-6.8.9-300.fc40.x86_64 2024-05-10 12:00:00\n";
+        let found = parse_advisories("dnf", output);
+
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, "openssl-libs");
+        assert_eq!(found[0].1.severity, AdvisorySeverity::High);
+        assert_eq!(found[0].1.ids, vec!["FEDORA-2024-1a2b3c"]);
+        assert_eq!(found[1].0, "kernel");
+        assert_eq!(found[1].1.severity, AdvisorySeverity::Critical);
+    }
+
+    #[test]
+    fn test_parse_zypper_advisories() {
+        let output = "Repository | Name | Category | Severity | Interactive | Status | Summary\n\
+                      -----------+------+----------+----------+-------------+--------+--------\n\
+                      Update | openSUSE-2024-123 | security | important | --- | needed | Security update for openssl-3\n\
+                      Update | openSUSE-2024-124 | recommended | low | --- | needed | Recommended update for vim\n\
+                      Update | openSUSE-2024-125 | security | moderate | --- | needed | Security update for curl, libcurl4\n";
+        let found = parse_advisories("zypper", output);
+
+        let names: Vec<&str> = found.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["openssl-3", "curl", "libcurl4"]);
+        assert_eq!(found[2].1.severity, AdvisorySeverity::Medium);
+    }
+
+    #[test]
+    fn test_apt_security_pocket() {
+        let output = "openssl/jammy-updates,jammy-security 3.0.2-0ubuntu1.15 amd64 [upgradable from: 3.0.2-0ubuntu1.14]\n\
+                      vim/jammy-updates 2:8.2.3995-1ubuntu2.16 amd64 [upgradable from: 2:8.2.3995-1ubuntu2.15]\n";
+        let pending = parse_check_output("apt", output);
+
+        assert_eq!(
+            pending[0].advisory.as_ref().map(|a| a.severity),
+            Some(AdvisorySeverity::Unknown)
+        );
+        assert!(pending[1].advisory.is_none());
+    }
+
+    #[test]
+    fn test_annotate_and_summarize() {
+        let mut pending =
+            parse_check_output("paru", "openssl 3.2.0-1 -> 3.2.1-1\nvim 9.0-1 -> 9.1-1\n");
+        let output =
+            "openssl|Medium|CVE-2024-0727\nopenssl|High|CVE-2024-2511\nlibxml2|Low|CVE-2024-1\n";
+        annotate(&mut pending, "paru", parse_advisories("paru", output));
+
+        let advisory = pending[0].advisory.as_ref().unwrap();
+        assert_eq!(advisory.severity, AdvisorySeverity::High);
+        assert_eq!(advisory.ids, vec!["CVE-2024-0727", "CVE-2024-2511"]);
+        assert!(pending[1].advisory.is_none());
+
+        let summary = SecuritySummary::of(&pending);
+        assert_eq!(summary.count, 1);
+        assert_eq!(summary.highest, Some(AdvisorySeverity::High));
+        assert!(summary.highest.unwrap().is_urgent());
+        assert_eq!(
+            SecuritySummary::of(&pending[1..]),
+            SecuritySummary::default()
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use async_std::process::Command;
+use std::{path::PathBuf, process::Stdio};
+
+/// Locations whose filesystems are measured to report reclaimed space:
+/// system caches live below `/`, user caches (npm, brew) in the home directory.
+pub fn measured_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("/")];
+    paths.extend(dirs::home_dir());
+    paths
+}
+
+/// Available space on a mounted filesystem, as reported by `df`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filesystem {
+    pub mount_point: String,
+    /// Bytes available to unprivileged users.
+    pub available: u64,
+}
+
+/// Returns the free space in bytes on the filesystems holding `paths`.
+///
+/// Each filesystem is counted once, even if several paths are on it.
+///
+/// # Errors
+///
+/// Returns an error if `df` cannot be run or its output cannot be parsed.
+pub async fn free_space(paths: &[PathBuf]) -> Result<u64> {
+    Ok(filesystems(paths)
+        .await?
+        .iter()
+        .map(|fs| fs.available)
+        .fold(0, u64::saturating_add))
+}
+
+/// Returns the filesystems holding `paths`, each listed once.
+///
+/// # Errors
+///
+/// Returns an error if `df` cannot be run or its output cannot be parsed.
+pub async fn filesystems(paths: &[PathBuf]) -> Result<Vec<Filesystem>> {
+    let output = Command::new("df")
+        .args(["-B1", "--output=avail,target"])
+        .args(paths)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to run df: {}", e))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("df failed for {:?}", paths));
+    }
+    parse_df_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses `df --output=avail,target`, skipping repeated mount points.
+fn parse_df_output(output: &str) -> Result<Vec<Filesystem>> {
+    let mut filesystems: Vec<Filesystem> = Vec::new();
+
+    for line in output.lines().skip(1) {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (avail, target) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let avail: u64 = avail
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Failed to parse df output {:?}: {}", line, e))?;
+        let target = target.trim();
+        if !filesystems.iter().any(|fs| fs.mount_point == target) {
+            filesystems.push(Filesystem {
+                mount_point: target.to_string(),
+                available: avail,
+            });
+        }
+    }
+
+    if filesystems.is_empty() {
+        return Err(anyhow::anyhow!("df reported no filesystems"));
+    }
+    Ok(filesystems)
+}
+
+/// Formats a byte count for humans, e.g. `1.5 GB`.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::cleanup::format_bytes;
+///
+/// assert_eq!(format_bytes(0), "0 bytes");
+/// assert_eq!(format_bytes(1_500_000_000), "1.5 GB");
+/// ```
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["kB", "MB", "GB", "TB"];
+
+    if bytes < 1000 {
+        return format!("{bytes} bytes");
+    }
+
+    let mut value = bytes as f64;
+    let mut unit = "";
+    for candidate in UNITS {
+        value /= 1000.0;
+        unit = candidate;
+        if value < 1000.0 {
+            break;
+        }
+    }
+    format!("{value:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_df_output() {
+        let output = "\
+       Avail Mounted on
+ 52031467520 /
+ 52031467520 /
+104857600000 /home/user data
+";
+        assert_eq!(
+            parse_df_output(output).unwrap(),
+            vec![
+                Filesystem {
+                    mount_point: "/".to_string(),
+                    available: 52_031_467_520,
+                },
+                Filesystem {
+                    mount_point: "/home/user data".to_string(),
+                    available: 104_857_600_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_df_output_errors() {
+        assert!(parse_df_output("Avail Mounted on\n").is_err());
+        assert!(parse_df_output("Avail Mounted on\n- /\n").is_err());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(999), "999 bytes");
+        assert_eq!(format_bytes(1_000), "1.0 kB");
+        assert_eq!(format_bytes(42_300_000), "42.3 MB");
+        assert_eq!(format_bytes(2_000_000_000_000), "2.0 TB");
+        assert_eq!(format_bytes(5_000_000_000_000_000), "5000.0 TB");
+    }
+}
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, ConstrainedPolicy};
+
+/// Battery charge in percent below which scheduled updates always wait,
+/// since an update interrupted by an empty battery can break the system.
+pub const LOW_BATTERY_PERCENT: f64 = 20.0;
+
+/// The network and power state scheduled runs are evaluated against.
+///
+/// The default describes an unconstrained system, which is also assumed
+/// when the state cannot be determined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conditions {
+    pub online: bool,
+    pub metered: bool,
+    pub power_saver: bool,
+    pub on_battery: bool,
+    /// Charge of the system battery in percent, if known.
+    pub battery_level: Option<f64>,
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self {
+            online: true,
+            metered: false,
+            power_saver: false,
+            on_battery: false,
+            battery_level: None,
+        }
+    }
+}
+
+/// What a scheduled run does with a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    Check,
+    Update,
+}
+
+/// A scheduled check or update that was postponed, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deferral {
+    pub source: String,
+    pub phase: Phase,
+    pub reason: String,
+    /// Unix timestamp (seconds) of the first time the source was deferred for this reason.
+    pub since: u64,
+}
+
+impl Conditions {
+    /// Returns why `phase` of `source` has to wait under these conditions, or
+    /// `None` if it may run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::conditions::{Conditions, Phase};
+    /// use uptodate::config::Config;
+    ///
+    /// let metered = Conditions {
+    ///     metered: true,
+    ///     ..Conditions::default()
+    /// };
+    /// // By default sources are still checked on metered connections, but not updated
+    /// let config = Config::default();
+    /// assert_eq!(metered.deferral_reason(&config, "flatpak", Phase::Check), None);
+    /// assert_eq!(
+    ///     metered.deferral_reason(&config, "flatpak", Phase::Update).as_deref(),
+    ///     Some("metered connection")
+    /// );
+    /// ```
+    pub fn deferral_reason(&self, config: &Config, source: &str, phase: Phase) -> Option<String> {
+        if !self.online {
+            return Some("offline".to_string());
+        }
+
+        let blocks = |policy: ConstrainedPolicy| match policy {
+            ConstrainedPolicy::Allow => false,
+            ConstrainedPolicy::CheckOnly => phase == Phase::Update,
+            ConstrainedPolicy::Defer => true,
+        };
+
+        if self.metered && blocks(config.metered_policy(source)) {
+            return Some("metered connection".to_string());
+        }
+
+        if (self.on_battery || self.power_saver) && blocks(config.battery_policy(source)) {
+            return Some(if self.on_battery {
+                "running on battery".to_string()
+            } else {
+                "power saver is on".to_string()
+            });
+        }
+
+        match self.battery_level {
+            Some(level)
+                if self.on_battery && level < LOW_BATTERY_PERCENT && phase == Phase::Update =>
+            {
+                Some(format!("battery at {level:.0}%"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Splits `sources` into those that may run `phase` and the deferred ones.
+    pub fn partition(
+        &self,
+        config: &Config,
+        sources: Vec<String>,
+        phase: Phase,
+        now: u64,
+    ) -> (Vec<String>, Vec<Deferral>) {
+        let mut allowed = Vec::new();
+        let mut deferred = Vec::new();
+        for source in sources {
+            match self.deferral_reason(config, &source, phase) {
+                Some(reason) => deferred.push(Deferral {
+                    source,
+                    phase,
+                    reason,
+                    since: now,
+                }),
+                None => allowed.push(source),
+            }
+        }
+        (allowed, deferred)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unconstrained_runs_everything() {
+        let config = Config::default();
+        let conditions = Conditions::default();
+        assert_eq!(
+            conditions.deferral_reason(&config, "paru", Phase::Check),
+            None
+        );
+        assert_eq!(
+            conditions.deferral_reason(&config, "paru", Phase::Update),
+            None
+        );
+    }
+
+    #[test]
+    fn test_offline_defers_everything() {
+        let mut config = Config::default();
+        config
+            .set_metered_policy("flatpak", ConstrainedPolicy::Allow)
+            .unwrap();
+        let conditions = Conditions {
+            online: false,
+            ..Conditions::default()
+        };
+        assert_eq!(
+            conditions
+                .deferral_reason(&config, "flatpak", Phase::Check)
+                .as_deref(),
+            Some("offline")
+        );
+    }
+
+    #[test]
+    fn test_metered_policies() {
+        let mut config = Config::default();
+        config
+            .set_metered_policy("rustup", ConstrainedPolicy::Allow)
+            .unwrap();
+        config
+            .set_metered_policy("flatpak", ConstrainedPolicy::Defer)
+            .unwrap();
+        let conditions = Conditions {
+            metered: true,
+            ..Conditions::default()
+        };
+
+        assert_eq!(
+            conditions.deferral_reason(&config, "rustup", Phase::Update),
+            None
+        );
+        assert_eq!(
+            conditions.deferral_reason(&config, "paru", Phase::Check),
+            None
+        );
+        assert!(
+            conditions
+                .deferral_reason(&config, "paru", Phase::Update)
+                .is_some()
+        );
+        assert!(
+            conditions
+                .deferral_reason(&config, "flatpak", Phase::Check)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_battery_policies() {
+        let mut config = Config::default();
+        config
+            .set_battery_policy("npm", ConstrainedPolicy::Allow)
+            .unwrap();
+
+        let power_saver = Conditions {
+            power_saver: true,
+            ..Conditions::default()
+        };
+        assert_eq!(
+            power_saver
+                .deferral_reason(&config, "paru", Phase::Update)
+                .as_deref(),
+            Some("power saver is on")
+        );
+        assert_eq!(
+            power_saver.deferral_reason(&config, "npm", Phase::Update),
+            None
+        );
+
+        // A nearly empty battery stops updates even where they are allowed
+        let low_battery = Conditions {
+            on_battery: true,
+            battery_level: Some(12.4),
+            ..Conditions::default()
+        };
+        assert_eq!(
+            low_battery
+                .deferral_reason(&config, "npm", Phase::Update)
+                .as_deref(),
+            Some("battery at 12%")
+        );
+        assert_eq!(
+            low_battery.deferral_reason(&config, "npm", Phase::Check),
+            None
+        );
+    }
+
+    #[test]
+    fn test_partition() {
+        let config = Config::default();
+        let conditions = Conditions {
+            on_battery: true,
+            battery_level: Some(80.0),
+            ..Conditions::default()
+        };
+        let sources = vec!["flatpak".to_string(), "rustup".to_string()];
+
+        let (allowed, deferred) = conditions.partition(&config, sources.clone(), Phase::Check, 7);
+        assert_eq!(allowed, sources);
+        assert!(deferred.is_empty());
+
+        let (allowed, deferred) = conditions.partition(&config, sources, Phase::Update, 7);
+        assert!(allowed.is_empty());
+        assert_eq!(
+            deferred[1],
+            Deferral {
+                source: "rustup".to_string(),
+                phase: Phase::Update,
+                reason: "running on battery".to_string(),
+                since: 7,
+            }
+        );
+    }
+}
@@ -0,0 +1,587 @@
+use anyhow::Result;
+use async_std::{fs, io::WriteExt, process::Command};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{info, warn};
+
+/// Files longer than this are not compared line by line; they are shown as
+/// replaced as a whole.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Markers around the differing lines of a merge draft.
+const MARKER_MINE: &str = "<<<<<<< yours";
+const MARKER_SPLIT: &str = "=======";
+const MARKER_NEW: &str = ">>>>>>> package";
+
+/// Why a package manager left a second version of a configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConffileKind {
+    /// pacman installed the new version next to the edited file.
+    Pacnew,
+    /// pacman saved the edited file when its package was removed or replaced it.
+    Pacsave,
+    /// rpm installed the new version next to the edited file.
+    Rpmnew,
+    /// dpkg kept the edited file and installed the maintainer's version next to it.
+    DpkgDist,
+    /// dpkg unpacked the new version but did not put it in place.
+    DpkgNew,
+}
+
+impl ConffileKind {
+    pub const ALL: [Self; 5] = [
+        Self::Pacnew,
+        Self::Pacsave,
+        Self::Rpmnew,
+        Self::DpkgDist,
+        Self::DpkgNew,
+    ];
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Pacnew => ".pacnew",
+            Self::Pacsave => ".pacsave",
+            Self::Rpmnew => ".rpmnew",
+            Self::DpkgDist => ".dpkg-dist",
+            Self::DpkgNew => ".dpkg-new",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Pacnew => "New version from pacman",
+            Self::Pacsave => "Your version, saved by pacman",
+            Self::Rpmnew => "New version from rpm",
+            Self::DpkgDist => "New version from dpkg",
+            Self::DpkgNew => "New version from dpkg, not yet in place",
+        }
+    }
+
+    /// Returns `true` if the extra file holds the user's version instead of
+    /// the packaged one.
+    pub fn saves_mine(&self) -> bool {
+        *self == Self::Pacsave
+    }
+}
+
+/// A configuration file with a second version left next to it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Conffile {
+    /// The configuration file in use.
+    pub path: PathBuf,
+    /// The version the package manager left next to it.
+    pub other: PathBuf,
+    pub kind: ConffileKind,
+}
+
+/// What to do with the two versions of a configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    KeepMine,
+    TakeNew,
+    /// Replace the file with merged content.
+    Merge(String),
+}
+
+impl Conffile {
+    /// Returns the configuration file that `other` is a second version of.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use uptodate::conffiles::{Conffile, ConffileKind};
+    ///
+    /// let conffile = Conffile::from_path(Path::new("/etc/pacman.conf.pacnew")).unwrap();
+    /// assert_eq!(conffile.path, Path::new("/etc/pacman.conf"));
+    /// assert_eq!(conffile.kind, ConffileKind::Pacnew);
+    /// assert!(Conffile::from_path(Path::new("/etc/pacman.conf")).is_none());
+    /// ```
+    pub fn from_path(other: &Path) -> Option<Self> {
+        let name = other.file_name()?.to_str()?;
+        ConffileKind::ALL.iter().find_map(|kind| {
+            let original = name.strip_suffix(kind.suffix())?;
+            (!original.is_empty()).then(|| Self {
+                path: other.with_file_name(original),
+                other: other.to_path_buf(),
+                kind: *kind,
+            })
+        })
+    }
+
+    /// The version the user edited.
+    pub fn mine(&self) -> &Path {
+        if self.kind.saves_mine() {
+            &self.other
+        } else {
+            &self.path
+        }
+    }
+
+    /// The version shipped by the package.
+    pub fn packaged(&self) -> &Path {
+        if self.kind.saves_mine() {
+            &self.path
+        } else {
+            &self.other
+        }
+    }
+
+    /// Compares the user's version with the packaged one.
+    ///
+    /// A missing file, e.g. after a package was removed, compares as empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file exists but cannot be read.
+    pub async fn diff(&self) -> Result<Vec<DiffRow>> {
+        let mine = read_optional(self.mine()).await?;
+        let packaged = read_optional(self.packaged()).await?;
+        Ok(diff_lines(&mine, &packaged))
+    }
+
+    /// Applies `resolution` through `pkexec`, leaving only the configuration
+    /// file in use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if merged content still has conflict markers, if
+    /// authorization is denied or if the files cannot be changed.
+    pub async fn resolve(&self, resolution: &Resolution) -> Result<()> {
+        self.resolve_with(resolution, &["pkexec"]).await
+    }
+
+    async fn resolve_with(&self, resolution: &Resolution, launcher: &[&str]) -> Result<()> {
+        // Merged content is handed to the privileged command on stdin, so no
+        // file another user could read or replace exists in between
+        let merged = match resolution {
+            Resolution::Merge(content) if has_conflict_markers(content) => {
+                return Err(anyhow::anyhow!(
+                    "The merge of {:?} still has conflict markers",
+                    self.path
+                ));
+            }
+            Resolution::Merge(content) => Some(content),
+            _ => None,
+        };
+
+        let cmd = self.resolution_command(resolution);
+        let mut child = Command::new(launcher.first().copied().unwrap_or(&cmd[0]))
+            .args(launcher.iter().skip(1))
+            .args(&cmd[usize::from(launcher.is_empty())..])
+            .stdin(if merged.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to update {:?}: {}", self.path, e))?;
+
+        if let (Some(content), Some(mut stdin)) = (merged, child.stdin.take()) {
+            // A failed write shows up as the command's failure below
+            if let Err(e) = stdin.write_all(content.as_bytes()).await {
+                warn!("Failed to pass the merge of {:?}: {}", self.path, e);
+            }
+        }
+
+        let output = child
+            .output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to update {:?}: {}", self.path, e))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to update {:?}: {}",
+                self.path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!(
+            "Resolved {:?}: {:?}",
+            self.other,
+            resolution_name(resolution)
+        );
+        Ok(())
+    }
+
+    /// Returns the command applying `resolution`; merged content is read
+    /// from stdin.
+    fn resolution_command(&self, resolution: &Resolution) -> Vec<String> {
+        let path = self.path.to_string_lossy().into_owned();
+        let other = self.other.to_string_lossy().into_owned();
+        let keep = match resolution {
+            Resolution::KeepMine => self.mine(),
+            Resolution::TakeNew => self.packaged(),
+            Resolution::Merge(_) => {
+                // Writing through the existing file keeps its owner and permissions
+                return [
+                    "sh",
+                    "-c",
+                    "cat > \"$1\" && rm -f -- \"$2\"",
+                    "sh",
+                    &path,
+                    &other,
+                ]
+                .map(String::from)
+                .to_vec();
+            }
+        };
+
+        if keep == self.path {
+            ["rm", "-f", "--", &other].map(String::from).to_vec()
+        } else {
+            ["mv", "-f", "--", &other, &path].map(String::from).to_vec()
+        }
+    }
+}
+
+fn resolution_name(resolution: &Resolution) -> &'static str {
+    match resolution {
+        Resolution::KeepMine => "kept mine",
+        Resolution::TakeNew => "took new",
+        Resolution::Merge(_) => "merged",
+    }
+}
+
+async fn read_optional(path: &Path) -> Result<String> {
+    match fs::read(path).await {
+        Ok(content) => Ok(String::from_utf8_lossy(&content).into_owned()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(anyhow::anyhow!("Failed to read {:?}: {}", path, e)),
+    }
+}
+
+/// A row of a side-by-side comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffRow {
+    Same(String),
+    /// Lines that differ; a side is `None` where the other one has extra lines.
+    Changed {
+        mine: Option<String>,
+        packaged: Option<String>,
+    },
+}
+
+impl DiffRow {
+    pub fn is_changed(&self) -> bool {
+        matches!(self, Self::Changed { .. })
+    }
+}
+
+/// Compares two texts line by line, pairing up the lines that differ.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::conffiles::{DiffRow, diff_lines};
+///
+/// let rows = diff_lines("a\nb\n", "a\nc\n");
+/// assert_eq!(rows[0], DiffRow::Same("a".to_string()));
+/// assert_eq!(
+///     rows[1],
+///     DiffRow::Changed {
+///         mine: Some("b".to_string()),
+///         packaged: Some("c".to_string())
+///     }
+/// );
+/// ```
+pub fn diff_lines(mine: &str, packaged: &str) -> Vec<DiffRow> {
+    let mine: Vec<&str> = mine.lines().collect();
+    let packaged: Vec<&str> = packaged.lines().collect();
+
+    // Longest common subsequence of the lines after each position
+    let (n, m) = (mine.len(), packaged.len());
+    let common = if n.saturating_mul(m) <= MAX_DIFF_CELLS {
+        let mut table = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                table[i][j] = if mine[i] == packaged[j] {
+                    table[i + 1][j + 1] + 1
+                } else {
+                    table[i + 1][j].max(table[i][j + 1])
+                };
+            }
+        }
+        Some(table)
+    } else {
+        None
+    };
+
+    let mut rows = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let same = i < n && j < m && mine[i] == packaged[j] && common.is_some();
+        if same {
+            flush_changes(&mut rows, &mut removed, &mut added);
+            rows.push(DiffRow::Same(mine[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if j == m
+            || (i < n
+                && common
+                    .as_ref()
+                    .is_none_or(|table| table[i + 1][j] >= table[i][j + 1]))
+        {
+            removed.push(mine[i]);
+            i += 1;
+        } else {
+            added.push(packaged[j]);
+            j += 1;
+        }
+    }
+    flush_changes(&mut rows, &mut removed, &mut added);
+    rows
+}
+
+fn flush_changes(rows: &mut Vec<DiffRow>, removed: &mut Vec<&str>, added: &mut Vec<&str>) {
+    for k in 0..removed.len().max(added.len()) {
+        rows.push(DiffRow::Changed {
+            mine: removed.get(k).map(|line| line.to_string()),
+            packaged: added.get(k).map(|line| line.to_string()),
+        });
+    }
+    removed.clear();
+    added.clear();
+}
+
+/// Returns both versions in one text, with each difference between
+/// conflict markers for the user to resolve.
+pub fn merge_draft(rows: &[DiffRow]) -> String {
+    let mut draft = String::new();
+    let mut hunk: Vec<(&Option<String>, &Option<String>)> = Vec::new();
+    let flush = |draft: &mut String, hunk: &mut Vec<(&Option<String>, &Option<String>)>| {
+        if hunk.is_empty() {
+            return;
+        }
+        draft.push_str(MARKER_MINE);
+        draft.push('\n');
+        for line in hunk.iter().filter_map(|(mine, _)| mine.as_ref()) {
+            draft.push_str(line);
+            draft.push('\n');
+        }
+        draft.push_str(MARKER_SPLIT);
+        draft.push('\n');
+        for line in hunk.iter().filter_map(|(_, packaged)| packaged.as_ref()) {
+            draft.push_str(line);
+            draft.push('\n');
+        }
+        draft.push_str(MARKER_NEW);
+        draft.push('\n');
+        hunk.clear();
+    };
+
+    for row in rows {
+        match row {
+            DiffRow::Same(line) => {
+                flush(&mut draft, &mut hunk);
+                draft.push_str(line);
+                draft.push('\n');
+            }
+            DiffRow::Changed { mine, packaged } => hunk.push((mine, packaged)),
+        }
+    }
+    flush(&mut draft, &mut hunk);
+    draft
+}
+
+/// Returns `true` if `text` still has markers of an unresolved difference.
+pub fn has_conflict_markers(text: &str) -> bool {
+    text.lines()
+        .any(|line| line == MARKER_MINE || line == MARKER_SPLIT || line == MARKER_NEW)
+}
+
+/// Looks for configuration files with a second version left next to them.
+#[derive(Debug, Clone)]
+pub struct ConffileScan {
+    root: PathBuf,
+}
+
+impl Default for ConffileScan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConffileScan {
+    pub fn new() -> Self {
+        Self::with_root(Path::new("/"))
+    }
+
+    /// Scans `root/etc` instead of `/etc`.
+    pub fn with_root(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Returns the files found under `/etc`, sorted by path.
+    ///
+    /// Directories that cannot be read are skipped and symbolic links are
+    /// not followed.
+    pub async fn scan(&self) -> Vec<Conffile> {
+        let mut found = Vec::new();
+        let mut directories = vec![self.root.join("etc")];
+        while let Some(directory) = directories.pop() {
+            let Ok(entries) = std::fs::read_dir(&directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let path = entry.path();
+                if file_type.is_dir() {
+                    directories.push(path);
+                } else if file_type.is_file()
+                    && let Some(conffile) = Conffile::from_path(&path)
+                {
+                    found.push(conffile);
+                }
+            }
+            // Large trees should not block the executor for long
+            async_std::task::yield_now().await;
+        }
+        found.sort();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write;
+    use tempfile::tempdir;
+
+    #[async_std::test]
+    async fn test_scan_finds_new_versions() {
+        let root = tempdir().unwrap();
+        write(root.path(), "etc/pacman.conf", "mine");
+        write(root.path(), "etc/pacman.conf.pacnew", "new");
+        write(root.path(), "etc/ssh/sshd_config.dpkg-dist", "new");
+        write(root.path(), "etc/yum.repos.d/fedora.repo.rpmnew", "new");
+        write(root.path(), "etc/makepkg.conf.pacsave", "mine");
+        write(root.path(), "etc/hosts", "");
+        write(root.path(), "usr/share/foo.conf.pacnew", "not in /etc");
+        std::os::unix::fs::symlink(root.path().join("usr"), root.path().join("etc/usr")).unwrap();
+
+        let found = ConffileScan::with_root(root.path()).scan().await;
+        let others: Vec<_> = found
+            .iter()
+            .map(|c| c.other.strip_prefix(root.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            others,
+            vec![
+                PathBuf::from("etc/makepkg.conf.pacsave"),
+                PathBuf::from("etc/pacman.conf.pacnew"),
+                PathBuf::from("etc/ssh/sshd_config.dpkg-dist"),
+                PathBuf::from("etc/yum.repos.d/fedora.repo.rpmnew"),
+            ]
+        );
+        assert_eq!(found[1].path, root.path().join("etc/pacman.conf"));
+    }
+
+    #[test]
+    fn test_pacsave_holds_mine() {
+        let pacsave = Conffile::from_path(Path::new("/etc/makepkg.conf.pacsave")).unwrap();
+        assert_eq!(pacsave.mine(), Path::new("/etc/makepkg.conf.pacsave"));
+        assert_eq!(pacsave.packaged(), Path::new("/etc/makepkg.conf"));
+        assert!(Conffile::from_path(Path::new("/etc/.pacnew")).is_none());
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let rows = diff_lines("a\nb\nc\nd\n", "a\nc\nd\ne\nf\n");
+        assert_eq!(
+            rows,
+            vec![
+                DiffRow::Same("a".to_string()),
+                DiffRow::Changed {
+                    mine: Some("b".to_string()),
+                    packaged: None
+                },
+                DiffRow::Same("c".to_string()),
+                DiffRow::Same("d".to_string()),
+                DiffRow::Changed {
+                    mine: None,
+                    packaged: Some("e".to_string())
+                },
+                DiffRow::Changed {
+                    mine: None,
+                    packaged: Some("f".to_string())
+                },
+            ]
+        );
+        assert!(
+            diff_lines("same\n", "same\n")
+                .iter()
+                .all(|row| !row.is_changed())
+        );
+    }
+
+    #[test]
+    fn test_merge_draft() {
+        let draft = merge_draft(&diff_lines("a\nmine\nz\n", "a\nnew\nz\n"));
+        assert_eq!(
+            draft,
+            "a\n<<<<<<< yours\nmine\n=======\nnew\n>>>>>>> package\nz\n"
+        );
+        assert!(has_conflict_markers(&draft));
+        assert!(!has_conflict_markers("a\nnew\nz\n"));
+    }
+
+    #[async_std::test]
+    async fn test_resolve_in_fake_root() {
+        let root = tempdir().unwrap();
+        let scan = ConffileScan::with_root(root.path());
+        write(root.path(), "etc/a.conf", "mine");
+        write(root.path(), "etc/a.conf.pacnew", "new");
+        write(root.path(), "etc/b.conf", "mine");
+        write(root.path(), "etc/b.conf.rpmnew", "new");
+        write(root.path(), "etc/c.conf", "a\nmine\n");
+        write(root.path(), "etc/c.conf.dpkg-dist", "a\nnew\n");
+
+        let found = scan.scan().await;
+        found[0]
+            .resolve_with(&Resolution::KeepMine, &[])
+            .await
+            .unwrap();
+        found[1]
+            .resolve_with(&Resolution::TakeNew, &[])
+            .await
+            .unwrap();
+
+        let draft = merge_draft(&found[2].diff().await.unwrap());
+        let unresolved = Resolution::Merge(draft);
+        assert!(found[2].resolve_with(&unresolved, &[]).await.is_err());
+        found[2]
+            .resolve_with(&Resolution::Merge("a\nmerged\n".to_string()), &[])
+            .await
+            .unwrap();
+
+        let read = |path: &str| std::fs::read_to_string(root.path().join(path)).unwrap();
+        assert_eq!(read("etc/a.conf"), "mine");
+        assert_eq!(read("etc/b.conf"), "new");
+        assert_eq!(read("etc/c.conf"), "a\nmerged\n");
+        assert!(scan.scan().await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_diff_of_removed_file() {
+        let root = tempdir().unwrap();
+        write(root.path(), "etc/gone.conf.pacsave", "setting\n");
+
+        let found = ConffileScan::with_root(root.path()).scan().await;
+        assert_eq!(
+            found[0].diff().await.unwrap(),
+            vec![DiffRow::Changed {
+                mine: Some("setting".to_string()),
+                packaged: None
+            }]
+        );
+    }
+}
@@ -2,13 +2,127 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, path::PathBuf};
 
+use crate::{
+    critical::{default_critical_packages, matches_critical, validate_critical_pattern},
+    updater::{ATTENDED_MANAGERS, RunMode, validate_package_name},
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub dry_run: bool,
     pub enabled_sources: HashMap<String, bool>,
     pub custom_commands: Vec<CustomCommand>,
     pub save_logs: bool,
     pub logs_dir: PathBuf,
+    /// Periodically run the check phase while the application is resident.
+    pub background_checks: bool,
+    /// Hours between two background checks.
+    pub check_interval_hours: u64,
+    pub show_notifications: bool,
+    /// Hours the "Snooze" notification action suppresses update reminders for.
+    pub snooze_hours: u64,
+    /// Run checks from a systemd user timer instead of keeping the application resident.
+    pub systemd_timer: bool,
+    /// `OnCalendar=` schedule of the systemd timer.
+    pub timer_schedule: String,
+    /// What scheduled runs may do per source; unlisted sources use the default policy.
+    pub source_policies: HashMap<String, SourcePolicy>,
+    /// What scheduled runs may do per source on metered connections.
+    pub metered_policies: HashMap<String, ConstrainedPolicy>,
+    /// What scheduled runs may do per source on battery or in power saver mode.
+    pub battery_policies: HashMap<String, ConstrainedPolicy>,
+    /// Packages that are never updated, whichever source provides them.
+    pub excluded_packages: Vec<String>,
+    /// Packages that are never updated by a particular source.
+    pub source_exclusions: HashMap<String, Vec<String>>,
+    /// Snapshot the root filesystem before and after system package updates.
+    pub snapshot_before_updates: bool,
+    /// Remove orphaned packages, old caches and unused runtimes after updating.
+    pub cleanup_after_updates: bool,
+    /// Wait for package manager locks held by other programs instead of skipping the source.
+    pub wait_for_locks: bool,
+    /// Overrides of the environment package managers run in; an empty value removes a variable.
+    pub child_environment: HashMap<String, String>,
+    /// Sources run under a pseudo-terminal in manual runs, so that their questions can be answered.
+    pub terminal_sources: Vec<String>,
+    /// Packages whose updates deserve extra care, per source; a trailing `*`
+    /// matches any suffix. Unlisted sources use the distribution's defaults.
+    pub critical_packages: HashMap<String, Vec<String>>,
+    /// Ask before updating critical packages, even when an update is started
+    /// from a notification, and leave them to the user in automatic runs.
+    pub confirm_critical_updates: bool,
+    /// Snapshot before updating critical packages if a snapshot tool is available.
+    pub snapshot_critical_updates: bool,
+}
+
+/// What background checks and scheduled runs may do with a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourcePolicy {
+    /// Never touched outside of runs started by the user.
+    Manual,
+    /// Checked in the background; the user is notified about pending updates.
+    #[default]
+    NotifyOnly,
+    /// Checked and updated in the background; the user is only notified on failure.
+    AutoUpdate,
+}
+
+impl SourcePolicy {
+    pub const ALL: [SourcePolicy; 3] = [Self::Manual, Self::NotifyOnly, Self::AutoUpdate];
+
+    /// Returns the policies `source` can have; system packages and firmware
+    /// need someone to authorize them, so they are never updated automatically.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::config::SourcePolicy;
+    ///
+    /// assert_eq!(SourcePolicy::available_for("flatpak").len(), 3);
+    /// assert!(!SourcePolicy::available_for("apt").contains(&SourcePolicy::AutoUpdate));
+    /// ```
+    pub fn available_for(source: &str) -> &'static [SourcePolicy] {
+        if ATTENDED_MANAGERS.contains(&source) {
+            &Self::ALL[..2]
+        } else {
+            &Self::ALL
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Manual => "Manual",
+            Self::NotifyOnly => "Notify only",
+            Self::AutoUpdate => "Update automatically",
+        }
+    }
+}
+
+/// What scheduled runs may do with a source on a metered connection or on battery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConstrainedPolicy {
+    /// Check and update as usual.
+    Allow,
+    /// Check for updates, but wait with downloading them.
+    #[default]
+    CheckOnly,
+    /// Neither check nor update until the constraint is gone.
+    Defer,
+}
+
+impl ConstrainedPolicy {
+    pub const ALL: [ConstrainedPolicy; 3] = [Self::Allow, Self::CheckOnly, Self::Defer];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Allow => "Check and update",
+            Self::CheckOnly => "Check only",
+            Self::Defer => "Wait",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -146,6 +260,25 @@ impl Default for Config {
             custom_commands: Vec::new(),
             save_logs: true,
             logs_dir,
+            background_checks: false,
+            check_interval_hours: 24,
+            show_notifications: true,
+            snooze_hours: 24,
+            systemd_timer: false,
+            timer_schedule: "daily".to_string(),
+            source_policies: HashMap::new(),
+            metered_policies: HashMap::new(),
+            battery_policies: HashMap::new(),
+            excluded_packages: Vec::new(),
+            source_exclusions: HashMap::new(),
+            snapshot_before_updates: false,
+            cleanup_after_updates: false,
+            wait_for_locks: false,
+            child_environment: HashMap::new(),
+            terminal_sources: Vec::new(),
+            critical_packages: HashMap::new(),
+            confirm_critical_updates: true,
+            snapshot_critical_updates: true,
         }
     }
 }
@@ -356,6 +489,261 @@ impl Config {
         self.enabled_sources.get(source).copied().unwrap_or(true)
     }
 
+    /// Returns the scheduling policy of a package source.
+    ///
+    /// Sources without an explicit policy default to [`SourcePolicy::NotifyOnly`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::config::{Config, SourcePolicy};
+    ///
+    /// let mut config = Config::default();
+    /// assert_eq!(config.source_policy("flatpak"), SourcePolicy::NotifyOnly);
+    ///
+    /// config.set_source_policy("flatpak", SourcePolicy::AutoUpdate).unwrap();
+    /// assert_eq!(config.source_policy("flatpak"), SourcePolicy::AutoUpdate);
+    /// ```
+    pub fn source_policy(&self, source: &str) -> SourcePolicy {
+        self.source_policies
+            .get(source)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sets the scheduling policy of a package source.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source name is invalid or `policy` is not
+    /// available for the source.
+    pub fn set_source_policy(&mut self, source: &str, policy: SourcePolicy) -> Result<()> {
+        validate_source_name(source)?;
+        if !SourcePolicy::available_for(source).contains(&policy) {
+            return Err(anyhow::anyhow!(
+                "{} needs authorization and cannot be updated automatically",
+                source
+            ));
+        }
+
+        tracing::info!("Set policy of {} to {:?}", source, policy);
+        self.source_policies.insert(source.to_string(), policy);
+        Ok(())
+    }
+
+    /// Returns what scheduled runs may do with `source` on metered connections.
+    ///
+    /// Sources without an explicit policy default to [`ConstrainedPolicy::CheckOnly`].
+    pub fn metered_policy(&self, source: &str) -> ConstrainedPolicy {
+        self.metered_policies
+            .get(source)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sets what scheduled runs may do with a source on metered connections.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source name is invalid.
+    pub fn set_metered_policy(&mut self, source: &str, policy: ConstrainedPolicy) -> Result<()> {
+        validate_source_name(source)?;
+
+        tracing::info!("Set metered policy of {} to {:?}", source, policy);
+        self.metered_policies.insert(source.to_string(), policy);
+        Ok(())
+    }
+
+    /// Returns what scheduled runs may do with `source` on battery or in power saver mode.
+    ///
+    /// Sources without an explicit policy default to [`ConstrainedPolicy::CheckOnly`].
+    pub fn battery_policy(&self, source: &str) -> ConstrainedPolicy {
+        self.battery_policies
+            .get(source)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sets what scheduled runs may do with a source on battery or in power saver mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source name is invalid.
+    pub fn set_battery_policy(&mut self, source: &str, policy: ConstrainedPolicy) -> Result<()> {
+        validate_source_name(source)?;
+
+        tracing::info!("Set battery policy of {} to {:?}", source, policy);
+        self.battery_policies.insert(source.to_string(), policy);
+        Ok(())
+    }
+
+    /// Returns `true` if `source` runs under a pseudo-terminal in manual runs.
+    pub fn runs_in_terminal(&self, source: &str) -> bool {
+        self.terminal_sources.iter().any(|s| s == source)
+    }
+
+    /// Sets whether `source` runs under a pseudo-terminal in manual runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source name is invalid.
+    pub fn set_runs_in_terminal(&mut self, source: &str, terminal: bool) -> Result<()> {
+        validate_source_name(source)?;
+
+        self.terminal_sources.retain(|s| s != source);
+        if terminal {
+            self.terminal_sources.push(source.to_string());
+        }
+        tracing::info!("Set {} to run in a terminal: {}", source, terminal);
+        Ok(())
+    }
+
+    /// Returns the packages excluded from updates of `source`, global exclusions first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::config::Config;
+    ///
+    /// let mut config = Config::default();
+    /// config.set_package_excluded(None, "linux", true).unwrap();
+    /// config.set_package_excluded(Some("flatpak"), "org.gimp.GIMP", true).unwrap();
+    ///
+    /// assert_eq!(config.excluded_packages_for("flatpak"), ["linux", "org.gimp.GIMP"]);
+    /// assert_eq!(config.excluded_packages_for("paru"), ["linux"]);
+    /// ```
+    pub fn excluded_packages_for(&self, source: &str) -> Vec<String> {
+        let mut excluded = self.excluded_packages.clone();
+        for package in self.source_exclusions.get(source).into_iter().flatten() {
+            if !excluded.contains(package) {
+                excluded.push(package.clone());
+            }
+        }
+        excluded
+    }
+
+    /// Returns the exclusions of each of `sources`, omitting sources without any.
+    pub fn exclusions_for(&self, sources: &[String]) -> HashMap<String, Vec<String>> {
+        sources
+            .iter()
+            .map(|source| (source.clone(), self.excluded_packages_for(source)))
+            .filter(|(_, excluded)| !excluded.is_empty())
+            .collect()
+    }
+
+    /// Returns the mode of update runs, i.e. whether they end with a cleanup.
+    pub fn update_mode(&self) -> RunMode {
+        if self.cleanup_after_updates {
+            RunMode::UpdateAndCleanup
+        } else {
+            RunMode::Update
+        }
+    }
+
+    /// Returns `true` if `package` must not be updated by `source`.
+    pub fn is_package_excluded(&self, source: &str, package: &str) -> bool {
+        self.excluded_packages.iter().any(|p| p == package)
+            || self
+                .source_exclusions
+                .get(source)
+                .is_some_and(|packages| packages.iter().any(|p| p == package))
+    }
+
+    /// Returns the critical package patterns of `source`, falling back to the
+    /// distribution's defaults.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::config::Config;
+    ///
+    /// let mut config = Config::default();
+    /// assert!(config.is_package_critical("dnf", "kernel-core"));
+    ///
+    /// config.set_critical_packages("dnf", vec!["mesa-*".to_string()]).unwrap();
+    /// assert!(!config.is_package_critical("dnf", "kernel-core"));
+    /// assert!(config.is_package_critical("dnf", "mesa-dri-drivers"));
+    /// ```
+    pub fn critical_packages_for(&self, source: &str) -> Vec<String> {
+        match self.critical_packages.get(source) {
+            Some(patterns) => patterns.clone(),
+            None => default_critical_packages(source)
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+        }
+    }
+
+    /// Returns `true` if updates of `package` from `source` deserve extra care.
+    pub fn is_package_critical(&self, source: &str, package: &str) -> bool {
+        match self.critical_packages.get(source) {
+            Some(patterns) => matches_critical(patterns, package),
+            None => matches_critical(default_critical_packages(source), package),
+        }
+    }
+
+    /// Sets the critical package patterns of `source`; the distribution's
+    /// defaults are not stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source name or one of the patterns is invalid.
+    pub fn set_critical_packages(&mut self, source: &str, patterns: Vec<String>) -> Result<()> {
+        validate_source_name(source)?;
+        for pattern in &patterns {
+            validate_critical_pattern(pattern)?;
+        }
+
+        if patterns
+            .iter()
+            .map(String::as_str)
+            .eq(default_critical_packages(source).iter().copied())
+        {
+            self.critical_packages.remove(source);
+        } else {
+            tracing::info!("Critical packages of {}: {}", source, patterns.join(", "));
+            self.critical_packages.insert(source.to_string(), patterns);
+        }
+        Ok(())
+    }
+
+    /// Excludes a package from updates, or lifts the exclusion.
+    ///
+    /// With `source` set to `None` the exclusion applies to every source.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source or package name is invalid.
+    pub fn set_package_excluded(
+        &mut self,
+        source: Option<&str>,
+        package: &str,
+        excluded: bool,
+    ) -> Result<()> {
+        validate_package_name(package)?;
+        let packages = match source {
+            Some(source) => {
+                validate_source_name(source)?;
+                self.source_exclusions
+                    .entry(source.to_string())
+                    .or_default()
+            }
+            None => &mut self.excluded_packages,
+        };
+
+        packages.retain(|p| p != package);
+        if excluded {
+            tracing::info!("Excluded {} from updates of {:?}", package, source);
+            packages.push(package.to_string());
+        } else {
+            tracing::info!("Included {} in updates of {:?}", package, source);
+        }
+
+        self.source_exclusions
+            .retain(|_, packages| !packages.is_empty());
+        Ok(())
+    }
+
     /// Adds a custom update command to the configuration.
     ///
     /// Custom commands are user-defined shell commands that will be executed
@@ -435,6 +823,32 @@ mod tests {
         assert!(config.enabled_sources.is_empty());
         assert!(config.custom_commands.is_empty());
         assert!(config.logs_dir.ends_with("uptodate"));
+        assert!(!config.background_checks);
+        assert_eq!(config.check_interval_hours, 24);
+        assert!(config.show_notifications);
+        assert_eq!(config.snooze_hours, 24);
+        assert!(!config.systemd_timer);
+        assert_eq!(config.timer_schedule, "daily");
+        assert!(config.source_policies.is_empty());
+        assert!(config.excluded_packages.is_empty());
+        assert!(config.source_exclusions.is_empty());
+        assert!(!config.snapshot_before_updates);
+        assert!(!config.cleanup_after_updates);
+        assert!(!config.wait_for_locks);
+        assert!(config.child_environment.is_empty());
+        assert!(config.terminal_sources.is_empty());
+        assert!(config.critical_packages.is_empty());
+        assert!(config.confirm_critical_updates);
+        assert!(config.snapshot_critical_updates);
+    }
+
+    #[test]
+    fn test_config_missing_fields_use_defaults() {
+        let config: Config = toml::from_str("dry_run = true\n").unwrap();
+
+        assert!(config.dry_run);
+        assert!(config.save_logs);
+        assert_eq!(config.check_interval_hours, 24);
     }
 
     #[test]
@@ -524,6 +938,171 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_config_source_policies() {
+        let mut config = Config::default();
+
+        config
+            .set_source_policy("paru", SourcePolicy::Manual)
+            .unwrap();
+        config
+            .set_source_policy("rustup", SourcePolicy::AutoUpdate)
+            .unwrap();
+
+        assert_eq!(config.source_policy("paru"), SourcePolicy::Manual);
+        assert_eq!(config.source_policy("rustup"), SourcePolicy::AutoUpdate);
+        assert_eq!(config.source_policy("npm"), SourcePolicy::NotifyOnly);
+        assert!(
+            config
+                .set_source_policy("bad name", SourcePolicy::Manual)
+                .is_err()
+        );
+        assert!(
+            config
+                .set_source_policy("apt", SourcePolicy::AutoUpdate)
+                .is_err()
+        );
+        assert_eq!(config.source_policy("apt"), SourcePolicy::NotifyOnly);
+        // Firmware is never flashed unattended
+        assert!(!SourcePolicy::available_for("fwupd").contains(&SourcePolicy::AutoUpdate));
+        assert!(
+            config
+                .set_source_policy("fwupd", SourcePolicy::AutoUpdate)
+                .is_err()
+        );
+
+        let serialized = toml::to_string(&config).unwrap();
+        assert!(serialized.contains("rustup = \"auto-update\""));
+    }
+
+    #[test]
+    fn test_config_constrained_policies() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.metered_policy("flatpak"),
+            ConstrainedPolicy::CheckOnly
+        );
+        assert_eq!(
+            config.battery_policy("flatpak"),
+            ConstrainedPolicy::CheckOnly
+        );
+
+        config
+            .set_metered_policy("flatpak", ConstrainedPolicy::Defer)
+            .unwrap();
+        config
+            .set_battery_policy("rustup", ConstrainedPolicy::Allow)
+            .unwrap();
+        assert!(
+            config
+                .set_metered_policy("bad name", ConstrainedPolicy::Allow)
+                .is_err()
+        );
+
+        let serialized = toml::to_string(&config).unwrap();
+        assert!(serialized.contains("flatpak = \"defer\""));
+        let loaded: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(loaded.metered_policy("flatpak"), ConstrainedPolicy::Defer);
+        assert_eq!(loaded.battery_policy("rustup"), ConstrainedPolicy::Allow);
+        assert_eq!(
+            loaded.battery_policy("flatpak"),
+            ConstrainedPolicy::CheckOnly
+        );
+    }
+
+    #[test]
+    fn test_config_terminal_sources() {
+        let mut config = Config::default();
+        assert!(!config.runs_in_terminal("paru"));
+
+        config.set_runs_in_terminal("paru", true).unwrap();
+        config.set_runs_in_terminal("paru", true).unwrap();
+        config.set_runs_in_terminal("apt", true).unwrap();
+        config.set_runs_in_terminal("apt", false).unwrap();
+        assert!(config.set_runs_in_terminal("bad name", true).is_err());
+
+        assert_eq!(config.terminal_sources, vec!["paru"]);
+        let loaded: Config = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert!(loaded.runs_in_terminal("paru"));
+    }
+
+    #[test]
+    fn test_config_critical_packages() {
+        let mut config = Config::default();
+        assert!(config.is_package_critical("paru", "nvidia-utils"));
+        assert!(!config.is_package_critical("flatpak", "nvidia-utils"));
+
+        config
+            .set_critical_packages("flatpak", vec!["org.freedesktop.Platform.GL.*".to_string()])
+            .unwrap();
+        assert!(config.is_package_critical("flatpak", "org.freedesktop.Platform.GL.nvidia"));
+        assert!(
+            config
+                .set_critical_packages("paru", vec!["-*".to_string()])
+                .is_err()
+        );
+        assert!(
+            config
+                .set_critical_packages("paru", vec!["*".to_string()])
+                .is_err()
+        );
+
+        // Setting the defaults again drops the override
+        let defaults = config.critical_packages_for("paru");
+        config.set_critical_packages("paru", Vec::new()).unwrap();
+        assert!(!config.is_package_critical("paru", "linux"));
+        config.set_critical_packages("paru", defaults).unwrap();
+        assert!(!config.critical_packages.contains_key("paru"));
+        assert!(config.is_package_critical("paru", "linux"));
+    }
+
+    #[test]
+    fn test_config_package_exclusions() {
+        let mut config = Config::default();
+
+        config.set_package_excluded(None, "linux", true).unwrap();
+        config
+            .set_package_excluded(Some("paru"), "nvidia-dkms", true)
+            .unwrap();
+        config
+            .set_package_excluded(Some("paru"), "linux", true)
+            .unwrap();
+
+        assert!(config.is_package_excluded("paru", "nvidia-dkms"));
+        assert!(config.is_package_excluded("flatpak", "linux"));
+        assert!(!config.is_package_excluded("flatpak", "nvidia-dkms"));
+        assert_eq!(
+            config.excluded_packages_for("paru"),
+            ["linux", "nvidia-dkms"]
+        );
+
+        let exclusions = config.exclusions_for(&["paru".to_string()]);
+        assert_eq!(exclusions["paru"], ["linux", "nvidia-dkms"]);
+
+        config
+            .set_package_excluded(Some("paru"), "nvidia-dkms", false)
+            .unwrap();
+        config
+            .set_package_excluded(Some("paru"), "linux", false)
+            .unwrap();
+        assert!(config.source_exclusions.is_empty());
+        assert!(
+            config
+                .exclusions_for(&["npm".to_string()])
+                .contains_key("npm")
+        );
+
+        config.set_package_excluded(None, "linux", false).unwrap();
+        assert!(config.exclusions_for(&["npm".to_string()]).is_empty());
+
+        assert!(config.set_package_excluded(None, "--all", true).is_err());
+        assert!(
+            config
+                .set_package_excluded(Some("bad name"), "vim", true)
+                .is_err()
+        );
+    }
+
     #[async_std::test]
     async fn test_config_save_load_cycle() {
         let temp_dir = tempdir().unwrap();
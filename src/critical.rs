@@ -0,0 +1,151 @@
+use anyhow::Result;
+
+use crate::updater::validate_package_name;
+
+/// Packages whose updates deserve extra care by default: kernels, the C
+/// library, init, graphics drivers and bootloaders, per system package manager.
+///
+/// A trailing `*` matches any suffix. Other sources have no defaults.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::critical::default_critical_packages;
+///
+/// assert!(default_critical_packages("paru").contains(&"glibc"));
+/// assert!(default_critical_packages("flatpak").is_empty());
+/// ```
+pub fn default_critical_packages(source: &str) -> &'static [&'static str] {
+    match source {
+        "paru" => &[
+            "linux",
+            "linux-lts",
+            "linux-zen",
+            "linux-hardened",
+            "linux-firmware*",
+            "intel-ucode",
+            "amd-ucode",
+            "glibc",
+            "systemd",
+            "mesa",
+            "nvidia*",
+            "grub",
+            "refind",
+            "limine",
+        ],
+        "apt" => &[
+            "linux-image-*",
+            "linux-firmware",
+            "intel-microcode",
+            "amd64-microcode",
+            "libc6",
+            "systemd",
+            "libgl1-mesa-dri",
+            "mesa-*",
+            "nvidia-driver-*",
+            "grub-*",
+            "shim-signed",
+        ],
+        "dnf" => &[
+            "kernel",
+            "kernel-core",
+            "linux-firmware",
+            "microcode_ctl",
+            "glibc",
+            "systemd",
+            "mesa-*",
+            "akmod-nvidia",
+            "xorg-x11-drv-nvidia*",
+            "grub2-*",
+            "shim-*",
+        ],
+        "zypper" => &[
+            "kernel-default",
+            "kernel-firmware*",
+            "ucode-*",
+            "glibc",
+            "systemd",
+            "Mesa*",
+            "nvidia-*",
+            "grub2*",
+            "shim",
+        ],
+        "apk" => &[
+            "linux-lts",
+            "linux-virt",
+            "linux-firmware*",
+            "musl",
+            "mesa-*",
+            "grub*",
+            "syslinux",
+        ],
+        _ => &[],
+    }
+}
+
+/// Returns `true` if `package` matches one of `patterns`.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::critical::matches_critical;
+///
+/// let patterns = ["linux", "nvidia*"];
+/// assert!(matches_critical(&patterns, "nvidia-utils"));
+/// assert!(!matches_critical(&patterns, "linux-api-headers"));
+/// ```
+pub fn matches_critical(patterns: &[impl AsRef<str>], package: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.as_ref().strip_suffix('*') {
+            Some(prefix) => package.starts_with(prefix),
+            None => pattern.as_ref() == package,
+        })
+}
+
+/// Validates a critical package pattern: a package name, optionally followed by `*`.
+///
+/// # Errors
+///
+/// Returns an error if the name before the `*` is not a valid package name.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::critical::validate_critical_pattern;
+///
+/// assert!(validate_critical_pattern("linux-image-*").is_ok());
+/// assert!(validate_critical_pattern("*").is_err());
+/// ```
+pub fn validate_critical_pattern(pattern: &str) -> Result<()> {
+    validate_package_name(pattern.strip_suffix('*').unwrap_or(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_match_kernels_and_drivers() {
+        assert!(matches_critical(default_critical_packages("paru"), "linux"));
+        assert!(matches_critical(
+            default_critical_packages("apt"),
+            "linux-image-6.8.0-45-generic"
+        ));
+        assert!(matches_critical(
+            default_critical_packages("dnf"),
+            "xorg-x11-drv-nvidia-cuda"
+        ));
+        assert!(!matches_critical(default_critical_packages("dnf"), "vim"));
+        assert!(!matches_critical(default_critical_packages("npm"), "linux"));
+    }
+
+    #[test]
+    fn test_wildcard_only_at_the_end() {
+        let patterns = vec!["grub*".to_string(), "mesa".to_string()];
+        assert!(matches_critical(&patterns, "grub"));
+        assert!(matches_critical(&patterns, "grub-efi-amd64"));
+        assert!(!matches_critical(&patterns, "mesa-utils"));
+        assert!(!matches_critical(&patterns, "libgrub"));
+    }
+}
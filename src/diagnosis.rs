@@ -0,0 +1,440 @@
+use anyhow::Result;
+use async_std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, process::Stdio};
+use tracing::info;
+
+use crate::environment::{ChildEnv, Pass};
+
+/// A known cause of a failed update, recognized from the package manager's output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Diagnosis {
+    ExpiredKey,
+    OutdatedKeyring,
+    HeldBrokenPackages,
+    ConflictingFiles,
+    MirrorUnreachable,
+    DiskFull,
+    MissingRemote,
+    PermissionDenied,
+}
+
+/// Output that identifies a [`Diagnosis`].
+struct Signature {
+    /// Sources printing this output; empty for any source.
+    sources: &'static [&'static str],
+    /// Lowercase text contained in the line.
+    pattern: &'static str,
+    diagnosis: Diagnosis,
+}
+
+const SIGNATURES: &[Signature] = &[
+    Signature {
+        sources: &["apt"],
+        pattern: "expkeysig",
+        diagnosis: Diagnosis::ExpiredKey,
+    },
+    Signature {
+        sources: &["apt"],
+        pattern: "keyexpired",
+        diagnosis: Diagnosis::ExpiredKey,
+    },
+    Signature {
+        sources: &["dnf"],
+        pattern: "are already installed but they are not correct",
+        diagnosis: Diagnosis::ExpiredKey,
+    },
+    Signature {
+        sources: &["paru"],
+        pattern: "is unknown trust",
+        diagnosis: Diagnosis::OutdatedKeyring,
+    },
+    Signature {
+        sources: &["paru"],
+        pattern: "invalid or corrupted package (pgp signature)",
+        diagnosis: Diagnosis::OutdatedKeyring,
+    },
+    Signature {
+        sources: &["paru"],
+        pattern: "could not be looked up remotely",
+        diagnosis: Diagnosis::OutdatedKeyring,
+    },
+    Signature {
+        sources: &["apt"],
+        pattern: "you have held broken packages",
+        diagnosis: Diagnosis::HeldBrokenPackages,
+    },
+    Signature {
+        sources: &["apt"],
+        pattern: "unmet dependencies",
+        diagnosis: Diagnosis::HeldBrokenPackages,
+    },
+    Signature {
+        sources: &["paru"],
+        pattern: "exists in filesystem",
+        diagnosis: Diagnosis::ConflictingFiles,
+    },
+    Signature {
+        sources: &["apt"],
+        pattern: "trying to overwrite",
+        diagnosis: Diagnosis::ConflictingFiles,
+    },
+    Signature {
+        sources: &["dnf", "zypper"],
+        pattern: "conflicts with file from package",
+        diagnosis: Diagnosis::ConflictingFiles,
+    },
+    Signature {
+        sources: &[],
+        pattern: "no space left on device",
+        diagnosis: Diagnosis::DiskFull,
+    },
+    Signature {
+        sources: &["paru"],
+        pattern: "not enough free disk space",
+        diagnosis: Diagnosis::DiskFull,
+    },
+    Signature {
+        sources: &["apt"],
+        pattern: "you don't have enough free space",
+        diagnosis: Diagnosis::DiskFull,
+    },
+    Signature {
+        sources: &[],
+        pattern: "could not resolve host",
+        diagnosis: Diagnosis::MirrorUnreachable,
+    },
+    Signature {
+        sources: &[],
+        pattern: "temporary failure resolving",
+        diagnosis: Diagnosis::MirrorUnreachable,
+    },
+    Signature {
+        sources: &["paru"],
+        pattern: "failed retrieving file",
+        diagnosis: Diagnosis::MirrorUnreachable,
+    },
+    Signature {
+        sources: &["dnf"],
+        pattern: "failed to download metadata",
+        diagnosis: Diagnosis::MirrorUnreachable,
+    },
+    Signature {
+        sources: &["flatpak"],
+        pattern: "no remote refs found",
+        diagnosis: Diagnosis::MissingRemote,
+    },
+    Signature {
+        sources: &["flatpak"],
+        pattern: "remote \"flathub\" not found",
+        diagnosis: Diagnosis::MissingRemote,
+    },
+    Signature {
+        sources: &["npm"],
+        pattern: "eacces",
+        diagnosis: Diagnosis::PermissionDenied,
+    },
+];
+
+/// How a [`Diagnosis`] can be fixed with one click.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fix {
+    /// Runs `command`, as root through `pkexec` if `privileged`.
+    Command {
+        label: &'static str,
+        command: Vec<String>,
+        privileged: bool,
+    },
+    /// Cleans up the source to free disk space; started as a cleanup run.
+    Cleanup,
+}
+
+impl Fix {
+    fn command(label: &'static str, command: &[&str], privileged: bool) -> Self {
+        Self::Command {
+            label,
+            command: command.iter().map(|arg| arg.to_string()).collect(),
+            privileged,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Command { label, .. } => label,
+            Self::Cleanup => "Clean Up",
+        }
+    }
+
+    /// Runs a [`Fix::Command`] in the same environment as updates with
+    /// `environment` overrides, so it never waits for questions. A
+    /// [`Fix::Cleanup`] is started as a run by the caller instead, so nothing
+    /// happens for it here.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if authorization is denied or the command fails.
+    pub async fn apply(&self, environment: &HashMap<String, String>) -> Result<()> {
+        let Self::Command {
+            command,
+            privileged,
+            ..
+        } = self
+        else {
+            return Ok(());
+        };
+
+        let env = ChildEnv::new(Pass::Display, &[], environment);
+        let cmd = if *privileged {
+            env.elevate(command)
+        } else {
+            env.wrap(command)
+        };
+        let mut process = Command::new(&cmd[0]);
+        process.args(&cmd[1..]);
+        env.apply(&mut process);
+        let output = process
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to run {:?}: {}", command.join(" "), e))?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to run {:?}: {}",
+                command.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("Applied fix: {}", command.join(" "));
+        Ok(())
+    }
+}
+
+impl Diagnosis {
+    /// Recognizes an output line of `source`, if it is a known failure.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::diagnosis::Diagnosis;
+    ///
+    /// assert_eq!(
+    ///     Diagnosis::recognize("apt", "E: You have held broken packages."),
+    ///     Some(Diagnosis::HeldBrokenPackages)
+    /// );
+    /// assert_eq!(Diagnosis::recognize("npm", "E: You have held broken packages."), None);
+    /// ```
+    pub fn recognize(source: &str, line: &str) -> Option<Self> {
+        let line = line.to_lowercase();
+        SIGNATURES
+            .iter()
+            .find(|signature| {
+                (signature.sources.is_empty() || signature.sources.contains(&source))
+                    && line.contains(signature.pattern)
+            })
+            .map(|signature| signature.diagnosis)
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::ExpiredKey => "Repository key expired",
+            Self::OutdatedKeyring => "Keyring out of date",
+            Self::HeldBrokenPackages => "Broken dependencies",
+            Self::ConflictingFiles => "Conflicting files",
+            Self::MirrorUnreachable => "Mirror unreachable",
+            Self::DiskFull => "Disk full",
+            Self::MissingRemote => "Flatpak remote missing",
+            Self::PermissionDenied => "Permission denied",
+        }
+    }
+
+    /// Explains the failure and what to do about it.
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            Self::ExpiredKey => {
+                "A repository is signed with a key that has expired. Install the \
+                 repository's new key, usually by updating its release package."
+            }
+            Self::OutdatedKeyring => {
+                "Packages are signed by keys your keyring does not know yet. Update \
+                 the Arch Linux keyring first, then update again."
+            }
+            Self::HeldBrokenPackages => {
+                "Some packages have dependencies that cannot be installed. Letting apt \
+                 fix the broken packages usually resolves this."
+            }
+            Self::ConflictingFiles => {
+                "A package wants to install files that already exist on disk, often \
+                 left by a manual installation. Remove or move those files, then update again."
+            }
+            Self::MirrorUnreachable => {
+                "The package server could not be reached. Check your network \
+                 connection, or try again later if the mirror is down."
+            }
+            Self::DiskFull => {
+                "There is not enough free disk space. Cleaning up package caches and \
+                 unused packages may free enough for the update."
+            }
+            Self::MissingRemote => {
+                "The Flathub remote your applications come from is not configured. \
+                 Adding it again lets Flatpak find updates."
+            }
+            Self::PermissionDenied => {
+                "npm installs global packages into a directory you cannot write to. \
+                 Installing them into your home directory avoids the need for root."
+            }
+        }
+    }
+
+    /// Returns how the failure can be fixed with one click, if it can.
+    pub fn fix(&self) -> Option<Fix> {
+        match self {
+            Self::OutdatedKeyring => Some(Fix::command(
+                "Update Keyring",
+                &[
+                    "pacman",
+                    "-Sy",
+                    "--needed",
+                    "--noconfirm",
+                    "archlinux-keyring",
+                ],
+                true,
+            )),
+            Self::HeldBrokenPackages => Some(Fix::command(
+                "Fix Packages",
+                &["apt-get", "--fix-broken", "install", "-y"],
+                true,
+            )),
+            Self::DiskFull => Some(Fix::Cleanup),
+            // Flatpak asks for authorization itself when adding a system remote
+            Self::MissingRemote => Some(Fix::command(
+                "Add Flathub",
+                &[
+                    "flatpak",
+                    "remote-add",
+                    "--if-not-exists",
+                    "flathub",
+                    "https://dl.flathub.org/repo/flathub.flatpakrepo",
+                ],
+                false,
+            )),
+            Self::PermissionDenied => {
+                let prefix = dirs::home_dir()?.join(".local");
+                Some(Fix::command(
+                    "Use Home Directory",
+                    &["npm", "config", "set", "prefix", &prefix.to_string_lossy()],
+                    false,
+                ))
+            }
+            Self::ExpiredKey | Self::ConflictingFiles | Self::MirrorUnreachable => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recognize_signatures() {
+        let cases = [
+            (
+                "apt",
+                "W: GPG error: https://repo.example.com stable InRelease: The following \
+                 signatures were invalid: EXPKEYSIG 1234ABCD Example <repo@example.com>",
+                Diagnosis::ExpiredKey,
+            ),
+            (
+                "paru",
+                "error: archlinux-keyring: signature from \"Someone <x@archlinux.org>\" is unknown trust",
+                Diagnosis::OutdatedKeyring,
+            ),
+            (
+                "apt",
+                "E: Unable to correct problems, you have held broken packages.",
+                Diagnosis::HeldBrokenPackages,
+            ),
+            (
+                "paru",
+                "python-pip: /usr/lib/python3.12/site-packages/pip exists in filesystem",
+                Diagnosis::ConflictingFiles,
+            ),
+            (
+                "dnf",
+                "Curl error (6): Couldn't resolve host name; Could not resolve host: mirrors.fedoraproject.org",
+                Diagnosis::MirrorUnreachable,
+            ),
+            (
+                "flatpak",
+                "error: Failed to write: No space left on device",
+                Diagnosis::DiskFull,
+            ),
+            (
+                "flatpak",
+                "error: No remote refs found similar to ‘flathub’",
+                Diagnosis::MissingRemote,
+            ),
+            (
+                "npm",
+                "npm ERR! Error: EACCES: permission denied, mkdir '/usr/lib/node_modules/npm'",
+                Diagnosis::PermissionDenied,
+            ),
+        ];
+
+        for (source, line, diagnosis) in cases {
+            assert_eq!(
+                Diagnosis::recognize(source, line),
+                Some(diagnosis),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_signatures_are_source_specific() {
+        assert_eq!(
+            Diagnosis::recognize("flatpak", "error: failed retrieving file 'core.db'"),
+            None
+        );
+        assert_eq!(
+            Diagnosis::recognize("npm", "Updating npm packages..."),
+            None
+        );
+        assert!(
+            SIGNATURES
+                .iter()
+                .all(|signature| signature.pattern == signature.pattern.to_lowercase())
+        );
+    }
+
+    #[test]
+    fn test_fixes() {
+        assert_eq!(Diagnosis::DiskFull.fix(), Some(Fix::Cleanup));
+        assert_eq!(Diagnosis::ConflictingFiles.fix(), None);
+
+        let Some(Fix::Command {
+            command,
+            privileged,
+            ..
+        }) = Diagnosis::HeldBrokenPackages.fix()
+        else {
+            panic!("broken packages should have a fix command");
+        };
+        assert_eq!(command, vec!["apt-get", "--fix-broken", "install", "-y"]);
+        assert!(privileged);
+    }
+
+    #[test]
+    fn test_diagnosis_serialization() {
+        assert_eq!(
+            toml::to_string(&std::collections::HashMap::from([(
+                "diagnosis",
+                Diagnosis::OutdatedKeyring
+            )]))
+            .unwrap()
+            .trim(),
+            "diagnosis = \"outdated-keyring\""
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use async_std::process::Command;
+use std::collections::{BTreeMap, HashMap};
+use tracing::warn;
+
+/// Locale of commands whose output is parsed, so that messages and number
+/// formats do not depend on the user's language.
+pub const NEUTRAL_LOCALE: &str = "C.UTF-8";
+
+/// Variables every package manager runs with: no prompts, colours or pagers.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("DEBIAN_FRONTEND", "noninteractive"),
+    ("NO_COLOR", "1"),
+    ("TERM", "dumb"),
+    ("PAGER", "cat"),
+    ("SYSTEMD_PAGER", "cat"),
+];
+
+/// Whether the output of a command is parsed or shown to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    /// The output is parsed, so it must not be translated.
+    Parse,
+    /// The output is shown in the log and keeps the user's locale.
+    Display,
+    /// The command runs on a terminal where the user answers its questions,
+    /// so prompts and the terminal type are left alone.
+    Terminal,
+}
+
+/// The environment a package manager is started with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChildEnv {
+    /// Variables to set; `None` removes an inherited variable.
+    vars: BTreeMap<String, Option<String>>,
+}
+
+impl ChildEnv {
+    /// Builds the environment for a `pass` of a manager that needs `extra`
+    /// on top of the defaults.
+    ///
+    /// `overrides` from the configuration are applied last; an empty value
+    /// removes the variable. Overrides with invalid names are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use uptodate::environment::{ChildEnv, Pass};
+    ///
+    /// let overrides = HashMap::from([("PAGER".to_string(), "less".to_string())]);
+    /// let env = ChildEnv::new(Pass::Parse, &[], &overrides);
+    /// assert_eq!(env.get("LC_ALL"), Some("C.UTF-8"));
+    /// assert_eq!(env.get("PAGER"), Some("less"));
+    /// ```
+    pub fn new(
+        pass: Pass,
+        extra: &[(String, String)],
+        overrides: &HashMap<String, String>,
+    ) -> Self {
+        let mut vars: BTreeMap<String, Option<String>> = DEFAULTS
+            .iter()
+            .map(|(name, value)| (name.to_string(), Some(value.to_string())))
+            .collect();
+
+        if pass == Pass::Terminal {
+            vars.remove("DEBIAN_FRONTEND");
+            vars.remove("TERM");
+        }
+        if pass == Pass::Parse {
+            vars.insert("LC_ALL".to_string(), Some(NEUTRAL_LOCALE.to_string()));
+            // gettext prefers LANGUAGE over LC_ALL for messages
+            vars.insert("LANGUAGE".to_string(), None);
+        }
+
+        for (name, value) in extra {
+            vars.insert(name.clone(), Some(value.clone()));
+        }
+
+        for (name, value) in overrides {
+            if !is_valid_name(name) {
+                warn!("Ignoring invalid environment variable name: {:?}", name);
+                continue;
+            }
+            let value = (!value.is_empty()).then(|| value.clone());
+            vars.insert(name.clone(), value);
+        }
+
+        Self { vars }
+    }
+
+    /// Returns the value `name` is set to, or `None` if it is removed or inherited.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.as_deref()
+    }
+
+    /// Sets the variables on a command that inherits this process's environment.
+    pub fn apply(&self, command: &mut Command) {
+        for (name, value) in &self.vars {
+            match value {
+                Some(value) => command.env(name, value),
+                None => command.env_remove(name),
+            };
+        }
+    }
+
+    /// Prefixes `cmd` with `env` so that the variables survive `pkexec`,
+    /// which starts programs with a minimal environment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use uptodate::environment::{ChildEnv, Pass};
+    ///
+    /// let env = ChildEnv::new(Pass::Display, &[], &HashMap::new());
+    /// let cmd = env.wrap(&["apt".to_string(), "upgrade".to_string()]);
+    /// assert_eq!(cmd[0], "env");
+    /// assert!(cmd.contains(&"DEBIAN_FRONTEND=noninteractive".to_string()));
+    /// assert_eq!(cmd[cmd.len() - 2..], ["apt", "upgrade"]);
+    /// ```
+    pub fn wrap(&self, cmd: &[String]) -> Vec<String> {
+        let mut wrapped = vec!["env".to_string()];
+        // env takes the first word after an assignment as the command, so
+        // all options go before the assignments
+        for (name, _) in self.vars.iter().filter(|(_, value)| value.is_none()) {
+            wrapped.extend(["-u".to_string(), name.clone()]);
+        }
+        for (name, value) in &self.vars {
+            if let Some(value) = value {
+                wrapped.push(format!("{name}={value}"));
+            }
+        }
+        wrapped.extend(cmd.iter().cloned());
+        wrapped
+    }
+
+    /// Returns `cmd` run as root through `pkexec`, with the variables set
+    /// again by [`wrap`](Self::wrap).
+    ///
+    /// Arguments are passed through unchanged, so package names are never
+    /// re-parsed by a shell.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use uptodate::environment::{ChildEnv, Pass};
+    ///
+    /// let env = ChildEnv::new(Pass::Display, &[], &HashMap::new());
+    /// let cmd = env.elevate(&["dnf".to_string(), "upgrade".to_string()]);
+    /// assert_eq!(cmd[..4], ["pkexec", "--user", "root", "env"]);
+    /// ```
+    pub fn elevate(&self, cmd: &[String]) -> Vec<String> {
+        let pkexec = ["pkexec", "--user", "root"].map(String::from);
+        [pkexec.to_vec(), self.wrap(cmd)].concat()
+    }
+}
+
+/// Returns `true` if `name` can be used as an environment variable name.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_keeps_user_locale() {
+        let env = ChildEnv::new(Pass::Display, &[], &HashMap::new());
+        assert_eq!(env.get("DEBIAN_FRONTEND"), Some("noninteractive"));
+        assert_eq!(env.get("NO_COLOR"), Some("1"));
+        assert_eq!(env.get("PAGER"), Some("cat"));
+        assert!(!env.vars.contains_key("LC_ALL"));
+        assert!(!env.vars.contains_key("LANGUAGE"));
+    }
+
+    #[test]
+    fn test_terminal_allows_prompts() {
+        let env = ChildEnv::new(Pass::Terminal, &[], &HashMap::new());
+        assert!(!env.vars.contains_key("DEBIAN_FRONTEND"));
+        assert!(!env.vars.contains_key("TERM"));
+        assert_eq!(env.get("PAGER"), Some("cat"));
+        assert_eq!(
+            env.wrap(&["apt".to_string()]),
+            ["env", "NO_COLOR=1", "PAGER=cat", "SYSTEMD_PAGER=cat", "apt"]
+        );
+    }
+
+    #[test]
+    fn test_parse_forces_neutral_locale() {
+        let env = ChildEnv::new(Pass::Parse, &[], &HashMap::new());
+        assert_eq!(env.get("LC_ALL"), Some(NEUTRAL_LOCALE));
+        assert_eq!(env.vars.get("LANGUAGE"), Some(&None));
+    }
+
+    #[test]
+    fn test_overrides_apply_last() {
+        let extra = vec![("HOMEBREW_NO_COLOR".to_string(), "1".to_string())];
+        let overrides = HashMap::from([
+            ("LC_ALL".to_string(), "de_DE.UTF-8".to_string()),
+            ("NO_COLOR".to_string(), String::new()),
+            ("HOMEBREW_NO_COLOR".to_string(), "0".to_string()),
+            ("BAD=NAME".to_string(), "x".to_string()),
+        ]);
+        let env = ChildEnv::new(Pass::Parse, &extra, &overrides);
+
+        assert_eq!(env.get("LC_ALL"), Some("de_DE.UTF-8"));
+        assert_eq!(env.get("HOMEBREW_NO_COLOR"), Some("0"));
+        assert_eq!(env.vars.get("NO_COLOR"), Some(&None));
+        assert!(!env.vars.contains_key("BAD=NAME"));
+    }
+
+    #[test]
+    fn test_wrap_removes_and_sets_variables() {
+        let overrides = HashMap::from([("PAGER".to_string(), String::new())]);
+        let env = ChildEnv::new(Pass::Parse, &[], &overrides);
+        let wrapped = env.wrap(&["dnf".to_string(), "check-update".to_string()]);
+
+        assert_eq!(
+            wrapped,
+            [
+                "env",
+                "-u",
+                "LANGUAGE",
+                "-u",
+                "PAGER",
+                "DEBIAN_FRONTEND=noninteractive",
+                "LC_ALL=C.UTF-8",
+                "NO_COLOR=1",
+                "SYSTEMD_PAGER=cat",
+                "TERM=dumb",
+                "dnf",
+                "check-update",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrapped_command_runs_through_env() {
+        let env = ChildEnv::new(Pass::Parse, &[], &HashMap::new());
+        let wrapped = env.wrap(&[
+            "sh".to_string(),
+            "-c".to_string(),
+            "echo \"$LC_ALL ${LANGUAGE-unset} $DEBIAN_FRONTEND\"".to_string(),
+        ]);
+
+        let output = std::process::Command::new(&wrapped[0])
+            .args(&wrapped[1..])
+            .env("LANGUAGE", "de")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "C.UTF-8 unset noninteractive\n"
+        );
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("LC_ALL"));
+        assert!(is_valid_name("npm_config_color"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("1ABC"));
+        assert!(!is_valid_name("A B"));
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+/// A firmware update offered by fwupd for one device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareUpdate {
+    pub device: String,
+    pub current_version: Option<String>,
+    pub new_version: Option<String>,
+    /// The firmware is only applied while the system restarts or shuts down.
+    pub needs_reboot: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct Updates {
+    devices: Vec<Device>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct Device {
+    name: String,
+    plugin: String,
+    version: Option<String>,
+    flags: Vec<String>,
+    releases: Vec<Release>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct Release {
+    version: Option<String>,
+}
+
+impl Device {
+    /// Capsule updates are staged and flashed by the firmware on the next
+    /// boot; other plugins flag devices that need a restart or shutdown.
+    fn needs_reboot(&self) -> bool {
+        self.plugin.starts_with("uefi")
+            || self
+                .flags
+                .iter()
+                .any(|flag| flag == "needs-reboot" || flag == "needs-shutdown")
+    }
+}
+
+/// Parses the output of `fwupdmgr get-updates --json`.
+///
+/// fwupd lists the releases of a device newest first, so the first one is
+/// the version the device is updated to. Devices without releases are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the output is not the JSON fwupd prints. Without
+/// updates `fwupdmgr` prints nothing or a plain message, which yields no
+/// updates instead.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::firmware::parse_updates;
+///
+/// let output = r#"{"Devices": [{"Name": "UEFI dbx", "Plugin": "uefi_dbx",
+///     "Version": "217", "Releases": [{"Version": "371"}]}]}"#;
+/// let updates = parse_updates(output).unwrap();
+/// assert_eq!(updates[0].device, "UEFI dbx");
+/// assert_eq!(updates[0].new_version.as_deref(), Some("371"));
+/// assert!(updates[0].needs_reboot);
+/// ```
+pub fn parse_updates(output: &str) -> Result<Vec<FirmwareUpdate>> {
+    if !output.trim_start().starts_with('{') {
+        return Ok(Vec::new());
+    }
+
+    let updates: Updates = serde_json::from_str(output)
+        .map_err(|e| anyhow::anyhow!("Invalid fwupd update list: {}", e))?;
+    Ok(updates
+        .devices
+        .into_iter()
+        .filter(|device| !device.name.is_empty() && !device.releases.is_empty())
+        .map(|device| FirmwareUpdate {
+            needs_reboot: device.needs_reboot(),
+            new_version: device.releases[0].version.clone(),
+            current_version: device.version,
+            device: device.name,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = r#"{
+  "Devices" : [
+    {
+      "Name" : "System Firmware",
+      "DeviceId" : "a45df35ac0e948ee180fe216a5f703f32dda163f",
+      "Plugin" : "uefi_capsule",
+      "Flags" : ["internal", "updatable", "require-ac", "supported", "registered"],
+      "Vendor" : "LENOVO",
+      "Version" : "0.1.31",
+      "Releases" : [
+        { "Version" : "0.1.33", "Flags" : ["is-upgrade"] },
+        { "Version" : "0.1.32", "Flags" : ["is-upgrade"] }
+      ]
+    },
+    {
+      "Name" : "USB-C Dock",
+      "Plugin" : "synaptics_mst",
+      "Flags" : ["updatable", "usable-during-update"],
+      "Version" : "5.3.8",
+      "Releases" : [ { "Version" : "5.3.9" } ]
+    },
+    {
+      "Name" : "Fingerprint Sensor",
+      "Plugin" : "synaptics_prometheus",
+      "Flags" : ["updatable", "needs-reboot"],
+      "Version" : "10.01.3121",
+      "Releases" : []
+    }
+  ]
+}"#;
+
+    #[test]
+    fn test_parse_updates() {
+        let updates = parse_updates(OUTPUT).unwrap();
+
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].device, "System Firmware");
+        assert_eq!(updates[0].current_version.as_deref(), Some("0.1.31"));
+        assert_eq!(updates[0].new_version.as_deref(), Some("0.1.33"));
+        assert!(updates[0].needs_reboot);
+        assert_eq!(updates[1].device, "USB-C Dock");
+        assert!(!updates[1].needs_reboot);
+    }
+
+    #[test]
+    fn test_parse_without_updates() {
+        assert!(parse_updates("").unwrap().is_empty());
+        assert!(parse_updates("No updatable devices\n").unwrap().is_empty());
+        assert!(parse_updates(r#"{ "Devices" : [] }"#).unwrap().is_empty());
+        assert!(parse_updates("{ \"Devices\" : [").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::Result;
+use tracing::{error, info, warn};
+
+use crate::{
+    AppState,
+    cleanup::format_bytes,
+    conditions::Conditions,
+    conffiles::ConffileScan,
+    history::{History, RunRecord},
+    recovery::{LeftoverScan, RunMarker},
+    scheduler::{self, CheckOutcome, ScheduleState},
+    updater::{RunMode, RunRequest, Severity, UpdateEvent},
+};
+
+/// Work that can be run from the command line without opening a window,
+/// e.g. from the generated systemd user service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadlessCommand {
+    /// `--check`: run the check phase and cache the result.
+    Check,
+    /// `--auto-update`: update sources whose policy allows unattended updates.
+    AutoUpdate,
+    /// `--update <source,...>`: update the given sources without supervision.
+    Update(Vec<String>),
+    /// `--cleanup`: remove orphans, caches and unused runtimes of all enabled sources.
+    Cleanup,
+}
+
+impl HeadlessCommand {
+    /// Parses command line arguments (without the program name).
+    ///
+    /// Returns `Ok(None)` when no headless option is present so the arguments
+    /// can be handed to GApplication instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `--update` is given without a source list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::headless::HeadlessCommand;
+    ///
+    /// let args = vec!["--update".to_string(), "flatpak,rustup".to_string()];
+    /// assert_eq!(
+    ///     HeadlessCommand::from_args(&args).unwrap(),
+    ///     Some(HeadlessCommand::Update(vec!["flatpak".into(), "rustup".into()]))
+    /// );
+    /// ```
+    pub fn from_args(args: &[String]) -> Result<Option<Self>> {
+        match args.first().map(String::as_str) {
+            Some("--check") => Ok(Some(Self::Check)),
+            Some("--auto-update") => Ok(Some(Self::AutoUpdate)),
+            Some("--cleanup") => Ok(Some(Self::Cleanup)),
+            Some("--update") => {
+                let sources: Vec<String> = args
+                    .get(1)
+                    .map(|list| {
+                        list.split(',')
+                            .map(str::trim)
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                if sources.is_empty() {
+                    return Err(anyhow::anyhow!("--update requires a list of sources"));
+                }
+                Ok(Some(Self::Update(sources)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Runs the check phase for all enabled sources and caches the result.
+///
+/// Sources that `conditions` do not allow to check are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the check fails.
+pub async fn check(state: &AppState, conditions: &Conditions) -> Result<CheckOutcome> {
+    scheduler::run_check(state, &ScheduleState::default_path(), conditions).await
+}
+
+/// Updates the sources whose policy allows unattended updates.
+///
+/// Returns `None` if none of them had pending updates or `conditions` defer
+/// all of them.
+///
+/// # Errors
+///
+/// Returns an error if the run cannot be started.
+pub async fn auto_update(state: &AppState, conditions: &Conditions) -> Result<Option<RunRecord>> {
+    scheduler::run_auto_updates(state, &ScheduleState::default_path(), conditions).await
+}
+
+/// Updates the given sources, logging their output instead of showing it.
+///
+/// Sources that are not available on this system are skipped. Returns whether
+/// every source was updated successfully.
+///
+/// # Errors
+///
+/// Returns an error if source detection fails or updates are already running.
+pub async fn update(state: &AppState, sources: &[String]) -> Result<bool> {
+    let available = state.updater.detect_sources().await?;
+    let sources: Vec<String> = sources
+        .iter()
+        .filter(|source| available.contains(source))
+        .cloned()
+        .collect();
+
+    let mut request = {
+        let config = state.config.read().await;
+        RunRequest {
+            exclusions: config.exclusions_for(&sources),
+            snapshots: config.snapshot_before_updates,
+            critical_snapshots: config.snapshot_critical_updates,
+            wait_for_locks: config.wait_for_locks,
+            environment: config.child_environment.clone(),
+            mode: config.update_mode(),
+            sources,
+            ..RunRequest::default()
+        }
+    };
+    // Asking for the update on the command line confirms its critical packages
+    request.critical = ScheduleState::load(&ScheduleState::default_path())
+        .await
+        .unwrap_or_default()
+        .critical_for(&request);
+    follow(state, request).await
+}
+
+/// Cleans up all enabled sources available on this system.
+///
+/// Returns whether every cleanup succeeded.
+///
+/// # Errors
+///
+/// Returns an error if source detection fails or updates are already running.
+pub async fn cleanup(state: &AppState) -> Result<bool> {
+    let available = state.updater.detect_sources().await?;
+    let request = {
+        let config = state.config.read().await;
+        RunRequest {
+            sources: available
+                .into_iter()
+                .filter(|source| config.is_source_enabled(source))
+                .collect(),
+            mode: RunMode::Cleanup,
+            snapshots: config.snapshot_before_updates,
+            wait_for_locks: config.wait_for_locks,
+            environment: config.child_environment.clone(),
+            ..RunRequest::default()
+        }
+    };
+    follow(state, request).await
+}
+
+/// Logs what a run that never finished left behind. Repairs are not run
+/// unattended, since they need the user's authorization.
+async fn report_interrupted_run() {
+    match RunMarker::take_stale(&RunMarker::default_path(), &History::default_path()).await {
+        Ok(Some(marker)) => {
+            let mut leftovers = marker.leftovers();
+            leftovers.extend(LeftoverScan::new().scan(&marker.sources).await);
+            for leftover in leftovers {
+                warn!(
+                    "[{}] {}; repair it with `{}`{}",
+                    leftover.source,
+                    leftover.problem,
+                    leftover.repair_command(),
+                    if leftover.root { " as root" } else { "" }
+                );
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to check for an interrupted run: {e}"),
+    }
+}
+
+/// Starts `request` and logs its events until it completes.
+async fn follow(state: &AppState, request: RunRequest) -> Result<bool> {
+    report_interrupted_run().await;
+
+    let action = if request.mode.updates() {
+        "Updating"
+    } else {
+        "Cleaning up"
+    };
+    let receiver = state.updater.run(request).await?;
+    let mut success = true;
+    let mut reclaimed = None;
+    while let Ok(event) = receiver.recv().await {
+        match event {
+            UpdateEvent::SourceStarted(name) => info!("{action} {name}"),
+            UpdateEvent::SourceCleanedUp(name, bytes) => {
+                info!("[{name}] Reclaimed {}", format_bytes(bytes));
+                *reclaimed.get_or_insert(0) += bytes;
+            }
+            UpdateEvent::SourceProgress(name, line) => info!("[{name}] {line}"),
+            UpdateEvent::SourceMessage(name, severity, line) => match severity {
+                Severity::Info => info!("[{name}] {line}"),
+                Severity::Warning => warn!("[{name}] {line}"),
+                Severity::Error => error!("[{name}] {line}"),
+            },
+            UpdateEvent::SourceDiagnosed(name, diagnosis) => {
+                warn!(
+                    "[{name}] {}: {}",
+                    diagnosis.title(),
+                    diagnosis.explanation()
+                )
+            }
+            UpdateEvent::SourceBlocked(name, reason) => warn!("Skipped {name}: {reason}"),
+            UpdateEvent::RebootRequired(reasons) => {
+                warn!("A reboot is required: {}", reasons.join(", "))
+            }
+            UpdateEvent::RestartNeeded(services) => {
+                for service in services {
+                    warn!(
+                        "{} uses outdated libraries: {}",
+                        service.name(),
+                        service.libraries().join(", ")
+                    );
+                }
+            }
+            UpdateEvent::Error(message) => error!("{message}"),
+            UpdateEvent::Completed(result) => {
+                success = result;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(bytes) = reclaimed {
+        info!("Cleanup reclaimed {} in total", format_bytes(bytes));
+    }
+    for conffile in ConffileScan::new().scan().await {
+        warn!("{}: {:?}", conffile.kind.description(), conffile.other);
+    }
+    Ok(success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args_check() {
+        assert_eq!(
+            HeadlessCommand::from_args(&args(&["--check"])).unwrap(),
+            Some(HeadlessCommand::Check)
+        );
+        assert_eq!(
+            HeadlessCommand::from_args(&args(&["--auto-update"])).unwrap(),
+            Some(HeadlessCommand::AutoUpdate)
+        );
+        assert_eq!(
+            HeadlessCommand::from_args(&args(&["--cleanup"])).unwrap(),
+            Some(HeadlessCommand::Cleanup)
+        );
+    }
+
+    #[test]
+    fn test_from_args_update_requires_sources() {
+        assert!(HeadlessCommand::from_args(&args(&["--update"])).is_err());
+        assert!(HeadlessCommand::from_args(&args(&["--update", ","])).is_err());
+    }
+
+    #[test]
+    fn test_from_args_passes_through_other_arguments() {
+        assert_eq!(HeadlessCommand::from_args(&args(&[])).unwrap(), None);
+        assert_eq!(
+            HeadlessCommand::from_args(&args(&["--gapplication-service"])).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,640 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diagnosis::Diagnosis,
+    reboot::RebootStatus,
+    release::ReleaseUpgrade,
+    restart::StaleService,
+    rollback::Rollback,
+    scheduler::now_secs,
+    snapshot::Snapshot,
+    updater::{RunMode, RunRequest, Severity, UpdateEvent},
+};
+
+/// Maximum number of runs kept in the history file.
+const MAX_RUNS: usize = 100;
+
+/// Maximum number of error lines kept per source.
+const MAX_ERRORS_PER_SOURCE: usize = 20;
+
+/// What started a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunTrigger {
+    #[default]
+    Manual,
+    Scheduled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceResult {
+    pub source: String,
+    pub success: bool,
+    pub errors: Vec<String>,
+    /// Packages selected for a targeted update; empty if the whole source was updated.
+    pub packages: Vec<String>,
+    /// Snapshots taken before and after updating the source.
+    pub snapshots: Vec<Snapshot>,
+    /// Bytes freed by the cleanup; `None` if the source was not cleaned up.
+    pub reclaimed: Option<u64>,
+    /// Why the source was skipped, e.g. a package manager lock held by another program.
+    pub blocked: Option<String>,
+    /// Known causes recognized in the error output.
+    pub diagnoses: Vec<Diagnosis>,
+    /// How to undo the update; `None` if it cannot be undone or already was.
+    pub rollback: Option<Rollback>,
+}
+
+/// A finished run as stored in the history file.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunRecord {
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub trigger: RunTrigger,
+    pub dry_run: bool,
+    pub mode: RunMode,
+    pub sources: Vec<SourceResult>,
+    /// Errors not attributed to a single source.
+    pub errors: Vec<String>,
+    /// Whether the system needed a reboot after the run.
+    pub reboot: RebootStatus,
+    /// Services and processes still using libraries replaced by the run.
+    pub stale_services: Vec<StaleService>,
+    /// Whether the run was stopped before it finished, e.g. when quitting.
+    pub interrupted: bool,
+    /// The release the distribution was upgraded to by a release upgrade.
+    pub release: Option<ReleaseUpgrade>,
+}
+
+impl RunRecord {
+    /// Returns `true` if the run finished, no source failed and no general
+    /// error occurred.
+    ///
+    /// Blocked sources did not run, so they do not count as failures.
+    pub fn success(&self) -> bool {
+        !self.interrupted
+            && self.errors.is_empty()
+            && self
+                .sources
+                .iter()
+                .all(|s| s.success || s.blocked.is_some())
+    }
+
+    /// Returns the names of the sources that failed.
+    pub fn failed_sources(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .filter(|s| !s.success && s.blocked.is_none())
+            .map(|s| s.source.clone())
+            .collect()
+    }
+
+    /// Returns the names of the sources that were blocked by the preflight.
+    pub fn blocked_sources(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .filter(|s| s.blocked.is_some())
+            .map(|s| s.source.clone())
+            .collect()
+    }
+
+    /// Returns the disk space freed by the cleanup of all sources.
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.sources.iter().filter_map(|s| s.reclaimed).sum()
+    }
+}
+
+/// Builds a [`RunRecord`] by observing the events of a run.
+#[derive(Debug)]
+pub struct RunRecorder {
+    record: RunRecord,
+    packages: HashMap<String, Vec<String>>,
+}
+
+impl RunRecorder {
+    pub fn new(trigger: RunTrigger, dry_run: bool) -> Self {
+        Self {
+            record: RunRecord {
+                started_at: now_secs(),
+                trigger,
+                dry_run,
+                ..RunRecord::default()
+            },
+            packages: HashMap::new(),
+        }
+    }
+
+    /// Creates a recorder for `request`, remembering which packages each source targets.
+    pub fn for_request(request: &RunRequest) -> Self {
+        let mut recorder = Self::new(request.trigger, request.dry_run);
+        recorder.record.mode = request.mode;
+        recorder.packages = request.packages.clone();
+        recorder
+    }
+
+    /// Creates a recorder for a release upgrade; each step is observed as a
+    /// run of the distribution's package manager.
+    pub fn for_release(upgrade: &ReleaseUpgrade) -> Self {
+        let mut recorder = Self::new(RunTrigger::Manual, false);
+        recorder.record.mode = RunMode::ReleaseUpgrade;
+        recorder.record.release = Some(upgrade.clone());
+        recorder
+    }
+
+    fn source_mut(&mut self, name: &str) -> &mut SourceResult {
+        let index = match self.record.sources.iter().position(|s| s.source == name) {
+            Some(index) => index,
+            None => {
+                self.record.sources.push(SourceResult {
+                    source: name.to_string(),
+                    packages: self.packages.get(name).cloned().unwrap_or_default(),
+                    ..SourceResult::default()
+                });
+                self.record.sources.len() - 1
+            }
+        };
+        &mut self.record.sources[index]
+    }
+
+    pub fn observe(&mut self, event: &UpdateEvent) {
+        match event {
+            UpdateEvent::SourceStarted(name) => {
+                self.source_mut(name);
+            }
+            UpdateEvent::SourceMessage(name, Severity::Error, line) => {
+                let source = self.source_mut(name);
+                if source.errors.len() < MAX_ERRORS_PER_SOURCE {
+                    source.errors.push(line.clone());
+                }
+            }
+            UpdateEvent::SourceDiagnosed(name, diagnosis) => {
+                let source = self.source_mut(name);
+                if !source.diagnoses.contains(diagnosis) {
+                    source.diagnoses.push(*diagnosis);
+                }
+            }
+            UpdateEvent::SourceCompleted(name, success) => {
+                self.source_mut(name).success = *success;
+            }
+            UpdateEvent::SourceSnapshot(name, snapshot) => {
+                self.source_mut(name).snapshots.push(snapshot.clone());
+            }
+            UpdateEvent::SourceBlocked(name, reason) => {
+                self.source_mut(name).blocked = Some(reason.clone());
+            }
+            UpdateEvent::SourceCleanedUp(name, bytes) => {
+                self.source_mut(name).reclaimed = Some(*bytes);
+            }
+            UpdateEvent::SourceRollback(name, rollback) => {
+                self.source_mut(name).rollback = Some(rollback.clone());
+            }
+            UpdateEvent::RebootRequired(reasons) => {
+                self.record.reboot = RebootStatus {
+                    required: true,
+                    reasons: reasons.clone(),
+                };
+            }
+            UpdateEvent::RestartNeeded(services) => {
+                self.record.stale_services = services.clone();
+            }
+            UpdateEvent::Interrupted => self.record.interrupted = true,
+            UpdateEvent::Error(message) => self.record.errors.push(message.clone()),
+            _ => {}
+        }
+    }
+
+    pub fn finish(mut self) -> RunRecord {
+        self.record.finished_at = now_secs();
+        self.record
+    }
+}
+
+/// Persisted list of past runs, oldest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    pub runs: Vec<RunRecord>,
+}
+
+impl History {
+    /// Returns the default location of the history file.
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join("uptodate")
+            .join("history.toml")
+    }
+
+    /// Loads the history, falling back to an empty history if the file is missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub async fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = async_std::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read history {:?}: {}", path, e))?;
+
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid TOML in history {:?}: {}", path, e))
+    }
+
+    /// Writes the history to disk, creating the parent directory if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history cannot be serialized or written.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            async_std::fs::create_dir_all(parent).await.map_err(|e| {
+                anyhow::anyhow!("Failed to create history directory {:?}: {}", parent, e)
+            })?;
+        }
+
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize history: {}", e))?;
+
+        async_std::fs::write(path, content)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to write history {:?}: {}", path, e))
+    }
+
+    /// Appends a record to the history file, dropping the oldest runs beyond the limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history cannot be loaded or saved.
+    pub async fn append(path: &Path, record: RunRecord) -> Result<()> {
+        let mut history = Self::load(path).await?;
+        history.runs.push(record);
+
+        let excess = history.runs.len().saturating_sub(MAX_RUNS);
+        history.runs.drain(..excess);
+
+        history.save(path).await
+    }
+
+    /// Returns the most recent run, if any.
+    pub fn last(&self) -> Option<&RunRecord> {
+        self.runs.last()
+    }
+
+    /// Returns the most recent run that updated something, skipping dry runs
+    /// and cleanups.
+    pub fn last_update(&self) -> Option<&RunRecord> {
+        self.runs
+            .iter()
+            .rev()
+            .find(|run| !run.dry_run && run.mode.updates())
+    }
+
+    /// Records that the update of `source` in the run started at `started_at`
+    /// was undone, so that it is not offered again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history cannot be loaded or saved.
+    pub async fn forget_rollback(path: &Path, started_at: u64, source: &str) -> Result<()> {
+        let mut history = Self::load(path).await?;
+        for result in history
+            .runs
+            .iter_mut()
+            .filter(|run| run.started_at == started_at)
+            .flat_map(|run| run.sources.iter_mut())
+            .filter(|result| result.source == source)
+        {
+            result.rollback = None;
+        }
+        history.save(path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_recorder_tracks_source_results() {
+        let mut recorder = RunRecorder::new(RunTrigger::Scheduled, false);
+
+        for event in [
+            UpdateEvent::Started,
+            UpdateEvent::SourceStarted("flatpak".to_string()),
+            UpdateEvent::SourceMessage(
+                "flatpak".to_string(),
+                Severity::Info,
+                "Looking for updates…".to_string(),
+            ),
+            UpdateEvent::SourceCompleted("flatpak".to_string(), true),
+            UpdateEvent::SourceStarted("rustup".to_string()),
+            UpdateEvent::SourceMessage(
+                "rustup".to_string(),
+                Severity::Warning,
+                "warning: retrying download".to_string(),
+            ),
+            UpdateEvent::SourceMessage(
+                "rustup".to_string(),
+                Severity::Error,
+                "error: network error".to_string(),
+            ),
+            UpdateEvent::SourceCompleted("rustup".to_string(), false),
+            UpdateEvent::RebootRequired(vec!["linux was updated".to_string()]),
+            UpdateEvent::Completed(false),
+        ] {
+            recorder.observe(&event);
+        }
+
+        let record = recorder.finish();
+        assert_eq!(record.trigger, RunTrigger::Scheduled);
+        assert_eq!(record.sources.len(), 2);
+        assert!(record.sources[0].success);
+        assert!(record.sources[0].errors.is_empty());
+        assert_eq!(record.sources[1].errors, vec!["error: network error"]);
+        assert!(!record.success());
+        assert_eq!(record.failed_sources(), vec!["rustup"]);
+        assert!(record.reboot.required);
+        assert_eq!(record.reboot.reasons, vec!["linux was updated"]);
+    }
+
+    #[test]
+    fn test_recorder_remembers_targeted_packages() {
+        let request = RunRequest {
+            sources: vec!["flatpak".to_string(), "rustup".to_string()],
+            packages: HashMap::from([("flatpak".to_string(), vec!["org.gimp.GIMP".to_string()])]),
+            ..RunRequest::default()
+        };
+        let mut recorder = RunRecorder::for_request(&request);
+        recorder.observe(&UpdateEvent::SourceStarted("flatpak".to_string()));
+        recorder.observe(&UpdateEvent::SourceStarted("rustup".to_string()));
+
+        let record = recorder.finish();
+        assert_eq!(record.sources[0].packages, vec!["org.gimp.GIMP"]);
+        assert!(record.sources[1].packages.is_empty());
+    }
+
+    #[test]
+    fn test_recorder_tracks_snapshots() {
+        use crate::snapshot::{SnapshotKind, SnapshotTool};
+
+        let snapshot = |kind, id: &str| Snapshot {
+            tool: SnapshotTool::Snapper,
+            kind,
+            id: id.to_string(),
+        };
+        let mut recorder = RunRecorder::new(RunTrigger::Manual, false);
+        recorder.observe(&UpdateEvent::SourceStarted("dnf".to_string()));
+        recorder.observe(&UpdateEvent::SourceSnapshot(
+            "dnf".to_string(),
+            snapshot(SnapshotKind::Pre, "42"),
+        ));
+        recorder.observe(&UpdateEvent::SourceSnapshot(
+            "dnf".to_string(),
+            snapshot(SnapshotKind::Post, "43"),
+        ));
+
+        let record = recorder.finish();
+        assert_eq!(
+            record.sources[0].snapshots,
+            vec![
+                snapshot(SnapshotKind::Pre, "42"),
+                snapshot(SnapshotKind::Post, "43")
+            ]
+        );
+
+        let serialized = toml::to_string(&History { runs: vec![record] }).unwrap();
+        assert!(serialized.contains("tool = \"snapper\""));
+    }
+
+    #[test]
+    fn test_recorder_tracks_release_upgrade() {
+        use crate::release::Distribution;
+
+        let upgrade = ReleaseUpgrade {
+            distribution: Distribution::Fedora,
+            current: "40".to_string(),
+            target: "41".to_string(),
+        };
+        let mut recorder = RunRecorder::for_release(&upgrade);
+        recorder.observe(&UpdateEvent::SourceStarted("dnf".to_string()));
+        recorder.observe(&UpdateEvent::SourceCompleted("dnf".to_string(), true));
+        recorder.observe(&UpdateEvent::RebootRequired(vec![
+            "Fedora Linux 41 is installed while restarting".to_string(),
+        ]));
+
+        let record = recorder.finish();
+        assert_eq!(record.mode, RunMode::ReleaseUpgrade);
+        assert!(record.success());
+
+        let history = History { runs: vec![record] };
+        let serialized = toml::to_string(&history).unwrap();
+        assert!(serialized.contains("mode = \"release-upgrade\""));
+        let parsed: History = toml::from_str(&serialized).unwrap();
+        assert_eq!(parsed.runs[0].release, Some(upgrade));
+        assert!(parsed.last_update().is_some());
+    }
+
+    #[test]
+    fn test_recorder_tracks_stale_services() {
+        let mut recorder = RunRecorder::new(RunTrigger::Manual, false);
+        let services = vec![StaleService {
+            unit: Some("sshd.service".to_string()),
+            user: false,
+            processes: vec![crate::restart::StaleProcess {
+                pid: 812,
+                command: "sshd".to_string(),
+                libraries: vec!["/usr/lib/libssl.so.3".to_string()],
+            }],
+        }];
+
+        recorder.observe(&UpdateEvent::RestartNeeded(services.clone()));
+        recorder.observe(&UpdateEvent::Completed(true));
+
+        let record = recorder.finish();
+        assert!(record.success());
+        assert_eq!(record.stale_services, services);
+    }
+
+    #[test]
+    fn test_recorder_tracks_blocked_sources() {
+        let mut recorder = RunRecorder::new(RunTrigger::Scheduled, false);
+
+        for event in [
+            UpdateEvent::SourceStarted("apt".to_string()),
+            UpdateEvent::SourceBlocked(
+                "apt".to_string(),
+                "/var/lib/dpkg/lock-frontend is locked by apt (PID 1234)".to_string(),
+            ),
+            UpdateEvent::SourceStarted("flatpak".to_string()),
+            UpdateEvent::SourceCompleted("flatpak".to_string(), true),
+            UpdateEvent::Completed(true),
+        ] {
+            recorder.observe(&event);
+        }
+
+        let record = recorder.finish();
+        assert!(!record.sources[0].success);
+        assert!(record.success());
+        assert!(record.failed_sources().is_empty());
+        assert_eq!(record.blocked_sources(), vec!["apt"]);
+    }
+
+    #[test]
+    fn test_recorder_tracks_diagnoses() {
+        let mut recorder = RunRecorder::new(RunTrigger::Manual, false);
+
+        for event in [
+            UpdateEvent::SourceStarted("paru".to_string()),
+            UpdateEvent::SourceDiagnosed("paru".to_string(), Diagnosis::OutdatedKeyring),
+            UpdateEvent::SourceDiagnosed("paru".to_string(), Diagnosis::OutdatedKeyring),
+            UpdateEvent::SourceCompleted("paru".to_string(), false),
+            UpdateEvent::Completed(false),
+        ] {
+            recorder.observe(&event);
+        }
+
+        let record = recorder.finish();
+        assert_eq!(
+            record.sources[0].diagnoses,
+            vec![Diagnosis::OutdatedKeyring]
+        );
+
+        let serialized = toml::to_string(&History { runs: vec![record] }).unwrap();
+        assert!(serialized.contains("diagnoses = [\"outdated-keyring\"]"));
+    }
+
+    #[test]
+    fn test_recorder_tracks_interruption() {
+        let mut recorder = RunRecorder::new(RunTrigger::Manual, false);
+
+        for event in [
+            UpdateEvent::SourceStarted("flatpak".to_string()),
+            UpdateEvent::SourceCompleted("flatpak".to_string(), true),
+            UpdateEvent::Interrupted,
+            UpdateEvent::Completed(false),
+        ] {
+            recorder.observe(&event);
+        }
+
+        let record = recorder.finish();
+        assert!(record.interrupted);
+        assert!(!record.success());
+        assert!(record.failed_sources().is_empty());
+
+        let serialized = toml::to_string(&History { runs: vec![record] }).unwrap();
+        assert!(serialized.contains("interrupted = true"));
+    }
+
+    #[test]
+    fn test_recorder_tracks_reclaimed_space() {
+        let request = RunRequest {
+            sources: vec!["flatpak".to_string(), "rustup".to_string()],
+            mode: RunMode::Cleanup,
+            ..RunRequest::default()
+        };
+        let mut recorder = RunRecorder::for_request(&request);
+
+        for event in [
+            UpdateEvent::SourceStarted("flatpak".to_string()),
+            UpdateEvent::SourceCleanedUp("flatpak".to_string(), 1_500_000),
+            UpdateEvent::SourceCompleted("flatpak".to_string(), true),
+            UpdateEvent::SourceStarted("rustup".to_string()),
+            UpdateEvent::SourceCompleted("rustup".to_string(), true),
+            UpdateEvent::Completed(true),
+        ] {
+            recorder.observe(&event);
+        }
+
+        let record = recorder.finish();
+        assert_eq!(record.mode, RunMode::Cleanup);
+        assert_eq!(record.sources[0].reclaimed, Some(1_500_000));
+        assert_eq!(record.sources[1].reclaimed, None);
+        assert_eq!(record.reclaimed_bytes(), 1_500_000);
+    }
+
+    #[test]
+    fn test_recorder_caps_error_lines() {
+        let mut recorder = RunRecorder::new(RunTrigger::Manual, false);
+        for i in 0..50 {
+            recorder.observe(&UpdateEvent::SourceMessage(
+                "npm".to_string(),
+                Severity::Error,
+                format!("line {i}"),
+            ));
+        }
+
+        let record = recorder.finish();
+        assert_eq!(record.sources[0].errors.len(), MAX_ERRORS_PER_SOURCE);
+    }
+
+    #[async_std::test]
+    async fn test_rollback_is_recorded_until_undone() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("history.toml");
+
+        let mut recorder = RunRecorder::new(RunTrigger::Manual, false);
+        for event in [
+            UpdateEvent::SourceStarted("dnf".to_string()),
+            UpdateEvent::SourceRollback("dnf".to_string(), Rollback::DnfTransaction { id: 42 }),
+            UpdateEvent::SourceCompleted("dnf".to_string(), true),
+            UpdateEvent::Completed(true),
+        ] {
+            recorder.observe(&event);
+        }
+        let record = recorder.finish();
+        let started_at = record.started_at;
+        History::append(&path, record).await.unwrap();
+        History::append(
+            &path,
+            RunRecord {
+                mode: RunMode::Cleanup,
+                ..RunRecord::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let history = History::load(&path).await.unwrap();
+        let last = history.last_update().unwrap();
+        assert_eq!(
+            last.sources[0].rollback,
+            Some(Rollback::DnfTransaction { id: 42 })
+        );
+
+        History::forget_rollback(&path, started_at, "dnf")
+            .await
+            .unwrap();
+        let history = History::load(&path).await.unwrap();
+        assert_eq!(history.last_update().unwrap().sources[0].rollback, None);
+    }
+
+    #[async_std::test]
+    async fn test_history_append_keeps_latest_runs() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("history.toml");
+
+        for i in 0..(MAX_RUNS as u64 + 5) {
+            let record = RunRecord {
+                started_at: i,
+                ..RunRecord::default()
+            };
+            History::append(&path, record).await.unwrap();
+        }
+
+        let history = History::load(&path).await.unwrap();
+        assert_eq!(history.runs.len(), MAX_RUNS);
+        assert_eq!(history.runs[0].started_at, 5);
+        assert_eq!(history.last().unwrap().started_at, MAX_RUNS as u64 + 4);
+    }
+}
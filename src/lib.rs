@@ -1,7 +1,31 @@
+pub mod advisories;
+pub mod cleanup;
+pub mod conditions;
+pub mod conffiles;
 pub mod config;
+pub mod critical;
+pub mod diagnosis;
+pub mod environment;
+pub mod firmware;
+pub mod headless;
+pub mod history;
+pub mod pending;
+pub mod preflight;
+pub mod pty;
+pub mod reboot;
+pub mod recovery;
+pub mod release;
+pub mod restart;
+pub mod rollback;
+pub mod scheduler;
+pub mod snapshot;
+pub mod systemd;
 pub mod ui;
 pub mod updater;
 
+#[cfg(test)]
+mod test_support;
+
 use async_std::sync::RwLock;
 use config::Config;
 use libadwaita::Application;
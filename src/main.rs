@@ -1,10 +1,24 @@
 use libadwaita::{AboutDialog, Application, prelude::*};
 use libadwaita::{gio, glib, gtk};
-use uptodate::ui::MainWindow;
+use uptodate::headless::{self, HeadlessCommand};
+use uptodate::scheduler::{self, ScheduleState};
+use uptodate::ui::{MainWindow, background, notifications, preferences};
 use uptodate::{APP_ID, AppState, setup_actions};
 
 fn main() -> glib::ExitCode {
     tracing_subscriber::fmt::init();
+
+    // Headless runs (e.g. from the systemd timer) must not require a display
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match HeadlessCommand::from_args(&args) {
+        Ok(Some(command)) => return run_headless(command),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{e}");
+            return glib::ExitCode::FAILURE;
+        }
+    }
+
     libadwaita::init().unwrap();
 
     // Load CSS styles
@@ -23,14 +37,104 @@ fn main() -> glib::ExitCode {
     let state = async_std::task::block_on(async { AppState::new().await });
 
     setup_actions(&app);
-    setup_app_actions(&app);
+    setup_app_actions(&app, &state);
 
-    app.connect_activate(move |app| MainWindow::new(app, state.clone()).present());
+    // Started with `--gapplication-service` no window is opened; the background
+    // scheduler holds the application while periodic checks are enabled.
+    app.connect_startup(glib::clone!(
+        #[strong]
+        state,
+        move |app| background::start(app, state.clone())
+    ));
+
+    app.connect_activate(move |app| {
+        present_window(app, &state);
+    });
 
     app.run()
 }
 
-fn setup_app_actions(app: &Application) {
+/// Presents the existing main window, or creates one if the app runs in the background.
+fn present_window(app: &Application, state: &AppState) -> gtk::Window {
+    if let Some(window) = app.active_window() {
+        window.present();
+        return window;
+    }
+    let window = MainWindow::new(app, state.clone());
+    window.present();
+    window.window.upcast()
+}
+
+fn run_headless(command: HeadlessCommand) -> glib::ExitCode {
+    // A non-unique instance can send notifications alongside a running GUI
+    let app = gio::Application::new(Some(APP_ID), gio::ApplicationFlags::NON_UNIQUE);
+    if let Err(e) = app.register(gio::Cancellable::NONE) {
+        tracing::warn!("Failed to register application, notifications disabled: {e}");
+    }
+
+    // The monitors and D-Bus calls need the GLib main context to make progress
+    let conditions = match command {
+        HeadlessCommand::Check | HeadlessCommand::AutoUpdate => {
+            glib::MainContext::default().block_on(background::probe_conditions())
+        }
+        _ => Default::default(),
+    };
+
+    async_std::task::block_on(async {
+        let state = AppState::new().await;
+        let notify = state.config.read().await.show_notifications;
+
+        match command {
+            HeadlessCommand::Check => match headless::check(&state, &conditions).await {
+                Ok(outcome) => {
+                    if notify && outcome.should_notify() {
+                        notifications::show_updates_available(
+                            &app,
+                            outcome.count,
+                            outcome.security,
+                        );
+                    }
+                    glib::ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    tracing::error!("Check failed: {e}");
+                    glib::ExitCode::FAILURE
+                }
+            },
+            HeadlessCommand::AutoUpdate => match headless::auto_update(&state, &conditions).await {
+                Ok(Some(record)) if !record.success() => {
+                    if notify {
+                        notifications::show_auto_update_failed(&app, &record.failed_sources());
+                    }
+                    glib::ExitCode::FAILURE
+                }
+                Ok(_) => glib::ExitCode::SUCCESS,
+                Err(e) => {
+                    tracing::error!("Automatic update failed: {e}");
+                    glib::ExitCode::FAILURE
+                }
+            },
+            HeadlessCommand::Update(sources) => match headless::update(&state, &sources).await {
+                Ok(true) => glib::ExitCode::SUCCESS,
+                Ok(false) => glib::ExitCode::FAILURE,
+                Err(e) => {
+                    tracing::error!("Update failed: {e}");
+                    glib::ExitCode::FAILURE
+                }
+            },
+            HeadlessCommand::Cleanup => match headless::cleanup(&state).await {
+                Ok(true) => glib::ExitCode::SUCCESS,
+                Ok(false) => glib::ExitCode::FAILURE,
+                Err(e) => {
+                    tracing::error!("Cleanup failed: {e}");
+                    glib::ExitCode::FAILURE
+                }
+            },
+        }
+    })
+}
+
+fn setup_app_actions(app: &Application, state: &AppState) {
     // Helper function to create actions with callbacks
     fn create_action_with_callback<F>(app: &Application, name: &str, callback: F)
     where
@@ -46,10 +150,51 @@ fn setup_app_actions(app: &Application) {
     }
 
     // Create all actions using the helper function
-    create_action_with_callback(app, "quit", |app| app.quit());
+    let quit_state = state.clone();
+    create_action_with_callback(app, "quit", move |app| {
+        // The window asks first whether to stop or keep a running update
+        match app.active_window() {
+            Some(window) if quit_state.updater.is_running() => {
+                window.present();
+                let _ = WidgetExt::activate_action(&window, "win.quit", None);
+            }
+            _ => app.quit(),
+        }
+    });
     create_action_with_callback(app, "about", show_about_dialog);
     create_action_with_callback(app, "shortcuts", show_shortcuts_window);
-    create_action_with_callback(app, "preferences", show_preferences_window);
+    let preferences_state = state.clone();
+    create_action_with_callback(app, "preferences", move |app| {
+        preferences::show_preferences_dialog(app, preferences_state.clone())
+    });
+
+    // Actions offered by the update notifications
+    let update_state = state.clone();
+    create_action_with_callback(app, "update-now", move |app| {
+        let window = present_window(app, &update_state);
+        let _ = WidgetExt::activate_action(&window, "win.update-pending", None);
+    });
+    let security_state = state.clone();
+    create_action_with_callback(app, "update-security", move |app| {
+        let window = present_window(app, &security_state);
+        let _ = WidgetExt::activate_action(&window, "win.update-security", None);
+    });
+    let details_state = state.clone();
+    create_action_with_callback(app, "show-details", move |app| {
+        let window = present_window(app, &details_state);
+        let _ = WidgetExt::activate_action(&window, "win.show-pending", None);
+    });
+    let snooze_state = state.clone();
+    create_action_with_callback(app, "snooze", move |app| {
+        notifications::withdraw_updates_available(app);
+        let state = snooze_state.clone();
+        glib::spawn_future_local(async move {
+            let hours = state.config.read().await.snooze_hours;
+            if let Err(e) = scheduler::snooze(&ScheduleState::default_path(), hours).await {
+                tracing::error!("Failed to snooze reminders: {e}");
+            }
+        });
+    });
 
     // Set up keyboard shortcuts
     app.set_accels_for_action("app.quit", &["<Primary>q"]);
@@ -132,37 +277,3 @@ fn create_shortcut_row(title: &str, shortcut: &str) -> libadwaita::ActionRow {
     row.add_suffix(&shortcut_label);
     row
 }
-
-fn show_preferences_window(app: &Application) {
-    let preferences = libadwaita::PreferencesDialog::new();
-
-    // Create a general page
-    let general_page = libadwaita::PreferencesPage::new();
-    general_page.set_title("General");
-    general_page.set_icon_name(Some("preferences-system-symbolic"));
-
-    // Add a group for update settings
-    let update_group = libadwaita::PreferencesGroup::new();
-    update_group.set_title("Update Settings");
-    update_group.set_description(Some("Configure how updates are performed"));
-
-    // Add the auto-refresh switch
-    let auto_refresh_row = libadwaita::SwitchRow::new();
-    auto_refresh_row.set_title("Auto-refresh sources");
-    auto_refresh_row.set_subtitle("Automatically refresh package lists on startup");
-    auto_refresh_row.set_active(true);
-
-    // Add the notification switch
-    let notification_row = libadwaita::SwitchRow::new();
-    notification_row.set_title("Show notifications");
-    notification_row.set_subtitle("Show system notifications when updates complete");
-    notification_row.set_active(true);
-
-    update_group.add(&auto_refresh_row);
-    update_group.add(&notification_row);
-
-    general_page.add(&update_group);
-    preferences.add(&general_page);
-
-    preferences.present(app.active_window().as_ref());
-}
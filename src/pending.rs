@@ -0,0 +1,336 @@
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    advisories::{Advisory, AdvisorySeverity},
+    config::Config,
+    firmware,
+};
+
+/// A single update reported by a package manager's check command.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingUpdate {
+    pub source: String,
+    pub package: String,
+    pub current_version: Option<String>,
+    pub new_version: Option<String>,
+    /// The package is excluded from updates and will be held back.
+    #[serde(default)]
+    pub held: bool,
+    /// The security issue the update fixes, if any.
+    #[serde(default)]
+    pub advisory: Option<Advisory>,
+    /// The package is one of the configured critical packages, e.g. the kernel,
+    /// or its update needs a reboot.
+    #[serde(default)]
+    pub critical: bool,
+    /// The update is only applied while the system restarts, e.g. firmware.
+    #[serde(default)]
+    pub needs_reboot: bool,
+}
+
+impl PendingUpdate {
+    fn new(source: &str, package: &str, current: Option<&str>, new: Option<&str>) -> Self {
+        Self {
+            source: source.to_string(),
+            package: package.to_string(),
+            current_version: current.map(str::to_string),
+            new_version: new.map(str::to_string),
+            ..Self::default()
+        }
+    }
+}
+
+/// Flags the updates of packages excluded in `config` as held.
+pub fn mark_held(pending: &mut [PendingUpdate], config: &Config) {
+    for update in pending {
+        update.held = config.is_package_excluded(&update.source, &update.package);
+    }
+}
+
+/// Flags the updates of packages `config` lists as critical, and those that
+/// need a reboot.
+pub fn mark_critical(pending: &mut [PendingUpdate], config: &Config) {
+    for update in pending {
+        update.critical =
+            update.needs_reboot || config.is_package_critical(&update.source, &update.package);
+    }
+}
+
+/// Parses the output of a package manager's check command into pending updates.
+///
+/// Unknown sources fall back to treating every non-empty line as one update.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::pending::parse_check_output;
+///
+/// let pending = parse_check_output("paru", "linux 6.9.1-1 -> 6.9.2-1\n");
+/// assert_eq!(pending.len(), 1);
+/// assert_eq!(pending[0].package, "linux");
+/// assert_eq!(pending[0].new_version.as_deref(), Some("6.9.2-1"));
+/// ```
+pub fn parse_check_output(source: &str, output: &str) -> Vec<PendingUpdate> {
+    let lines = output.lines().map(str::trim).filter(|l| !l.is_empty());
+
+    match source {
+        "paru" => lines
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let name = parts.next()?;
+                let current = parts.next();
+                let new = parts.skip_while(|p| *p != "->").nth(1);
+                Some(PendingUpdate::new(source, name, current, new))
+            })
+            .collect(),
+        "apt" => lines
+            .filter(|line| line.contains('/'))
+            .filter_map(|line| {
+                let (name, rest) = line.split_once('/')?;
+                let mut parts = rest.split_whitespace();
+                let origins = parts.next()?;
+                let new = parts.next();
+                let current = line
+                    .split_once("upgradable from: ")
+                    .map(|(_, v)| v.trim_end_matches(']'));
+                let mut update = PendingUpdate::new(source, name, current, new);
+                // Debian and Ubuntu publish security fixes in `<release>-security` pockets,
+                // which carry no severity rating
+                if origins
+                    .split(',')
+                    .any(|origin| origin.ends_with("-security"))
+                {
+                    update.advisory = Some(Advisory {
+                        ids: Vec::new(),
+                        severity: AdvisorySeverity::Unknown,
+                    });
+                }
+                Some(update)
+            })
+            .collect(),
+        "dnf" => lines
+            .take_while(|line| !line.starts_with("Obsoleting"))
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() != 3 || line.starts_with("Last metadata") {
+                    return None;
+                }
+                let name = parts[0].rsplit_once('.').map_or(parts[0], |(n, _)| n);
+                Some(PendingUpdate::new(source, name, None, Some(parts[1])))
+            })
+            .collect(),
+        "zypper" => lines
+            .filter(|line| line.starts_with("v "))
+            .filter_map(|line| {
+                let cols: Vec<&str> = line.split('|').map(str::trim).collect();
+                let name = cols.get(2)?;
+                Some(PendingUpdate::new(
+                    source,
+                    name,
+                    cols.get(3).copied(),
+                    cols.get(4).copied(),
+                ))
+            })
+            .collect(),
+        "apk" => lines
+            .filter(|line| line.contains("upgradable from"))
+            .filter_map(|line| {
+                let full = line.split_whitespace().next()?;
+                let (name, new) = split_apk_version(full)?;
+                let current = line
+                    .split_once("upgradable from: ")
+                    .and_then(|(_, v)| split_apk_version(v.trim_end_matches(']')))
+                    .map(|(_, v)| v);
+                Some(PendingUpdate::new(source, name, current, Some(new)))
+            })
+            .collect(),
+        "flatpak" => lines
+            .filter_map(|line| {
+                let mut cols = line.split('\t').map(str::trim);
+                let app = cols.next().filter(|a| a.contains('.'))?;
+                let version = cols.next().filter(|v| !v.is_empty());
+                Some(PendingUpdate::new(source, app, None, version))
+            })
+            .collect(),
+        "snap" => lines
+            .filter(|line| !line.starts_with("Name ") && !line.starts_with("All snaps"))
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let name = parts.next()?;
+                Some(PendingUpdate::new(source, name, None, parts.next()))
+            })
+            .collect(),
+        "pipx" => lines
+            .filter_map(|line| {
+                let rest = line.strip_prefix("package ")?;
+                let mut parts = rest.split_whitespace();
+                let name = parts.next()?;
+                let version = parts.next().map(|v| v.trim_end_matches(','));
+                Some(PendingUpdate::new(source, name, version, None))
+            })
+            .collect(),
+        "npm" => lines
+            .filter(|line| !line.starts_with("Package "))
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                let name = parts.first()?;
+                Some(PendingUpdate::new(
+                    source,
+                    name,
+                    parts.get(1).copied(),
+                    parts.get(3).copied(),
+                ))
+            })
+            .collect(),
+        "rustup" => lines
+            .filter(|line| line.contains("Update available"))
+            .filter_map(|line| {
+                let (name, rest) = line.split_once(" - ")?;
+                let (current, new) = rest.split_once("->").map_or((None, None), |(c, n)| {
+                    (c.split(':').nth(1), n.split_whitespace().next())
+                });
+                Some(PendingUpdate::new(
+                    source,
+                    name.trim(),
+                    current.and_then(|c| c.split_whitespace().next()),
+                    new,
+                ))
+            })
+            .collect(),
+        "brew" => lines
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let name = parts.next()?;
+                let current = parts
+                    .next()
+                    .map(|v| v.trim_start_matches('(').trim_end_matches(')'));
+                let new = parts.skip_while(|p| *p != "<").nth(1);
+                Some(PendingUpdate::new(source, name, current, new))
+            })
+            .collect(),
+        "fwupd" => match firmware::parse_updates(output) {
+            Ok(updates) => updates
+                .into_iter()
+                .map(|firmware| {
+                    let mut update = PendingUpdate::new(
+                        source,
+                        &firmware.device,
+                        firmware.current_version.as_deref(),
+                        firmware.new_version.as_deref(),
+                    );
+                    update.needs_reboot = firmware.needs_reboot;
+                    update
+                })
+                .collect(),
+            Err(e) => {
+                warn!("Failed to parse firmware updates: {}", e);
+                Vec::new()
+            }
+        },
+        _ => lines
+            .map(|line| PendingUpdate::new(source, line, None, None))
+            .collect(),
+    }
+}
+
+/// Splits an apk package string such as `musl-1.2.4-r2` into name and version.
+fn split_apk_version(full: &str) -> Option<(&str, &str)> {
+    let (rest, _release) = full.rsplit_once('-')?;
+    let (name, _version) = rest.rsplit_once('-')?;
+    Some((name, &full[name.len() + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_paru_output() {
+        let output = "linux 6.9.1.arch1-1 -> 6.9.2.arch1-1\nmesa 1:24.1.0-1 -> 1:24.1.1-1\n";
+        let pending = parse_check_output("paru", output);
+
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].package, "linux");
+        assert_eq!(pending[0].current_version.as_deref(), Some("6.9.1.arch1-1"));
+        assert_eq!(pending[1].new_version.as_deref(), Some("1:24.1.1-1"));
+    }
+
+    #[test]
+    fn test_parse_apt_output() {
+        let output = "Listing... Done\n\
+                      openssl/jammy-security 3.0.2-0ubuntu1.15 amd64 [upgradable from: 3.0.2-0ubuntu1.14]\n";
+        let pending = parse_check_output("apt", output);
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].package, "openssl");
+        assert_eq!(pending[0].new_version.as_deref(), Some("3.0.2-0ubuntu1.15"));
+        assert_eq!(
+            pending[0].current_version.as_deref(),
+            Some("3.0.2-0ubuntu1.14")
+        );
+    }
+
+    #[test]
+    fn test_parse_dnf_output() {
+        let output = "Last metadata expiration check: 0:12:01 ago on Mon 01 Jan 2024.\n\n\
+                      kernel.x86_64    6.8.9-300.fc40    updates\n\
+                      Obsoleting Packages\n\
+                      grub2-tools.x86_64    1:2.06-1.fc40    updates\n";
+        let pending = parse_check_output("dnf", output);
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].package, "kernel");
+    }
+
+    #[test]
+    fn test_parse_rustup_output() {
+        let output = "stable-x86_64-unknown-linux-gnu - Update available : 1.79.0 (129f3b996 2024-06-10) -> 1.80.0 (051478957 2024-07-21)\n\
+                      rustup - Up to date : 1.27.1\n";
+        let pending = parse_check_output("rustup", output);
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].package, "stable-x86_64-unknown-linux-gnu");
+        assert_eq!(pending[0].current_version.as_deref(), Some("1.79.0"));
+        assert_eq!(pending[0].new_version.as_deref(), Some("1.80.0"));
+    }
+
+    #[test]
+    fn test_parse_apk_output() {
+        let output = "musl-1.2.4-r3 x86_64 {musl} (MIT) [upgradable from: musl-1.2.4-r2]\n";
+        let pending = parse_check_output("apk", output);
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].package, "musl");
+        assert_eq!(pending[0].new_version.as_deref(), Some("1.2.4-r3"));
+        assert_eq!(pending[0].current_version.as_deref(), Some("1.2.4-r2"));
+    }
+
+    #[test]
+    fn test_parse_fwupd_output() {
+        let output = r#"{"Devices": [
+            {"Name": "System Firmware", "Plugin": "uefi_capsule", "Version": "0.1.31",
+             "Releases": [{"Version": "0.1.33"}]},
+            {"Name": "USB-C Dock", "Plugin": "synaptics_mst", "Version": "5.3.8",
+             "Releases": [{"Version": "5.3.9"}]}
+        ]}"#;
+        let mut pending = parse_check_output("fwupd", output);
+
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].package, "System Firmware");
+        assert_eq!(pending[0].new_version.as_deref(), Some("0.1.33"));
+        assert!(pending[0].needs_reboot);
+        assert!(!pending[1].needs_reboot);
+
+        mark_critical(&mut pending, &Config::default());
+        assert!(pending[0].critical);
+        assert!(!pending[1].critical);
+        assert!(parse_check_output("fwupd", "{ not json").is_empty());
+    }
+
+    #[test]
+    fn test_parse_unknown_source_falls_back_to_lines() {
+        let pending = parse_check_output("custom", "one\n\ntwo\n");
+        assert_eq!(pending.len(), 2);
+    }
+}
@@ -0,0 +1,417 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::cleanup::{self, format_bytes};
+
+/// How long a run waits for a package manager lock to be released.
+pub const LOCK_WAIT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How often a held lock is checked again while waiting.
+pub const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A lock a package manager takes while it modifies the system.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lock {
+    /// Held while the file exists, e.g. pacman's `db.lck`.
+    File(PathBuf),
+    /// Held while the process whose PID the file contains is running, e.g. `zypp.pid`.
+    Pid(PathBuf),
+    /// Held while a process has an advisory lock on the file, e.g. dpkg's `lock-frontend`.
+    Advisory(PathBuf),
+}
+
+/// What a package manager needs before it can run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Requirements {
+    pub locks: Vec<Lock>,
+    /// Directories whose filesystems need `min_free_mb` available each;
+    /// `~/` refers to the home directory. Missing directories are skipped.
+    pub space: Vec<PathBuf>,
+    pub min_free_mb: u64,
+}
+
+impl Requirements {
+    pub fn new(locks: Vec<Lock>, space: &[&str], min_free_mb: u64) -> Self {
+        Self {
+            locks,
+            space: space.iter().map(PathBuf::from).collect(),
+            min_free_mb,
+        }
+    }
+}
+
+/// Why a source cannot run right now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Blocker {
+    /// Another program holds a package manager lock.
+    Locked {
+        path: PathBuf,
+        /// The program holding the lock, if it is known.
+        holder: Option<String>,
+    },
+    /// A filesystem has less free space than the manager needs.
+    LowSpace {
+        mount_point: String,
+        available: u64,
+        required: u64,
+    },
+    /// Another process is running updates.
+    Busy { pid: u32, sources: Vec<String> },
+}
+
+impl Blocker {
+    /// Returns `true` if the blocker may clear by itself, i.e. it is a lock.
+    pub fn is_lock(&self) -> bool {
+        matches!(self, Self::Locked { .. })
+    }
+}
+
+impl fmt::Display for Blocker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Locked {
+                path,
+                holder: Some(holder),
+            } => write!(f, "{} is locked by {}", path.display(), holder),
+            Self::Locked { path, holder: None } => write!(
+                f,
+                "{} exists; another package manager is running or was interrupted",
+                path.display()
+            ),
+            Self::LowSpace {
+                mount_point,
+                available,
+                required,
+            } => write!(
+                f,
+                "only {} free on {}, {} needed",
+                format_bytes(*available),
+                mount_point,
+                format_bytes(*required)
+            ),
+            Self::Busy { pid, sources } => write!(
+                f,
+                "another run (process {}) is updating {}",
+                pid,
+                sources.join(", ")
+            ),
+        }
+    }
+}
+
+/// Checks that a package manager can run: its locks are free and there is
+/// enough disk space for downloads and unpacked packages.
+#[derive(Debug, Clone)]
+pub struct Preflight {
+    root: PathBuf,
+    home: Option<PathBuf>,
+}
+
+impl Default for Preflight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Preflight {
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            home: dirs::home_dir(),
+        }
+    }
+
+    /// Resolves absolute paths (including `/proc`) below `root` and `~/` below `home`.
+    pub fn with_root(root: &Path, home: Option<&Path>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            home: home.map(Path::to_path_buf),
+        }
+    }
+
+    /// Returns the first reason why a manager with `requirements` cannot run, if any.
+    ///
+    /// Locks are checked before disk space. Disk space that cannot be
+    /// determined does not block the run.
+    pub async fn check(&self, requirements: &Requirements) -> Option<Blocker> {
+        if let Some(blocker) = self.held_lock(&requirements.locks) {
+            return Some(blocker);
+        }
+        self.low_space(requirements).await
+    }
+
+    /// Returns the first of `locks` that is held by another program.
+    pub fn held_lock(&self, locks: &[Lock]) -> Option<Blocker> {
+        locks.iter().find_map(|lock| self.lock_holder(lock))
+    }
+
+    fn lock_holder(&self, lock: &Lock) -> Option<Blocker> {
+        match lock {
+            Lock::File(path) => self.resolve(path).exists().then(|| Blocker::Locked {
+                path: path.clone(),
+                holder: None,
+            }),
+            Lock::Pid(path) => {
+                let pid: u32 = std::fs::read_to_string(self.resolve(path))
+                    .ok()?
+                    .trim()
+                    .parse()
+                    .ok()?;
+                // A stale PID file of a crashed process does not block
+                self.resolve(Path::new("/proc"))
+                    .join(pid.to_string())
+                    .exists()
+                    .then(|| Blocker::Locked {
+                        path: path.clone(),
+                        holder: Some(self.describe_process(pid)),
+                    })
+            }
+            Lock::Advisory(path) => {
+                let metadata = std::fs::metadata(self.resolve(path)).ok()?;
+                let locks = std::fs::read_to_string(self.resolve(Path::new("/proc/locks"))).ok()?;
+                let pid = find_lock_owner(&locks, metadata.dev(), metadata.ino())?;
+                Some(Blocker::Locked {
+                    path: path.clone(),
+                    holder: Some(self.describe_process(pid)),
+                })
+            }
+        }
+    }
+
+    async fn low_space(&self, requirements: &Requirements) -> Option<Blocker> {
+        let required = requirements.min_free_mb.saturating_mul(1024 * 1024);
+        let paths: Vec<PathBuf> = requirements
+            .space
+            .iter()
+            .filter_map(|path| self.resolve_space_path(path))
+            .filter(|path| path.exists())
+            .collect();
+        if required == 0 || paths.is_empty() {
+            return None;
+        }
+
+        let filesystems = match cleanup::filesystems(&paths).await {
+            Ok(filesystems) => filesystems,
+            Err(e) => {
+                tracing::warn!("Failed to check free space: {}", e);
+                return None;
+            }
+        };
+        filesystems
+            .into_iter()
+            .find(|fs| fs.available < required)
+            .map(|fs| Blocker::LowSpace {
+                mount_point: fs.mount_point,
+                available: fs.available,
+                required,
+            })
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    fn resolve_space_path(&self, path: &Path) -> Option<PathBuf> {
+        match path.strip_prefix("~") {
+            Ok(relative) => Some(self.home.as_ref()?.join(relative)),
+            Err(_) => Some(self.resolve(path)),
+        }
+    }
+
+    fn describe_process(&self, pid: u32) -> String {
+        let comm = self
+            .resolve(Path::new("/proc"))
+            .join(pid.to_string())
+            .join("comm");
+        match std::fs::read_to_string(comm) {
+            Ok(command) => format!("{} (PID {})", command.trim(), pid),
+            Err(_) => format!("PID {pid}"),
+        }
+    }
+}
+
+/// Finds the process holding a lock on the file with device `dev` and inode
+/// `ino` in the contents of `/proc/locks`.
+///
+/// Processes waiting for the lock (`->` entries) are not owners.
+fn find_lock_owner(locks: &str, dev: u64, ino: u64) -> Option<u32> {
+    let (major, minor) = dev_major_minor(dev);
+    let id = format!("{major:02x}:{minor:02x}:{ino}");
+
+    locks.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) == Some(&"->") {
+            return None;
+        }
+        // "1: POSIX  ADVISORY  WRITE 1234 08:01:131090 0 EOF"
+        let position = fields.iter().position(|field| *field == id)?;
+        fields.get(position.checked_sub(1)?)?.parse().ok()
+    })
+}
+
+/// Splits a device number the way the kernel prints it in `/proc/locks`.
+fn dev_major_minor(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major, minor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write;
+    use tempfile::tempdir;
+
+    #[async_std::test]
+    async fn test_nothing_blocks() {
+        let root = tempdir().unwrap();
+        write(root.path(), "var/lib/dpkg/lock-frontend", "");
+        write(root.path(), "proc/locks", "");
+
+        let requirements = Requirements::new(
+            vec![
+                Lock::File(PathBuf::from("/var/lib/pacman/db.lck")),
+                Lock::Pid(PathBuf::from("/run/zypp.pid")),
+                Lock::Advisory(PathBuf::from("/var/lib/dpkg/lock-frontend")),
+            ],
+            &["/", "/var/cache/apt/archives"],
+            1,
+        );
+        let preflight = Preflight::with_root(root.path(), None);
+        assert_eq!(preflight.check(&requirements).await, None);
+    }
+
+    #[async_std::test]
+    async fn test_file_lock() {
+        let root = tempdir().unwrap();
+        write(root.path(), "var/lib/pacman/db.lck", "");
+
+        let requirements = Requirements::new(
+            vec![Lock::File(PathBuf::from("/var/lib/pacman/db.lck"))],
+            &[],
+            0,
+        );
+        let blocker = Preflight::with_root(root.path(), None)
+            .check(&requirements)
+            .await
+            .unwrap();
+        assert!(blocker.is_lock());
+        assert_eq!(
+            blocker.to_string(),
+            "/var/lib/pacman/db.lck exists; another package manager is running or was interrupted"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_pid_lock_ignores_stale_files() {
+        let root = tempdir().unwrap();
+        write(root.path(), "run/zypp.pid", "4242\n");
+        let requirements =
+            Requirements::new(vec![Lock::Pid(PathBuf::from("/run/zypp.pid"))], &[], 0);
+        let preflight = Preflight::with_root(root.path(), None);
+
+        assert_eq!(preflight.check(&requirements).await, None);
+
+        write(root.path(), "proc/4242/comm", "zypper\n");
+        assert_eq!(
+            preflight.check(&requirements).await.unwrap().to_string(),
+            "/run/zypp.pid is locked by zypper (PID 4242)"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_advisory_lock() {
+        let root = tempdir().unwrap();
+        write(root.path(), "var/lib/dpkg/lock-frontend", "");
+        write(root.path(), "proc/1234/comm", "apt\n");
+
+        let metadata = std::fs::metadata(root.path().join("var/lib/dpkg/lock-frontend")).unwrap();
+        let (major, minor) = dev_major_minor(metadata.dev());
+        let id = format!("{major:02x}:{minor:02x}:{}", metadata.ino());
+        write(
+            root.path(),
+            "proc/locks",
+            &format!(
+                "1: POSIX  ADVISORY  WRITE 999 00:1a:77 0 EOF\n\
+                 2: POSIX  ADVISORY  WRITE 1234 {id} 0 EOF\n\
+                 2: -> POSIX  ADVISORY  WRITE 5678 {id} 0 EOF\n"
+            ),
+        );
+
+        let requirements = Requirements::new(
+            vec![Lock::Advisory(PathBuf::from("/var/lib/dpkg/lock-frontend"))],
+            &[],
+            0,
+        );
+        assert_eq!(
+            Preflight::with_root(root.path(), None)
+                .check(&requirements)
+                .await,
+            Some(Blocker::Locked {
+                path: PathBuf::from("/var/lib/dpkg/lock-frontend"),
+                holder: Some("apt (PID 1234)".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_lock_owner_skips_waiters() {
+        let locks = "1: FLOCK  ADVISORY  WRITE 77 08:01:500 0 EOF\n\
+                     1: -> FLOCK  ADVISORY  WRITE 88 08:01:600 0 EOF\n";
+        let dev = (8 << 8) | 1;
+        assert_eq!(find_lock_owner(locks, dev, 500), Some(77));
+        assert_eq!(find_lock_owner(locks, dev, 600), None);
+        assert_eq!(find_lock_owner(locks, dev, 700), None);
+    }
+
+    #[test]
+    fn test_dev_major_minor() {
+        assert_eq!(dev_major_minor((8 << 8) | 1), (8, 1));
+        // Large minor numbers, e.g. of device-mapper or btrfs subvolumes
+        assert_eq!(dev_major_minor(0x0000_0000_0010_0030), (0, 0x100 | 0x30));
+    }
+
+    #[async_std::test]
+    async fn test_low_space() {
+        let root = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        std::fs::create_dir_all(home.path().join(".rustup")).unwrap();
+
+        // No filesystem has an exabyte free
+        let requirements = Requirements::new(Vec::new(), &["~/.rustup"], 1 << 40);
+        let blocker = Preflight::with_root(root.path(), Some(home.path()))
+            .check(&requirements)
+            .await
+            .unwrap();
+        assert!(!blocker.is_lock());
+        assert!(matches!(blocker, Blocker::LowSpace { .. }));
+
+        // Missing directories are not checked
+        let requirements = Requirements::new(Vec::new(), &["/var/lib/snapd"], 1 << 40);
+        assert_eq!(
+            Preflight::with_root(root.path(), Some(home.path()))
+                .check(&requirements)
+                .await,
+            None
+        );
+    }
+
+    #[test]
+    fn test_low_space_message() {
+        let blocker = Blocker::LowSpace {
+            mount_point: "/var".to_string(),
+            available: 120_000_000,
+            required: 1_073_741_824,
+        };
+        assert_eq!(
+            blocker.to_string(),
+            "only 120.0 MB free on /var, 1.1 GB needed"
+        );
+    }
+}
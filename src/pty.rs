@@ -0,0 +1,473 @@
+use anyhow::Result;
+use async_std::process::{Command, Stdio};
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{fs::OpenOptionsExt, process::CommandExt},
+    },
+    time::Duration,
+};
+
+/// How long output has to stay quiet before an unrecognized partial line is
+/// taken for a question.
+pub const PROMPT_IDLE: Duration = Duration::from_secs(3);
+
+/// Complete lines kept as context for the next question.
+const CONTEXT_LINES: usize = 8;
+
+/// What kind of answer a question expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptKind {
+    /// A yes or no question, answered with `y` or `n`.
+    Confirm,
+    /// One of a fixed set of answers, e.g. apt's `Y/I/N/O/D/Z`.
+    Choice(Vec<String>),
+    /// A free-form answer such as the number of a provider.
+    Text,
+}
+
+/// A question a package manager asked on its terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    /// The question as printed, without trailing whitespace.
+    pub question: String,
+    /// Lines printed right before the question, e.g. a list of providers.
+    pub context: Vec<String>,
+    pub kind: PromptKind,
+    /// The answer used when the user just presses Enter.
+    pub default: Option<String>,
+    /// `false` if the program only fell silent after printing a partial line,
+    /// which may or may not be a question.
+    pub recognized: bool,
+}
+
+impl Prompt {
+    /// Recognizes the questions of the supported package managers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::pty::{Prompt, PromptKind};
+    ///
+    /// let prompt = Prompt::recognize(":: Proceed with installation? [Y/n] ", &[]).unwrap();
+    /// assert_eq!(prompt.kind, PromptKind::Confirm);
+    /// assert_eq!(prompt.default.as_deref(), Some("y"));
+    /// assert!(Prompt::recognize("Downloading 45%", &[]).is_none());
+    /// ```
+    pub fn recognize(partial: &str, context: &[String]) -> Option<Self> {
+        let question = partial.trim_end();
+        let lower = question.to_lowercase();
+
+        let (question, kind, default) = if let Some(default) = confirm_default(question) {
+            (question.to_string(), PromptKind::Confirm, default)
+        } else if let Some(choices) = choices(question) {
+            (
+                question.to_string(),
+                PromptKind::Choice(choices),
+                default_value(question),
+            )
+        } else if lower.contains("(default=") || lower.contains("[default=") {
+            // paru: `Enter a number (default=1):`
+            (
+                question.to_string(),
+                PromptKind::Text,
+                default_value(question),
+            )
+        } else if question == "==>" {
+            // paru's menus print their question on the line before the `==>` prompt
+            (context.last()?.clone(), PromptKind::Text, None)
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            question,
+            context: context.to_vec(),
+            kind,
+            default,
+            recognized: true,
+        })
+    }
+
+    /// A partial line that was followed by silence.
+    pub fn unrecognized(partial: &str, context: &[String]) -> Self {
+        Self {
+            question: partial.trim_end().to_string(),
+            context: context.to_vec(),
+            kind: PromptKind::Text,
+            default: None,
+            recognized: false,
+        }
+    }
+}
+
+/// Returns the default of a yes or no question, or `None` if `question` is
+/// not one. An answer is the default if it is capitalized.
+fn confirm_default(question: &str) -> Option<Option<String>> {
+    let lower = question.to_lowercase();
+    ["[y/n]", "(y/n)", "[yes/no]"].iter().find_map(|pattern| {
+        let start = lower.rfind(pattern)?;
+        let options = &question[start..start + pattern.len()];
+        let yes = options.contains('Y');
+        let no = options.contains('N');
+        Some(match (yes, no) {
+            (true, false) => Some("y".to_string()),
+            (false, true) => Some("n".to_string()),
+            _ => None,
+        })
+    })
+}
+
+/// Returns the answers of a question like `(Y/I/N/O/D/Z)`.
+fn choices(question: &str) -> Option<Vec<String>> {
+    let start = question.rfind('(')?;
+    let end = start + question[start..].find(')')?;
+    let choices: Vec<String> = question[start + 1..end]
+        .split('/')
+        .map(|choice| choice.trim().to_string())
+        .collect();
+    let is_choice =
+        |choice: &String| choice.len() == 1 && choice.chars().all(char::is_alphanumeric);
+    (choices.len() > 2 && choices.iter().all(is_choice)).then_some(choices)
+}
+
+/// Returns the value of `default=` in a question, e.g. `[default=N]`.
+fn default_value(question: &str) -> Option<String> {
+    let start = question.to_lowercase().find("default=")? + "default=".len();
+    let value: String = question[start..]
+        .chars()
+        .take_while(|c| !matches!(c, ']' | ')'))
+        .collect();
+    (!value.is_empty()).then_some(value)
+}
+
+/// Removes terminal escape sequences such as colours and cursor movements.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::pty::strip_escapes;
+///
+/// assert_eq!(strip_escapes("\x1b[1;32m::\x1b[0m Synchronizing"), ":: Synchronizing");
+/// ```
+pub fn strip_escapes(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters up to a final byte in `@`..=`~`
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: up to BEL or ST
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    plain
+}
+
+/// Splits terminal output into lines and keeps the partial last line, where
+/// questions are printed.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    partial: Vec<u8>,
+    context: Vec<String>,
+    /// The partial line that was last reported as a question.
+    asked: Option<String>,
+}
+
+impl Transcript {
+    /// Adds output and returns the lines it completed.
+    ///
+    /// A carriage return also ends a line, so that progress bars which
+    /// redraw themselves are reported once per update.
+    pub fn feed(&mut self, output: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in output {
+            if byte == b'\n' || byte == b'\r' {
+                let line = strip_escapes(&String::from_utf8_lossy(&self.partial));
+                self.partial.clear();
+                // The same question may be asked again on a later line
+                self.asked = None;
+                if !line.trim().is_empty() {
+                    self.context.push(line.clone());
+                    lines.push(line);
+                }
+            } else {
+                self.partial.push(byte);
+            }
+        }
+        if self.context.len() > CONTEXT_LINES {
+            self.context.drain(..self.context.len() - CONTEXT_LINES);
+        }
+        lines
+    }
+
+    /// Returns the partial last line.
+    pub fn partial(&self) -> String {
+        strip_escapes(&String::from_utf8_lossy(&self.partial))
+    }
+
+    /// Returns the question on the partial line, if it is one and has not
+    /// been reported yet.
+    ///
+    /// With `idle` set, the program fell silent, so any partial line is
+    /// taken for a question.
+    pub fn question(&mut self, idle: bool) -> Option<Prompt> {
+        let partial = self.partial();
+        if partial.trim().is_empty() || self.asked.as_ref() == Some(&partial) {
+            return None;
+        }
+
+        let prompt = Prompt::recognize(&partial, &self.context)
+            .or_else(|| idle.then(|| Prompt::unrecognized(&partial, &self.context)))?;
+        self.asked = Some(partial);
+        Some(prompt)
+    }
+}
+
+/// A pseudo-terminal that a package manager runs in.
+#[derive(Debug)]
+pub struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Pty {
+    /// Opens a new pseudo-terminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no pseudo-terminal is available.
+    pub fn open() -> Result<Self> {
+        let error = |e: io::Error| anyhow::anyhow!("Failed to open a pseudo-terminal: {}", e);
+
+        // SAFETY: posix_openpt returns a new file descriptor or -1, and the
+        // descriptor is owned by `master` from here on.
+        let master = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(error(io::Error::last_os_error()));
+            }
+            File::from_raw_fd(fd)
+        };
+
+        let mut name = [0; 128];
+        // SAFETY: `master` is a valid descriptor and `name` outlives the calls,
+        // which write at most `name.len()` bytes including the terminating NUL.
+        let path = unsafe {
+            let fd = master.as_raw_fd();
+            if libc::grantpt(fd) != 0
+                || libc::unlockpt(fd) != 0
+                || libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0
+            {
+                return Err(error(io::Error::last_os_error()));
+            }
+            CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned()
+        };
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)
+            .map_err(error)?;
+
+        // Wide enough that package lists are not wrapped
+        let size = libc::winsize {
+            ws_row: 40,
+            ws_col: 160,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: `size` is a valid winsize for the duration of the call.
+        unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+
+        Ok(Self { master, slave })
+    }
+
+    /// Returns a command running `cmd` with the terminal as its standard
+    /// streams and controlling terminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the terminal cannot be shared with the command.
+    pub fn command(&self, cmd: &[String]) -> Result<Command> {
+        let mut command = std::process::Command::new(&cmd[0]);
+        command.args(&cmd[1..]);
+        // SAFETY: only async-signal-safe functions are called between fork and exec.
+        unsafe {
+            command.pre_exec(|| {
+                // A new session makes the terminal's signals, e.g. on hangup, reach the child
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let stream = || {
+            self.slave
+                .try_clone()
+                .map_err(|e| anyhow::anyhow!("Failed to share the pseudo-terminal: {}", e))
+        };
+        let mut command = Command::from(command);
+        command
+            .stdin(Stdio::from(stream()?))
+            .stdout(Stdio::from(stream()?))
+            .stderr(Stdio::from(stream()?));
+        Ok(command)
+    }
+
+    /// Closes this process's side of the terminal and returns the end that
+    /// reads the child's output and writes its input.
+    ///
+    /// Reading fails once the child and everything it started have exited.
+    pub fn into_master(self) -> File {
+        self.master
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recognize_confirmations() {
+        let prompt = Prompt::recognize("Is this ok [y/N]: ", &[]).unwrap();
+        assert_eq!(prompt.kind, PromptKind::Confirm);
+        assert_eq!(prompt.question, "Is this ok [y/N]:");
+        assert_eq!(prompt.default.as_deref(), Some("n"));
+
+        let prompt = Prompt::recognize("==> Proceed to review? [Y/n]", &[]).unwrap();
+        assert_eq!(prompt.default.as_deref(), Some("y"));
+    }
+
+    #[test]
+    fn test_recognize_apt_conffile_question() {
+        let context = vec!["Configuration file '/etc/ssh/sshd_config'".to_string()];
+        let prompt =
+            Prompt::recognize("*** sshd_config (Y/I/N/O/D/Z) [default=N] ? ", &context).unwrap();
+        assert_eq!(
+            prompt.kind,
+            PromptKind::Choice(vec![
+                "Y".to_string(),
+                "I".to_string(),
+                "N".to_string(),
+                "O".to_string(),
+                "D".to_string(),
+                "Z".to_string()
+            ])
+        );
+        assert_eq!(prompt.default.as_deref(), Some("N"));
+        assert_eq!(prompt.context, context);
+    }
+
+    #[test]
+    fn test_recognize_paru_questions() {
+        let prompt = Prompt::recognize("Enter a number (default=1): ", &[]).unwrap();
+        assert_eq!(prompt.kind, PromptKind::Text);
+        assert_eq!(prompt.default.as_deref(), Some("1"));
+
+        let context = vec!["==> Packages to cleanBuild?".to_string()];
+        let prompt = Prompt::recognize("==> ", &context).unwrap();
+        assert_eq!(prompt.question, "==> Packages to cleanBuild?");
+
+        assert!(Prompt::recognize("==> ", &[]).is_none());
+        assert!(Prompt::recognize("(1/3) checking keys in keyring", &[]).is_none());
+    }
+
+    #[test]
+    fn test_strip_escapes() {
+        assert_eq!(strip_escapes("plain"), "plain");
+        assert_eq!(strip_escapes("\x1b[?25l\x1b[1mbold\x1b[0m"), "bold");
+        assert_eq!(strip_escapes("\x1b]0;title\x07text"), "text");
+    }
+
+    #[test]
+    fn test_transcript_splits_lines_and_asks_once() {
+        let mut transcript = Transcript::default();
+        assert_eq!(
+            transcript.feed(b"Resolving\r\n\x1b[1m:: Proceed"),
+            vec!["Resolving"]
+        );
+        assert!(transcript.question(false).is_none());
+
+        assert!(transcript.feed(b" with installation? [Y/n] ").is_empty());
+        let prompt = transcript.question(false).unwrap();
+        assert_eq!(prompt.context, vec!["Resolving"]);
+        assert!(transcript.question(true).is_none());
+
+        // The answer is echoed, which completes the line
+        assert_eq!(
+            transcript.feed(b"y\r\n"),
+            vec![":: Proceed with installation? [Y/n] y"]
+        );
+        assert!(transcript.question(true).is_none());
+    }
+
+    #[test]
+    fn test_transcript_reports_silence() {
+        let mut transcript = Transcript::default();
+        transcript.feed(b"Overwrite? ");
+        assert!(transcript.question(false).is_none());
+
+        let prompt = transcript.question(true).unwrap();
+        assert!(!prompt.recognized);
+        assert_eq!(prompt.question, "Overwrite?");
+    }
+
+    #[async_std::test]
+    async fn test_pty_runs_interactive_command() {
+        let pty = Pty::open().unwrap();
+        let script = "printf 'Continue? [Y/n] '; read answer; echo \"got $answer\"";
+        let mut child = pty
+            .command(&["sh".to_string(), "-c".to_string(), script.to_string()])
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let mut master = pty.into_master();
+
+        std::io::Write::write_all(&mut master, b"n\n").unwrap();
+        assert!(child.status().await.unwrap().success());
+
+        let mut transcript = Transcript::default();
+        let mut buffer = [0; 1024];
+        let mut lines = Vec::new();
+        while let Ok(read @ 1..) = std::io::Read::read(&mut master, &mut buffer) {
+            lines.extend(transcript.feed(&buffer[..read]));
+        }
+        // Where the echoed answer appears depends on when it was written
+        assert!(lines.last().unwrap().ends_with("got n"));
+    }
+
+    #[test]
+    fn test_transcript_keeps_recent_context() {
+        let mut transcript = Transcript::default();
+        for i in 0..20 {
+            transcript.feed(format!("line {i}\n").as_bytes());
+        }
+        transcript.feed(b"Continue? [Y/n]");
+        let prompt = transcript.question(false).unwrap();
+        assert_eq!(prompt.context.len(), CONTEXT_LINES);
+        assert_eq!(prompt.context.last().unwrap(), "line 19");
+    }
+}
@@ -0,0 +1,258 @@
+use async_std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{info, warn};
+
+/// Whether the system needs a reboot to finish applying updates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RebootStatus {
+    pub required: bool,
+    /// Why a reboot is needed, e.g. the packages that asked for it.
+    pub reasons: Vec<String>,
+}
+
+impl RebootStatus {
+    /// Marks a reboot as required for `reason`, unless it is already known.
+    pub fn require(&mut self, reason: impl Into<String>) {
+        let reason = reason.into();
+        self.required = true;
+        if !self.reasons.contains(&reason) {
+            self.reasons.push(reason);
+        }
+    }
+}
+
+/// Detects whether updates require a reboot, using each distribution's own signal:
+///
+/// - `/run/reboot-required` on Debian and Ubuntu
+/// - `dnf needs-restarting -r` on Fedora
+/// - a running kernel whose modules are no longer installed, e.g. on Arch Linux
+#[derive(Debug, Clone)]
+pub struct RebootCheck {
+    root: PathBuf,
+    /// Directory `dnf` is looked up in; `None` searches `$PATH`.
+    bin_dir: Option<PathBuf>,
+}
+
+impl Default for RebootCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RebootCheck {
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            bin_dir: None,
+        }
+    }
+
+    /// Checks the filesystem below `root` instead of `/`, looking up binaries in `bin_dir`.
+    pub fn with_root(root: &Path, bin_dir: Option<&Path>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            bin_dir: bin_dir.map(Path::to_path_buf),
+        }
+    }
+
+    /// Runs all checks that apply to this system.
+    pub async fn run(&self) -> RebootStatus {
+        let mut status = RebootStatus::default();
+        self.check_reboot_required_file(&mut status);
+        self.check_running_kernel(&mut status);
+        self.check_dnf(&mut status).await;
+
+        if status.required {
+            info!("Reboot required: {}", status.reasons.join(", "));
+        }
+        status
+    }
+
+    fn check_reboot_required_file(&self, status: &mut RebootStatus) {
+        if !self.root.join("run/reboot-required").exists() {
+            return;
+        }
+
+        let packages =
+            std::fs::read_to_string(self.root.join("run/reboot-required.pkgs")).unwrap_or_default();
+        let mut packages = packages.lines().map(str::trim).filter(|l| !l.is_empty());
+        match packages.next() {
+            Some(first) => {
+                status.require(format!("{first} was updated"));
+                for package in packages {
+                    status.require(format!("{package} was updated"));
+                }
+            }
+            None => status.require("The system requested a reboot"),
+        }
+    }
+
+    fn check_running_kernel(&self, status: &mut RebootStatus) {
+        let Ok(release) = std::fs::read_to_string(self.root.join("proc/sys/kernel/osrelease"))
+        else {
+            return;
+        };
+        let release = release.trim();
+        let modules_dir = self.root.join("usr/lib/modules");
+
+        // Containers often have no modules at all; only a kernel replaced by
+        // another one counts
+        let has_modules = std::fs::read_dir(&modules_dir)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false);
+        if !release.is_empty() && has_modules && !modules_dir.join(release).exists() {
+            status.require(format!("Running kernel {release} is no longer installed"));
+        }
+    }
+
+    async fn check_dnf(&self, status: &mut RebootStatus) {
+        let dnf = match &self.bin_dir {
+            Some(dir) => dir.join("dnf"),
+            None => match find_in_path("dnf") {
+                Some(dnf) => dnf,
+                None => return,
+            },
+        };
+        if !dnf.is_file() {
+            return;
+        }
+
+        let output = match Command::new(&dnf)
+            .args(["needs-restarting", "-r"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("Failed to run dnf needs-restarting: {}", e);
+                return;
+            }
+        };
+
+        // Exit code 1 means a reboot is required
+        if output.status.code() != Some(1) {
+            return;
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut packages = stdout
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("* "))
+            .peekable();
+        if packages.peek().is_none() {
+            status.require("dnf reports that a reboot is required");
+        }
+        for package in packages {
+            status.require(format!("{} was updated", package.trim()));
+        }
+    }
+}
+
+pub(crate) fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{stub, write};
+    use tempfile::tempdir;
+
+    #[async_std::test]
+    async fn test_nothing_required() {
+        let root = tempdir().unwrap();
+        write(root.path(), "proc/sys/kernel/osrelease", "6.9.2-arch1-1\n");
+        std::fs::create_dir_all(root.path().join("usr/lib/modules/6.9.2-arch1-1")).unwrap();
+
+        let status = RebootCheck::with_root(root.path(), Some(root.path()))
+            .run()
+            .await;
+        assert_eq!(status, RebootStatus::default());
+    }
+
+    #[async_std::test]
+    async fn test_debian_reboot_required() {
+        let root = tempdir().unwrap();
+        write(
+            root.path(),
+            "run/reboot-required",
+            "*** System restart required ***\n",
+        );
+        write(
+            root.path(),
+            "run/reboot-required.pkgs",
+            "linux-image-6.8.0-40-generic\nlibc6\n",
+        );
+
+        let status = RebootCheck::with_root(root.path(), Some(root.path()))
+            .run()
+            .await;
+        assert!(status.required);
+        assert_eq!(
+            status.reasons,
+            vec![
+                "linux-image-6.8.0-40-generic was updated",
+                "libc6 was updated"
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_replaced_kernel() {
+        let root = tempdir().unwrap();
+        write(root.path(), "proc/sys/kernel/osrelease", "6.9.1-arch1-1\n");
+        std::fs::create_dir_all(root.path().join("usr/lib/modules/6.9.2-arch1-1")).unwrap();
+
+        let status = RebootCheck::with_root(root.path(), Some(root.path()))
+            .run()
+            .await;
+        assert!(status.required);
+        assert_eq!(
+            status.reasons,
+            vec!["Running kernel 6.9.1-arch1-1 is no longer installed"]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_kernel_check_ignores_missing_modules() {
+        let root = tempdir().unwrap();
+        write(root.path(), "proc/sys/kernel/osrelease", "6.9.1-arch1-1\n");
+
+        let status = RebootCheck::with_root(root.path(), Some(root.path()))
+            .run()
+            .await;
+        assert!(!status.required);
+    }
+
+    #[async_std::test]
+    async fn test_dnf_needs_restarting() {
+        let root = tempdir().unwrap();
+        stub(
+            root.path(),
+            "dnf",
+            "echo 'Core libraries or services have been updated since boot-up:'\n\
+             echo '  * glibc'\n\
+             echo '  * systemd'\n\
+             exit 1",
+        );
+
+        let status = RebootCheck::with_root(root.path(), Some(root.path()))
+            .run()
+            .await;
+        assert!(status.required);
+        assert_eq!(
+            status.reasons,
+            vec!["glibc was updated", "systemd was updated"]
+        );
+    }
+}
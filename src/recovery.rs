@@ -0,0 +1,732 @@
+use anyhow::Result;
+use async_std::{io::WriteExt, process::Command};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{info, warn};
+
+use crate::{
+    environment::{ChildEnv, Pass},
+    history::{History, RunRecord, RunTrigger, SourceResult},
+    preflight::{Blocker, Lock, Preflight},
+    reboot::find_in_path,
+    scheduler::now_secs,
+    updater::{PRIVILEGED_MANAGERS, RunMode, RunRequest},
+};
+
+/// Written when a run starts and removed once it is recorded, so that a run
+/// cut short by a crash or power loss can be detected on the next start.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunMarker {
+    pub started_at: u64,
+    /// Process that performed the run.
+    pub pid: u32,
+    /// Command name of that process, telling it apart from an unrelated
+    /// process that got the same PID later.
+    #[serde(default)]
+    pub command: String,
+    /// Kernel boot ID at the time of the run; a different one means the system restarted.
+    pub boot_id: String,
+    pub trigger: RunTrigger,
+    pub mode: RunMode,
+    pub sources: Vec<String>,
+    /// Commands that release the packages the run holds back, keyed by
+    /// source; a source is removed once its holds are released.
+    #[serde(default)]
+    pub holds: BTreeMap<String, Vec<String>>,
+}
+
+impl RunMarker {
+    /// Returns the default location of the marker file.
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join("uptodate")
+            .join("run-in-progress.toml")
+    }
+
+    /// Creates the marker for `request`, started by this process.
+    pub fn for_request(request: &RunRequest) -> Self {
+        Self {
+            started_at: now_secs(),
+            pid: std::process::id(),
+            command: command(Path::new("/"), "self").unwrap_or_default(),
+            boot_id: boot_id(Path::new("/")).unwrap_or_default(),
+            trigger: request.trigger,
+            mode: request.mode,
+            sources: request.sources.clone(),
+            holds: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the marker, returning `None` if no run is in progress.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub async fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = async_std::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read run marker {:?}: {}", path, e))?;
+
+        toml::from_str(&content)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid TOML in run marker {:?}: {}", path, e))
+    }
+
+    /// Writes the marker, creating the parent directory if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the marker cannot be serialized or written.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            async_std::fs::create_dir_all(parent).await.map_err(|e| {
+                anyhow::anyhow!("Failed to create marker directory {:?}: {}", parent, e)
+            })?;
+        }
+
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize run marker: {}", e))?;
+
+        async_std::fs::write(path, content)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to write run marker {:?}: {}", path, e))
+    }
+
+    /// Writes the marker unless another process has a run in progress, which
+    /// is returned as the blocker instead. The file is created exclusively, so
+    /// two processes starting at the same time cannot both claim it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the marker cannot be serialized or written.
+    pub async fn claim(&self, path: &Path) -> Result<Option<Blocker>> {
+        if let Some(parent) = path.parent() {
+            async_std::fs::create_dir_all(parent).await.map_err(|e| {
+                anyhow::anyhow!("Failed to create marker directory {:?}: {}", parent, e)
+            })?;
+        }
+
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize run marker: {}", e))?;
+        match async_std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await
+        {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes())
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to write run marker {:?}: {}", path, e))?;
+                return Ok(None);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to write run marker {:?}: {}",
+                    path,
+                    e
+                ));
+            }
+        }
+
+        // An unreadable marker or one left by this process cannot block the run
+        match Self::load(path).await.ok().flatten() {
+            Some(other) if other.pid != self.pid && !other.is_stale(Path::new("/")) => {
+                Ok(Some(Blocker::Busy {
+                    pid: other.pid,
+                    sources: other.sources,
+                }))
+            }
+            _ => self.save(path).await.map(|()| None),
+        }
+    }
+
+    /// Removes the marker once the run is over.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the marker exists but cannot be removed.
+    pub async fn remove(path: &Path) -> Result<()> {
+        match async_std::fs::remove_file(path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::anyhow!(
+                "Failed to remove run marker {:?}: {}",
+                path,
+                e
+            )),
+        }
+    }
+
+    /// Records in the marker at `path` that `release` frees the packages the
+    /// run holds back for `source`; `None` once they are released.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no marker or it cannot be read or written.
+    pub async fn set_holds(path: &Path, source: &str, release: Option<Vec<String>>) -> Result<()> {
+        let mut marker = Self::load(path)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No run in progress at {:?}", path))?;
+        match release {
+            Some(release) => marker.holds.insert(source.to_string(), release),
+            None => marker.holds.remove(source),
+        };
+        marker.save(path).await
+    }
+
+    /// Returns the holds the run did not release, with the commands that
+    /// release them.
+    pub fn leftovers(&self) -> Vec<Leftover> {
+        self.holds
+            .iter()
+            .map(|(source, release)| Leftover {
+                source: source.clone(),
+                problem: format!("Packages excluded from the {source} update are still held"),
+                repair: release.clone(),
+                root: PRIVILEGED_MANAGERS.contains(&source.as_str()),
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the run can no longer be in progress: the system was
+    /// restarted since, or the process that performed it is gone, possibly
+    /// replaced by another program with the same PID.
+    ///
+    /// `/proc` is read below `root`.
+    pub fn is_stale(&self, root: &Path) -> bool {
+        if boot_id(root).is_some_and(|current| current != self.boot_id) {
+            return true;
+        }
+        match command(root, &self.pid.to_string()) {
+            Some(command) => !self.command.is_empty() && command != self.command,
+            None => true,
+        }
+    }
+
+    /// Describes the unfinished run as an interrupted history entry.
+    pub fn to_record(&self) -> RunRecord {
+        RunRecord {
+            started_at: self.started_at,
+            finished_at: self.started_at,
+            trigger: self.trigger,
+            mode: self.mode,
+            sources: self
+                .sources
+                .iter()
+                .map(|source| SourceResult {
+                    source: source.clone(),
+                    ..SourceResult::default()
+                })
+                .collect(),
+            errors: vec!["The run did not finish, e.g. because the system lost power".to_string()],
+            interrupted: true,
+            ..RunRecord::default()
+        }
+    }
+
+    /// Takes over the marker of a run that did not finish: it is recorded in
+    /// the history at `history_path` and removed.
+    ///
+    /// Returns `None` if there is no marker or its run is still in progress.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the marker or the history cannot be read or written.
+    pub async fn take_stale(path: &Path, history_path: &Path) -> Result<Option<Self>> {
+        let Some(marker) = Self::load(path).await? else {
+            return Ok(None);
+        };
+        if !marker.is_stale(Path::new("/")) {
+            return Ok(None);
+        }
+
+        warn!(
+            "The run of {} started at {} did not finish",
+            marker.sources.join(", "),
+            marker.started_at
+        );
+        History::append(history_path, marker.to_record()).await?;
+        Self::remove(path).await?;
+        Ok(Some(marker))
+    }
+}
+
+/// Reads the command name of process `pid` below `root`.
+fn command(root: &Path, pid: &str) -> Option<String> {
+    std::fs::read_to_string(root.join("proc").join(pid).join("comm"))
+        .ok()
+        .map(|comm| comm.trim().to_string())
+}
+
+fn boot_id(root: &Path) -> Option<String> {
+    std::fs::read_to_string(root.join("proc/sys/kernel/random/boot_id"))
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// Something an interrupted package manager left behind, with the command
+/// that repairs it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leftover {
+    pub source: String,
+    /// What is wrong, shown to the user.
+    pub problem: String,
+    /// Command that repairs the problem.
+    pub repair: Vec<String>,
+    /// The repair is run as root.
+    pub root: bool,
+}
+
+impl Leftover {
+    fn new(source: &str, problem: impl Into<String>, repair: &[&str]) -> Self {
+        Self {
+            source: source.to_string(),
+            problem: problem.into(),
+            repair: repair.iter().map(|arg| arg.to_string()).collect(),
+            root: true,
+        }
+    }
+
+    /// Returns the repair command as it would be typed in a terminal.
+    pub fn repair_command(&self) -> String {
+        self.repair.join(" ")
+    }
+
+    /// Runs the repair command, through `pkexec` if it needs root, in the same
+    /// environment as updates with `environment` overrides, so it never waits
+    /// for questions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if authorization is denied or the command fails.
+    pub async fn repair(&self, environment: &HashMap<String, String>) -> Result<()> {
+        let env = ChildEnv::new(Pass::Display, &[], environment);
+        let cmd = if self.root {
+            env.elevate(&self.repair)
+        } else {
+            env.wrap(&self.repair)
+        };
+        let mut command = Command::new(&cmd[0]);
+        command.args(&cmd[1..]);
+        env.apply(&mut command);
+        let output = command
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to run {:?}: {}", self.repair_command(), e))?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to run {:?}: {}",
+                self.repair_command(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("Repaired {}: {}", self.source, self.repair_command());
+        Ok(())
+    }
+}
+
+/// Looks for what an interrupted run left behind:
+///
+/// - a stale pacman database lock (`db.lck`) without a running pacman
+/// - an interrupted dpkg, found in `/var/lib/dpkg/updates` or by `dpkg --audit`
+/// - an unfinished transaction in `dnf history` and duplicate packages
+///   reported by `dnf check`
+#[derive(Debug, Clone)]
+pub struct LeftoverScan {
+    root: PathBuf,
+    /// Directory `dpkg` and `dnf` are looked up in; `None` searches `$PATH`.
+    bin_dir: Option<PathBuf>,
+}
+
+impl Default for LeftoverScan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LeftoverScan {
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            bin_dir: None,
+        }
+    }
+
+    /// Checks the filesystem below `root` instead of `/`, looking up binaries in `bin_dir`.
+    pub fn with_root(root: &Path, bin_dir: Option<&Path>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            bin_dir: bin_dir.map(Path::to_path_buf),
+        }
+    }
+
+    /// Returns the leftovers of the package managers of `sources`.
+    pub async fn scan(&self, sources: &[String]) -> Vec<Leftover> {
+        let mut leftovers = Vec::new();
+        for source in sources {
+            match source.as_str() {
+                "paru" => leftovers.extend(self.check_pacman()),
+                "apt" => leftovers.extend(self.check_dpkg().await),
+                "dnf" => leftovers.extend(self.check_dnf().await),
+                _ => {}
+            }
+        }
+        leftovers
+    }
+
+    fn check_pacman(&self) -> Option<Leftover> {
+        let lock = PathBuf::from("/var/lib/pacman/db.lck");
+        Preflight::with_root(&self.root, None).held_lock(&[Lock::File(lock.clone())])?;
+        if self.is_running("pacman") {
+            return None;
+        }
+        Some(Leftover::new(
+            "paru",
+            "The pacman database is still locked",
+            &["rm", "-f", &lock.to_string_lossy()],
+        ))
+    }
+
+    async fn check_dpkg(&self) -> Option<Leftover> {
+        let repair = ["dpkg", "--configure", "-a"];
+        let pending = std::fs::read_dir(self.root.join("var/lib/dpkg/updates"))
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false);
+        if pending {
+            return Some(Leftover::new("apt", "dpkg was interrupted", &repair));
+        }
+
+        // Lists packages that are unpacked or half-configured
+        let audit = self.output("dpkg", &["--audit"]).await?;
+        (!audit.trim().is_empty()).then(|| {
+            Leftover::new(
+                "apt",
+                "dpkg reports packages that are not fully installed",
+                &repair,
+            )
+        })
+    }
+
+    async fn check_dnf(&self) -> Vec<Leftover> {
+        let mut leftovers = Vec::new();
+
+        // dnf keeps its history in /var/lib/dnf; without it there is nothing to finish
+        if self.root.join("var/lib/dnf").is_dir()
+            && let Some(history) = self.output("dnf", &["history", "list"]).await
+            && let Some(id) = unfinished_transaction(&history)
+        {
+            leftovers.push(Leftover::new(
+                "dnf",
+                format!("dnf transaction {id} did not finish"),
+                &["dnf", "history", "redo", &id, "-y"],
+            ));
+        }
+
+        // An interrupted rpm transaction leaves old and new versions installed side by side
+        if let Some(duplicates) = self.output("dnf", &["check", "--duplicates"]).await
+            && !duplicates.trim().is_empty()
+        {
+            leftovers.push(Leftover::new(
+                "dnf",
+                "Old and new versions of some packages are installed",
+                &["dnf", "remove", "--duplicates", "-y"],
+            ));
+        }
+        leftovers
+    }
+
+    /// Returns `true` if a process named `name` is running.
+    fn is_running(&self, name: &str) -> bool {
+        let Ok(entries) = std::fs::read_dir(self.root.join("proc")) else {
+            return false;
+        };
+        entries.flatten().any(|entry| {
+            std::fs::read_to_string(entry.path().join("comm")).is_ok_and(|comm| comm.trim() == name)
+        })
+    }
+
+    /// Runs `name` with `args` and returns its standard output, or `None` if
+    /// the binary is not installed or cannot be run.
+    async fn output(&self, name: &str, args: &[&str]) -> Option<String> {
+        let binary = match &self.bin_dir {
+            Some(dir) => dir.join(name),
+            None => find_in_path(name)?,
+        };
+        if !binary.is_file() {
+            return None;
+        }
+
+        match Command::new(&binary)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await
+        {
+            Ok(output) => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Err(e) => {
+                warn!("Failed to run {} {}: {}", name, args.join(" "), e);
+                None
+            }
+        }
+    }
+}
+
+/// Returns the ID of the latest transaction in `dnf history list` if it never
+/// finished, which dnf flags with `*` in the "Altered" column.
+fn unfinished_transaction(history: &str) -> Option<String> {
+    let latest = history.lines().find_map(|line| {
+        let (id, rest) = line.split_once('|')?;
+        let id = id.trim();
+        id.parse::<u64>().is_ok().then_some((id, rest))
+    })?;
+    let (id, rest) = latest;
+    let altered = rest.rsplit('|').next()?;
+    altered.contains('*').then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{stub, write};
+    use tempfile::tempdir;
+
+    fn marker(pid: u32, boot_id: &str) -> RunMarker {
+        RunMarker {
+            started_at: 1_700_000_000,
+            pid,
+            command: String::new(),
+            boot_id: boot_id.to_string(),
+            trigger: RunTrigger::Scheduled,
+            mode: RunMode::Update,
+            sources: vec!["apt".to_string(), "flatpak".to_string()],
+            holds: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_marker_staleness() {
+        let root = tempdir().unwrap();
+        write(root.path(), "proc/sys/kernel/random/boot_id", "b1\n");
+        write(root.path(), "proc/4242/comm", "uptodate\n");
+
+        assert!(!marker(4242, "b1").is_stale(root.path()));
+        // The process is gone
+        assert!(marker(4243, "b1").is_stale(root.path()));
+        // The system was restarted, even if the PID was reused
+        assert!(marker(4242, "b0").is_stale(root.path()));
+
+        // The PID was reused by another program after a crash
+        write(root.path(), "proc/4242/comm", "bash\n");
+        let mut uptodate = marker(4242, "b1");
+        uptodate.command = "uptodate".to_string();
+        assert!(uptodate.is_stale(root.path()));
+        write(root.path(), "proc/4242/comm", "uptodate\n");
+        assert!(!uptodate.is_stale(root.path()));
+    }
+
+    #[test]
+    fn test_marker_to_record() {
+        let record = marker(1, "b1").to_record();
+        assert!(record.interrupted);
+        assert!(!record.success());
+        assert_eq!(record.trigger, RunTrigger::Scheduled);
+        assert_eq!(record.started_at, 1_700_000_000);
+        assert_eq!(record.sources.len(), 2);
+    }
+
+    #[async_std::test]
+    async fn test_take_stale_marker() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("nested").join("run-in-progress.toml");
+        let history_path = dir.path().join("history.toml");
+
+        assert_eq!(
+            RunMarker::take_stale(&path, &history_path).await.unwrap(),
+            None
+        );
+        RunMarker::remove(&path).await.unwrap();
+
+        // A boot ID that can never match the running system
+        let stale = marker(std::process::id(), "previous boot");
+        stale.save(&path).await.unwrap();
+        assert_eq!(RunMarker::load(&path).await.unwrap(), Some(stale.clone()));
+
+        assert_eq!(
+            RunMarker::take_stale(&path, &history_path).await.unwrap(),
+            Some(stale)
+        );
+        assert!(!path.exists());
+        let history = History::load(&history_path).await.unwrap();
+        assert!(history.last().unwrap().interrupted);
+    }
+
+    #[test]
+    fn test_unfinished_transaction() {
+        let history = "ID | Command line | Date and time | Action(s) | Altered\n\
+                       ------------------------------------------------------\n\
+                       \x20 7 | upgrade | 2024-05-01 12:00 | Upgrade | 3 <\n\
+                       \x20 6 | upgrade | 2024-04-01 12:00 | Upgrade | 5 **\n";
+        assert_eq!(unfinished_transaction(history), None);
+        assert_eq!(
+            unfinished_transaction(&history.replace("3 <", "3 **")),
+            Some("7".to_string())
+        );
+        assert_eq!(unfinished_transaction("No transactions\n"), None);
+    }
+
+    #[async_std::test]
+    async fn test_claim_marker() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("nested").join("run-in-progress.toml");
+        let current = boot_id(Path::new("/")).unwrap_or_default();
+
+        let mine = marker(std::process::id(), &current);
+        assert_eq!(mine.claim(&path).await.unwrap(), None);
+        // A marker left by this process does not block it
+        assert_eq!(mine.claim(&path).await.unwrap(), None);
+
+        // The parent process outlives the test
+        let other = marker(std::os::unix::process::parent_id(), &current);
+        other.save(&path).await.unwrap();
+        let blocker = mine.claim(&path).await.unwrap().unwrap();
+        assert_eq!(
+            blocker.to_string(),
+            format!(
+                "another run (process {}) is updating apt, flatpak",
+                other.pid
+            )
+        );
+        assert_eq!(RunMarker::load(&path).await.unwrap(), Some(other));
+
+        // Its process is gone
+        marker(u32::MAX, &current).save(&path).await.unwrap();
+        assert_eq!(mine.claim(&path).await.unwrap(), None);
+        assert_eq!(RunMarker::load(&path).await.unwrap(), Some(mine));
+    }
+
+    #[async_std::test]
+    async fn test_marker_holds() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("run-in-progress.toml");
+        let release = |args: &[&str]| Some(args.iter().map(|arg| arg.to_string()).collect());
+
+        assert!(RunMarker::set_holds(&path, "apt", None).await.is_err());
+        marker(1, "b1").save(&path).await.unwrap();
+        RunMarker::set_holds(&path, "apt", release(&["apt-mark", "unhold", "firefox"]))
+            .await
+            .unwrap();
+        RunMarker::set_holds(&path, "brew", release(&["brew", "unpin", "node"]))
+            .await
+            .unwrap();
+        RunMarker::set_holds(&path, "brew", None).await.unwrap();
+
+        let leftovers = RunMarker::load(&path).await.unwrap().unwrap().leftovers();
+        assert_eq!(leftovers.len(), 1);
+        assert_eq!(leftovers[0].source, "apt");
+        assert_eq!(leftovers[0].repair_command(), "apt-mark unhold firefox");
+        assert!(leftovers[0].root);
+    }
+
+    #[async_std::test]
+    async fn test_stale_pacman_lock() {
+        let root = tempdir().unwrap();
+        write(root.path(), "var/lib/pacman/db.lck", "");
+        let sources = vec!["paru".to_string()];
+
+        let leftovers = LeftoverScan::with_root(root.path(), Some(root.path()))
+            .scan(&sources)
+            .await;
+        assert_eq!(
+            leftovers,
+            vec![Leftover::new(
+                "paru",
+                "The pacman database is still locked",
+                &["rm", "-f", "/var/lib/pacman/db.lck"]
+            )]
+        );
+        assert_eq!(
+            leftovers[0].repair_command(),
+            "rm -f /var/lib/pacman/db.lck"
+        );
+
+        // The lock belongs to a pacman that is still running
+        write(root.path(), "proc/812/comm", "pacman\n");
+        assert!(
+            LeftoverScan::with_root(root.path(), Some(root.path()))
+                .scan(&sources)
+                .await
+                .is_empty()
+        );
+    }
+
+    #[async_std::test]
+    async fn test_interrupted_dpkg() {
+        let root = tempdir().unwrap();
+        let sources = vec!["apt".to_string()];
+        stub(root.path(), "dpkg", "exit 0");
+        let leftover_scan = LeftoverScan::with_root(root.path(), Some(root.path()));
+        assert!(leftover_scan.scan(&sources).await.is_empty());
+
+        write(root.path(), "var/lib/dpkg/updates/0001", "");
+        let leftovers = leftover_scan.scan(&sources).await;
+        assert_eq!(leftovers[0].problem, "dpkg was interrupted");
+        assert_eq!(leftovers[0].repair_command(), "dpkg --configure -a");
+
+        std::fs::remove_file(root.path().join("var/lib/dpkg/updates/0001")).unwrap();
+        stub(
+            root.path(),
+            "dpkg",
+            "echo 'The following packages are only half configured'\necho ' libc6'",
+        );
+        let leftovers = leftover_scan.scan(&sources).await;
+        assert_eq!(leftovers.len(), 1);
+        assert_eq!(leftovers[0].repair_command(), "dpkg --configure -a");
+    }
+
+    #[async_std::test]
+    async fn test_unfinished_dnf_transaction() {
+        let root = tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("var/lib/dnf")).unwrap();
+        stub(
+            root.path(),
+            "dnf",
+            "case \"$1\" in\n\
+             history) cat <<'EOF'\n\
+             ID | Command line   | Date and time    | Action(s)      | Altered\n\
+             ----------------------------------------------------------------\n\
+             \x20 42 | upgrade -y     | 2024-05-01 12:00 | Upgrade        |   12 **\n\
+             \x20 41 | install vim    | 2024-04-30 09:13 | Install        |    1\n\
+             EOF\n\
+             ;;\n\
+             *) echo 'glibc-2.39-2.fc40.x86_64 is a duplicate with glibc-2.39-1.fc40.x86_64'\n\
+             exit 1 ;;\n\
+             esac",
+        );
+
+        let leftovers = LeftoverScan::with_root(root.path(), Some(root.path()))
+            .scan(&["dnf".to_string(), "flatpak".to_string()])
+            .await;
+        assert_eq!(
+            leftovers
+                .iter()
+                .map(Leftover::repair_command)
+                .collect::<Vec<_>>(),
+            vec!["dnf history redo 42 -y", "dnf remove --duplicates -y"]
+        );
+    }
+}
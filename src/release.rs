@@ -0,0 +1,613 @@
+use anyhow::Result;
+use async_std::{
+    io::{BufReader, prelude::*},
+    process::Command,
+    stream::StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{info, warn};
+
+use crate::reboot::find_in_path;
+
+/// Where openSUSE publishes its Leap releases.
+const LEAP_MIRROR: &str = "https://download.opensuse.org/distribution/leap";
+
+/// Distributions whose upgrades to a new release are detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Distribution {
+    Ubuntu,
+    Fedora,
+    OpensuseLeap,
+}
+
+impl Distribution {
+    /// Returns the source that updates the distribution's packages.
+    pub fn source(&self) -> &'static str {
+        match self {
+            Self::Ubuntu => "apt",
+            Self::Fedora => "dnf",
+            Self::OpensuseLeap => "zypper",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ubuntu => "Ubuntu",
+            Self::Fedora => "Fedora Linux",
+            Self::OpensuseLeap => "openSUSE Leap",
+        }
+    }
+}
+
+/// The fields of `/etc/os-release` that identify the installed release.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OsRelease {
+    pub id: String,
+    pub version_id: String,
+}
+
+impl OsRelease {
+    /// Parses the `KEY=value` lines of an os-release file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::release::OsRelease;
+    ///
+    /// let os = OsRelease::parse("NAME=\"Fedora Linux\"\nID=fedora\nVERSION_ID=40\n");
+    /// assert_eq!(os.id, "fedora");
+    /// assert_eq!(os.version_id, "40");
+    /// ```
+    pub fn parse(content: &str) -> Self {
+        let mut os = Self::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches(['"', '\'']).to_string();
+            match key.trim() {
+                "ID" => os.id = value,
+                "VERSION_ID" => os.version_id = value,
+                _ => {}
+            }
+        }
+        os
+    }
+
+    /// Returns the distribution, if its release upgrades are supported.
+    pub fn distribution(&self) -> Option<Distribution> {
+        match self.id.as_str() {
+            "ubuntu" => Some(Distribution::Ubuntu),
+            "fedora" => Some(Distribution::Fedora),
+            "opensuse-leap" => Some(Distribution::OpensuseLeap),
+            _ => None,
+        }
+    }
+}
+
+/// A newer release the installed distribution can be upgraded to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseUpgrade {
+    pub distribution: Distribution,
+    /// The installed release, e.g. `40`.
+    pub current: String,
+    /// The release to upgrade to, e.g. `41`.
+    pub target: String,
+}
+
+/// What a step of the upgrade does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepAction {
+    /// Commands run as root, one after another.
+    Commands(Vec<Vec<String>>),
+    /// The system is restarted; the upgrade continues or is applied while it boots.
+    Reboot,
+}
+
+/// One step of the guided upgrade, confirmed by the user before it runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpgradeStep {
+    pub title: String,
+    pub description: String,
+    pub action: StepAction,
+    /// The last command restarts the system by itself.
+    pub reboots: bool,
+}
+
+fn own(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+impl UpgradeStep {
+    fn commands(title: &str, description: &str, commands: Vec<Vec<String>>) -> Self {
+        Self {
+            title: title.to_string(),
+            description: description.to_string(),
+            action: StepAction::Commands(commands),
+            reboots: false,
+        }
+    }
+
+    fn reboot(description: &str) -> Self {
+        Self {
+            title: "Restart".to_string(),
+            description: description.to_string(),
+            action: StepAction::Reboot,
+            reboots: true,
+        }
+    }
+
+    /// Runs the commands of the step through pkexec, passing each line they
+    /// print to `on_line`.
+    ///
+    /// Restarting is left to the caller; a [`StepAction::Reboot`] step does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a command cannot be started or fails; later
+    /// commands of the step are not run.
+    pub async fn run(&self, on_line: impl FnMut(&str)) -> Result<()> {
+        self.run_with(&["pkexec"], on_line).await
+    }
+
+    /// Runs the commands prefixed with `launcher`, e.g. `pkexec`.
+    async fn run_with(&self, launcher: &[&str], mut on_line: impl FnMut(&str)) -> Result<()> {
+        let StepAction::Commands(commands) = &self.action else {
+            return Ok(());
+        };
+
+        for cmd in commands {
+            let program = launcher.first().copied().unwrap_or(&cmd[0]);
+            let mut command = Command::new(program);
+            command
+                .args(launcher.iter().skip(1))
+                .args(&cmd[usize::from(launcher.is_empty())..])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            let mut child = command
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("Failed to run {:?}: {}", cmd[0], e))?;
+
+            // stderr is read alongside, so a chatty command cannot block on a full pipe
+            let stderr = child.stderr.take().map(|stderr| {
+                async_std::task::spawn(async move {
+                    let mut lines = Vec::new();
+                    let mut reader = BufReader::new(stderr).lines();
+                    while let Some(Ok(line)) = reader.next().await {
+                        lines.push(line);
+                    }
+                    lines
+                })
+            });
+            if let Some(stdout) = child.stdout.take() {
+                let mut lines = BufReader::new(stdout).lines();
+                while let Some(Ok(line)) = lines.next().await {
+                    on_line(&line);
+                }
+            }
+            let errors = match stderr {
+                Some(task) => task.await,
+                None => Vec::new(),
+            };
+            for line in &errors {
+                on_line(line);
+            }
+
+            let status = child
+                .status()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to wait for {:?}: {}", cmd[0], e))?;
+            if !status.success() {
+                return Err(anyhow::anyhow!(
+                    "{} failed: {}",
+                    cmd.join(" "),
+                    errors.last().map_or("no error output", |line| line.trim())
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReleaseUpgrade {
+    /// Returns the name of the release to upgrade to, e.g. `Fedora Linux 41`.
+    pub fn target_name(&self) -> String {
+        format!("{} {}", self.distribution.name(), self.target)
+    }
+
+    /// Returns the steps of the upgrade, in order.
+    ///
+    /// Pending updates of the installed release come first, since the
+    /// upgrade tools expect an up-to-date system.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::release::{Distribution, ReleaseUpgrade};
+    ///
+    /// let upgrade = ReleaseUpgrade {
+    ///     distribution: Distribution::Fedora,
+    ///     current: "40".to_string(),
+    ///     target: "41".to_string(),
+    /// };
+    /// let steps = upgrade.steps();
+    /// assert_eq!(steps.len(), 3);
+    /// assert!(steps[2].reboots);
+    /// ```
+    pub fn steps(&self) -> Vec<UpgradeStep> {
+        let target = self.target.as_str();
+        let name = self.target_name();
+        match self.distribution {
+            Distribution::Ubuntu => vec![
+                UpgradeStep::commands(
+                    "Install Pending Updates",
+                    "Brings the installed release up to date.",
+                    vec![own(&[
+                        "env",
+                        "DEBIAN_FRONTEND=noninteractive",
+                        "sh",
+                        "-c",
+                        "apt-get update && apt-get full-upgrade -y",
+                    ])],
+                ),
+                UpgradeStep::commands(
+                    &format!("Upgrade to {name}"),
+                    "Downloads and installs the new release. Keep the computer \
+                     connected to power and the network; this can take an hour.",
+                    vec![own(&[
+                        "do-release-upgrade",
+                        "--frontend",
+                        "DistUpgradeViewNonInteractive",
+                    ])],
+                ),
+                UpgradeStep::reboot(&format!("Starts {name}.")),
+            ],
+            Distribution::Fedora => vec![
+                UpgradeStep::commands(
+                    "Install Pending Updates",
+                    "Brings the installed release up to date.",
+                    vec![own(&["dnf", "upgrade", "--refresh", "-y"])],
+                ),
+                UpgradeStep::commands(
+                    &format!("Download {name}"),
+                    "Downloads the packages of the new release. Nothing is \
+                     installed yet, so the system keeps working as before.",
+                    vec![vec![
+                        "dnf".to_string(),
+                        "system-upgrade".to_string(),
+                        "download".to_string(),
+                        format!("--releasever={target}"),
+                        "-y".to_string(),
+                    ]],
+                ),
+                UpgradeStep {
+                    title: "Restart and Upgrade".to_string(),
+                    description: format!(
+                        "Restarts into the offline upgrade, which installs {name} \
+                         and restarts again. Do not turn off the computer meanwhile."
+                    ),
+                    action: StepAction::Commands(vec![own(&["dnf", "system-upgrade", "reboot"])]),
+                    reboots: true,
+                },
+            ],
+            Distribution::OpensuseLeap => {
+                let zypper = |args: &[&str]| {
+                    let mut cmd = own(&["zypper", "--non-interactive", "--releasever", target]);
+                    cmd.extend(own(args));
+                    cmd
+                };
+                vec![
+                    UpgradeStep::commands(
+                        "Install Pending Updates",
+                        "Brings the installed release up to date.",
+                        vec![own(&["zypper", "--non-interactive", "update"])],
+                    ),
+                    UpgradeStep::commands(
+                        &format!("Download {name}"),
+                        "Switches the repositories to the new release and downloads its \
+                         packages. Nothing is installed yet.",
+                        vec![
+                            zypper(&["refresh"]),
+                            zypper(&["dist-upgrade", "--download-only", "--allow-vendor-change"]),
+                        ],
+                    ),
+                    UpgradeStep::commands(
+                        &format!("Install {name}"),
+                        "Installs the downloaded packages. Keep the computer connected \
+                         to power.",
+                        vec![zypper(&["dist-upgrade", "--allow-vendor-change"])],
+                    ),
+                    UpgradeStep::reboot(&format!("Starts {name}.")),
+                ]
+            }
+        }
+    }
+}
+
+/// Detects whether a newer release of the installed distribution is available:
+///
+/// - `do-release-upgrade -c` on Ubuntu, honoring its LTS-only setting
+/// - the next release in dnf's repositories on Fedora, i.e. the target of
+///   `dnf system-upgrade`
+/// - the next minor Leap release on openSUSE's download server
+#[derive(Debug, Clone)]
+pub struct ReleaseCheck {
+    root: PathBuf,
+    /// Directory the tools are looked up in; `None` searches `$PATH`.
+    bin_dir: Option<PathBuf>,
+}
+
+impl Default for ReleaseCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReleaseCheck {
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            bin_dir: None,
+        }
+    }
+
+    /// Reads the os-release file below `root` instead of `/`, looking up
+    /// binaries in `bin_dir`.
+    pub fn with_root(root: &Path, bin_dir: Option<&Path>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            bin_dir: bin_dir.map(Path::to_path_buf),
+        }
+    }
+
+    /// Reads the installed release from `etc/os-release`, falling back to
+    /// `usr/lib/os-release`.
+    pub fn os_release(&self) -> Option<OsRelease> {
+        ["etc/os-release", "usr/lib/os-release"]
+            .iter()
+            .find_map(|path| std::fs::read_to_string(self.root.join(path)).ok())
+            .map(|content| OsRelease::parse(&content))
+    }
+
+    /// Returns the upgrade to the next release, if there is one.
+    ///
+    /// A missing tool or network only means that no upgrade is found.
+    pub async fn run(&self) -> Option<ReleaseUpgrade> {
+        let os = self.os_release()?;
+        let distribution = os.distribution()?;
+        let target = match distribution {
+            Distribution::Ubuntu => self.check_ubuntu().await,
+            Distribution::Fedora => self.check_fedora(&os.version_id).await,
+            Distribution::OpensuseLeap => self.check_leap(&os.version_id).await,
+        }?;
+
+        info!("{} {} is available", distribution.name(), target);
+        Some(ReleaseUpgrade {
+            distribution,
+            current: os.version_id,
+            target,
+        })
+    }
+
+    fn program(&self, name: &str) -> Option<PathBuf> {
+        match &self.bin_dir {
+            Some(dir) => Some(dir.join(name)).filter(|path| path.is_file()),
+            None => find_in_path(name),
+        }
+    }
+
+    /// Runs `name` with `args`, returning its stdout if it succeeded.
+    async fn output(&self, name: &str, args: &[&str]) -> Option<String> {
+        let program = self.program(name)?;
+        match Command::new(&program)
+            .args(args)
+            .env("LC_ALL", crate::environment::NEUTRAL_LOCALE)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await
+        {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to run {}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// `do-release-upgrade -c` prints `New release '24.04.1 LTS' available.`
+    /// and exits successfully only if there is one.
+    async fn check_ubuntu(&self) -> Option<String> {
+        let output = self.output("do-release-upgrade", &["-c"]).await?;
+        output.lines().find_map(|line| {
+            let (_, rest) = line.split_once("New release '")?;
+            let (version, _) = rest.split_once('\'')?;
+            Some(version.to_string())
+        })
+    }
+
+    /// The next release is available once dnf finds its `fedora-release`
+    /// package, which is how `dnf system-upgrade` resolves it as well.
+    async fn check_fedora(&self, version_id: &str) -> Option<String> {
+        let next = version_id.parse::<u32>().ok()? + 1;
+        let releasever = format!("--releasever={next}");
+        let output = self
+            .output(
+                "dnf",
+                &[
+                    "--quiet",
+                    &releasever,
+                    "--repo=fedora",
+                    "repoquery",
+                    "fedora-release-common",
+                ],
+            )
+            .await?;
+        output
+            .lines()
+            .any(|line| line.starts_with("fedora-release-common"))
+            .then(|| next.to_string())
+    }
+
+    /// Leap releases are published on openSUSE's download server. Major
+    /// upgrades, e.g. to Leap 16, need openSUSE's migration tool instead.
+    async fn check_leap(&self, version_id: &str) -> Option<String> {
+        let (major, minor) = version_id.split_once('.')?;
+        let next = format!("{major}.{}", minor.parse::<u32>().ok()? + 1);
+        let url = format!("{LEAP_MIRROR}/{next}/repo/oss/repodata/repomd.xml");
+        self.output(
+            "curl",
+            &["--silent", "--fail", "--head", "--max-time", "30", &url],
+        )
+        .await
+        .map(|_| next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{stub, write};
+    use tempfile::tempdir;
+
+    #[async_std::test]
+    async fn test_ubuntu_new_release() {
+        let root = tempdir().unwrap();
+        write(
+            root.path(),
+            "etc/os-release",
+            "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nID=ubuntu\n",
+        );
+        stub(
+            root.path(),
+            "do-release-upgrade",
+            "[ \"$1\" = -c ] || exit 2\n\
+             echo 'Checking for a new Ubuntu release'\n\
+             echo \"New release '24.04.1 LTS' available.\"\n\
+             echo \"Run 'do-release-upgrade' to upgrade to it.\"",
+        );
+
+        let upgrade = ReleaseCheck::with_root(root.path(), Some(root.path()))
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(upgrade.distribution, Distribution::Ubuntu);
+        assert_eq!(upgrade.current, "22.04");
+        assert_eq!(upgrade.target, "24.04.1 LTS");
+        assert_eq!(upgrade.target_name(), "Ubuntu 24.04.1 LTS");
+    }
+
+    #[async_std::test]
+    async fn test_ubuntu_without_new_release() {
+        let root = tempdir().unwrap();
+        write(
+            root.path(),
+            "etc/os-release",
+            "ID=ubuntu\nVERSION_ID=24.04\n",
+        );
+        stub(
+            root.path(),
+            "do-release-upgrade",
+            "echo 'No new release found.'\nexit 1",
+        );
+
+        let check = ReleaseCheck::with_root(root.path(), Some(root.path()));
+        assert!(check.run().await.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_fedora_next_release() {
+        let root = tempdir().unwrap();
+        write(
+            root.path(),
+            "usr/lib/os-release",
+            "ID=fedora\nVERSION_ID=40\n",
+        );
+        stub(
+            root.path(),
+            "dnf",
+            "[ \"$2\" = --releasever=41 ] || exit 1\n\
+             echo 'fedora-release-common-0:41-25.noarch'",
+        );
+
+        let upgrade = ReleaseCheck::with_root(root.path(), Some(root.path()))
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(upgrade.distribution, Distribution::Fedora);
+        assert_eq!(upgrade.target, "41");
+
+        let steps = upgrade.steps();
+        assert_eq!(
+            steps[1].action,
+            StepAction::Commands(vec![own(&[
+                "dnf",
+                "system-upgrade",
+                "download",
+                "--releasever=41",
+                "-y"
+            ])])
+        );
+    }
+
+    #[async_std::test]
+    async fn test_leap_and_unknown_distributions() {
+        let root = tempdir().unwrap();
+        write(
+            root.path(),
+            "etc/os-release",
+            "ID=\"opensuse-leap\"\nVERSION_ID=\"15.5\"\n",
+        );
+        stub(
+            root.path(),
+            "curl",
+            "echo \"$6\" > \"$(dirname \"$0\")/url\"",
+        );
+
+        let check = ReleaseCheck::with_root(root.path(), Some(root.path()));
+        let upgrade = check.run().await.unwrap();
+        assert_eq!(upgrade.target, "15.6");
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("url")).unwrap(),
+            format!("{LEAP_MIRROR}/15.6/repo/oss/repodata/repomd.xml\n")
+        );
+        assert_eq!(upgrade.steps().len(), 4);
+
+        write(root.path(), "etc/os-release", "ID=arch\n");
+        assert!(check.run().await.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_step_stops_at_failing_command() {
+        let dir = tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let step = UpgradeStep::commands(
+            "Test",
+            "",
+            vec![
+                own(&["sh", "-c", "echo first; echo oops >&2; exit 3"]),
+                vec!["touch".to_string(), marker.display().to_string()],
+            ],
+        );
+
+        let mut lines = Vec::new();
+        let error = step
+            .run_with(&[], |line| lines.push(line.to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(lines, vec!["first", "oops"]);
+        assert!(error.to_string().ends_with("failed: oops"));
+        assert!(!marker.exists());
+
+        assert!(UpgradeStep::reboot("").run_with(&[], |_| {}).await.is_ok());
+    }
+}
@@ -0,0 +1,447 @@
+use anyhow::Result;
+use async_std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::info;
+
+/// Units whose restart would end the graphical session or the service manager itself.
+const SESSION_UNITS: &[&str] = &[
+    "dbus.service",
+    "dbus-broker.service",
+    "display-manager.service",
+    "gdm.service",
+    "sddm.service",
+    "lightdm.service",
+    "systemd-logind.service",
+];
+
+/// A running process that maps shared libraries removed or replaced by an update.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaleProcess {
+    pub pid: u32,
+    pub command: String,
+    /// Paths of the deleted libraries, as listed in `/proc/<pid>/maps`.
+    pub libraries: Vec<String>,
+}
+
+/// Processes using outdated libraries, grouped by the systemd unit they run in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaleService {
+    /// The service the processes belong to; `None` for processes outside a
+    /// service (e.g. applications), which are grouped by command instead.
+    pub unit: Option<String>,
+    /// Whether `unit` is managed by the user's service manager.
+    #[serde(default)]
+    pub user: bool,
+    pub processes: Vec<StaleProcess>,
+}
+
+impl StaleService {
+    /// The unit name, or the command of processes outside a service.
+    pub fn name(&self) -> &str {
+        match &self.unit {
+            Some(unit) => unit,
+            None => self
+                .processes
+                .first()
+                .map_or("unknown", |process| &process.command),
+        }
+    }
+
+    /// The outdated libraries used by any of the processes, without duplicates.
+    pub fn libraries(&self) -> Vec<&str> {
+        let mut libraries: Vec<&str> = Vec::new();
+        for library in self.processes.iter().flat_map(|p| &p.libraries) {
+            if !libraries.contains(&library.as_str()) {
+                libraries.push(library);
+            }
+        }
+        libraries
+    }
+
+    /// Whether the service can be restarted without ending the session.
+    ///
+    /// Applications outside a service have to be restarted by the user.
+    pub fn can_restart(&self) -> bool {
+        match &self.unit {
+            Some(unit) => !SESSION_UNITS.contains(&unit.as_str()) && !unit.starts_with("user@"),
+            None => false,
+        }
+    }
+
+    /// Restarts the unit with `systemctl`, through `pkexec` for system services.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service cannot be restarted safely or `systemctl` fails.
+    pub async fn restart(&self) -> Result<()> {
+        let unit = match &self.unit {
+            Some(unit) if self.can_restart() => unit,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "{} cannot be restarted automatically",
+                    self.name()
+                ));
+            }
+        };
+
+        let mut command = if self.user {
+            let mut command = Command::new("systemctl");
+            command.arg("--user");
+            command
+        } else {
+            let mut command = Command::new("pkexec");
+            command.arg("systemctl");
+            command
+        };
+        let output = command
+            .args(["restart", unit])
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to restart {:?}: {}", unit, e))?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to restart {:?}: {}",
+                unit,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("Restarted {unit}");
+        Ok(())
+    }
+}
+
+/// Finds processes that still use shared libraries deleted or replaced by an
+/// update, by looking for `(deleted)` entries in `/proc/<pid>/maps`.
+///
+/// Without root, only processes the user is allowed to inspect are found.
+#[derive(Debug, Clone)]
+pub struct StaleLibraryScan {
+    proc_root: PathBuf,
+}
+
+impl Default for StaleLibraryScan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StaleLibraryScan {
+    pub fn new() -> Self {
+        Self {
+            proc_root: PathBuf::from("/proc"),
+        }
+    }
+
+    /// Scans the procfs mounted at `proc_root` instead of `/proc`.
+    pub fn with_root(proc_root: &Path) -> Self {
+        Self {
+            proc_root: proc_root.to_path_buf(),
+        }
+    }
+
+    /// Returns the affected processes grouped by service, ordered by name.
+    pub fn scan(&self) -> Vec<StaleService> {
+        let Ok(entries) = std::fs::read_dir(&self.proc_root) else {
+            return Vec::new();
+        };
+
+        let mut pids: Vec<u32> = entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+        pids.sort_unstable();
+
+        let mut services: Vec<StaleService> = Vec::new();
+        for pid in pids {
+            let dir = self.proc_root.join(pid.to_string());
+            // Processes may exit during the scan or belong to other users
+            let Ok(maps) = std::fs::read_to_string(dir.join("maps")) else {
+                continue;
+            };
+            let libraries = parse_deleted_libraries(&maps);
+            if libraries.is_empty() {
+                continue;
+            }
+
+            let command = std::fs::read_to_string(dir.join("comm"))
+                .map(|comm| comm.trim().to_string())
+                .unwrap_or_else(|_| pid.to_string());
+            let (unit, user) = std::fs::read_to_string(dir.join("cgroup"))
+                .map(|cgroup| parse_cgroup_unit(&cgroup))
+                .unwrap_or_default();
+
+            let process = StaleProcess {
+                pid,
+                command,
+                libraries,
+            };
+            let existing = services.iter_mut().find(|service| match &unit {
+                Some(_) => service.unit == unit && service.user == user,
+                None => service.unit.is_none() && service.name() == process.command,
+            });
+            match existing {
+                Some(service) => service.processes.push(process),
+                None => services.push(StaleService {
+                    unit,
+                    user,
+                    processes: vec![process],
+                }),
+            }
+        }
+
+        services.sort_by(|a, b| a.name().cmp(b.name()));
+        services
+    }
+}
+
+/// Extracts the deleted shared libraries from the contents of a `maps` file.
+fn parse_deleted_libraries(maps: &str) -> Vec<String> {
+    let mut libraries: Vec<String> = Vec::new();
+    for line in maps.lines() {
+        let Some(path) = line
+            .find(" /")
+            .and_then(|start| line[start..].trim().strip_suffix(" (deleted)"))
+        else {
+            continue;
+        };
+
+        // Scratch files (e.g. libraries unpacked to /tmp) are deleted on purpose
+        let is_library = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(".so") || name.contains(".so."));
+        let is_scratch = ["/tmp/", "/var/tmp/", "/dev/", "/run/", "/memfd:"]
+            .iter()
+            .any(|prefix| path.starts_with(prefix));
+
+        if is_library && !is_scratch && !libraries.iter().any(|l| l == path) {
+            libraries.push(path.to_string());
+        }
+    }
+    libraries
+}
+
+/// Finds the service a process runs in from its `/proc/<pid>/cgroup` file.
+///
+/// Returns the unit and whether it belongs to a user's service manager;
+/// processes in scopes (e.g. applications started from a session) have no unit.
+fn parse_cgroup_unit(cgroup: &str) -> (Option<String>, bool) {
+    // cgroup v2 ("0::/path") or the systemd hierarchy of cgroup v1
+    let Some(path) = cgroup.lines().find_map(|line| {
+        line.strip_prefix("0::")
+            .or_else(|| line.split_once(":name=systemd:").map(|(_, path)| path))
+    }) else {
+        return (None, false);
+    };
+
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    let user_manager = components
+        .iter()
+        .position(|c| c.starts_with("user@") && c.ends_with(".service"));
+    let (components, user) = match user_manager {
+        Some(index) => (&components[index + 1..], true),
+        None => (&components[..], false),
+    };
+
+    let unit = components
+        .iter()
+        .rev()
+        .find(|c| c.ends_with(".service"))
+        .map(|c| c.to_string());
+    (unit, user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn add_process(root: &Path, pid: u32, comm: &str, cgroup: &str, maps: &str) {
+        let dir = root.join(pid.to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+        std::fs::write(dir.join("cgroup"), cgroup).unwrap();
+        std::fs::write(dir.join("maps"), maps).unwrap();
+    }
+
+    const LIBC_DELETED: &str = "\
+55d0c0a00000-55d0c0a28000 r--p 00000000 fd:01 1048601                    /usr/bin/sshd
+7f2b1c000000-7f2b1c028000 r--p 00000000 fd:01 1050213                    /usr/lib/libc.so.6 (deleted)
+7f2b1c200000-7f2b1c210000 r-xp 00000000 fd:01 1050300                    /usr/lib/libcrypto.so.3
+";
+
+    const SSL_DELETED: &str = "\
+7f2b1c000000-7f2b1c028000 r--p 00000000 fd:01 1050213                    /usr/lib/libc.so.6
+7f2b1c200000-7f2b1c210000 r-xp 00000000 fd:01 1050301                    /usr/lib/libssl.so.3 (deleted)
+7f2b1c210000-7f2b1c220000 r--p 00010000 fd:01 1050301                    /usr/lib/libssl.so.3 (deleted)
+";
+
+    #[test]
+    fn test_parse_deleted_libraries() {
+        let maps = "\
+7f2b1c000000-7f2b1c028000 r--p 00000000 fd:01 1050213   /usr/lib/libc.so.6 (deleted)
+7f2b1c030000-7f2b1c038000 rw-s 00000000 00:01 2048      /memfd:wayland-cursor (deleted)
+7f2b1c040000-7f2b1c048000 r-xp 00000000 00:1f 4096      /tmp/jna-123/libjnidispatch.so (deleted)
+7f2b1c050000-7f2b1c058000 r--p 00000000 fd:01 1050400   /usr/share/icons/cache (deleted)
+7f2b1c060000-7f2b1c068000 r-xp 00000000 fd:01 1050401   /opt/My App/libapp.so (deleted)
+7f2b1c070000-7f2b1c078000 rw-p 00000000 00:00 0         [heap]
+";
+        assert_eq!(
+            parse_deleted_libraries(maps),
+            vec!["/usr/lib/libc.so.6", "/opt/My App/libapp.so"]
+        );
+    }
+
+    #[test]
+    fn test_parse_cgroup_unit() {
+        assert_eq!(
+            parse_cgroup_unit("0::/system.slice/sshd.service\n"),
+            (Some("sshd.service".to_string()), false)
+        );
+        assert_eq!(
+            parse_cgroup_unit(
+                "0::/user.slice/user-1000.slice/user@1000.service/session.slice/pipewire.service\n"
+            ),
+            (Some("pipewire.service".to_string()), true)
+        );
+        assert_eq!(
+            parse_cgroup_unit(
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox-1234.scope\n"
+            ),
+            (None, true)
+        );
+        assert_eq!(
+            parse_cgroup_unit("0::/user.slice/user-1000.slice/session-2.scope\n"),
+            (None, false)
+        );
+        assert_eq!(
+            parse_cgroup_unit(
+                "12:memory:/system.slice/cron.service\n1:name=systemd:/system.slice/cron.service\n"
+            ),
+            (Some("cron.service".to_string()), false)
+        );
+        assert_eq!(parse_cgroup_unit(""), (None, false));
+    }
+
+    #[test]
+    fn test_scan_groups_by_unit() {
+        let root = tempdir().unwrap();
+        add_process(
+            root.path(),
+            812,
+            "sshd",
+            "0::/system.slice/sshd.service\n",
+            LIBC_DELETED,
+        );
+        add_process(
+            root.path(),
+            813,
+            "sshd",
+            "0::/system.slice/sshd.service\n",
+            SSL_DELETED,
+        );
+        add_process(
+            root.path(),
+            2001,
+            "pipewire",
+            "0::/user.slice/user-1000.slice/user@1000.service/session.slice/pipewire.service\n",
+            LIBC_DELETED,
+        );
+        add_process(
+            root.path(),
+            3000,
+            "firefox",
+            "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox.scope\n",
+            SSL_DELETED,
+        );
+        add_process(
+            root.path(),
+            3001,
+            "firefox",
+            "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox.scope\n",
+            LIBC_DELETED,
+        );
+        // Up-to-date process and non-process entries are ignored
+        add_process(
+            root.path(),
+            4000,
+            "bash",
+            "0::/user.slice/user-1000.slice/session-2.scope\n",
+            "7f2b1c000000-7f2b1c028000 r--p 00000000 fd:01 1050213   /usr/lib/libc.so.6\n",
+        );
+        std::fs::create_dir_all(root.path().join("self")).unwrap();
+        std::fs::write(root.path().join("uptime"), "42.0 84.0\n").unwrap();
+
+        let services = StaleLibraryScan::with_root(root.path()).scan();
+        let names: Vec<&str> = services.iter().map(StaleService::name).collect();
+        assert_eq!(names, vec!["firefox", "pipewire.service", "sshd.service"]);
+
+        let firefox = &services[0];
+        assert_eq!(firefox.unit, None);
+        assert!(!firefox.can_restart());
+        assert_eq!(firefox.processes.len(), 2);
+
+        let pipewire = &services[1];
+        assert!(pipewire.user);
+        assert!(pipewire.can_restart());
+
+        let sshd = &services[2];
+        assert!(!sshd.user);
+        assert_eq!(
+            sshd.processes.iter().map(|p| p.pid).collect::<Vec<_>>(),
+            vec![812, 813]
+        );
+        assert_eq!(
+            sshd.libraries(),
+            vec!["/usr/lib/libc.so.6", "/usr/lib/libssl.so.3"]
+        );
+    }
+
+    #[test]
+    fn test_scan_skips_unreadable_processes() {
+        let root = tempdir().unwrap();
+        // A process that exited, or whose maps are not readable
+        std::fs::create_dir_all(root.path().join("77")).unwrap();
+
+        assert!(StaleLibraryScan::with_root(root.path()).scan().is_empty());
+        assert!(
+            StaleLibraryScan::with_root(&root.path().join("missing"))
+                .scan()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_session_units_are_not_restarted() {
+        let service = |unit: &str| StaleService {
+            unit: Some(unit.to_string()),
+            user: false,
+            processes: Vec::new(),
+        };
+        assert!(service("sshd.service").can_restart());
+        assert!(!service("dbus-broker.service").can_restart());
+        assert!(!service("gdm.service").can_restart());
+        assert!(!service("user@1000.service").can_restart());
+    }
+
+    #[async_std::test]
+    async fn test_restart_refuses_processes_outside_units() {
+        let service = StaleService {
+            unit: None,
+            user: true,
+            processes: vec![StaleProcess {
+                pid: 3000,
+                command: "firefox".to_string(),
+                libraries: vec!["/usr/lib/libc.so.6".to_string()],
+            }],
+        };
+        let err = service.restart().await.unwrap_err();
+        assert_eq!(err.to_string(), "firefox cannot be restarted automatically");
+    }
+}
@@ -0,0 +1,593 @@
+use anyhow::Result;
+use async_std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{info, warn};
+
+use crate::environment::{ChildEnv, Pass};
+
+/// Where pacman keeps downloaded packages.
+const PACMAN_CACHE: &str = "/var/cache/pacman/pkg";
+
+/// Whether the last update of a source can be undone, and how well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollbackSupport {
+    /// Undoing works, within the given limits.
+    Available(&'static str),
+    /// Undoing is not possible, for the given reason.
+    Unavailable(&'static str),
+}
+
+impl RollbackSupport {
+    pub fn for_source(source: &str) -> Self {
+        match source {
+            "dnf" => Self::Available(
+                "Undoes the whole transaction; the previous versions must still be \
+                 available in the repositories.",
+            ),
+            "paru" => Self::Available(
+                "Reinstalls previous versions from the package cache; packages removed \
+                 by a cleanup cannot be restored, and newly installed dependencies stay.",
+            ),
+            "flatpak" => Self::Available(
+                "Returns each application and runtime to its previous commit, which \
+                 must still be available on the remote.",
+            ),
+            "snap" => Self::Available(
+                "Reverts to the revision snapd kept, together with the snap's data.",
+            ),
+            "rustup" => Self::Available(
+                "Installs the previous release and makes it the default; run \
+                 `rustup default stable` to follow stable again.",
+            ),
+            "apt" => Self::Unavailable("apt keeps no transaction history to undo."),
+            "zypper" => Self::Unavailable("zypper keeps no transaction history to undo."),
+            _ => Self::Unavailable("This source keeps no earlier versions."),
+        }
+    }
+}
+
+/// A package and the version it had before an update.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Previous {
+    pub name: String,
+    pub version: String,
+}
+
+/// How to undo the update of one source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Rollback {
+    /// Undo the dnf transaction the update created.
+    DnfTransaction { id: u64 },
+    /// Reinstall the previous versions from the pacman cache.
+    PacmanCache { packages: Vec<Previous> },
+    /// Return Flatpak refs to their previous commits.
+    FlatpakCommits { refs: Vec<Previous> },
+    /// Revert snaps to the revision snapd kept.
+    SnapRevert { snaps: Vec<String> },
+    /// Make the previous Rust release the default toolchain.
+    RustupToolchain { version: String },
+}
+
+impl Rollback {
+    /// Compares the inventories taken before and after an update.
+    ///
+    /// Returns `None` if nothing that can be undone changed.
+    pub fn between(before: &Inventory, after: &Inventory) -> Option<Self> {
+        if before.source != after.source {
+            return None;
+        }
+        let changed: Vec<Previous> = before
+            .items
+            .iter()
+            .filter(|(name, version)| after.items.get(*name).is_some_and(|new| new != *version))
+            .map(|(name, version)| Previous {
+                name: name.clone(),
+                version: version.clone(),
+            })
+            .collect();
+
+        let rollback = match before.source.as_str() {
+            "dnf" => {
+                let id = after.items.get("transaction")?.parse().ok()?;
+                (before.items.get("transaction") != after.items.get("transaction"))
+                    .then_some(Self::DnfTransaction { id })?
+            }
+            "paru" => Self::PacmanCache { packages: changed },
+            "flatpak" => Self::FlatpakCommits { refs: changed },
+            "snap" => Self::SnapRevert {
+                snaps: changed.into_iter().map(|p| p.name).collect(),
+            },
+            "rustup" => Self::RustupToolchain {
+                version: changed.into_iter().next()?.version,
+            },
+            _ => return None,
+        };
+        (!rollback.is_empty()).then_some(rollback)
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::PacmanCache { packages: items } | Self::FlatpakCommits { refs: items } => {
+                items.is_empty()
+            }
+            Self::SnapRevert { snaps } => snaps.is_empty(),
+            Self::DnfTransaction { .. } | Self::RustupToolchain { .. } => false,
+        }
+    }
+
+    /// Describes what undoing does.
+    pub fn description(&self) -> String {
+        let names = |items: &[Previous]| {
+            items
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Self::DnfTransaction { id } => format!("Undo dnf transaction {id}"),
+            Self::PacmanCache { packages } => format!(
+                "Reinstall the previous versions of {} package(s): {}",
+                packages.len(),
+                names(packages)
+            ),
+            Self::FlatpakCommits { refs } => {
+                format!(
+                    "Return {} ref(s) to their previous commit: {}",
+                    refs.len(),
+                    names(refs)
+                )
+            }
+            Self::SnapRevert { snaps } => format!("Revert {}", snaps.join(", ")),
+            Self::RustupToolchain { version } => format!("Switch back to Rust {version}"),
+        }
+    }
+
+    /// Returns `true` if undoing needs administrator rights.
+    pub fn needs_root(&self) -> bool {
+        // Flatpak asks for authorization itself, and rustup works in the home directory
+        matches!(
+            self,
+            Self::DnfTransaction { .. } | Self::PacmanCache { .. } | Self::SnapRevert { .. }
+        )
+    }
+
+    /// Builds the commands that undo the update, in order.
+    ///
+    /// Previous pacman packages are looked up in `caches`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a previous package is no longer cached or a name
+    /// could be mistaken for an option.
+    pub fn commands(&self, caches: &[PathBuf]) -> Result<Vec<Vec<String>>> {
+        let own = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let commands = match self {
+            Self::DnfTransaction { id } => {
+                vec![own(&["dnf", "history", "undo", &id.to_string(), "-y"])]
+            }
+            Self::PacmanCache { packages } => {
+                let mut cmd = own(&["pacman", "-U", "--noconfirm", "--"]);
+                let mut missing = Vec::new();
+                for package in packages {
+                    match cached_package(caches, package) {
+                        Some(path) => cmd.push(path.to_string_lossy().into_owned()),
+                        None => missing.push(format!("{} {}", package.name, package.version)),
+                    }
+                }
+                if !missing.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Previous versions are no longer cached: {}",
+                        missing.join(", ")
+                    ));
+                }
+                vec![cmd]
+            }
+            Self::FlatpakCommits { refs } => refs
+                .iter()
+                .map(|previous| {
+                    own(&[
+                        "flatpak",
+                        "update",
+                        "-y",
+                        &format!("--commit={}", previous.version),
+                        &previous.name,
+                    ])
+                })
+                .collect(),
+            Self::SnapRevert { snaps } => snaps
+                .iter()
+                .map(|snap| own(&["snap", "revert", snap]))
+                .collect(),
+            Self::RustupToolchain { version } => vec![
+                own(&["rustup", "toolchain", "install", version]),
+                own(&["rustup", "default", version]),
+            ],
+        };
+
+        for arg in commands.iter().flat_map(|cmd| cmd.iter().skip(1)) {
+            if arg.starts_with('-') && !arg.starts_with("--commit=") && !is_flag(arg) {
+                return Err(anyhow::anyhow!("Invalid rollback argument: {:?}", arg));
+            }
+        }
+        Ok(commands)
+    }
+
+    /// Undoes the update, asking for authorization through `pkexec` where
+    /// needed. The commands run in the child environment with `environment`
+    /// overrides, like updates do.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the commands cannot be built, authorization is
+    /// denied or a command fails.
+    pub async fn apply(&self, environment: &HashMap<String, String>) -> Result<()> {
+        let mut caches = vec![PathBuf::from(PACMAN_CACHE)];
+        if let Some(cache) = dirs::cache_dir() {
+            // paru keeps the packages it built in their clone directories
+            caches.push(cache.join("paru").join("clone"));
+        }
+
+        let env = ChildEnv::new(Pass::Display, &[], environment);
+        for cmd in self.commands(&caches)? {
+            let launched = if self.needs_root() {
+                env.elevate(&cmd)
+            } else {
+                cmd.clone()
+            };
+            let mut command = Command::new(&launched[0]);
+            command.args(&launched[1..]);
+            env.apply(&mut command);
+            let output = command
+                .stdin(Stdio::null())
+                .output()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to run {:?}: {}", cmd[0], e))?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "{} failed: {}",
+                    cmd.join(" "),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+        }
+        info!("{}", self.description());
+        Ok(())
+    }
+}
+
+fn is_flag(arg: &str) -> bool {
+    ["-U", "--noconfirm", "--", "-y"].contains(&arg)
+}
+
+/// Finds the cached package file of `previous` in `caches` or their
+/// per-package subdirectories.
+fn cached_package(caches: &[PathBuf], previous: &Previous) -> Option<PathBuf> {
+    let prefix = format!("{}-{}-", previous.name, previous.version);
+    let matches = |dir: &Path| {
+        std::fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+            let name = entry.file_name();
+            let name = name.to_str()?;
+            (name.starts_with(&prefix) && name.contains(".pkg.tar") && !name.ends_with(".sig"))
+                .then(|| entry.path())
+        })
+    };
+    caches
+        .iter()
+        .find_map(|cache| matches(cache).or_else(|| matches(&cache.join(&previous.name))))
+}
+
+/// What a package manager has installed, as far as needed to undo an update.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inventory {
+    source: String,
+    /// Versions, commits or revisions keyed by name.
+    items: BTreeMap<String, String>,
+}
+
+impl Inventory {
+    /// Returns the command listing what `source` has installed.
+    fn command(source: &str) -> Option<&'static [&'static str]> {
+        match source {
+            "paru" => Some(&["pacman", "-Q"]),
+            "dnf" => Some(&["dnf", "history", "list"]),
+            // The active column is shortened; the commits are read with `flatpak info`
+            "flatpak" => Some(&["flatpak", "list", "--columns=ref"]),
+            "snap" => Some(&["snap", "list"]),
+            "rustup" => Some(&["rustc", "--version"]),
+            _ => None,
+        }
+    }
+
+    /// Lists what `source` has installed; `None` if the source cannot be
+    /// rolled back or the listing fails.
+    pub async fn capture(source: &str, environment: &HashMap<String, String>) -> Option<Self> {
+        let env = ChildEnv::new(Pass::Parse, &[], environment);
+        let output = Self::list(source, Self::command(source)?, &env).await?;
+        if source != "flatpak" {
+            return Some(Self::parse(source, &output));
+        }
+
+        // `flatpak update --commit` needs the full checksum of each ref
+        let mut listing = String::new();
+        for reference in output.lines().map(str::trim).filter(|l| l.contains('/')) {
+            let commit = Self::list(
+                source,
+                &["flatpak", "info", "--show-commit", reference],
+                &env,
+            )
+            .await?;
+            listing.push_str(&format!("{reference}\t{}\n", commit.trim()));
+        }
+        Some(Self::parse(source, &listing))
+    }
+
+    /// Runs `cmd`, returning its output if it succeeded.
+    async fn list(source: &str, cmd: &[&str], env: &ChildEnv) -> Option<String> {
+        let mut command = Command::new(cmd[0]);
+        command
+            .args(&cmd[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null());
+        env.apply(&mut command);
+        match command.output().await {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            Ok(output) => {
+                warn!("Listing {} for rollback failed: {}", source, output.status);
+                None
+            }
+            Err(e) => {
+                warn!("Failed to list {} for rollback: {}", source, e);
+                None
+            }
+        }
+    }
+
+    /// Parses the output of the listing command of `source`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::rollback::{Inventory, Rollback};
+    ///
+    /// let before = Inventory::parse("paru", "linux 6.9.1-1\nmesa 24.1.0-1\n");
+    /// let after = Inventory::parse("paru", "linux 6.9.2-1\nmesa 24.1.0-1\n");
+    /// let rollback = Rollback::between(&before, &after).unwrap();
+    /// assert!(rollback.description().ends_with("linux"));
+    /// ```
+    pub fn parse(source: &str, output: &str) -> Self {
+        let lines = output.lines().map(str::trim).filter(|l| !l.is_empty());
+        let pair = |name: &str, version: &str| (name.to_string(), version.to_string());
+
+        let items = match source {
+            "paru" => lines
+                .filter_map(|line| {
+                    let mut parts = line.split_whitespace();
+                    Some(pair(parts.next()?, parts.next()?))
+                })
+                .collect(),
+            // Transactions are listed newest first, in a table for dnf 4
+            "dnf" => lines
+                .filter_map(|line| line.split([' ', '|']).find(|part| !part.is_empty()))
+                .find(|id| id.chars().all(|c| c.is_ascii_digit()))
+                .map(|id| pair("transaction", id))
+                .into_iter()
+                .collect(),
+            "flatpak" => lines
+                .filter_map(|line| {
+                    let mut cols = line.split('\t').map(str::trim);
+                    let reference = cols.next().filter(|r| r.contains('/'))?;
+                    Some(pair(reference, cols.next().filter(|c| !c.is_empty())?))
+                })
+                .collect(),
+            "snap" => lines
+                .skip_while(|line| line.starts_with("Name "))
+                .filter_map(|line| {
+                    let parts: Vec<&str> = line.split_whitespace().collect();
+                    Some(pair(parts.first()?, parts.get(2)?))
+                })
+                .collect(),
+            "rustup" => lines
+                .filter_map(|line| Some(pair("rustc", line.split_whitespace().nth(1)?)))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        Self {
+            source: source.to_string(),
+            items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::stub;
+    use tempfile::tempdir;
+
+    fn between(source: &str, before: &str, after: &str) -> Option<Rollback> {
+        Rollback::between(
+            &Inventory::parse(source, before),
+            &Inventory::parse(source, after),
+        )
+    }
+
+    #[test]
+    fn test_dnf_transaction() {
+        let before = "ID     | Command line             | Date and time    | Action(s)      | Altered\n\
+                      -------------------------------------------------------------------------------\n\
+                      41 | install vim              | 2024-05-01 10:00 | Install        |    1\n";
+        let after = "ID     | Command line             | Date and time    | Action(s)      | Altered\n\
+                     -------------------------------------------------------------------------------\n\
+                     42 | upgrade -y               | 2024-05-10 12:00 | Upgrade        |   12\n\
+                     41 | install vim              | 2024-05-01 10:00 | Install        |    1\n";
+        assert_eq!(
+            between("dnf", before, after),
+            Some(Rollback::DnfTransaction { id: 42 })
+        );
+        assert_eq!(between("dnf", before, before), None);
+
+        // dnf 5 prints no table borders
+        let after = "ID Command line Date and time Action(s) Altered\n43 dnf upgrade -y 2024-06-01 12:00:00 3\n";
+        assert_eq!(
+            between("dnf", before, after),
+            Some(Rollback::DnfTransaction { id: 43 })
+        );
+    }
+
+    #[test]
+    fn test_changed_versions() {
+        let rollback = between(
+            "flatpak",
+            "org.gnome.Maps/x86_64/stable\tabc123\norg.gnome.Platform/x86_64/46\tdef456\n",
+            "org.gnome.Maps/x86_64/stable\tabc999\norg.gnome.Platform/x86_64/46\tdef456\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rollback.commands(&[]).unwrap(),
+            vec![vec![
+                "flatpak",
+                "update",
+                "-y",
+                "--commit=abc123",
+                "org.gnome.Maps/x86_64/stable"
+            ]]
+        );
+        assert!(!rollback.needs_root());
+
+        let snap = "Name Version Rev Tracking Publisher Notes\n";
+        let rollback = between(
+            "snap",
+            &format!("{snap}firefox 126.0 4336 latest/stable mozilla✓ -\n"),
+            &format!("{snap}firefox 127.0 4451 latest/stable mozilla✓ -\n"),
+        );
+        assert_eq!(
+            rollback,
+            Some(Rollback::SnapRevert {
+                snaps: vec!["firefox".to_string()]
+            })
+        );
+
+        let rollback = between(
+            "rustup",
+            "rustc 1.79.0 (129f3b996 2024-06-10)\n",
+            "rustc 1.80.0 (051478957 2024-07-21)\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rollback.commands(&[]).unwrap(),
+            vec![
+                vec!["rustup", "toolchain", "install", "1.79.0"],
+                vec!["rustup", "default", "1.79.0"]
+            ]
+        );
+
+        assert_eq!(between("paru", "linux 6.9.1-1\n", "linux 6.9.1-1\n"), None);
+        assert_eq!(between("apt", "", ""), None);
+    }
+
+    #[async_std::test]
+    async fn test_flatpak_inventory_has_full_commits() {
+        let maps = "a".repeat(63) + "1";
+        let platform = "b".repeat(63) + "2";
+        let bin = tempdir().unwrap();
+        stub(
+            bin.path(),
+            "flatpak",
+            &format!(
+                "case \"$1 $3\" in\n\
+                 'list ') printf 'org.gnome.Maps/x86_64/stable\\norg.gnome.Platform/x86_64/46\\n' ;;\n\
+                 'info org.gnome.Maps/x86_64/stable') echo {maps} ;;\n\
+                 'info org.gnome.Platform/x86_64/46') echo {platform} ;;\n\
+                 *) exit 1 ;;\n\
+                 esac"
+            ),
+        );
+        let environment = HashMap::from([(
+            "PATH".to_string(),
+            format!("{}:/usr/bin:/bin", bin.path().display()),
+        )]);
+
+        let before = Inventory::capture("flatpak", &environment).await.unwrap();
+        assert_eq!(before.items["org.gnome.Maps/x86_64/stable"], maps);
+        assert_eq!(before.items["org.gnome.Platform/x86_64/46"], platform);
+
+        let after = Inventory::parse(
+            "flatpak",
+            &format!(
+                "org.gnome.Maps/x86_64/stable\t{}\norg.gnome.Platform/x86_64/46\t{platform}\n",
+                "c".repeat(64)
+            ),
+        );
+        let rollback = Rollback::between(&before, &after).unwrap();
+        assert_eq!(
+            rollback.commands(&[]).unwrap()[0][3],
+            format!("--commit={maps}")
+        );
+    }
+
+    #[test]
+    fn test_pacman_cache_lookup() {
+        let cache = tempdir().unwrap();
+        let paru = tempdir().unwrap();
+        for name in [
+            "linux-6.9.1.arch1-1-x86_64.pkg.tar.zst",
+            "linux-6.9.1.arch1-1-x86_64.pkg.tar.zst.sig",
+            "linux-headers-6.9.1.arch1-1-x86_64.pkg.tar.zst",
+        ] {
+            std::fs::write(cache.path().join(name), "").unwrap();
+        }
+        std::fs::create_dir(paru.path().join("yay-bin")).unwrap();
+        std::fs::write(
+            paru.path()
+                .join("yay-bin/yay-bin-12.3.5-1-x86_64.pkg.tar.zst"),
+            "",
+        )
+        .unwrap();
+        let caches = [cache.path().to_path_buf(), paru.path().to_path_buf()];
+
+        let rollback = between(
+            "paru",
+            "linux 6.9.1.arch1-1\nyay-bin 12.3.5-1\n",
+            "linux 6.9.2.arch1-1\nyay-bin 12.4.0-1\n",
+        )
+        .unwrap();
+        let commands = rollback.commands(&caches).unwrap();
+        assert_eq!(commands[0][..4], ["pacman", "-U", "--noconfirm", "--"]);
+        assert!(commands[0][4].ends_with("linux-6.9.1.arch1-1-x86_64.pkg.tar.zst"));
+        assert!(commands[0][5].ends_with("yay-bin/yay-bin-12.3.5-1-x86_64.pkg.tar.zst"));
+        assert!(rollback.needs_root());
+
+        let gone = between("paru", "mesa 24.1.0-1\n", "mesa 24.1.1-1\n").unwrap();
+        let error = gone.commands(&caches).unwrap_err().to_string();
+        assert!(error.contains("mesa 24.1.0-1"));
+    }
+
+    #[test]
+    fn test_rejects_option_like_names() {
+        let rollback = Rollback::SnapRevert {
+            snaps: vec!["--purge".to_string()],
+        };
+        assert!(rollback.commands(&[]).is_err());
+    }
+
+    #[test]
+    fn test_support() {
+        assert!(matches!(
+            RollbackSupport::for_source("dnf"),
+            RollbackSupport::Available(_)
+        ));
+        assert!(matches!(
+            RollbackSupport::for_source("apt"),
+            RollbackSupport::Unavailable(_)
+        ));
+    }
+}
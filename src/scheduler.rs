@@ -0,0 +1,992 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+use crate::{
+    AppState,
+    advisories::SecuritySummary,
+    conditions::{Conditions, Deferral, Phase},
+    config::{Config, SourcePolicy},
+    history::{RunRecord, RunRecorder, RunTrigger},
+    pending::{PendingUpdate, mark_critical, mark_held},
+    updater::{ATTENDED_MANAGERS, RunMode, RunRequest, UpdateEvent, Updater},
+};
+
+/// Persisted state of the background check scheduler.
+///
+/// Stored separately from [`Config`](crate::config::Config) because it is
+/// written by the application rather than edited by the user.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleState {
+    /// Unix timestamp (seconds) of the last completed check.
+    pub last_check: Option<u64>,
+    /// Updates found by the last completed check.
+    pub pending: Vec<PendingUpdate>,
+    /// Unix timestamp (seconds) until which update reminders are suppressed.
+    pub snoozed_until: Option<u64>,
+    /// Scheduled checks and updates postponed by network or power conditions.
+    pub deferrals: Vec<Deferral>,
+}
+
+/// Result of a single scheduled check.
+///
+/// Counts only include sources the user wants to be notified about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckOutcome {
+    pub previous_count: usize,
+    pub count: usize,
+    /// Whether reminders were snoozed when the check finished.
+    pub snoozed: bool,
+    /// The counted updates that fix security issues.
+    pub security: SecuritySummary,
+}
+
+impl CheckOutcome {
+    /// Returns `true` if the number of pending updates differs from the previous check.
+    pub fn changed(&self) -> bool {
+        self.previous_count != self.count
+    }
+
+    /// Returns `true` if the user should be told about the result of this check.
+    ///
+    /// Urgent security fixes are announced even while reminders are snoozed.
+    pub fn should_notify(&self) -> bool {
+        self.changed() && (!self.snoozed || self.security.highest.is_some_and(|s| s.is_urgent()))
+    }
+}
+
+/// Returns the current time as seconds since the Unix epoch.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl ScheduleState {
+    /// Returns the default location of the scheduler state file.
+    pub fn default_path() -> PathBuf {
+        dirs::state_dir()
+            .or_else(dirs::data_dir)
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join("uptodate")
+            .join("schedule.toml")
+    }
+
+    /// Loads the scheduler state, falling back to an empty state if the file is missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub async fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = async_std::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read schedule state {:?}: {}", path, e))?;
+
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid TOML in schedule state {:?}: {}", path, e))
+    }
+
+    /// Writes the scheduler state to disk, creating the parent directory if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state cannot be serialized or written.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            async_std::fs::create_dir_all(parent).await.map_err(|e| {
+                anyhow::anyhow!("Failed to create state directory {:?}: {}", parent, e)
+            })?;
+        }
+
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize schedule state: {}", e))?;
+
+        async_std::fs::write(path, content)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to write schedule state {:?}: {}", path, e))
+    }
+
+    /// Returns `true` if at least `interval_hours` have passed since the last check.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::scheduler::ScheduleState;
+    ///
+    /// let mut state = ScheduleState::default();
+    /// assert!(state.is_due(1_000, 24)); // Never checked
+    ///
+    /// state.last_check = Some(1_000);
+    /// assert!(!state.is_due(1_000 + 3_600, 24));
+    /// assert!(state.is_due(1_000 + 24 * 3_600, 24));
+    /// ```
+    pub fn is_due(&self, now: u64, interval_hours: u64) -> bool {
+        self.last_check.is_none_or(|last| {
+            now.saturating_sub(last) >= interval_hours.max(1).saturating_mul(3600)
+        })
+    }
+
+    /// Returns the number of cached updates that are not held back.
+    pub fn pending_count(&self) -> usize {
+        self.pending.iter().filter(|update| !update.held).count()
+    }
+
+    /// Summarizes the cached security fixes that are not held back.
+    pub fn security(&self) -> SecuritySummary {
+        SecuritySummary::of(self.pending.iter().filter(|update| !update.held))
+    }
+
+    /// Returns the cached critical updates that `request` would install, by source.
+    ///
+    /// Targeted sources only count their selected packages and security-only
+    /// runs only the security fixes. Dry runs and cleanups install nothing.
+    pub fn critical_for(&self, request: &RunRequest) -> HashMap<String, Vec<String>> {
+        let mut critical: HashMap<String, Vec<String>> = HashMap::new();
+        if request.dry_run || !request.mode.updates() {
+            return critical;
+        }
+
+        for update in self.pending.iter().filter(|update| {
+            update.critical
+                && !update.held
+                && request.sources.contains(&update.source)
+                && request
+                    .packages
+                    .get(&update.source)
+                    .is_none_or(|selected| selected.contains(&update.package))
+                && (request.mode != RunMode::SecurityOnly || update.advisory.is_some())
+        }) {
+            critical
+                .entry(update.source.clone())
+                .or_default()
+                .push(update.package.clone());
+        }
+        critical
+    }
+
+    /// Returns `true` if update reminders are suppressed at `now`.
+    pub fn is_snoozed(&self, now: u64) -> bool {
+        self.snoozed_until.is_some_and(|until| now < until)
+    }
+
+    /// Returns the sources with cached updates that are not held back, in the
+    /// order they were found.
+    pub fn pending_sources(&self) -> Vec<String> {
+        let mut sources: Vec<String> = Vec::new();
+        for update in self.pending.iter().filter(|update| !update.held) {
+            if !sources.contains(&update.source) {
+                sources.push(update.source.clone());
+            }
+        }
+        sources
+    }
+
+    /// Replaces the deferrals of `phase`, keeping the time a source was first
+    /// deferred for the same reason.
+    ///
+    /// Returns `true` if the recorded deferrals changed.
+    pub fn record_deferrals(&mut self, phase: Phase, deferrals: Vec<Deferral>) -> bool {
+        let previous: Vec<Deferral> = self
+            .deferrals
+            .iter()
+            .filter(|deferral| deferral.phase == phase)
+            .cloned()
+            .collect();
+        let deferrals: Vec<Deferral> = deferrals
+            .into_iter()
+            .map(|mut deferral| {
+                if let Some(earlier) = previous
+                    .iter()
+                    .find(|p| p.source == deferral.source && p.reason == deferral.reason)
+                {
+                    deferral.since = earlier.since;
+                }
+                deferral
+            })
+            .collect();
+        if deferrals == previous {
+            return false;
+        }
+
+        for deferral in &deferrals {
+            info!(
+                "Deferred {:?} of {}: {}",
+                deferral.phase, deferral.source, deferral.reason
+            );
+        }
+        self.deferrals.retain(|deferral| deferral.phase != phase);
+        self.deferrals.extend(deferrals);
+        true
+    }
+
+    /// Returns `true` if automatic updates are waiting for better conditions.
+    pub fn has_deferred_updates(&self) -> bool {
+        self.deferrals
+            .iter()
+            .any(|deferral| deferral.phase == Phase::Update)
+    }
+
+    /// Drops the cached updates that `record` applied successfully.
+    ///
+    /// A source updated as a whole drops all its updates, a targeted update
+    /// only the selected packages and a security-only update only the
+    /// security fixes. Held updates are kept since the run skipped them. Dry
+    /// runs and cleanup-only runs do not change anything.
+    pub fn forget_updated(&mut self, record: &RunRecord) {
+        if record.dry_run || !record.mode.updates() {
+            return;
+        }
+
+        self.pending.retain(|update| {
+            update.held
+                || !record.sources.iter().any(|result| {
+                    result.success
+                        && result.source == update.source
+                        && (result.packages.is_empty() || result.packages.contains(&update.package))
+                        && (record.mode != RunMode::SecurityOnly || update.advisory.is_some())
+                })
+        });
+    }
+}
+
+/// Builds a run that installs only the cached updates fixing security issues.
+///
+/// Sources with a security-only command use it; the others get a targeted
+/// update of their flagged packages. Sources that support neither are
+/// returned separately so the user can be told to update them as a whole.
+pub fn security_request(schedule: &ScheduleState, updater: &Updater) -> (RunRequest, Vec<String>) {
+    let mut request = RunRequest {
+        mode: RunMode::SecurityOnly,
+        trigger: RunTrigger::Manual,
+        ..RunRequest::default()
+    };
+    let mut unsupported: Vec<String> = Vec::new();
+
+    for update in schedule
+        .pending
+        .iter()
+        .filter(|update| !update.held && update.advisory.is_some())
+    {
+        let source = &update.source;
+        let Some(manager) = updater.get_manager_info(source) else {
+            continue;
+        };
+        if manager.supports_security_only() {
+            // The manager picks the security fixes itself
+        } else if manager.targeted_blocked_reason().is_none() {
+            request
+                .packages
+                .entry(source.clone())
+                .or_default()
+                .push(update.package.clone());
+        } else {
+            if !unsupported.contains(source) {
+                unsupported.push(source.clone());
+            }
+            continue;
+        }
+        if !request.sources.contains(source) {
+            request.sources.push(source.clone());
+        }
+    }
+    (request, unsupported)
+}
+
+/// Updates the cached pending list after a finished run.
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or written.
+pub async fn apply_run(path: &Path, record: &RunRecord) -> Result<ScheduleState> {
+    let mut schedule = ScheduleState::load(path).await?;
+    schedule.forget_updated(record);
+    schedule.save(path).await?;
+    Ok(schedule)
+}
+
+/// Suppresses update reminders for `hours` from now.
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or written.
+pub async fn snooze(path: &Path, hours: u64) -> Result<()> {
+    let mut schedule = ScheduleState::load(path).await?;
+    schedule.snoozed_until = Some(now_secs().saturating_add(hours.saturating_mul(3600)));
+    schedule.save(path).await?;
+
+    info!("Snoozed update reminders for {} hours", hours);
+    Ok(())
+}
+
+/// Counts the pending updates of sources with the [`SourcePolicy::NotifyOnly`] policy.
+///
+/// Held packages are never counted.
+pub fn notifiable_count(pending: &[PendingUpdate], config: &Config) -> usize {
+    notifiable(pending, config).count()
+}
+
+/// Summarizes the security fixes among the updates [`notifiable_count`] counts.
+pub fn notifiable_security(pending: &[PendingUpdate], config: &Config) -> SecuritySummary {
+    SecuritySummary::of(notifiable(pending, config))
+}
+
+fn notifiable<'a>(
+    pending: &'a [PendingUpdate],
+    config: &'a Config,
+) -> impl Iterator<Item = &'a PendingUpdate> {
+    pending.iter().filter(|update| {
+        !update.held && config.source_policy(&update.source) == SourcePolicy::NotifyOnly
+    })
+}
+
+/// Re-evaluates which cached updates are held or critical after the
+/// exclusions or critical packages in `config` changed.
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or written.
+pub async fn refresh_held(path: &Path, config: &Config) -> Result<ScheduleState> {
+    let mut schedule = ScheduleState::load(path).await?;
+    mark_held(&mut schedule.pending, config);
+    mark_critical(&mut schedule.pending, config);
+    schedule.save(path).await?;
+    Ok(schedule)
+}
+
+/// Runs the check phase for all enabled, non-manual sources and persists the result.
+///
+/// Sources that `conditions` do not allow to check keep their earlier pending
+/// updates. If every source is deferred, the check is not recorded as done so
+/// that it is retried on the next tick.
+///
+/// # Errors
+///
+/// Returns an error if source detection or the check itself fails, or if the
+/// state file cannot be read or written.
+pub async fn run_check(
+    state: &AppState,
+    path: &Path,
+    conditions: &Conditions,
+) -> Result<CheckOutcome> {
+    let mut schedule = ScheduleState::load(path).await?;
+    let config = state.config.read().await.clone();
+    let now = now_secs();
+
+    let sources = checked_sources(state, &config, false).await?;
+    let (sources, deferred) = conditions.partition(&config, sources, Phase::Check, now);
+    let deferred_sources: Vec<String> = deferred.iter().map(|d| d.source.clone()).collect();
+    schedule.record_deferrals(Phase::Check, deferred);
+
+    let previous_count = notifiable_count(&schedule.pending, &config);
+    if sources.is_empty() && !deferred_sources.is_empty() {
+        // Not marked as checked, so the check is retried once conditions change
+        schedule.save(path).await?;
+        info!("Background check deferred");
+        return Ok(CheckOutcome {
+            previous_count,
+            count: previous_count,
+            snoozed: schedule.is_snoozed(now),
+            security: notifiable_security(&schedule.pending, &config),
+        });
+    }
+
+    let mut pending = state
+        .updater
+        .check_pending(&sources, &config.child_environment)
+        .await?;
+    // Deferred sources keep the updates found by an earlier check
+    pending.extend(
+        schedule
+            .pending
+            .iter()
+            .filter(|update| deferred_sources.contains(&update.source))
+            .cloned(),
+    );
+    mark_held(&mut pending, &config);
+    mark_critical(&mut pending, &config);
+    let outcome = CheckOutcome {
+        previous_count,
+        count: notifiable_count(&pending, &config),
+        snoozed: schedule.is_snoozed(now),
+        security: notifiable_security(&pending, &config),
+    };
+
+    schedule.last_check = Some(now);
+    schedule.pending = pending;
+    schedule.save(path).await?;
+
+    info!(
+        "Background check finished: {} pending updates (previously {})",
+        outcome.count, outcome.previous_count
+    );
+    Ok(outcome)
+}
+
+/// Checks every enabled source, including those with the
+/// [`SourcePolicy::Manual`] policy, and persists the result.
+///
+/// Used when the user asks for a check, so nothing is notified.
+///
+/// # Errors
+///
+/// Returns an error if source detection or the check itself fails, or if the
+/// state file cannot be read or written.
+pub async fn run_manual_check(state: &AppState, path: &Path) -> Result<ScheduleState> {
+    let mut schedule = ScheduleState::load(path).await?;
+    let config = state.config.read().await.clone();
+
+    let sources = checked_sources(state, &config, true).await?;
+    schedule.pending = state
+        .updater
+        .check_pending(&sources, &config.child_environment)
+        .await?;
+    mark_held(&mut schedule.pending, &config);
+    mark_critical(&mut schedule.pending, &config);
+    schedule.last_check = Some(now_secs());
+    schedule.save(path).await?;
+
+    info!(
+        "Manual check finished: {} pending updates",
+        schedule.pending_count()
+    );
+    Ok(schedule)
+}
+
+async fn checked_sources(
+    state: &AppState,
+    config: &Config,
+    include_manual: bool,
+) -> Result<Vec<String>> {
+    Ok(state
+        .updater
+        .detect_sources()
+        .await?
+        .into_iter()
+        .filter(|source| {
+            config.is_source_enabled(source)
+                && (include_manual || config.source_policy(source) != SourcePolicy::Manual)
+        })
+        .collect())
+}
+
+/// Builds the run updating the sources with the [`SourcePolicy::AutoUpdate`]
+/// policy that have pending updates according to the last check.
+///
+/// Sources that `conditions` do not allow to update are recorded as deferred
+/// and retried on a later tick. Returns `None` if there is nothing to update.
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or written.
+pub async fn auto_update_request(
+    state: &AppState,
+    path: &Path,
+    conditions: &Conditions,
+) -> Result<Option<RunRequest>> {
+    let mut schedule = ScheduleState::load(path).await?;
+
+    let config = state.config.read().await.clone();
+    let sources: Vec<String> = schedule
+        .pending_sources()
+        .into_iter()
+        .filter(|source| {
+            config.is_source_enabled(source)
+                && config.source_policy(source) == SourcePolicy::AutoUpdate
+        })
+        .filter(|source| {
+            // Configuration files written by hand may still ask for it
+            let attended = ATTENDED_MANAGERS.contains(&source.as_str());
+            if attended {
+                info!(
+                    "Skipping automatic update of {}: needs authorization",
+                    source
+                );
+            }
+            !attended
+        })
+        .filter(|source| {
+            // Unattended runs must not update held packages the manager cannot skip
+            let can_hold = state
+                .updater
+                .get_manager_info(source)
+                .is_some_and(|manager| manager.supports_exclusions());
+            let has_held = schedule
+                .pending
+                .iter()
+                .any(|update| update.held && &update.source == source);
+            if has_held && !can_hold {
+                info!("Skipping automatic update of {}: held packages", source);
+            }
+            can_hold || !has_held
+        })
+        .filter(|source| {
+            // Critical updates wait for the user to confirm them
+            let has_critical = config.confirm_critical_updates
+                && schedule
+                    .pending
+                    .iter()
+                    .any(|update| update.critical && !update.held && &update.source == source);
+            if has_critical {
+                info!("Skipping automatic update of {}: critical packages", source);
+            }
+            !has_critical
+        })
+        .collect();
+
+    let (sources, deferred) = conditions.partition(&config, sources, Phase::Update, now_secs());
+    if schedule.record_deferrals(Phase::Update, deferred) {
+        schedule.save(path).await?;
+    }
+
+    if sources.is_empty() {
+        return Ok(None);
+    }
+
+    info!("Applying automatic updates for {}", sources.join(", "));
+    let mut request = RunRequest {
+        exclusions: config.exclusions_for(&sources),
+        sources,
+        trigger: RunTrigger::Scheduled,
+        mode: config.update_mode(),
+        snapshots: config.snapshot_before_updates,
+        critical_snapshots: config.snapshot_critical_updates,
+        wait_for_locks: config.wait_for_locks,
+        environment: config.child_environment.clone(),
+        ..RunRequest::default()
+    };
+    request.critical = schedule.critical_for(&request);
+    Ok(Some(request))
+}
+
+/// Runs the automatic updates built by [`auto_update_request`].
+///
+/// Sources that were updated successfully are removed from the cached
+/// pending list. Returns `None` if there was nothing to update.
+///
+/// # Errors
+///
+/// Returns an error if the run cannot be started or the state file cannot be
+/// read or written.
+pub async fn run_auto_updates(
+    state: &AppState,
+    path: &Path,
+    conditions: &Conditions,
+) -> Result<Option<RunRecord>> {
+    let Some(request) = auto_update_request(state, path, conditions).await? else {
+        return Ok(None);
+    };
+    let receiver = state.updater.run(request).await?;
+
+    let mut recorder = RunRecorder::new(RunTrigger::Scheduled, false);
+    while let Ok(event) = receiver.recv().await {
+        recorder.observe(&event);
+        if matches!(event, UpdateEvent::Completed(_)) {
+            break;
+        }
+    }
+    let record = recorder.finish();
+    apply_run(path, &record).await?;
+
+    Ok(Some(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advisories::{Advisory, AdvisorySeverity};
+    use tempfile::tempdir;
+
+    fn update(source: &str, package: &str) -> PendingUpdate {
+        PendingUpdate {
+            source: source.to_string(),
+            package: package.to_string(),
+            ..PendingUpdate::default()
+        }
+    }
+
+    #[test]
+    fn test_is_due_respects_interval() {
+        let state = ScheduleState {
+            last_check: Some(10_000),
+            ..ScheduleState::default()
+        };
+
+        assert!(!state.is_due(10_000, 1));
+        assert!(!state.is_due(10_000 + 3_599, 1));
+        assert!(state.is_due(10_000 + 3_600, 1));
+        // A zero interval is treated as one hour rather than "always due"
+        assert!(!state.is_due(10_001, 0));
+    }
+
+    #[test]
+    fn test_check_outcome_changed() {
+        let same = CheckOutcome {
+            previous_count: 3,
+            count: 3,
+            snoozed: false,
+            security: SecuritySummary::default(),
+        };
+        let different = CheckOutcome {
+            previous_count: 0,
+            count: 2,
+            snoozed: false,
+            security: SecuritySummary::default(),
+        };
+        let snoozed = CheckOutcome {
+            snoozed: true,
+            ..different.clone()
+        };
+
+        assert!(!same.changed());
+        assert!(different.changed());
+        assert!(different.should_notify());
+        assert!(snoozed.changed());
+        assert!(!snoozed.should_notify());
+
+        let urgent = CheckOutcome {
+            security: SecuritySummary {
+                count: 1,
+                highest: Some(AdvisorySeverity::Critical),
+            },
+            ..snoozed.clone()
+        };
+        assert!(urgent.should_notify());
+        let minor = CheckOutcome {
+            security: SecuritySummary {
+                count: 1,
+                highest: Some(AdvisorySeverity::Low),
+            },
+            ..snoozed
+        };
+        assert!(!minor.should_notify());
+    }
+
+    #[test]
+    fn test_record_deferrals() {
+        let deferral = |source: &str, phase: Phase, reason: &str, since: u64| Deferral {
+            source: source.to_string(),
+            phase,
+            reason: reason.to_string(),
+            since,
+        };
+        let mut state = ScheduleState::default();
+        assert!(!state.has_deferred_updates());
+
+        assert!(state.record_deferrals(
+            Phase::Update,
+            vec![deferral(
+                "flatpak",
+                Phase::Update,
+                "metered connection",
+                100
+            )]
+        ));
+        assert!(state.record_deferrals(
+            Phase::Check,
+            vec![deferral("rustup", Phase::Check, "offline", 100)]
+        ));
+        assert!(state.has_deferred_updates());
+
+        // Still deferred for the same reason: nothing changes, not even the time
+        assert!(!state.record_deferrals(
+            Phase::Update,
+            vec![deferral(
+                "flatpak",
+                Phase::Update,
+                "metered connection",
+                200
+            )]
+        ));
+        assert!(state.record_deferrals(
+            Phase::Update,
+            vec![
+                deferral("flatpak", Phase::Update, "metered connection", 300),
+                deferral("paru", Phase::Update, "running on battery", 300),
+            ]
+        ));
+        assert_eq!(state.deferrals[0].source, "rustup");
+        assert_eq!(state.deferrals[1].since, 100);
+        assert_eq!(state.deferrals[2].since, 300);
+
+        assert!(state.record_deferrals(Phase::Update, Vec::new()));
+        assert!(!state.has_deferred_updates());
+        assert_eq!(state.deferrals.len(), 1);
+    }
+
+    #[test]
+    fn test_security_request() {
+        let security = |source: &str, package: &str| PendingUpdate {
+            advisory: Some(Advisory::default()),
+            ..update(source, package)
+        };
+        let schedule = ScheduleState {
+            pending: vec![
+                security("dnf", "openssl"),
+                security("dnf", "kernel"),
+                security("apt", "openssl"),
+                update("apt", "vim"),
+                security("paru", "openssl"),
+                update("flatpak", "org.gnome.Maps"),
+            ],
+            ..ScheduleState::default()
+        };
+
+        let (request, unsupported) = security_request(&schedule, &Updater::new());
+        assert_eq!(request.mode, RunMode::SecurityOnly);
+        assert_eq!(request.sources, vec!["dnf", "apt"]);
+        assert!(!request.packages.contains_key("dnf"));
+        assert_eq!(request.packages["apt"], vec!["openssl"]);
+        assert_eq!(unsupported, vec!["paru"]);
+    }
+
+    #[test]
+    fn test_critical_for_request() {
+        let mut config = Config::default();
+        config.set_package_excluded(None, "mesa", true).unwrap();
+        let mut schedule = ScheduleState {
+            pending: vec![
+                update("paru", "linux"),
+                update("paru", "mesa"),
+                update("paru", "vim"),
+                update("dnf", "kernel-core"),
+            ],
+            ..ScheduleState::default()
+        };
+        mark_held(&mut schedule.pending, &config);
+        mark_critical(&mut schedule.pending, &config);
+
+        let mut request = RunRequest {
+            sources: vec!["paru".to_string()],
+            ..RunRequest::default()
+        };
+        let critical = schedule.critical_for(&request);
+        assert_eq!(critical.len(), 1);
+        assert_eq!(critical["paru"], vec!["linux"]);
+
+        // Only the selected packages of a targeted update count
+        request
+            .packages
+            .insert("paru".to_string(), vec!["vim".to_string()]);
+        assert!(schedule.critical_for(&request).is_empty());
+
+        request.packages.clear();
+        request.dry_run = true;
+        assert!(schedule.critical_for(&request).is_empty());
+    }
+
+    #[test]
+    fn test_forget_updated_sources() {
+        use crate::history::SourceResult;
+
+        let mut state = ScheduleState {
+            pending: vec![
+                update("flatpak", "org.gnome.Maps"),
+                update("rustup", "stable"),
+                update("flatpak", "org.gnome.Weather"),
+            ],
+            ..ScheduleState::default()
+        };
+        assert_eq!(state.pending_sources(), vec!["flatpak", "rustup"]);
+
+        let result = |source: &str, success: bool| SourceResult {
+            source: source.to_string(),
+            success,
+            ..SourceResult::default()
+        };
+        let mut record = RunRecord {
+            dry_run: true,
+            sources: vec![result("flatpak", true), result("rustup", false)],
+            ..RunRecord::default()
+        };
+
+        state.forget_updated(&record);
+        assert_eq!(state.pending.len(), 3);
+
+        // Cleaning up a source does not apply its updates
+        record.dry_run = false;
+        record.mode = RunMode::Cleanup;
+        state.forget_updated(&record);
+        assert_eq!(state.pending.len(), 3);
+
+        // A security-only update leaves the other updates of the source pending
+        state.pending[0].advisory = Some(Advisory::default());
+        record.mode = RunMode::SecurityOnly;
+        state.forget_updated(&record);
+        assert_eq!(state.pending.len(), 2);
+        assert_eq!(state.pending[1].package, "org.gnome.Weather");
+
+        record.mode = RunMode::UpdateAndCleanup;
+        state.forget_updated(&record);
+        assert_eq!(state.pending_sources(), vec!["rustup"]);
+    }
+
+    #[test]
+    fn test_forget_targeted_packages() {
+        use crate::history::SourceResult;
+
+        let mut state = ScheduleState {
+            pending: vec![
+                update("flatpak", "org.gnome.Maps"),
+                update("flatpak", "org.gnome.Weather"),
+            ],
+            ..ScheduleState::default()
+        };
+
+        state.forget_updated(&RunRecord {
+            sources: vec![SourceResult {
+                source: "flatpak".to_string(),
+                success: true,
+                packages: vec!["org.gnome.Weather".to_string()],
+                ..SourceResult::default()
+            }],
+            ..RunRecord::default()
+        });
+
+        assert_eq!(state.pending, vec![update("flatpak", "org.gnome.Maps")]);
+    }
+
+    #[test]
+    fn test_held_updates_are_not_pending() {
+        use crate::history::SourceResult;
+
+        let mut config = Config::default();
+        config
+            .set_package_excluded(Some("flatpak"), "org.gnome.Maps", true)
+            .unwrap();
+
+        let mut state = ScheduleState {
+            pending: vec![
+                update("flatpak", "org.gnome.Maps"),
+                update("rustup", "stable"),
+            ],
+            ..ScheduleState::default()
+        };
+        mark_held(&mut state.pending, &config);
+
+        assert!(state.pending[0].held);
+        assert_eq!(state.pending_count(), 1);
+        assert_eq!(state.pending_sources(), vec!["rustup"]);
+        assert_eq!(notifiable_count(&state.pending, &config), 1);
+
+        // A successful run of the source skipped the held package
+        state.forget_updated(&RunRecord {
+            sources: vec![SourceResult {
+                source: "flatpak".to_string(),
+                success: true,
+                ..SourceResult::default()
+            }],
+            ..RunRecord::default()
+        });
+        assert_eq!(state.pending.len(), 2);
+    }
+
+    #[test]
+    fn test_is_snoozed() {
+        let state = ScheduleState {
+            snoozed_until: Some(5_000),
+            ..ScheduleState::default()
+        };
+
+        assert!(state.is_snoozed(4_999));
+        assert!(!state.is_snoozed(5_000));
+        assert!(!ScheduleState::default().is_snoozed(0));
+    }
+
+    #[test]
+    fn test_notifiable_count_ignores_other_policies() {
+        let mut config = Config::default();
+        config
+            .set_source_policy("rustup", SourcePolicy::AutoUpdate)
+            .unwrap();
+
+        let pending = vec![
+            update("flatpak", "pkg"),
+            update("flatpak", "pkg"),
+            update("rustup", "pkg"),
+        ];
+
+        assert_eq!(notifiable_count(&pending, &config), 2);
+    }
+
+    #[async_std::test]
+    async fn test_schedule_state_save_load_cycle() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("nested").join("schedule.toml");
+
+        // Missing file loads as empty state
+        let empty = ScheduleState::load(&path).await.unwrap();
+        assert!(empty.last_check.is_none());
+
+        let state = ScheduleState {
+            last_check: Some(42),
+            snoozed_until: None,
+            pending: vec![PendingUpdate {
+                new_version: Some("46.1".to_string()),
+                held: true,
+                ..update("flatpak", "org.gnome.Maps")
+            }],
+            deferrals: vec![Deferral {
+                source: "flatpak".to_string(),
+                phase: Phase::Update,
+                reason: "metered connection".to_string(),
+                since: 40,
+            }],
+        };
+        state.save(&path).await.unwrap();
+
+        let loaded = ScheduleState::load(&path).await.unwrap();
+        assert_eq!(loaded.last_check, Some(42));
+        assert_eq!(loaded.pending, state.pending);
+        assert_eq!(loaded.deferrals, state.deferrals);
+    }
+
+    #[async_std::test]
+    async fn test_auto_update_skips_attended_sources() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("schedule.toml");
+        ScheduleState {
+            pending: vec![
+                update("apt", "pkg"),
+                update("fwupd", "System Firmware"),
+                update("rustup", "pkg"),
+            ],
+            ..ScheduleState::default()
+        }
+        .save(&path)
+        .await
+        .unwrap();
+
+        let state = AppState::new().await;
+        {
+            let mut config = state.config.write().await;
+            *config = Config::default();
+            config
+                .set_source_policy("rustup", SourcePolicy::AutoUpdate)
+                .unwrap();
+            // As if written into the configuration file by hand
+            for source in ["apt", "fwupd"] {
+                config
+                    .source_policies
+                    .insert(source.to_string(), SourcePolicy::AutoUpdate);
+            }
+        }
+
+        let request = auto_update_request(&state, &path, &Conditions::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.sources, vec!["rustup"]);
+        assert_eq!(request.trigger, RunTrigger::Scheduled);
+    }
+}
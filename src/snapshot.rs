@@ -0,0 +1,385 @@
+use anyhow::Result;
+use async_std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::info;
+
+use crate::{reboot::find_in_path, scheduler::now_secs};
+
+/// Directory plain btrfs snapshots are created in when neither snapper nor timeshift is set up.
+pub const BTRFS_SNAPSHOT_DIR: &str = "/.snapshots";
+
+/// A tool that can snapshot the root filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SnapshotTool {
+    Snapper,
+    Timeshift,
+    Btrfs,
+}
+
+/// Whether a snapshot was taken before or after an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SnapshotKind {
+    Pre,
+    Post,
+}
+
+/// A snapshot created around an update, as recorded in the run history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tool: SnapshotTool,
+    pub kind: SnapshotKind,
+    /// snapper snapshot number, timeshift snapshot name or btrfs snapshot path.
+    pub id: String,
+}
+
+/// Creates snapshots with the snapshot tool available on this system.
+#[derive(Debug, Clone)]
+pub struct Snapshotter {
+    tool: SnapshotTool,
+    /// Directory the tool binaries are looked up in; `None` searches `$PATH`.
+    bin_dir: Option<PathBuf>,
+    /// Run the tool through `pkexec`, since snapshots of `/` need root.
+    elevate: bool,
+    btrfs_dir: PathBuf,
+}
+
+impl Snapshotter {
+    /// Detects snapper, timeshift or a btrfs root with a snapshot directory, in that order.
+    pub async fn detect() -> Option<Self> {
+        let mut snapshotter = Self::detect_in(None, Path::new(BTRFS_SNAPSHOT_DIR)).await?;
+        snapshotter.elevate = true;
+        Some(snapshotter)
+    }
+
+    /// Detects the snapshot tool, looking up binaries in `bin_dir` if given.
+    ///
+    /// Plain btrfs snapshots are only used if `btrfs_dir` exists, so snapshots
+    /// never end up in an unexpected location.
+    pub async fn detect_in(bin_dir: Option<&Path>, btrfs_dir: &Path) -> Option<Self> {
+        let probe = |tool| Self {
+            tool,
+            bin_dir: bin_dir.map(Path::to_path_buf),
+            elevate: false,
+            btrfs_dir: btrfs_dir.to_path_buf(),
+        };
+
+        let snapper = probe(SnapshotTool::Snapper);
+        if snapper
+            .output("snapper", &["list-configs"])
+            .await
+            .is_ok_and(|out| {
+                out.lines()
+                    .any(|l| l.split('|').next().map(str::trim) == Some("root"))
+            })
+        {
+            return Some(snapper);
+        }
+
+        let timeshift = probe(SnapshotTool::Timeshift);
+        if timeshift.program_exists("timeshift") {
+            return Some(timeshift);
+        }
+
+        let btrfs = probe(SnapshotTool::Btrfs);
+        if btrfs.program_exists("btrfs")
+            && btrfs_dir.is_dir()
+            && btrfs
+                .output("findmnt", &["-n", "-o", "FSTYPE", "/"])
+                .await
+                .is_ok_and(|fs_type| fs_type.trim() == "btrfs")
+        {
+            return Some(btrfs);
+        }
+
+        None
+    }
+
+    pub fn tool(&self) -> SnapshotTool {
+        self.tool
+    }
+
+    fn program(&self, name: &str) -> PathBuf {
+        match &self.bin_dir {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+
+    fn program_exists(&self, name: &str) -> bool {
+        match &self.bin_dir {
+            Some(dir) => dir.join(name).is_file(),
+            None => find_in_path(name).is_some(),
+        }
+    }
+
+    async fn output(&self, name: &str, args: &[&str]) -> Result<String> {
+        let mut command = if self.elevate {
+            let mut command = Command::new("pkexec");
+            command.arg(self.program(name));
+            command
+        } else {
+            Command::new(self.program(name))
+        };
+
+        let output = command
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", name, e))?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "{} failed: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Creates a snapshot labelled `label`.
+    ///
+    /// For [`SnapshotKind::Post`] snapshots, `pre` links snapper's pre/post pair.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tool fails or its output does not identify the snapshot.
+    pub async fn create(
+        &self,
+        kind: SnapshotKind,
+        label: &str,
+        pre: Option<&Snapshot>,
+    ) -> Result<Snapshot> {
+        let id = match self.tool {
+            SnapshotTool::Snapper => {
+                let mut args = vec!["-c", "root", "create", "--cleanup-algorithm", "number"];
+                match (kind, pre) {
+                    (SnapshotKind::Post, Some(pre)) => {
+                        args.extend(["--type", "post", "--pre-number", pre.id.as_str()])
+                    }
+                    (SnapshotKind::Pre, _) => args.extend(["--type", "pre"]),
+                    (SnapshotKind::Post, None) => args.extend(["--type", "single"]),
+                }
+                args.extend(["--print-number", "--description", label]);
+
+                let output = self.output("snapper", &args).await?;
+                let number = output.trim();
+                if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(anyhow::anyhow!("Unexpected snapper output: '{}'", number));
+                }
+                number.to_string()
+            }
+            SnapshotTool::Timeshift => {
+                let output = self
+                    .output(
+                        "timeshift",
+                        &["--create", "--scripted", "--tags", "O", "--comments", label],
+                    )
+                    .await?;
+                parse_timeshift_output(&output).ok_or_else(|| {
+                    anyhow::anyhow!("Could not find the snapshot name in timeshift output")
+                })?
+            }
+            SnapshotTool::Btrfs => {
+                let suffix = match kind {
+                    SnapshotKind::Pre => "pre",
+                    SnapshotKind::Post => "post",
+                };
+                let path = self
+                    .btrfs_dir
+                    .join(format!("uptodate-{}-{suffix}", now_secs()));
+                let path = path.to_string_lossy().into_owned();
+                self.output("btrfs", &["subvolume", "snapshot", "-r", "/", &path])
+                    .await?;
+                path
+            }
+        };
+
+        info!("Created {:?} snapshot {} with {:?}", kind, id, self.tool);
+        Ok(Snapshot {
+            tool: self.tool,
+            kind,
+            id,
+        })
+    }
+}
+
+/// Extracts the snapshot name from `timeshift --create` output.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::snapshot::parse_timeshift_output;
+///
+/// let output = "Creating new backup...(BTRFS)\nTagged snapshot '2024-05-01_10-00-00': ondemand\n";
+/// assert_eq!(parse_timeshift_output(output).as_deref(), Some("2024-05-01_10-00-00"));
+/// ```
+pub fn parse_timeshift_output(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (_, rest) = line.split_once("Tagged snapshot '")?;
+        let (name, _) = rest.split_once('\'')?;
+        Some(name.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::stub;
+    use tempfile::{TempDir, tempdir};
+
+    fn log(dir: &TempDir) -> String {
+        std::fs::read_to_string(dir.path().join("log")).unwrap_or_default()
+    }
+
+    #[async_std::test]
+    async fn test_detect_nothing() {
+        let bin = tempdir().unwrap();
+        let btrfs_dir = bin.path().join("snapshots");
+
+        assert!(
+            Snapshotter::detect_in(Some(bin.path()), &btrfs_dir)
+                .await
+                .is_none()
+        );
+    }
+
+    #[async_std::test]
+    async fn test_snapper_pre_and_post() {
+        let bin = tempdir().unwrap();
+        let log_path = bin.path().join("log");
+        stub(
+            bin.path(),
+            "snapper",
+            &format!(
+                "if [ \"$1\" = list-configs ]; then echo 'root | /'; exit 0; fi\n\
+                 echo \"$@\" >> '{}'\n\
+                 case \"$*\" in *post*) echo 43 ;; *) echo 42 ;; esac",
+                log_path.display()
+            ),
+        );
+        stub(bin.path(), "timeshift", "exit 0");
+
+        let snapshotter = Snapshotter::detect_in(Some(bin.path()), Path::new("/nonexistent"))
+            .await
+            .unwrap();
+        assert_eq!(snapshotter.tool(), SnapshotTool::Snapper);
+
+        let pre = snapshotter
+            .create(SnapshotKind::Pre, "uptodate: dnf", None)
+            .await
+            .unwrap();
+        assert_eq!(pre.id, "42");
+
+        let post = snapshotter
+            .create(SnapshotKind::Post, "uptodate: dnf", Some(&pre))
+            .await
+            .unwrap();
+        assert_eq!(post.id, "43");
+        assert_eq!(post.kind, SnapshotKind::Post);
+
+        let log = log(&bin);
+        assert!(log.contains("--type pre"));
+        assert!(log.contains("--type post --pre-number 42"));
+        assert!(log.contains("--description uptodate: dnf"));
+    }
+
+    #[async_std::test]
+    async fn test_timeshift_snapshot() {
+        let bin = tempdir().unwrap();
+        stub(
+            bin.path(),
+            "timeshift",
+            "echo 'Creating new backup...(BTRFS)'\n\
+             echo \"Tagged snapshot '2024-05-01_10-00-00': ondemand\"",
+        );
+
+        let snapshotter = Snapshotter::detect_in(Some(bin.path()), Path::new("/nonexistent"))
+            .await
+            .unwrap();
+        assert_eq!(snapshotter.tool(), SnapshotTool::Timeshift);
+
+        let snapshot = snapshotter
+            .create(SnapshotKind::Pre, "uptodate: paru", None)
+            .await
+            .unwrap();
+        assert_eq!(snapshot.id, "2024-05-01_10-00-00");
+    }
+
+    #[async_std::test]
+    async fn test_btrfs_snapshot() {
+        let bin = tempdir().unwrap();
+        let btrfs_dir = bin.path().join("snapshots");
+        std::fs::create_dir(&btrfs_dir).unwrap();
+        let log_path = bin.path().join("log");
+        stub(bin.path(), "findmnt", "echo btrfs");
+        stub(
+            bin.path(),
+            "btrfs",
+            &format!("echo \"$@\" >> '{}'", log_path.display()),
+        );
+
+        let snapshotter = Snapshotter::detect_in(Some(bin.path()), &btrfs_dir)
+            .await
+            .unwrap();
+        assert_eq!(snapshotter.tool(), SnapshotTool::Btrfs);
+
+        let snapshot = snapshotter
+            .create(SnapshotKind::Pre, "uptodate: apt", None)
+            .await
+            .unwrap();
+        assert!(
+            snapshot
+                .id
+                .starts_with(&btrfs_dir.to_string_lossy().into_owned())
+        );
+        assert!(snapshot.id.ends_with("-pre"));
+        assert!(log(&bin).starts_with("subvolume snapshot -r / "));
+    }
+
+    #[async_std::test]
+    async fn test_btrfs_requires_snapshot_dir() {
+        let bin = tempdir().unwrap();
+        stub(bin.path(), "findmnt", "echo btrfs");
+        stub(bin.path(), "btrfs", "exit 0");
+
+        let missing = bin.path().join("snapshots");
+        assert!(
+            Snapshotter::detect_in(Some(bin.path()), &missing)
+                .await
+                .is_none()
+        );
+    }
+
+    #[async_std::test]
+    async fn test_failed_snapshot_is_an_error() {
+        let bin = tempdir().unwrap();
+        stub(
+            bin.path(),
+            "snapper",
+            "if [ \"$1\" = list-configs ]; then echo 'root | /'; exit 0; fi\n\
+             echo 'Creating snapshot failed.' >&2; exit 1",
+        );
+
+        let snapshotter = Snapshotter::detect_in(Some(bin.path()), Path::new("/nonexistent"))
+            .await
+            .unwrap();
+        let error = snapshotter
+            .create(SnapshotKind::Pre, "uptodate: zypper", None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Creating snapshot failed."));
+    }
+
+    #[test]
+    fn test_parse_timeshift_output_without_snapshot() {
+        assert_eq!(parse_timeshift_output("E: Not enough space\n"), None);
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::Result;
+use async_std::process::Command;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::config::Config;
+
+pub const SERVICE_UNIT: &str = "uptodate-check.service";
+pub const TIMER_UNIT: &str = "uptodate-check.timer";
+
+/// Schedules offered in Preferences, as systemd `OnCalendar=` expressions.
+pub const TIMER_SCHEDULES: &[&str] = &["hourly", "daily", "weekly"];
+
+/// Returns the systemd user unit directory below the given XDG config home.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use uptodate::systemd::unit_dir_for;
+///
+/// assert_eq!(
+///     unit_dir_for(Path::new("/home/user/.config")),
+///     Path::new("/home/user/.config/systemd/user")
+/// );
+/// ```
+pub fn unit_dir_for(config_home: &Path) -> PathBuf {
+    config_home.join("systemd").join("user")
+}
+
+/// Returns the systemd user unit directory (`$XDG_CONFIG_HOME/systemd/user`).
+///
+/// # Errors
+///
+/// Returns an error if the config directory cannot be determined.
+pub fn unit_dir() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|dir| unit_dir_for(&dir))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Cannot determine config directory. Please set $HOME environment variable."
+            )
+        })
+}
+
+/// Validates a systemd `OnCalendar=` expression before it is written to a unit.
+///
+/// Only the characters used by calendar specifications are accepted, which
+/// also keeps newlines and other unit directives out of the generated file.
+///
+/// # Errors
+///
+/// Returns an error if the expression is empty, too long or contains invalid characters.
+pub fn validate_on_calendar(expr: &str) -> Result<()> {
+    let expr = expr.trim();
+
+    if expr.is_empty() {
+        return Err(anyhow::anyhow!("Timer schedule cannot be empty"));
+    }
+
+    if expr.len() > 100 {
+        return Err(anyhow::anyhow!(
+            "Timer schedule too long (max 100 characters)"
+        ));
+    }
+
+    if !expr
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || " -:*/,.~".contains(c))
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid characters in timer schedule: '{}'",
+            expr
+        ));
+    }
+
+    Ok(())
+}
+
+fn quote_exec_path(exe: &Path) -> String {
+    let exe = exe.to_string_lossy();
+    if exe.contains(char::is_whitespace) {
+        format!("\"{exe}\"")
+    } else {
+        exe.into_owned()
+    }
+}
+
+/// Renders the oneshot service that runs the headless check, followed by the
+/// automatic updates of sources whose policy allows them.
+pub fn render_service(exe: &Path) -> String {
+    let exe = quote_exec_path(exe);
+    format!(
+        "[Unit]\n\
+         Description=UpToDate scheduled update check\n\
+         Documentation=https://github.com/atayozcan/uptodate\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={exe} --check\n\
+         ExecStart={exe} --auto-update\n"
+    )
+}
+
+/// Renders the timer that triggers [`SERVICE_UNIT`] on the given calendar schedule.
+pub fn render_timer(on_calendar: &str) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Run UpToDate update check on a schedule\n\
+         \n\
+         [Timer]\n\
+         OnCalendar={}\n\
+         Persistent=true\n\
+         RandomizedDelaySec=15min\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        on_calendar.trim()
+    )
+}
+
+/// Writes the service and timer units generated from `config` into `dir`.
+///
+/// # Errors
+///
+/// Returns an error if the schedule is invalid or the units cannot be written.
+pub async fn write_units(dir: &Path, exe: &Path, config: &Config) -> Result<()> {
+    validate_on_calendar(&config.timer_schedule)?;
+
+    async_std::fs::create_dir_all(dir)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to create unit directory {:?}: {}", dir, e))?;
+
+    for (name, content) in [
+        (SERVICE_UNIT, render_service(exe)),
+        (TIMER_UNIT, render_timer(&config.timer_schedule)),
+    ] {
+        let path = dir.join(name);
+        async_std::fs::write(&path, content)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to write unit {:?}: {}", path, e))?;
+    }
+
+    Ok(())
+}
+
+/// Removes the generated units from `dir`, ignoring units that do not exist.
+///
+/// # Errors
+///
+/// Returns an error if an existing unit cannot be removed.
+pub async fn remove_units(dir: &Path) -> Result<()> {
+    for name in [TIMER_UNIT, SERVICE_UNIT] {
+        let path = dir.join(name);
+        if path.exists() {
+            async_std::fs::remove_file(&path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to remove unit {:?}: {}", path, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if the timer unit has been generated in `dir`.
+pub fn units_installed(dir: &Path) -> bool {
+    dir.join(TIMER_UNIT).exists()
+}
+
+async fn systemctl_user(args: &[&str]) -> Result<()> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to run systemctl: {}", e))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "systemctl --user {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Generates the units for the running executable and enables the timer.
+///
+/// Calling this again after the configuration changed rewrites the units and
+/// restarts the timer so the new schedule takes effect.
+///
+/// # Errors
+///
+/// Returns an error if the units cannot be written or systemd rejects them.
+pub async fn install(config: &Config) -> Result<()> {
+    let dir = unit_dir()?;
+    let exe = std::env::current_exe()
+        .map_err(|e| anyhow::anyhow!("Cannot determine executable path: {}", e))?;
+
+    write_units(&dir, &exe, config).await?;
+    systemctl_user(&["daemon-reload"]).await?;
+    systemctl_user(&["enable", TIMER_UNIT]).await?;
+    systemctl_user(&["restart", TIMER_UNIT]).await?;
+
+    info!("Installed systemd timer {:?}", dir.join(TIMER_UNIT));
+    Ok(())
+}
+
+/// Disables the timer and removes the generated units.
+///
+/// # Errors
+///
+/// Returns an error if the units cannot be removed.
+pub async fn uninstall() -> Result<()> {
+    let dir = unit_dir()?;
+
+    if let Err(e) = systemctl_user(&["disable", "--now", TIMER_UNIT]).await {
+        warn!("Failed to disable timer: {}", e);
+    }
+    remove_units(&dir).await?;
+    systemctl_user(&["daemon-reload"]).await?;
+
+    info!("Removed systemd timer from {:?}", dir);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_validate_on_calendar() {
+        assert!(validate_on_calendar("daily").is_ok());
+        assert!(validate_on_calendar("Mon..Fri *-*-* 09:00:00").is_ok());
+        assert!(validate_on_calendar("*:0/30").is_ok());
+
+        assert!(validate_on_calendar("").is_err());
+        assert!(validate_on_calendar("daily\nExecStart=/bin/evil").is_err());
+        assert!(validate_on_calendar(&"a".repeat(101)).is_err());
+    }
+
+    #[test]
+    fn test_render_service() {
+        let unit = render_service(Path::new("/usr/bin/uptodate"));
+
+        assert!(unit.contains("Type=oneshot"));
+        assert!(unit.contains("ExecStart=/usr/bin/uptodate --check\n"));
+        assert!(unit.contains("ExecStart=/usr/bin/uptodate --auto-update\n"));
+    }
+
+    #[test]
+    fn test_render_service_quotes_paths_with_spaces() {
+        let unit = render_service(Path::new("/opt/Up To Date/uptodate"));
+
+        assert!(unit.contains("ExecStart=\"/opt/Up To Date/uptodate\" --check"));
+    }
+
+    #[async_std::test]
+    async fn test_write_and_remove_units() {
+        let xdg_config_home = tempdir().unwrap();
+        let dir = unit_dir_for(xdg_config_home.path());
+
+        let config = Config {
+            timer_schedule: "weekly".to_string(),
+            ..Config::default()
+        };
+
+        assert!(!units_installed(&dir));
+        write_units(&dir, Path::new("/usr/bin/uptodate"), &config)
+            .await
+            .unwrap();
+        assert!(units_installed(&dir));
+
+        let service = std::fs::read_to_string(dir.join(SERVICE_UNIT)).unwrap();
+        assert!(service.contains("ExecStart=/usr/bin/uptodate --check"));
+
+        let timer = std::fs::read_to_string(dir.join(TIMER_UNIT)).unwrap();
+        assert!(timer.contains("OnCalendar=weekly"));
+        assert!(timer.contains("Persistent=true"));
+        assert!(timer.contains("WantedBy=timers.target"));
+
+        remove_units(&dir).await.unwrap();
+        assert!(!units_installed(&dir));
+        assert!(!dir.join(SERVICE_UNIT).exists());
+    }
+
+    #[async_std::test]
+    async fn test_write_units_rejects_invalid_schedule() {
+        let xdg_config_home = tempdir().unwrap();
+        let dir = unit_dir_for(xdg_config_home.path());
+
+        let config = Config {
+            timer_schedule: "daily\n[Service]".to_string(),
+            ..Config::default()
+        };
+
+        assert!(
+            write_units(&dir, Path::new("/usr/bin/uptodate"), &config)
+                .await
+                .is_err()
+        );
+        assert!(!units_installed(&dir));
+    }
+}
@@ -0,0 +1,18 @@
+//! Fixtures shared by the unit tests.
+
+use std::{os::unix::fs::PermissionsExt, path::Path};
+
+/// Writes `content` to `path` below `root`, creating missing directories.
+pub fn write(root: &Path, path: &str, content: &str) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
+
+/// Writes an executable shell script `name` into `dir` that runs `script`,
+/// standing in for a command looked up on `PATH`.
+pub fn stub(dir: &Path, name: &str, script: &str) {
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
@@ -0,0 +1,201 @@
+use crate::{
+    AppState,
+    conditions::Conditions,
+    scheduler::{self, ScheduleState, now_secs},
+    ui::notifications,
+};
+use libadwaita::{Application, gio, glib, prelude::*};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+use tracing::{debug, error, warn};
+
+/// Seconds between two evaluations of the check schedule.
+const TICK_SECONDS: u32 = 60;
+
+thread_local! {
+    static HOLD_GUARD: RefCell<Option<gio::ApplicationHoldGuard>> = const { RefCell::new(None) };
+}
+
+/// Keeps the application alive without any open window while `resident` is true.
+pub fn set_resident(app: &Application, resident: bool) {
+    HOLD_GUARD.with(|guard| {
+        let mut guard = guard.borrow_mut();
+        match (resident, guard.is_some()) {
+            (true, false) => {
+                debug!("Holding application for background checks");
+                *guard = Some(app.hold());
+            }
+            (false, true) => {
+                debug!("Releasing background hold");
+                *guard = None;
+            }
+            _ => {}
+        }
+    });
+}
+
+/// Reads the current network and power state for scheduled runs.
+///
+/// Anything that cannot be determined, such as the battery on systems without
+/// UPower, is assumed to be unconstrained.
+pub async fn probe_conditions() -> Conditions {
+    let network = gio::NetworkMonitor::default();
+    let mut conditions = Conditions {
+        online: network.is_network_available(),
+        metered: network.is_network_metered(),
+        power_saver: gio::PowerProfileMonitor::get_default().is_power_saver_enabled(),
+        ..Conditions::default()
+    };
+
+    match upower_property(
+        "/org/freedesktop/UPower",
+        "org.freedesktop.UPower",
+        "OnBattery",
+    )
+    .await
+    {
+        Ok(value) => conditions.on_battery = value.get::<bool>().unwrap_or(false),
+        Err(e) => warn!("Failed to query battery state: {e}"),
+    }
+    if conditions.on_battery {
+        conditions.battery_level = upower_property(
+            "/org/freedesktop/UPower/devices/DisplayDevice",
+            "org.freedesktop.UPower.Device",
+            "Percentage",
+        )
+        .await
+        .ok()
+        .and_then(|value| value.get::<f64>());
+    }
+
+    debug!("Run conditions: {conditions:?}");
+    conditions
+}
+
+/// Reads a property of a UPower object from the system bus.
+async fn upower_property(
+    path: &str,
+    interface: &str,
+    property: &str,
+) -> Result<glib::Variant, glib::Error> {
+    let connection = gio::bus_get_future(gio::BusType::System).await?;
+    let reply = connection
+        .call_future(
+            Some("org.freedesktop.UPower"),
+            path,
+            "org.freedesktop.DBus.Properties",
+            "Get",
+            Some(&(interface, property).to_variant()),
+            Some(glib::VariantTy::new("(v)").expect("valid variant type")),
+            gio::DBusCallFlags::NONE,
+            -1,
+        )
+        .await?;
+    Ok(reply.child_value(0).as_variant().unwrap_or(reply))
+}
+
+/// Starts the background check scheduler.
+///
+/// The schedule is re-evaluated every minute against the persisted last-check
+/// time, so restarting the application does not reset the interval.
+pub fn start(app: &Application, state: AppState) {
+    let checking = Rc::new(Cell::new(false));
+
+    tick(app, &state, &checking);
+    glib::timeout_add_seconds_local(
+        TICK_SECONDS,
+        glib::clone!(
+            #[weak]
+            app,
+            #[upgrade_or]
+            glib::ControlFlow::Break,
+            move || {
+                tick(&app, &state, &checking);
+                glib::ControlFlow::Continue
+            }
+        ),
+    );
+}
+
+fn tick(app: &Application, state: &AppState, checking: &Rc<Cell<bool>>) {
+    let app = app.clone();
+    let state = state.clone();
+    let checking = checking.clone();
+
+    glib::spawn_future_local(async move {
+        let config = state.config.read().await.clone();
+
+        set_resident(&app, config.background_checks);
+        if !config.background_checks || checking.get() || state.updater.is_running() {
+            return;
+        }
+
+        let path = ScheduleState::default_path();
+        let mut schedule = ScheduleState::load(&path).await.unwrap_or_default();
+        let now = now_secs();
+
+        // Remind again about whatever is still pending once a snooze runs out
+        if schedule.snoozed_until.is_some_and(|until| until <= now) {
+            schedule.snoozed_until = None;
+            if let Err(e) = schedule.save(&path).await {
+                error!("Failed to clear snooze: {e}");
+            }
+            if config.show_notifications {
+                let count = scheduler::notifiable_count(&schedule.pending, &config);
+                let security = scheduler::notifiable_security(&schedule.pending, &config);
+                notifications::show_updates_available(&app, count, security);
+            }
+        }
+
+        let due = schedule.is_due(now, config.check_interval_hours);
+        // Deferred updates are retried every tick until conditions allow them
+        if !due && !schedule.has_deferred_updates() {
+            return;
+        }
+
+        checking.set(true);
+        let conditions = probe_conditions().await;
+        let outcome = if due {
+            match scheduler::run_check(&state, &path, &conditions).await {
+                Ok(outcome) => Some(outcome),
+                Err(e) => {
+                    error!("Background check failed: {e}");
+                    checking.set(false);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        // An open window follows the run like one started by the user, so
+        // that it shows progress and blocks other runs meanwhile
+        let window = app
+            .windows()
+            .into_iter()
+            .find(|window| window.is::<libadwaita::ApplicationWindow>());
+        if let Some(window) = window {
+            if let Err(e) = WidgetExt::activate_action(&window, "win.auto-update", None) {
+                error!("Failed to start automatic updates: {e}");
+            }
+        } else {
+            match scheduler::run_auto_updates(&state, &path, &conditions).await {
+                Ok(Some(record)) if config.show_notifications && !record.success() => {
+                    notifications::show_auto_update_failed(&app, &record.failed_sources());
+                }
+                Ok(_) => {}
+                Err(e) => error!("Automatic updates failed: {e}"),
+            }
+        }
+
+        if let Some(outcome) = outcome
+            && config.show_notifications
+            && outcome.should_notify()
+        {
+            notifications::show_updates_available(&app, outcome.count, outcome.security);
+        }
+        checking.set(false);
+    });
+}
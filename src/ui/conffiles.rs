@@ -0,0 +1,370 @@
+use crate::conffiles::{
+    Conffile, ConffileScan, DiffRow, Resolution, has_conflict_markers, merge_draft,
+};
+use libadwaita::{
+    ActionRow, Banner, Dialog, HeaderBar, PreferencesGroup, PreferencesPage, Toast, ToastOverlay,
+    ToolbarView, ViewStackPage, glib, gtk, prelude::*,
+};
+use std::{cell::RefCell, rc::Rc};
+use tracing::error;
+
+/// The "Config Files" page listing new versions of configuration files left
+/// by package updates.
+#[derive(Debug, Clone)]
+pub struct ConffileView {
+    page: PreferencesPage,
+    stack_page: ViewStackPage,
+    toast_overlay: ToastOverlay,
+    groups: Rc<RefCell<Vec<PreferencesGroup>>>,
+}
+
+impl ConffileView {
+    pub fn new(
+        page: PreferencesPage,
+        stack_page: ViewStackPage,
+        toast_overlay: ToastOverlay,
+    ) -> Self {
+        Self {
+            page,
+            stack_page,
+            toast_overlay,
+            groups: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Scans `/etc` again and rebuilds the page.
+    pub fn refresh(&self) {
+        let this = self.clone();
+        glib::spawn_future_local(async move {
+            let conffiles = ConffileScan::new().scan().await;
+            this.show(&conffiles);
+        });
+    }
+
+    fn show(&self, conffiles: &[Conffile]) {
+        let mut groups = self.groups.borrow_mut();
+        groups.drain(..).for_each(|group| self.page.remove(&group));
+
+        let count = conffiles.len() as u32;
+        self.stack_page.set_badge_number(count);
+        self.stack_page.set_needs_attention(count > 0);
+
+        let group = PreferencesGroup::new();
+        group.set_title("Configuration Files");
+        group.set_description(Some(
+            "Package updates left these versions next to configuration files you changed",
+        ));
+
+        let rescan = gtk::Button::builder()
+            .label("Scan Again")
+            .valign(gtk::Align::Center)
+            .build();
+        rescan.add_css_class("flat");
+        let this = self.clone();
+        rescan.connect_clicked(move |_| this.refresh());
+        group.set_header_suffix(Some(&rescan));
+
+        if conffiles.is_empty() {
+            let row = ActionRow::builder()
+                .title("Nothing to review")
+                .subtitle("No new configuration file versions were found")
+                .build();
+            row.add_prefix(&gtk::Image::from_icon_name("emblem-ok-symbolic"));
+            group.add(&row);
+        }
+
+        for conffile in conffiles {
+            group.add(&self.create_row(conffile));
+        }
+
+        self.page.add(&group);
+        groups.push(group);
+    }
+
+    fn create_row(&self, conffile: &Conffile) -> ActionRow {
+        let row = ActionRow::builder()
+            .title(conffile.path.to_string_lossy())
+            .subtitle(format!(
+                "{} · {}",
+                conffile.kind.description(),
+                conffile
+                    .other
+                    .file_name()
+                    .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+            ))
+            .activatable(true)
+            .build();
+        row.set_title_lines(1);
+        row.add_prefix(&gtk::Image::from_icon_name("document-properties-symbolic"));
+        row.add_suffix(&gtk::Image::from_icon_name("go-next-symbolic"));
+
+        let this = self.clone();
+        let conffile = conffile.clone();
+        row.connect_activated(move |_| this.review(conffile.clone()));
+        row
+    }
+
+    /// Shows both versions side by side with the actions resolving them.
+    fn review(&self, conffile: Conffile) {
+        let this = self.clone();
+        glib::spawn_future_local(async move {
+            let rows = match conffile.diff().await {
+                Ok(rows) => rows,
+                Err(e) => {
+                    this.show_toast(&e.to_string());
+                    return;
+                }
+            };
+
+            let (mine, mine_view) = Self::create_side("Yours", &conffile.mine().to_string_lossy());
+            let (packaged, packaged_view) =
+                Self::create_side("Package", &conffile.packaged().to_string_lossy());
+            Self::fill_diff(&rows, &mine_view.buffer(), &packaged_view.buffer());
+
+            let sides = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+            sides.set_homogeneous(true);
+            sides.append(&mine);
+            sides.append(&packaged);
+
+            let scrolled = gtk::ScrolledWindow::builder()
+                .child(&sides)
+                .vexpand(true)
+                .build();
+            scrolled.add_css_class("card");
+
+            let content = gtk::Box::new(gtk::Orientation::Vertical, 12);
+            content.set_margin_top(12);
+            content.set_margin_bottom(12);
+            content.set_margin_start(12);
+            content.set_margin_end(12);
+            if !rows.iter().any(DiffRow::is_changed) {
+                let label = gtk::Label::new(Some("Both versions are identical"));
+                label.add_css_class("dim-label");
+                content.append(&label);
+            }
+            content.append(&scrolled);
+
+            let dialog = Dialog::builder()
+                .title(conffile.path.to_string_lossy())
+                .content_width(960)
+                .content_height(600)
+                .build();
+
+            let actions = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+            actions.set_halign(gtk::Align::End);
+            actions.set_margin_top(6);
+            actions.set_margin_bottom(6);
+            actions.set_margin_start(12);
+            actions.set_margin_end(12);
+            let keep = gtk::Button::with_label("Keep Mine");
+            let take = gtk::Button::with_label("Take New");
+            let merge = gtk::Button::with_label("Merge…");
+            merge.add_css_class("suggested-action");
+            actions.append(&keep);
+            actions.append(&take);
+            actions.append(&merge);
+
+            keep.connect_clicked(glib::clone!(
+                #[strong]
+                this,
+                #[strong]
+                conffile,
+                #[weak]
+                dialog,
+                move |_| this.resolve(&conffile, Resolution::KeepMine, &dialog)
+            ));
+            take.connect_clicked(glib::clone!(
+                #[strong]
+                this,
+                #[strong]
+                conffile,
+                #[weak]
+                dialog,
+                move |_| this.resolve(&conffile, Resolution::TakeNew, &dialog)
+            ));
+            merge.connect_clicked(glib::clone!(
+                #[strong]
+                this,
+                #[strong]
+                conffile,
+                #[weak]
+                dialog,
+                move |_| this.edit_merge(&conffile, &rows, &dialog)
+            ));
+
+            let toolbar = ToolbarView::new();
+            toolbar.add_top_bar(&HeaderBar::new());
+            toolbar.set_content(Some(&content));
+            toolbar.add_bottom_bar(&actions);
+            dialog.set_child(Some(&toolbar));
+            dialog.present(Some(&this.page));
+        });
+    }
+
+    /// Lets the user edit a draft holding both versions, and saves it once
+    /// no conflict markers are left.
+    fn edit_merge(&self, conffile: &Conffile, rows: &[DiffRow], review: &Dialog) {
+        let buffer = gtk::TextBuffer::new(None);
+        buffer.set_text(&merge_draft(rows));
+        let view = gtk::TextView::builder()
+            .buffer(&buffer)
+            .monospace(true)
+            .top_margin(6)
+            .bottom_margin(6)
+            .left_margin(6)
+            .right_margin(6)
+            .build();
+
+        let scrolled = gtk::ScrolledWindow::builder()
+            .child(&view)
+            .vexpand(true)
+            .build();
+        scrolled.add_css_class("card");
+
+        let banner = Banner::new("Choose between the lines marked “yours” and “package”");
+        banner.set_revealed(true);
+
+        let save = gtk::Button::with_label("Save");
+        save.add_css_class("suggested-action");
+        save.set_sensitive(false);
+        buffer.connect_changed(glib::clone!(
+            #[weak]
+            save,
+            #[weak]
+            banner,
+            move |buffer| {
+                let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+                let unresolved = has_conflict_markers(&text);
+                save.set_sensitive(!unresolved);
+                banner.set_revealed(unresolved);
+            }
+        ));
+
+        let content = gtk::Box::new(gtk::Orientation::Vertical, 12);
+        content.set_margin_top(12);
+        content.set_margin_bottom(12);
+        content.set_margin_start(12);
+        content.set_margin_end(12);
+        content.append(&scrolled);
+
+        let header = HeaderBar::new();
+        header.pack_end(&save);
+
+        let toolbar = ToolbarView::new();
+        toolbar.add_top_bar(&header);
+        toolbar.add_top_bar(&banner);
+        toolbar.set_content(Some(&content));
+
+        let dialog = Dialog::builder()
+            .title(format!("Merge {}", conffile.path.to_string_lossy()))
+            .content_width(720)
+            .content_height(600)
+            .child(&toolbar)
+            .build();
+
+        let this = self.clone();
+        let conffile = conffile.clone();
+        save.connect_clicked(glib::clone!(
+            #[weak]
+            dialog,
+            #[weak]
+            review,
+            move |_| {
+                let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+                dialog.close();
+                this.resolve(&conffile, Resolution::Merge(text.to_string()), &review);
+            }
+        ));
+        dialog.present(Some(&self.page));
+    }
+
+    fn resolve(&self, conffile: &Conffile, resolution: Resolution, dialog: &Dialog) {
+        let this = self.clone();
+        let conffile = conffile.clone();
+        let dialog = dialog.clone();
+        glib::spawn_future_local(async move {
+            match conffile.resolve(&resolution).await {
+                Ok(()) => {
+                    let path = conffile.path.to_string_lossy();
+                    this.show_toast(&match resolution {
+                        Resolution::KeepMine => format!("Kept your version of {path}"),
+                        Resolution::TakeNew => format!("Installed the new version of {path}"),
+                        Resolution::Merge(_) => format!("Saved the merged {path}"),
+                    });
+                    dialog.close();
+                    this.refresh();
+                }
+                Err(e) => {
+                    error!("{e}");
+                    this.show_toast(&e.to_string());
+                }
+            }
+        });
+    }
+
+    fn create_side(title: &str, path: &str) -> (gtk::Box, gtk::TextView) {
+        let heading = gtk::Label::builder()
+            .label(format!("{title} · {path}"))
+            .xalign(0.0)
+            .ellipsize(gtk::pango::EllipsizeMode::Middle)
+            .margin_top(6)
+            .margin_start(6)
+            .build();
+        heading.add_css_class("heading");
+
+        let view = gtk::TextView::builder()
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .hexpand(true)
+            .top_margin(6)
+            .bottom_margin(6)
+            .left_margin(6)
+            .right_margin(6)
+            .build();
+
+        let side = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        side.append(&heading);
+        side.append(&view);
+        (side, view)
+    }
+
+    /// Writes the rows into both buffers, one line per row, so that the
+    /// sides stay aligned.
+    fn fill_diff(rows: &[DiffRow], mine: &gtk::TextBuffer, packaged: &gtk::TextBuffer) {
+        let removed = mine
+            .create_tag(
+                Some("changed"),
+                &[("paragraph-background", &"rgba(224, 27, 36, 0.15)")],
+            )
+            .unwrap();
+        let added = packaged
+            .create_tag(
+                Some("changed"),
+                &[("paragraph-background", &"rgba(46, 194, 126, 0.15)")],
+            )
+            .unwrap();
+
+        for row in rows {
+            match row {
+                DiffRow::Same(line) => {
+                    mine.insert(&mut mine.end_iter(), &format!("{line}\n"));
+                    packaged.insert(&mut packaged.end_iter(), &format!("{line}\n"));
+                }
+                DiffRow::Changed {
+                    mine: mine_line,
+                    packaged: packaged_line,
+                } => {
+                    let line = format!("{}\n", mine_line.as_deref().unwrap_or_default());
+                    mine.insert_with_tags(&mut mine.end_iter(), &line, &[&removed]);
+                    let line = format!("{}\n", packaged_line.as_deref().unwrap_or_default());
+                    packaged.insert_with_tags(&mut packaged.end_iter(), &line, &[&added]);
+                }
+            }
+        }
+    }
+
+    fn show_toast(&self, message: &str) {
+        self.toast_overlay.add_toast(Toast::new(message));
+    }
+}
@@ -1,3 +1,10 @@
+pub mod background;
+pub mod conffiles;
+pub mod notifications;
+pub mod pending;
+pub mod preferences;
+pub mod release;
+pub mod rollback;
 mod window;
 
 pub use window::MainWindow;
@@ -0,0 +1,111 @@
+use crate::advisories::SecuritySummary;
+use libadwaita::{gio, prelude::*};
+
+const UPDATES_AVAILABLE_ID: &str = "updates-available";
+const AUTO_UPDATE_FAILED_ID: &str = "auto-update-failed";
+const UPDATE_COMPLETE_ID: &str = "update-complete";
+
+/// Notifies the user about pending updates, or withdraws the notification if there are none.
+///
+/// The notification offers to update right away, to show the pending list or
+/// to snooze further reminders. Security fixes are called out with their
+/// highest severity, and urgent ones raise the priority.
+pub fn show_updates_available(
+    app: &impl IsA<gio::Application>,
+    count: usize,
+    security: SecuritySummary,
+) {
+    if count == 0 {
+        app.withdraw_notification(UPDATES_AVAILABLE_ID);
+        return;
+    }
+
+    let mut body = match count {
+        1 => "1 update is ready to install".to_string(),
+        n => format!("{n} updates are ready to install"),
+    };
+    let notification = match security.highest {
+        Some(severity) => {
+            let fixes = match security.count {
+                1 => "1 fixes a security issue".to_string(),
+                n => format!("{n} fix security issues"),
+            };
+            body.push_str(&format!(". {fixes} (severity: {})", severity.label()));
+
+            let notification = gio::Notification::new("Security Updates Available");
+            notification.set_icon(&gio::ThemedIcon::new("security-medium-symbolic"));
+            if severity.is_urgent() {
+                notification.set_priority(gio::NotificationPriority::High);
+            }
+            notification
+        }
+        None => {
+            let notification = gio::Notification::new("Updates Available");
+            notification.set_icon(&gio::ThemedIcon::new("software-update-available-symbolic"));
+            notification
+        }
+    };
+    notification.set_body(Some(&body));
+    notification.set_default_action("app.show-details");
+    // Notification servers show at most three buttons; the body shows details
+    if security.count > 0 {
+        notification.add_button("Install Security Fixes", "app.update-security");
+        notification.add_button("Update Now", "app.update-now");
+    } else {
+        notification.add_button("Update Now", "app.update-now");
+        notification.add_button("Show Details", "app.show-details");
+    }
+    notification.add_button("Snooze", "app.snooze");
+
+    app.send_notification(Some(UPDATES_AVAILABLE_ID), &notification);
+}
+
+/// Tells the user that automatic updates failed for the given sources.
+pub fn show_auto_update_failed(app: &impl IsA<gio::Application>, sources: &[String]) {
+    let notification = gio::Notification::new("Automatic Updates Failed");
+    notification.set_body(Some(&format!(
+        "Could not update {}. Open UpToDate to update manually.",
+        sources.join(", ")
+    )));
+    notification.set_icon(&gio::ThemedIcon::new("dialog-warning-symbolic"));
+    notification.set_priority(gio::NotificationPriority::High);
+    notification.set_default_action("app.show-details");
+    notification.add_button("Show Details", "app.show-details");
+
+    app.send_notification(Some(AUTO_UPDATE_FAILED_ID), &notification);
+}
+
+/// Summarizes a finished run.
+pub fn show_run_completed(app: &impl IsA<gio::Application>, completed: i32, failed: i32) {
+    let notification = gio::Notification::new("Updates Complete");
+
+    let message = match (completed, failed) {
+        (0, 0) => "No updates were performed".to_string(),
+        (c, 0) => format!("Successfully updated {c} package manager(s)"),
+        (0, f) => format!("Failed to update {f} package manager(s)"),
+        (c, f) => format!("Updated {c} package manager(s), {f} failed"),
+    };
+
+    notification.set_body(Some(&message));
+    notification.set_icon(&gio::ThemedIcon::new("system-software-update"));
+    notification.set_default_action("app.show-details");
+    notification.add_button("Show Details", "app.show-details");
+
+    app.send_notification(Some(UPDATE_COMPLETE_ID), &notification);
+}
+
+/// Withdraws the reminder about pending updates, e.g. after it was snoozed.
+pub fn withdraw_updates_available(app: &impl IsA<gio::Application>) {
+    app.withdraw_notification(UPDATES_AVAILABLE_ID);
+}
+
+/// Withdraws every notification that describes the state before a new run started.
+pub fn withdraw_stale(app: &impl IsA<gio::Application>) {
+    for id in [
+        UPDATES_AVAILABLE_ID,
+        AUTO_UPDATE_FAILED_ID,
+        UPDATE_COMPLETE_ID,
+    ] {
+        app.withdraw_notification(id);
+    }
+}
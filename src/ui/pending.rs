@@ -0,0 +1,398 @@
+use crate::{
+    AppState,
+    advisories::Advisory,
+    conditions::Phase,
+    pending::PendingUpdate,
+    scheduler::{self, ScheduleState, now_secs},
+};
+use libadwaita::{ActionRow, PreferencesGroup, PreferencesPage, glib, gtk, prelude::*};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use tracing::error;
+
+/// The "Pending" page listing the updates found by the last check.
+#[derive(Debug, Clone)]
+pub struct PendingView {
+    page: PreferencesPage,
+    groups: Rc<RefCell<Vec<PreferencesGroup>>>,
+    /// Packages picked for a targeted update, keyed by source.
+    selection: Rc<RefCell<HashMap<String, Vec<String>>>>,
+}
+
+impl PendingView {
+    pub fn new(page: PreferencesPage) -> Self {
+        Self {
+            page,
+            groups: Rc::new(RefCell::new(Vec::new())),
+            selection: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the packages of `source` picked for a targeted update.
+    pub fn selected(&self, source: &str) -> Vec<String> {
+        self.selection
+            .borrow()
+            .get(source)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Rebuilds the page from the cached check results.
+    pub fn show(&self, schedule: &ScheduleState, state: &AppState) {
+        let mut groups = self.groups.borrow_mut();
+        groups.drain(..).for_each(|group| self.page.remove(&group));
+        self.selection.borrow_mut().clear();
+
+        let summary = PreferencesGroup::new();
+        summary.set_title("Pending Updates");
+        summary.set_description(Some(&Self::describe(schedule)));
+        summary.set_header_suffix(Some(&Self::create_header_buttons(schedule)));
+
+        if schedule.pending_count() == 0 {
+            let row = ActionRow::builder()
+                .title("Everything is up to date")
+                .subtitle("No updates were found by the last check")
+                .build();
+            row.add_prefix(&gtk::Image::from_icon_name("emblem-ok-symbolic"));
+            summary.add(&row);
+        }
+
+        for deferral in &schedule.deferrals {
+            let name = state
+                .updater
+                .get_manager_info(&deferral.source)
+                .map_or(deferral.source.clone(), |manager| {
+                    manager.description.clone()
+                });
+            let title = match deferral.phase {
+                Phase::Check => format!("Check of {name} deferred"),
+                Phase::Update => format!("Automatic update of {name} deferred"),
+            };
+            let since = glib::DateTime::from_unix_local(deferral.since as i64)
+                .ok()
+                .and_then(|time| time.format("%c").ok())
+                .map_or(String::new(), |time| format!(" since {time}"));
+            let row = ActionRow::builder()
+                .title(title)
+                .subtitle(format!("Waiting: {}{since}", deferral.reason))
+                .build();
+            row.add_prefix(&gtk::Image::from_icon_name("alarm-symbolic"));
+            summary.add(&row);
+        }
+
+        self.page.add(&summary);
+        groups.push(summary);
+
+        let mut sources: Vec<&str> = Vec::new();
+        for update in &schedule.pending {
+            if !sources.contains(&update.source.as_str()) {
+                sources.push(&update.source);
+            }
+        }
+
+        for source in sources {
+            let updates: Vec<_> = schedule
+                .pending
+                .iter()
+                .filter(|update| update.source == source)
+                .collect();
+
+            let group = PreferencesGroup::new();
+            group.set_title(
+                &state
+                    .updater
+                    .get_manager_info(source)
+                    .map_or(source.to_string(), |manager| manager.description.clone()),
+            );
+            let held = updates.iter().filter(|update| update.held).count();
+            group.set_description(Some(&match held {
+                0 => format!("{source} · {} pending", updates.len()),
+                held => format!("{source} · {} pending, {held} held", updates.len() - held),
+            }));
+
+            let blocked_reason = state
+                .updater
+                .get_manager_info(source)
+                .map_or(Some("Unknown source"), |manager| {
+                    manager.targeted_blocked_reason()
+                });
+            let select_button = match blocked_reason {
+                Some(reason) => {
+                    let row = ActionRow::builder()
+                        .title("Single packages cannot be updated")
+                        .subtitle(reason)
+                        .build();
+                    row.add_prefix(&gtk::Image::from_icon_name("dialog-information-symbolic"));
+                    group.add(&row);
+                    None
+                }
+                None => {
+                    let button = Self::create_select_button(source);
+                    group.set_header_suffix(Some(&button));
+                    Some(button)
+                }
+            };
+
+            for update in updates {
+                let row = self.create_update_row(update, state);
+                if let Some(button) = &select_button
+                    && !update.held
+                {
+                    row.add_prefix(&self.create_select_check(update, button));
+                }
+                group.add(&row);
+            }
+
+            self.page.add(&group);
+            groups.push(group);
+        }
+    }
+
+    fn create_update_row(&self, update: &PendingUpdate, state: &AppState) -> ActionRow {
+        let row = ActionRow::builder().title(&update.package).build();
+        row.set_title_lines(1);
+
+        let versions = Self::describe_versions(
+            update.current_version.as_deref(),
+            update.new_version.as_deref(),
+        );
+        match (update.held, versions) {
+            (true, Some(versions)) => row.set_subtitle(&format!("Held · {versions}")),
+            (true, None) => row.set_subtitle("Held"),
+            (false, Some(versions)) => row.set_subtitle(&versions),
+            (false, None) => {}
+        }
+        if update.held {
+            row.add_css_class("dim-label");
+        } else if update.critical {
+            row.add_css_class("critical-update");
+            row.add_suffix(&Self::create_critical_label(update.needs_reboot));
+        }
+        if let Some(advisory) = &update.advisory {
+            row.add_suffix(&Self::create_severity_label(advisory));
+        }
+
+        // A source that cannot skip packages would update a held one anyway,
+        // and fwupd lists devices rather than packages; only releasing is
+        // offered there
+        let holdable = state
+            .updater
+            .get_manager_info(&update.source)
+            .is_some_and(|manager| manager.supports_exclusions());
+        if !holdable && !update.held {
+            return row;
+        }
+
+        let hold_button = gtk::Button::builder()
+            .icon_name(if update.held {
+                "changes-allow-symbolic"
+            } else {
+                "changes-prevent-symbolic"
+            })
+            .tooltip_text(if update.held {
+                "Release: include in updates again"
+            } else {
+                "Hold: exclude from updates"
+            })
+            .valign(gtk::Align::Center)
+            .build();
+        hold_button.add_css_class("flat");
+
+        let source = update.source.clone();
+        let package = update.package.clone();
+        let hold = !update.held;
+        hold_button.connect_clicked(glib::clone!(
+            #[strong]
+            state,
+            #[weak(rename_to = page)]
+            self.page,
+            move |_| {
+                let state = state.clone();
+                let (source, package) = (source.clone(), package.clone());
+                glib::spawn_future_local(async move {
+                    if let Err(e) = Self::set_held(&state, &source, &package, hold).await {
+                        error!("Failed to update exclusions: {e}");
+                    }
+                    let _ = page.activate_action("win.show-pending", None);
+                });
+            }
+        ));
+        row.add_suffix(&hold_button);
+        row
+    }
+
+    /// Flags a security fix with its severity; the advisories are in the tooltip.
+    fn create_severity_label(advisory: &Advisory) -> gtk::Label {
+        let label = gtk::Label::builder()
+            .label(format!("Security · {}", advisory.severity.label()))
+            .valign(gtk::Align::Center)
+            .build();
+        label.add_css_class("caption-heading");
+        label.add_css_class(if advisory.severity.is_urgent() {
+            "error"
+        } else {
+            "warning"
+        });
+        if !advisory.ids.is_empty() {
+            label.set_tooltip_text(Some(&advisory.ids.join("\n")));
+        }
+        label
+    }
+
+    /// Flags an update of a critical package like the kernel or the C library,
+    /// or one that is only applied on the next boot like firmware.
+    fn create_critical_label(needs_reboot: bool) -> gtk::Label {
+        let label = gtk::Label::builder()
+            .label(if needs_reboot {
+                "Needs Reboot"
+            } else {
+                "Critical"
+            })
+            .valign(gtk::Align::Center)
+            .tooltip_text(if needs_reboot {
+                "The update is applied while the system restarts"
+            } else {
+                "Updates of this package deserve extra care and need a reboot"
+            })
+            .build();
+        label.add_css_class("caption-heading");
+        label.add_css_class("accent");
+        label
+    }
+
+    fn create_select_button(source: &str) -> gtk::Button {
+        let button = gtk::Button::builder()
+            .label("Update Selected")
+            .sensitive(false)
+            .valign(gtk::Align::Center)
+            .build();
+        button.add_css_class("flat");
+
+        let source = source.to_string();
+        button.connect_clicked(move |button| {
+            let _ = button.activate_action("win.update-selected", Some(&source.to_variant()));
+        });
+        button
+    }
+
+    fn create_select_check(
+        &self,
+        update: &PendingUpdate,
+        button: &gtk::Button,
+    ) -> gtk::CheckButton {
+        let check = gtk::CheckButton::builder()
+            .valign(gtk::Align::Center)
+            .tooltip_text("Select for a targeted update")
+            .build();
+
+        let source = update.source.clone();
+        let package = update.package.clone();
+        let selection = self.selection.clone();
+        check.connect_toggled(glib::clone!(
+            #[weak]
+            button,
+            move |check| {
+                let mut selection = selection.borrow_mut();
+                let selected = selection.entry(source.clone()).or_default();
+                selected.retain(|p| p != &package);
+                if check.is_active() {
+                    selected.push(package.clone());
+                }
+                button.set_sensitive(!selected.is_empty());
+            }
+        ));
+        check
+    }
+
+    /// Holds back or releases a package and re-evaluates the cached pending list.
+    ///
+    /// Releasing also lifts a global exclusion, so the package is updated again.
+    async fn set_held(
+        state: &AppState,
+        source: &str,
+        package: &str,
+        hold: bool,
+    ) -> anyhow::Result<()> {
+        let mut config = state.config.write().await;
+        config.set_package_excluded(Some(source), package, hold)?;
+        if !hold {
+            config.set_package_excluded(None, package, false)?;
+        }
+        config.save().await?;
+
+        scheduler::refresh_held(&ScheduleState::default_path(), &config).await?;
+        Ok(())
+    }
+
+    fn describe(schedule: &ScheduleState) -> String {
+        let security = schedule.security();
+        let last_check = schedule
+            .last_check
+            .and_then(|ts| glib::DateTime::from_unix_local(ts as i64).ok())
+            .and_then(|time| time.format("%c").ok())
+            .map_or("Not checked yet".to_string(), |time| {
+                format!("Last checked {time}")
+            });
+        let last_check = match (security.count, security.highest) {
+            (0, _) | (_, None) => last_check,
+            (1, Some(highest)) => {
+                format!("{last_check} · 1 security fix ({})", highest.label())
+            }
+            (n, Some(highest)) => {
+                format!(
+                    "{last_check} · {n} security fixes, up to {}",
+                    highest.label()
+                )
+            }
+        };
+
+        let now = now_secs();
+        match schedule
+            .snoozed_until
+            .filter(|_| schedule.is_snoozed(now))
+            .and_then(|ts| glib::DateTime::from_unix_local(ts as i64).ok())
+            .and_then(|time| time.format("%c").ok())
+        {
+            Some(until) => format!("{last_check} · Reminders snoozed until {until}"),
+            None => last_check,
+        }
+    }
+
+    fn describe_versions(current: Option<&str>, new: Option<&str>) -> Option<String> {
+        match (current, new) {
+            (Some(current), Some(new)) => Some(format!("{current} → {new}")),
+            (None, Some(new)) => Some(new.to_string()),
+            (Some(current), None) => Some(format!("{current} (outdated)")),
+            (None, None) => None,
+        }
+    }
+
+    fn create_header_buttons(schedule: &ScheduleState) -> gtk::Box {
+        let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+
+        if schedule.security().count > 0 {
+            let security_button = gtk::Button::builder()
+                .label("Security Fixes")
+                .tooltip_text("Install only the updates that fix security issues")
+                .action_name("win.update-security")
+                .build();
+            security_button.add_css_class("flat");
+            buttons.append(&security_button);
+        }
+
+        let check_button = gtk::Button::builder()
+            .label("Check Now")
+            .action_name("win.check-now")
+            .build();
+        check_button.add_css_class("flat");
+
+        let update_button = gtk::Button::builder()
+            .label("Update All")
+            .action_name("win.update-pending")
+            .build();
+        update_button.add_css_class("suggested-action");
+
+        buttons.append(&check_button);
+        buttons.append(&update_button);
+        buttons
+    }
+}
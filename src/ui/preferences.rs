@@ -0,0 +1,646 @@
+use crate::{
+    AppState,
+    config::{Config, ConstrainedPolicy, SourcePolicy},
+    critical::validate_critical_pattern,
+    scheduler::{self, ScheduleState},
+    systemd::{self, TIMER_SCHEDULES},
+    ui::background,
+    updater::validate_package_name,
+};
+use libadwaita::{Application, glib, gtk, prelude::*};
+use tracing::error;
+
+/// Shows the preferences dialog, wired to the shared configuration.
+pub fn show_preferences_dialog(app: &Application, state: AppState) {
+    let app = app.clone();
+
+    glib::spawn_future_local(async move {
+        let config = state.config.read().await.clone();
+        let preferences = libadwaita::PreferencesDialog::new();
+
+        // Create a general page
+        let general_page = libadwaita::PreferencesPage::new();
+        general_page.set_title("General");
+        general_page.set_icon_name(Some("preferences-system-symbolic"));
+
+        // Add a group for update settings
+        let update_group = libadwaita::PreferencesGroup::new();
+        update_group.set_title("Update Settings");
+        update_group.set_description(Some("Configure how updates are performed"));
+
+        // Add the auto-refresh switch
+        let auto_refresh_row = libadwaita::SwitchRow::new();
+        auto_refresh_row.set_title("Auto-refresh sources");
+        auto_refresh_row.set_subtitle("Automatically refresh package lists on startup");
+        auto_refresh_row.set_active(true);
+
+        // Add the notification switch
+        let notification_row = libadwaita::SwitchRow::new();
+        notification_row.set_title("Show notifications");
+        notification_row.set_subtitle("Show system notifications when updates complete");
+        notification_row.set_active(config.show_notifications);
+        notification_row.connect_active_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let active = row.is_active();
+                update_config(&state, move |config| config.show_notifications = active);
+            }
+        ));
+
+        // Add the snapshot switch
+        let snapshot_row = libadwaita::SwitchRow::new();
+        snapshot_row.set_title("Snapshot before system updates");
+        snapshot_row.set_subtitle(
+            "Create snapper, timeshift or btrfs snapshots around system package updates. \
+             Updates are not started if the snapshot fails.",
+        );
+        snapshot_row.set_active(config.snapshot_before_updates);
+        snapshot_row.connect_active_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let active = row.is_active();
+                update_config(&state, move |config| {
+                    config.snapshot_before_updates = active
+                });
+            }
+        ));
+
+        // Add the cleanup switch
+        let cleanup_row = libadwaita::SwitchRow::new();
+        cleanup_row.set_title("Clean up after updates");
+        cleanup_row.set_subtitle(
+            "Remove orphaned packages, old package caches and unused runtimes \
+             once a source was updated",
+        );
+        cleanup_row.set_active(config.cleanup_after_updates);
+        cleanup_row.connect_active_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let active = row.is_active();
+                update_config(&state, move |config| config.cleanup_after_updates = active);
+            }
+        ));
+
+        update_group.add(&auto_refresh_row);
+        update_group.add(&notification_row);
+        update_group.add(&snapshot_row);
+        update_group.add(&cleanup_row);
+
+        // Add the lock wait switch
+        let wait_row = libadwaita::SwitchRow::new();
+        wait_row.set_title("Wait for other package managers");
+        wait_row.set_subtitle(
+            "If another program is installing packages, wait up to 10 minutes \
+             instead of skipping the source",
+        );
+        wait_row.set_active(config.wait_for_locks);
+        wait_row.connect_active_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let active = row.is_active();
+                update_config(&state, move |config| config.wait_for_locks = active);
+            }
+        ));
+        update_group.add(&wait_row);
+
+        // Add a group for background checks
+        let schedule_group = libadwaita::PreferencesGroup::new();
+        schedule_group.set_title("Background Checks");
+        schedule_group.set_description(Some(
+            "Keep running in the background and check for updates periodically",
+        ));
+
+        let background_row = libadwaita::SwitchRow::new();
+        background_row.set_title("Check for updates automatically");
+        background_row.set_active(config.background_checks);
+        background_row.connect_active_notify(glib::clone!(
+            #[strong]
+            state,
+            #[weak]
+            app,
+            move |row| {
+                let active = row.is_active();
+                background::set_resident(&app, active);
+                update_config(&state, move |config| config.background_checks = active);
+            }
+        ));
+
+        let interval_row = libadwaita::SpinRow::with_range(1.0, 168.0, 1.0);
+        interval_row.set_title("Check interval");
+        interval_row.set_subtitle("Hours between two checks");
+        interval_row.set_value(config.check_interval_hours as f64);
+        background_row
+            .bind_property("active", &interval_row, "sensitive")
+            .sync_create()
+            .build();
+        interval_row.connect_value_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let hours = row.value() as u64;
+                update_config(&state, move |config| config.check_interval_hours = hours);
+            }
+        ));
+
+        let snooze_row = libadwaita::SpinRow::with_range(1.0, 168.0, 1.0);
+        snooze_row.set_title("Snooze duration");
+        snooze_row.set_subtitle("Hours to wait before reminding again after snoozing");
+        snooze_row.set_value(config.snooze_hours as f64);
+        snooze_row.connect_value_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let hours = row.value() as u64;
+                update_config(&state, move |config| config.snooze_hours = hours);
+            }
+        ));
+
+        schedule_group.add(&background_row);
+        schedule_group.add(&interval_row);
+        schedule_group.add(&snooze_row);
+
+        let timer_group = build_timer_group(&state, &config);
+        let policy_group = build_policy_group(&state, &config).await;
+        let conditions_group = build_conditions_group(&state, &config).await;
+        let terminal_group = build_terminal_group(&state, &config).await;
+        let exclusion_group = build_exclusion_group(&state, &config);
+        let critical_group = build_critical_group(&state, &config).await;
+
+        general_page.add(&update_group);
+        general_page.add(&schedule_group);
+        general_page.add(&timer_group);
+        general_page.add(&policy_group);
+        general_page.add(&conditions_group);
+        general_page.add(&terminal_group);
+        general_page.add(&exclusion_group);
+        general_page.add(&critical_group);
+        preferences.add(&general_page);
+
+        preferences.present(app.active_window().as_ref());
+    });
+}
+
+fn build_timer_group(state: &AppState, config: &Config) -> libadwaita::PreferencesGroup {
+    let timer_group = libadwaita::PreferencesGroup::new();
+    timer_group.set_title("Scheduled Tasks");
+    timer_group.set_description(Some(
+        "Run checks from a systemd user timer, even when UpToDate is not running",
+    ));
+
+    let timer_row = libadwaita::SwitchRow::new();
+    timer_row.set_title("Use systemd timer");
+    timer_row.set_active(config.systemd_timer);
+    timer_row.connect_active_notify(glib::clone!(
+        #[strong]
+        state,
+        move |row| {
+            let active = row.is_active();
+            update_timer_config(&state, move |config| config.systemd_timer = active);
+        }
+    ));
+
+    let mut schedules: Vec<&str> = TIMER_SCHEDULES.to_vec();
+    if !schedules.contains(&config.timer_schedule.as_str()) {
+        schedules.push(&config.timer_schedule);
+    }
+    let schedule_row = libadwaita::ComboRow::new();
+    schedule_row.set_title("Schedule");
+    schedule_row.set_model(Some(&gtk::StringList::new(&schedules)));
+    schedule_row.set_selected(
+        schedules
+            .iter()
+            .position(|s| *s == config.timer_schedule)
+            .unwrap_or(0) as u32,
+    );
+    let schedules: Vec<String> = schedules.into_iter().map(str::to_string).collect();
+    schedule_row.connect_selected_notify(glib::clone!(
+        #[strong]
+        state,
+        move |row| {
+            if let Some(schedule) = schedules.get(row.selected() as usize).cloned() {
+                update_timer_config(&state, move |config| config.timer_schedule = schedule);
+            }
+        }
+    ));
+
+    timer_row
+        .bind_property("active", &schedule_row, "sensitive")
+        .sync_create()
+        .build();
+
+    timer_group.add(&timer_row);
+    timer_group.add(&schedule_row);
+    timer_group
+}
+
+async fn build_policy_group(state: &AppState, config: &Config) -> libadwaita::PreferencesGroup {
+    let policy_group = libadwaita::PreferencesGroup::new();
+    policy_group.set_title("Scheduled Updates");
+    policy_group.set_description(Some(
+        "Choose what background checks and the systemd timer may do with each source",
+    ));
+
+    let sources = state.updater.detect_sources().await.unwrap_or_default();
+    for source in sources {
+        // System packages need supervision and are never updated automatically
+        let policies = SourcePolicy::available_for(&source);
+        let labels: Vec<&str> = policies.iter().map(SourcePolicy::label).collect();
+        let policy_row = libadwaita::ComboRow::new();
+        policy_row.set_title(
+            &state
+                .updater
+                .get_manager_info(&source)
+                .map_or(source.clone(), |manager| manager.description.clone()),
+        );
+        policy_row.set_subtitle(&source);
+        policy_row.set_model(Some(&gtk::StringList::new(&labels)));
+        policy_row.set_selected(
+            policies
+                .iter()
+                .position(|policy| *policy == config.source_policy(&source))
+                .or_else(|| {
+                    policies
+                        .iter()
+                        .position(|policy| *policy == SourcePolicy::NotifyOnly)
+                })
+                .unwrap_or(0) as u32,
+        );
+        policy_row.connect_selected_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                if let Some(policy) = policies.get(row.selected() as usize).copied() {
+                    let source = source.clone();
+                    update_config(&state, move |config| {
+                        if let Err(e) = config.set_source_policy(&source, policy) {
+                            error!("Failed to set source policy: {e}");
+                        }
+                    });
+                }
+            }
+        ));
+        policy_group.add(&policy_row);
+    }
+
+    policy_group
+}
+
+async fn build_conditions_group(state: &AppState, config: &Config) -> libadwaita::PreferencesGroup {
+    let conditions_group = libadwaita::PreferencesGroup::new();
+    conditions_group.set_title("Network and Power");
+    conditions_group.set_description(Some(
+        "Choose what scheduled runs may do on metered connections and on battery power",
+    ));
+
+    let sources = state.updater.detect_sources().await.unwrap_or_default();
+    for source in sources {
+        let source_row = libadwaita::ExpanderRow::new();
+        source_row.set_title(
+            &state
+                .updater
+                .get_manager_info(&source)
+                .map_or(source.clone(), |manager| manager.description.clone()),
+        );
+        source_row.set_subtitle(&source);
+
+        let metered_row = build_constrained_row(
+            state,
+            "On metered connections",
+            config.metered_policy(&source),
+            glib::clone!(
+                #[strong]
+                source,
+                move |config: &mut Config, policy| config.set_metered_policy(&source, policy)
+            ),
+        );
+        let battery_row = build_constrained_row(
+            state,
+            "On battery power",
+            config.battery_policy(&source),
+            glib::clone!(
+                #[strong]
+                source,
+                move |config: &mut Config, policy| config.set_battery_policy(&source, policy)
+            ),
+        );
+
+        source_row.add_row(&metered_row);
+        source_row.add_row(&battery_row);
+        conditions_group.add(&source_row);
+    }
+
+    conditions_group
+}
+
+async fn build_terminal_group(state: &AppState, config: &Config) -> libadwaita::PreferencesGroup {
+    let terminal_group = libadwaita::PreferencesGroup::new();
+    terminal_group.set_title("Questions");
+    terminal_group.set_description(Some(
+        "Run sources in a terminal to answer their questions yourself, e.g. which provider \
+         to install or whether to keep a changed configuration file",
+    ));
+
+    let sources = state.updater.detect_sources().await.unwrap_or_default();
+    for source in sources {
+        let terminal_row = libadwaita::SwitchRow::new();
+        terminal_row.set_title(
+            &state
+                .updater
+                .get_manager_info(&source)
+                .map_or(source.clone(), |manager| manager.description.clone()),
+        );
+        terminal_row.set_subtitle(&source);
+        terminal_row.set_active(config.runs_in_terminal(&source));
+        terminal_row.connect_active_notify(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let (source, active) = (source.clone(), row.is_active());
+                update_config(&state, move |config| {
+                    if let Err(e) = config.set_runs_in_terminal(&source, active) {
+                        error!("Failed to set terminal source: {e}");
+                    }
+                });
+            }
+        ));
+        terminal_group.add(&terminal_row);
+    }
+
+    terminal_group
+}
+
+/// Creates a row choosing a [`ConstrainedPolicy`], stored with `set`.
+fn build_constrained_row<F>(
+    state: &AppState,
+    title: &str,
+    current: ConstrainedPolicy,
+    set: F,
+) -> libadwaita::ComboRow
+where
+    F: Fn(&mut Config, ConstrainedPolicy) -> anyhow::Result<()> + Clone + 'static,
+{
+    let labels: Vec<&str> = ConstrainedPolicy::ALL
+        .iter()
+        .map(ConstrainedPolicy::label)
+        .collect();
+    let row = libadwaita::ComboRow::new();
+    row.set_title(title);
+    row.set_model(Some(&gtk::StringList::new(&labels)));
+    row.set_selected(
+        ConstrainedPolicy::ALL
+            .iter()
+            .position(|policy| *policy == current)
+            .unwrap_or(0) as u32,
+    );
+    row.connect_selected_notify(glib::clone!(
+        #[strong]
+        state,
+        move |row| {
+            if let Some(policy) = ConstrainedPolicy::ALL.get(row.selected() as usize).copied() {
+                let set = set.clone();
+                update_config(&state, move |config| {
+                    if let Err(e) = set(config, policy) {
+                        error!("Failed to set network and power policy: {e}");
+                    }
+                });
+            }
+        }
+    ));
+    row
+}
+
+/// Applies `change` to the configuration, then installs, refreshes or
+/// removes the systemd timer units to match it.
+fn update_timer_config<F>(state: &AppState, change: F)
+where
+    F: FnOnce(&mut Config) + 'static,
+{
+    let state = state.clone();
+    glib::spawn_future_local(async move {
+        let config = {
+            let mut config = state.config.write().await;
+            change(&mut config);
+            if let Err(e) = config.save().await {
+                error!("Failed to save configuration: {e}");
+            }
+            config.clone()
+        };
+
+        let installed = systemd::unit_dir().is_ok_and(|dir| systemd::units_installed(&dir));
+        let result = match (config.systemd_timer, installed) {
+            (true, _) => systemd::install(&config).await,
+            (false, true) => systemd::uninstall().await,
+            (false, false) => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("Failed to update systemd timer: {e}");
+        }
+    });
+}
+
+fn build_exclusion_group(state: &AppState, config: &Config) -> libadwaita::PreferencesGroup {
+    let exclusion_group = libadwaita::PreferencesGroup::new();
+    exclusion_group.set_title("Excluded Packages");
+    exclusion_group.set_description(Some(
+        "Never update these packages, whichever source provides them. \
+         Packages can also be held per source from the Pending page.",
+    ));
+
+    let entry_row = libadwaita::EntryRow::new();
+    entry_row.set_title("Add package");
+    entry_row.set_show_apply_button(true);
+    exclusion_group.add(&entry_row);
+
+    for package in &config.excluded_packages {
+        exclusion_group.add(&create_exclusion_row(state, &exclusion_group, package));
+    }
+
+    entry_row.connect_apply(glib::clone!(
+        #[strong]
+        state,
+        #[weak]
+        exclusion_group,
+        move |row| {
+            let package = row.text().trim().to_string();
+            if let Err(e) = validate_package_name(&package) {
+                error!("Cannot exclude package: {e}");
+                row.add_css_class("error");
+                return;
+            }
+            row.remove_css_class("error");
+            row.set_text("");
+
+            exclusion_group.add(&create_exclusion_row(&state, &exclusion_group, &package));
+            set_globally_excluded(&state, package, true);
+        }
+    ));
+
+    exclusion_group
+}
+
+async fn build_critical_group(state: &AppState, config: &Config) -> libadwaita::PreferencesGroup {
+    let critical_group = libadwaita::PreferencesGroup::new();
+    critical_group.set_title("Critical Packages");
+    critical_group.set_description(Some(
+        "Updates of these packages are highlighted and need a reboot. \
+         Separate packages with commas; a trailing * matches any suffix.",
+    ));
+
+    let confirm_row = libadwaita::SwitchRow::new();
+    confirm_row.set_title("Ask before updating critical packages");
+    confirm_row.set_subtitle(
+        "Also when updating from a notification; automatic updates leave them to you",
+    );
+    confirm_row.set_active(config.confirm_critical_updates);
+    confirm_row.connect_active_notify(glib::clone!(
+        #[strong]
+        state,
+        move |row| {
+            let active = row.is_active();
+            update_config(&state, move |config| {
+                config.confirm_critical_updates = active
+            });
+        }
+    ));
+    critical_group.add(&confirm_row);
+
+    let snapshot_row = libadwaita::SwitchRow::new();
+    snapshot_row.set_title("Snapshot before critical updates");
+    snapshot_row.set_subtitle("If snapper, timeshift or btrfs snapshots are available");
+    snapshot_row.set_active(config.snapshot_critical_updates);
+    snapshot_row.connect_active_notify(glib::clone!(
+        #[strong]
+        state,
+        move |row| {
+            let active = row.is_active();
+            update_config(&state, move |config| {
+                config.snapshot_critical_updates = active
+            });
+        }
+    ));
+    critical_group.add(&snapshot_row);
+
+    let sources = state.updater.detect_sources().await.unwrap_or_default();
+    for source in sources {
+        let entry_row = libadwaita::EntryRow::new();
+        entry_row.set_title(
+            &state
+                .updater
+                .get_manager_info(&source)
+                .map_or(source.clone(), |manager| manager.description.clone()),
+        );
+        entry_row.set_text(&config.critical_packages_for(&source).join(", "));
+        entry_row.set_show_apply_button(true);
+        entry_row.connect_apply(glib::clone!(
+            #[strong]
+            state,
+            move |row| {
+                let patterns: Vec<String> = row
+                    .text()
+                    .split([',', ' '])
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if let Err(e) = patterns
+                    .iter()
+                    .try_for_each(|pattern| validate_critical_pattern(pattern))
+                {
+                    error!("Cannot set critical packages: {e}");
+                    row.add_css_class("error");
+                    return;
+                }
+                row.remove_css_class("error");
+                set_critical_packages(&state, source.clone(), patterns);
+            }
+        ));
+        critical_group.add(&entry_row);
+    }
+
+    critical_group
+}
+
+/// Updates the critical packages of `source` and re-evaluates the cached updates.
+fn set_critical_packages(state: &AppState, source: String, patterns: Vec<String>) {
+    let state = state.clone();
+    glib::spawn_future_local(async move {
+        let mut config = state.config.write().await;
+        if let Err(e) = config.set_critical_packages(&source, patterns) {
+            error!("Failed to update critical packages: {e}");
+            return;
+        }
+        if let Err(e) = config.save().await {
+            error!("Failed to save configuration: {e}");
+        }
+        if let Err(e) = scheduler::refresh_held(&ScheduleState::default_path(), &config).await {
+            error!("Failed to update pending list: {e}");
+        }
+    });
+}
+
+fn create_exclusion_row(
+    state: &AppState,
+    exclusion_group: &libadwaita::PreferencesGroup,
+    package: &str,
+) -> libadwaita::ActionRow {
+    let row = libadwaita::ActionRow::new();
+    row.set_title(package);
+
+    let remove_button = gtk::Button::from_icon_name("user-trash-symbolic");
+    remove_button.set_tooltip_text(Some("Remove exclusion"));
+    remove_button.set_valign(gtk::Align::Center);
+    remove_button.add_css_class("flat");
+
+    let package = package.to_string();
+    remove_button.connect_clicked(glib::clone!(
+        #[strong]
+        state,
+        #[weak]
+        exclusion_group,
+        #[weak]
+        row,
+        move |_| {
+            exclusion_group.remove(&row);
+            set_globally_excluded(&state, package.clone(), false);
+        }
+    ));
+
+    row.add_suffix(&remove_button);
+    row
+}
+
+/// Updates a global exclusion and re-evaluates which cached updates are held.
+fn set_globally_excluded(state: &AppState, package: String, excluded: bool) {
+    let state = state.clone();
+    glib::spawn_future_local(async move {
+        let mut config = state.config.write().await;
+        if let Err(e) = config.set_package_excluded(None, &package, excluded) {
+            error!("Failed to update exclusions: {e}");
+            return;
+        }
+        if let Err(e) = config.save().await {
+            error!("Failed to save configuration: {e}");
+        }
+        if let Err(e) = scheduler::refresh_held(&ScheduleState::default_path(), &config).await {
+            error!("Failed to update pending list: {e}");
+        }
+    });
+}
+
+/// Applies `change` to the shared configuration and saves it to disk.
+fn update_config<F>(state: &AppState, change: F)
+where
+    F: FnOnce(&mut Config) + 'static,
+{
+    let state = state.clone();
+    glib::spawn_future_local(async move {
+        let mut config = state.config.write().await;
+        change(&mut config);
+        if let Err(e) = config.save().await {
+            error!("Failed to save configuration: {e}");
+        }
+    });
+}
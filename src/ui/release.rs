@@ -0,0 +1,308 @@
+use crate::{
+    history::{History, RunRecorder, RunTrigger},
+    release::{ReleaseUpgrade, StepAction, UpgradeStep},
+    ui::MainWindow,
+    updater::{Reservation, RunMode, RunRequest, Severity, UpdateEvent},
+};
+use libadwaita::{
+    ActionRow, AlertDialog, Dialog, HeaderBar, PreferencesGroup, PreferencesPage,
+    ResponseAppearance, ToolbarView, glib, gtk, prelude::*,
+};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+use tracing::error;
+
+/// Asks for confirmation before upgrading to a new release, then guides the
+/// user through the upgrade one step at a time.
+pub fn show_upgrade(window: &MainWindow, upgrade: &ReleaseUpgrade) {
+    let name = upgrade.target_name();
+    let dialog = AlertDialog::new(
+        Some(&format!("Upgrade to {name}?")),
+        Some(
+            "Upgrading downloads several gigabytes and can take an hour or more. \
+             Back up your files first, keep the computer connected to power and \
+             the network, and save your work: the computer restarts to finish \
+             the upgrade.",
+        ),
+    );
+    dialog.add_responses(&[("cancel", "Cancel"), ("continue", "Continue")]);
+    dialog.set_response_appearance("continue", ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+
+    let upgrade = upgrade.clone();
+    dialog.connect_response(
+        Some("continue"),
+        glib::clone!(
+            #[strong]
+            window,
+            move |_, _| UpgradeFlow::new(&window, &upgrade).present()
+        ),
+    );
+    dialog.present(Some(&window.window));
+}
+
+/// The dialog of a release upgrade in progress.
+#[derive(Clone)]
+struct UpgradeFlow {
+    upgrade: ReleaseUpgrade,
+    steps: Rc<Vec<UpgradeStep>>,
+    /// Status icon of each step.
+    icons: Rc<Vec<gtk::Image>>,
+    /// Index of the next step to run.
+    current: Rc<Cell<usize>>,
+    /// Records the upgrade once its first step ran; taken when it is saved.
+    recorder: Rc<RefCell<Option<RunRecorder>>>,
+    /// Keeps update runs from starting until the upgrade is saved.
+    reservation: Rc<RefCell<Option<Reservation>>>,
+    window: MainWindow,
+    buffer: gtk::TextBuffer,
+    button: gtk::Button,
+    dialog: Dialog,
+}
+
+impl UpgradeFlow {
+    fn new(window: &MainWindow, upgrade: &ReleaseUpgrade) -> Self {
+        let steps = upgrade.steps();
+
+        let group = PreferencesGroup::new();
+        group.set_title("Steps");
+        group.set_description(Some(&format!(
+            "Upgrading from {} {}",
+            upgrade.distribution.name(),
+            upgrade.current
+        )));
+        let icons: Vec<_> = steps
+            .iter()
+            .map(|step| {
+                let row = ActionRow::builder()
+                    .title(&step.title)
+                    .subtitle(&step.description)
+                    .build();
+                row.set_subtitle_lines(0);
+                let icon = gtk::Image::from_icon_name("emblem-default-symbolic");
+                icon.add_css_class("status-icon");
+                row.add_prefix(&icon);
+                group.add(&row);
+                icon
+            })
+            .collect();
+
+        let buffer = gtk::TextBuffer::new(None);
+        let view = gtk::TextView::builder()
+            .buffer(&buffer)
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .wrap_mode(gtk::WrapMode::WordChar)
+            .top_margin(6)
+            .bottom_margin(6)
+            .left_margin(6)
+            .right_margin(6)
+            .build();
+        let end = buffer.create_mark(None, &buffer.end_iter(), false);
+        buffer.connect_changed(glib::clone!(
+            #[weak]
+            view,
+            #[weak]
+            end,
+            move |_| view.scroll_to_mark(&end, 0.0, false, 0.0, 1.0)
+        ));
+        let scrolled = gtk::ScrolledWindow::builder()
+            .child(&view)
+            .height_request(200)
+            .build();
+        scrolled.add_css_class("card");
+
+        let output = PreferencesGroup::new();
+        output.set_title("Output");
+        output.add(&scrolled);
+
+        let page = PreferencesPage::new();
+        page.add(&group);
+        page.add(&output);
+
+        let button = gtk::Button::builder()
+            .halign(gtk::Align::Center)
+            .margin_top(12)
+            .margin_bottom(12)
+            .build();
+        button.add_css_class("suggested-action");
+        button.add_css_class("pill");
+
+        let toolbar = ToolbarView::new();
+        toolbar.add_top_bar(&HeaderBar::new());
+        toolbar.set_content(Some(&page));
+        toolbar.add_bottom_bar(&button);
+
+        let dialog = Dialog::builder()
+            .title(format!("Upgrade to {}", upgrade.target_name()))
+            .content_width(640)
+            .content_height(640)
+            .child(&toolbar)
+            .build();
+
+        let flow = Self {
+            upgrade: upgrade.clone(),
+            steps: Rc::new(steps),
+            icons: Rc::new(icons),
+            current: Rc::new(Cell::new(0)),
+            recorder: Rc::new(RefCell::new(None)),
+            reservation: Rc::new(RefCell::new(None)),
+            window: window.clone(),
+            buffer,
+            button,
+            dialog,
+        };
+        flow.show_step(false);
+
+        let this = flow.clone();
+        flow.button.connect_clicked(move |_| {
+            let this = this.clone();
+            glib::spawn_future_local(async move { this.run_step().await });
+        });
+
+        // Leaving half-way is recorded as an interrupted upgrade
+        let this = flow.clone();
+        flow.dialog.connect_closed(move |_| {
+            let this = this.clone();
+            glib::spawn_future_local(async move { this.save(false).await });
+        });
+        flow
+    }
+
+    fn present(&self) {
+        self.dialog.present(Some(&self.window.window));
+    }
+
+    /// Updates the button for the current step, offering a retry after a failure.
+    fn show_step(&self, failed: bool) {
+        let Some(step) = self.steps.get(self.current.get()) else {
+            self.button.set_label("Done");
+            return;
+        };
+        let label = match (&step.action, failed) {
+            (_, true) => "Retry".to_string(),
+            (StepAction::Reboot, _) => "Restart Now".to_string(),
+            (StepAction::Commands(_), _) => step.title.clone(),
+        };
+        self.button.set_label(&label);
+    }
+
+    fn set_status(&self, index: usize, icon_name: &str, css_class: Option<&str>) {
+        let icon = &self.icons[index];
+        ["success", "error", "running"]
+            .iter()
+            .for_each(|class| icon.remove_css_class(class));
+        icon.set_icon_name(Some(icon_name));
+        if let Some(css_class) = css_class {
+            icon.add_css_class(css_class);
+        }
+    }
+
+    fn observe(&self, event: UpdateEvent) {
+        if let Some(recorder) = self.recorder.borrow_mut().as_mut() {
+            recorder.observe(&event);
+        }
+    }
+
+    async fn run_step(&self) {
+        let index = self.current.get();
+        let Some(step) = self.steps.get(index).cloned() else {
+            self.dialog.close();
+            return;
+        };
+        let source = self.upgrade.distribution.source().to_string();
+        if index == 0 && self.recorder.borrow().is_none() {
+            // No update may use the package manager while it upgrades the release
+            let request = RunRequest {
+                sources: vec![source.clone()],
+                trigger: RunTrigger::Manual,
+                mode: RunMode::ReleaseUpgrade,
+                ..RunRequest::default()
+            };
+            match self.window.state.updater.reserve(&request).await {
+                Ok(reservation) => self.reservation.replace(Some(reservation)),
+                Err(e) => {
+                    self.buffer
+                        .insert(&mut self.buffer.end_iter(), &format!("{e}\n"));
+                    return;
+                }
+            };
+            self.window.inhibit();
+            self.recorder
+                .replace(Some(RunRecorder::for_release(&self.upgrade)));
+            self.observe(UpdateEvent::SourceStarted(source.clone()));
+        }
+
+        self.button.set_sensitive(false);
+        self.dialog.set_can_close(false);
+        self.set_status(index, "process-working-symbolic", Some("running"));
+        let buffer = self.buffer.clone();
+        let result = match step.action {
+            StepAction::Reboot => MainWindow::request_reboot()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to restart: {}", e)),
+            StepAction::Commands(_) => {
+                step.run(|line| {
+                    buffer.insert(&mut buffer.end_iter(), &format!("{line}\n"));
+                })
+                .await
+            }
+        };
+        self.dialog.set_can_close(true);
+        self.button.set_sensitive(true);
+
+        match result {
+            Ok(()) => {
+                self.set_status(index, "emblem-ok-symbolic", Some("success"));
+                self.current.set(index + 1);
+                self.show_step(false);
+
+                // The application may not survive the restart, so the upgrade
+                // is recorded as soon as the last step succeeded
+                if step.reboots {
+                    self.observe(UpdateEvent::RebootRequired(vec![format!(
+                        "{} is installed while restarting",
+                        self.upgrade.target_name()
+                    )]));
+                }
+                if index + 1 == self.steps.len() {
+                    self.save(true).await;
+                }
+            }
+            Err(e) => {
+                error!("Release upgrade step {:?} failed: {e}", step.title);
+                self.buffer
+                    .insert(&mut self.buffer.end_iter(), &format!("{e}\n"));
+                self.observe(UpdateEvent::SourceMessage(
+                    source,
+                    Severity::Error,
+                    e.to_string(),
+                ));
+                self.set_status(index, "dialog-error-symbolic", Some("error"));
+                self.show_step(true);
+            }
+        }
+    }
+
+    /// Appends the upgrade to the history, once, and lets updates run again;
+    /// `completed` tells whether all steps succeeded.
+    async fn save(&self, completed: bool) {
+        let Some(mut recorder) = self.recorder.take() else {
+            return;
+        };
+        self.reservation.take();
+        self.window.uninhibit();
+        let source = self.upgrade.distribution.source().to_string();
+        recorder.observe(&UpdateEvent::SourceCompleted(source, completed));
+        if !completed {
+            recorder.observe(&UpdateEvent::Interrupted);
+        }
+        if let Err(e) = History::append(&History::default_path(), recorder.finish()).await {
+            error!("Failed to record the release upgrade: {e}");
+        }
+    }
+}
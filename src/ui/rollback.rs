@@ -0,0 +1,199 @@
+use crate::{
+    AppState,
+    history::{History, RunRecord, SourceResult},
+    rollback::{Rollback, RollbackSupport},
+};
+use libadwaita::{
+    ActionRow, AlertDialog, Dialog, HeaderBar, PreferencesGroup, PreferencesPage,
+    ResponseAppearance, Toast, ToastOverlay, ToolbarView, glib, gtk, prelude::*,
+};
+use tracing::error;
+
+/// Shows how each source of the last update can be undone, with the limits
+/// of its rollback.
+pub fn show_undo_dialog(
+    parent: &impl IsA<gtk::Widget>,
+    toast_overlay: &ToastOverlay,
+    state: &AppState,
+) {
+    let parent = parent.clone().upcast::<gtk::Widget>();
+    let toast_overlay = toast_overlay.clone();
+    let state = state.clone();
+    glib::spawn_future_local(async move {
+        let history = match History::load(&History::default_path()).await {
+            Ok(history) => history,
+            Err(e) => {
+                error!("Failed to load history: {e}");
+                toast_overlay.add_toast(Toast::new("Failed to load the run history"));
+                return;
+            }
+        };
+        let Some(record) = history.last_update() else {
+            toast_overlay.add_toast(Toast::new("No update has been run yet"));
+            return;
+        };
+
+        let page = PreferencesPage::new();
+        let group = PreferencesGroup::new();
+        group.set_title("Last Update");
+        group.set_description(Some(&describe(record)));
+        for result in &record.sources {
+            group.add(&create_source_row(
+                result,
+                record.started_at,
+                &state,
+                &toast_overlay,
+            ));
+        }
+        page.add(&group);
+
+        let toolbar = ToolbarView::new();
+        toolbar.add_top_bar(&HeaderBar::new());
+        toolbar.set_content(Some(&page));
+
+        let dialog = Dialog::builder()
+            .title("Undo Last Update")
+            .content_width(560)
+            .content_height(480)
+            .child(&toolbar)
+            .build();
+        dialog.present(Some(&parent));
+    });
+}
+
+fn describe(record: &RunRecord) -> String {
+    glib::DateTime::from_unix_local(record.started_at as i64)
+        .ok()
+        .and_then(|time| time.format("%c").ok())
+        .map_or("Started at an unknown time".to_string(), |time| {
+            format!("Started {time}")
+        })
+}
+
+fn create_source_row(
+    result: &SourceResult,
+    started_at: u64,
+    state: &AppState,
+    toast_overlay: &ToastOverlay,
+) -> ActionRow {
+    let title = state
+        .updater
+        .get_manager_info(&result.source)
+        .map_or(result.source.clone(), |manager| manager.description.clone());
+    let row = ActionRow::builder()
+        .title(format!("{title} ({})", result.source))
+        .build();
+    row.set_subtitle_lines(0);
+
+    let support = RollbackSupport::for_source(&result.source);
+    let rollback = match (&result.rollback, support) {
+        (_, RollbackSupport::Unavailable(reason)) => {
+            row.set_subtitle(reason);
+            None
+        }
+        (None, _) if result.blocked.is_some() => {
+            row.set_subtitle("The source was skipped; nothing changed.");
+            None
+        }
+        (None, _) => {
+            row.set_subtitle("Nothing to undo: no changes were recorded, or they were undone.");
+            None
+        }
+        (Some(rollback), RollbackSupport::Available(limitation)) => {
+            row.set_subtitle(&format!(
+                "{}\n{limitation}",
+                glib::markup_escape_text(&rollback.description())
+            ));
+            Some(rollback.clone())
+        }
+    };
+
+    if let Some(rollback) = rollback {
+        let button = gtk::Button::builder()
+            .label("Undo")
+            .valign(gtk::Align::Center)
+            .build();
+        button.add_css_class("destructive-action");
+
+        let source = result.source.clone();
+        let state = state.clone();
+        let toast_overlay = toast_overlay.clone();
+        button.connect_clicked(move |button| {
+            confirm(
+                button,
+                &source,
+                &rollback,
+                started_at,
+                &state,
+                &toast_overlay,
+            );
+        });
+        row.add_suffix(&button);
+    } else {
+        row.add_css_class("dim-label");
+    }
+    row
+}
+
+fn confirm(
+    button: &gtk::Button,
+    source: &str,
+    rollback: &Rollback,
+    started_at: u64,
+    state: &AppState,
+    toast_overlay: &ToastOverlay,
+) {
+    let dialog = AlertDialog::new(
+        Some(&format!("Undo the {source} Update?")),
+        Some(&rollback.description()),
+    );
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("undo", "Undo");
+    dialog.set_response_appearance("undo", ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+
+    let source = source.to_string();
+    let rollback = rollback.clone();
+    let state = state.clone();
+    let toast_overlay = toast_overlay.clone();
+    dialog.connect_response(
+        Some("undo"),
+        glib::clone!(
+            #[weak]
+            button,
+            move |_, _| {
+                let source = source.clone();
+                let rollback = rollback.clone();
+                let state = state.clone();
+                let toast_overlay = toast_overlay.clone();
+                button.set_sensitive(false);
+                glib::spawn_future_local(async move {
+                    let environment = state.config.read().await.child_environment.clone();
+                    match rollback.apply(&environment).await {
+                        Ok(()) => {
+                            if let Err(e) = History::forget_rollback(
+                                &History::default_path(),
+                                started_at,
+                                &source,
+                            )
+                            .await
+                            {
+                                error!("Failed to record the rollback: {e}");
+                            }
+                            toast_overlay
+                                .add_toast(Toast::new(&format!("Undid the {source} update")));
+                        }
+                        Err(e) => {
+                            error!("Rollback of {source} failed: {e}");
+                            toast_overlay.add_toast(Toast::new(&format!(
+                                "Could not undo the {source} update: {e}"
+                            )));
+                            button.set_sensitive(true);
+                        }
+                    }
+                });
+            }
+        ),
+    );
+    dialog.present(Some(button));
+}
@@ -1,12 +1,35 @@
-use crate::{AppState, updater::UpdateEvent};
+use crate::{
+    AppState,
+    cleanup::format_bytes,
+    conditions::Phase,
+    diagnosis::{Diagnosis, Fix},
+    history::{History, RunRecorder, RunTrigger},
+    pty::{Prompt, PromptKind},
+    recovery::{Leftover, LeftoverScan, RunMarker},
+    release::{ReleaseCheck, ReleaseUpgrade},
+    restart::StaleService,
+    scheduler::{self, ScheduleState},
+    ui::{
+        background, conffiles::ConffileView, notifications, pending::PendingView, release, rollback,
+    },
+    updater::{RunMode, RunRequest, Stop, UpdateEvent},
+};
 use async_std::channel::Receiver;
 use gtk::gio;
 use gtk::{Align, Box, Button, Image, ListBox, Orientation, ProgressBar};
 use libadwaita::{
-    ActionRow, ApplicationWindow, Banner, SwitchRow, ToastOverlay, glib, gtk, prelude::*,
+    ActionRow, AlertDialog, ApplicationWindow, Banner, PreferencesPage, ResponseAppearance,
+    SwitchRow, Toast, ToastOverlay, ViewStack, ViewStackPage, glib, gtk, prelude::*,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
 };
-use std::collections::HashMap;
-use tracing::error;
+use tracing::{error, info};
+
+/// Shown by the session when logging out or suspending is blocked by a run.
+const INHIBIT_REASON: &str = "Updates are being installed";
 
 #[derive(Debug, Clone)]
 pub enum BannerType {
@@ -16,7 +39,7 @@ pub enum BannerType {
     Info,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MainWindow {
     pub window: ApplicationWindow,
     pub state: AppState,
@@ -27,7 +50,19 @@ pub struct MainWindow {
     pub source_rows: HashMap<String, (ActionRow, Box, ProgressBar)>,
     pub toast_overlay: ToastOverlay,
     pub main_box: Box,
-    pub current_banner: Option<Banner>,
+    pub view_stack: ViewStack,
+    pub pending_view: PendingView,
+    pub conffile_view: ConffileView,
+    pub current_banner: Rc<RefCell<Option<Banner>>>,
+    /// Offers the upgrade to a new distribution release, apart from the run banners.
+    release_banner: Banner,
+    release_upgrade: Rc<RefCell<Option<ReleaseUpgrade>>>,
+    /// Cookie of the logout and suspend inhibitor held during a run.
+    inhibit_cookie: Rc<Cell<Option<u32>>>,
+    /// Set when the user chose to stop the run and quit.
+    quit_after_run: Rc<Cell<bool>>,
+    /// Output of the sources that run under a pseudo-terminal.
+    terminals: Rc<RefCell<HashMap<String, gtk::TextBuffer>>>,
 }
 
 impl MainWindow {
@@ -43,6 +78,11 @@ impl MainWindow {
             dry_run_switch,
             toast_overlay,
             main_box,
+            view_stack,
+            pending_page,
+            conffiles_page,
+            conffiles_stack_page,
+            release_banner,
         ) = (
             builder.object::<ApplicationWindow>("MainWindow").unwrap(),
             builder.object::<Button>("start_button").unwrap(),
@@ -51,11 +91,18 @@ impl MainWindow {
             builder.object::<SwitchRow>("dry_run_switch").unwrap(),
             builder.object::<ToastOverlay>("toast_overlay").unwrap(),
             builder.object::<Box>("main_box").unwrap(),
+            builder.object::<ViewStack>("view_stack").unwrap(),
+            builder.object::<PreferencesPage>("pending_page").unwrap(),
+            builder.object::<PreferencesPage>("conffiles_page").unwrap(),
+            builder
+                .object::<ViewStackPage>("conffiles_stack_page")
+                .unwrap(),
+            builder.object::<Banner>("release_banner").unwrap(),
         );
 
         window.set_application(Some(app));
 
-        let mut window_self = Self {
+        let window_self = Self {
             window,
             state,
             start_button,
@@ -63,93 +110,144 @@ impl MainWindow {
             sources_list,
             dry_run_switch,
             source_rows: HashMap::new(),
+            conffile_view: ConffileView::new(
+                conffiles_page,
+                conffiles_stack_page,
+                toast_overlay.clone(),
+            ),
             toast_overlay,
             main_box,
-            current_banner: None,
+            view_stack,
+            pending_view: PendingView::new(pending_page),
+            current_banner: Rc::new(RefCell::new(None)),
+            release_banner,
+            release_upgrade: Rc::new(RefCell::new(None)),
+            inhibit_cookie: Rc::new(Cell::new(None)),
+            quit_after_run: Rc::new(Cell::new(false)),
+            terminals: Rc::new(RefCell::new(HashMap::new())),
         };
 
         window_self.setup_actions();
+        window_self.setup_quit_confirmation();
         window_self.setup_keyboard_shortcuts();
+        window_self.setup_pending_actions();
         window_self.load_sources();
+        window_self.refresh_pending();
+        window_self.conffile_view.refresh();
+        window_self.check_interrupted_run();
+        window_self.setup_release_banner();
+        window_self.check_release(RunTrigger::Scheduled);
         window_self
     }
 
     fn setup_actions(&self) {
-        let state = self.state.clone();
-        let start_button = self.start_button.clone();
-        let stop_button = self.stop_button.clone();
-        let dry_run_switch = self.dry_run_switch.clone();
-        let sources_list = self.sources_list.clone();
-
+        let this = self.clone();
         self.start_button.connect_clicked(move |_| {
-            let state = state.clone();
-            let start_button = start_button.clone();
-            let stop_button = stop_button.clone();
-            let dry_run = dry_run_switch.is_active();
-            let sources_list = sources_list.clone();
+            let enabled_sources = Self::collect_enabled_sources(&this.sources_list);
+            this.start_run(enabled_sources, this.dry_run_switch.is_active());
+        });
 
+        let this = self.clone();
+        self.stop_button.connect_clicked(move |_| {
+            let this = this.clone();
             glib::spawn_future_local(async move {
-                // Get enabled sources
-                let enabled_sources = Self::collect_enabled_sources(&sources_list);
-
-                if enabled_sources.is_empty() {
-                    return;
-                } else {
-                    {}
-                };
-
-                start_button.set_sensitive(false);
-                stop_button.set_sensitive(true);
-
-                state
-                    .updater
-                    .run_updates(&enabled_sources, dry_run)
-                    .await
-                    .map_or_else(
-                        |e| {
-                            error!("Failed to start updates: {e}");
-                            start_button.set_sensitive(true);
-                            stop_button.set_sensitive(false);
-                        },
-                        |receiver| {
-                            let sources_list = sources_list.clone();
-                            let start_button = start_button.clone();
-                            let stop_button = stop_button.clone();
-                            glib::spawn_future_local(async move {
-                                Self::handle_updates(
-                                    receiver,
-                                    sources_list,
-                                    start_button,
-                                    stop_button,
-                                )
-                                .await;
-                            });
-                        },
-                    );
+                // The start button comes back once the stopped run completes
+                this.stop_button.set_sensitive(false);
+                if this.state.updater.stop().await == Stop::AfterCurrentStep {
+                    this.show_toast("Stopping after the current step, which runs as root");
+                }
             });
         });
+    }
 
-        let state_stop = self.state.clone();
-        let start_button_stop = self.start_button.clone();
-        let stop_button_stop = self.stop_button.clone();
+    /// Asks before closing the window or quitting while a run is in progress.
+    fn setup_quit_confirmation(&self) {
+        let this = self.clone();
+        self.window.connect_close_request(move |_| {
+            if !this.state.updater.is_running() {
+                return glib::Propagation::Proceed;
+            }
+            this.confirm_quit();
+            glib::Propagation::Stop
+        });
 
-        self.stop_button.connect_clicked(move |_| {
-            let state = state_stop.clone();
-            let start_button = start_button_stop.clone();
-            let stop_button = stop_button_stop.clone();
+        // `app.quit` forwards here while a run is in progress
+        let quit = gio::SimpleAction::new("quit", None);
+        let this = self.clone();
+        quit.connect_activate(move |_, _| {
+            if this.state.updater.is_running() {
+                this.confirm_quit();
+            } else if let Some(app) = this.window.application() {
+                app.quit();
+            }
+        });
+        self.window.add_action(&quit);
+    }
 
+    /// Lets the user stop the run and quit, or keep it going with the window hidden.
+    ///
+    /// Either way the window stays alive until the run completes, so that it
+    /// is recorded and the inhibitor is released.
+    fn confirm_quit(&self) {
+        let dialog = AlertDialog::new(
+            Some("Updates Are Running"),
+            Some(
+                "Stopping now can leave packages half-installed. The run can keep \
+                 running in the background instead, and you will be notified when it finishes.",
+            ),
+        );
+        dialog.add_responses(&[
+            ("cancel", "Cancel"),
+            ("stop", "Stop and Quit"),
+            ("background", "Keep Running in Background"),
+        ]);
+        dialog.set_response_appearance("stop", ResponseAppearance::Destructive);
+        dialog.set_response_appearance("background", ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("background"));
+        dialog.set_close_response("cancel");
+
+        dialog.connect_response(
+            Some("background"),
+            glib::clone!(
+                #[weak(rename_to = window)]
+                self.window,
+                move |_, _| window.set_visible(false)
+            ),
+        );
+        let this = self.clone();
+        dialog.connect_response(Some("stop"), move |_, _| {
+            let this = this.clone();
             glib::spawn_future_local(async move {
-                state
-                    .updater
-                    .stop()
-                    .await
-                    .is_err()
-                    .then(|| error!("Failed to stop updates"));
-
-                start_button.set_sensitive(true);
-                stop_button.set_sensitive(false);
+                // The application quits once the stopped run completes; a step
+                // running as root finishes first
+                this.quit_after_run.set(true);
+                this.window.set_visible(false);
+                this.state.updater.stop().await;
             });
         });
+        dialog.present(Some(&self.window));
+    }
+
+    /// Keeps the session from logging out or suspending until [`Self::uninhibit`].
+    pub(crate) fn inhibit(&self) {
+        if self.inhibit_cookie.get().is_some() {
+            return;
+        }
+        if let Some(app) = self.window.application() {
+            let cookie = app.inhibit(
+                Some(&self.window),
+                gtk::ApplicationInhibitFlags::LOGOUT | gtk::ApplicationInhibitFlags::SUSPEND,
+                Some(INHIBIT_REASON),
+            );
+            // Zero means the session refused or does not support inhibitors
+            self.inhibit_cookie.set((cookie != 0).then_some(cookie));
+        }
+    }
+
+    pub(crate) fn uninhibit(&self) {
+        if let (Some(app), Some(cookie)) = (self.window.application(), self.inhibit_cookie.take()) {
+            app.uninhibit(cookie);
+        }
     }
 
     fn setup_keyboard_shortcuts(&self) {
@@ -187,7 +285,280 @@ impl MainWindow {
         self.window.add_action(&action);
     }
 
-    fn load_sources(&mut self) {
+    /// Starts a user-initiated run for `sources` and follows its progress.
+    pub fn start_run(&self, sources: Vec<String>, dry_run: bool) {
+        self.start_request(RunRequest {
+            sources,
+            dry_run,
+            trigger: RunTrigger::Manual,
+            ..RunRequest::default()
+        });
+    }
+
+    fn start_request(&self, mut request: RunRequest) {
+        if request.sources.is_empty() {
+            return;
+        }
+
+        let this = self.clone();
+        glib::spawn_future_local(async move {
+            let confirm_critical = {
+                let config = this.state.config.read().await;
+                request.exclusions = config.exclusions_for(&request.sources);
+                request.snapshots = config.snapshot_before_updates;
+                request.critical_snapshots = config.snapshot_critical_updates;
+                request.wait_for_locks = config.wait_for_locks;
+                request.environment = config.child_environment.clone();
+                request.terminal = config.terminal_sources.clone();
+                if request.mode == RunMode::Update {
+                    request.mode = config.update_mode();
+                }
+                config.confirm_critical_updates
+            };
+            request.critical = ScheduleState::load(&ScheduleState::default_path())
+                .await
+                .unwrap_or_default()
+                .critical_for(&request);
+
+            if confirm_critical && !request.critical.is_empty() {
+                this.confirm_critical(request);
+            } else {
+                this.launch(request).await;
+            }
+        });
+    }
+
+    /// Asks before a run that updates critical packages like the kernel.
+    fn confirm_critical(&self, request: RunRequest) {
+        let mut packages: Vec<&str> = request
+            .critical
+            .values()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        packages.sort_unstable();
+        let dialog = AlertDialog::new(
+            Some("Update Critical Packages?"),
+            Some(&format!(
+                "This run updates {}. A reboot will be needed afterwards; make sure \
+                 nothing important is running.",
+                packages.join(", ")
+            )),
+        );
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("update", "Update");
+        dialog.set_response_appearance("update", ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("cancel"));
+
+        let this = self.clone();
+        dialog.connect_response(Some("update"), move |_, _| {
+            let this = this.clone();
+            let request = request.clone();
+            glib::spawn_future_local(async move { this.launch(request).await });
+        });
+        dialog.present(Some(&self.window));
+    }
+
+    async fn launch(&self, request: RunRequest) {
+        self.start_button.set_sensitive(false);
+        self.stop_button.set_sensitive(true);
+
+        let recorder = RunRecorder::for_request(&request);
+        match self.state.updater.run(request).await {
+            Ok(receiver) => self.handle_updates(receiver, recorder).await,
+            Err(e) => {
+                error!("Failed to start updates: {e}");
+                self.show_toast(&e.to_string());
+                self.start_button.set_sensitive(true);
+                self.stop_button.set_sensitive(false);
+            }
+        }
+    }
+
+    fn setup_pending_actions(&self) {
+        let show_pending = gio::SimpleAction::new("show-pending", None);
+        let this = self.clone();
+        show_pending.connect_activate(move |_, _| {
+            this.view_stack.set_visible_child_name("pending");
+            this.refresh_pending();
+        });
+        self.window.add_action(&show_pending);
+
+        let update_pending = gio::SimpleAction::new("update-pending", None);
+        let this = self.clone();
+        update_pending.connect_activate(move |_, _| {
+            let this = this.clone();
+            glib::spawn_future_local(async move {
+                let schedule = ScheduleState::load(&ScheduleState::default_path())
+                    .await
+                    .unwrap_or_default();
+                this.view_stack.set_visible_child_name("sources");
+                this.start_run(schedule.pending_sources(), false);
+            });
+        });
+        self.window.add_action(&update_pending);
+
+        let update_security = gio::SimpleAction::new("update-security", None);
+        let this = self.clone();
+        update_security.connect_activate(move |_, _| {
+            let this = this.clone();
+            glib::spawn_future_local(async move {
+                let schedule = ScheduleState::load(&ScheduleState::default_path())
+                    .await
+                    .unwrap_or_default();
+                let (request, unsupported) =
+                    scheduler::security_request(&schedule, &this.state.updater);
+                if !unsupported.is_empty() {
+                    this.show_toast(&format!(
+                        "{} cannot install security fixes alone; update everything instead",
+                        unsupported.join(", ")
+                    ));
+                } else if request.sources.is_empty() {
+                    this.show_toast("No security updates are pending");
+                }
+                if !request.sources.is_empty() {
+                    this.view_stack.set_visible_child_name("sources");
+                    this.start_request(request);
+                }
+            });
+        });
+        self.window.add_action(&update_security);
+
+        // Started by the background scheduler while the window is open
+        let auto_update = gio::SimpleAction::new("auto-update", None);
+        let this = self.clone();
+        auto_update.connect_activate(move |_, _| {
+            if this.state.updater.is_running() {
+                return;
+            }
+            let this = this.clone();
+            glib::spawn_future_local(async move {
+                let conditions = background::probe_conditions().await;
+                match scheduler::auto_update_request(
+                    &this.state,
+                    &ScheduleState::default_path(),
+                    &conditions,
+                )
+                .await
+                {
+                    Ok(Some(request)) => this.launch(request).await,
+                    Ok(None) => {}
+                    Err(e) => error!("Automatic updates failed: {e}"),
+                }
+            });
+        });
+        self.window.add_action(&auto_update);
+
+        let update_selected =
+            gio::SimpleAction::new("update-selected", Some(glib::VariantTy::STRING));
+        let this = self.clone();
+        update_selected.connect_activate(move |_, parameter| {
+            let Some(source) = parameter.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            let packages = this.pending_view.selected(&source);
+            if packages.is_empty() {
+                return;
+            }
+
+            this.view_stack.set_visible_child_name("sources");
+            this.start_request(RunRequest {
+                sources: vec![source.clone()],
+                packages: HashMap::from([(source, packages)]),
+                trigger: RunTrigger::Manual,
+                ..RunRequest::default()
+            });
+        });
+        self.window.add_action(&update_selected);
+
+        let check_now = gio::SimpleAction::new("check-now", None);
+        let this = self.clone();
+        check_now.connect_activate(move |action, _| {
+            let this = this.clone();
+            let action = action.clone();
+            glib::spawn_future_local(async move {
+                action.set_enabled(false);
+                match scheduler::run_manual_check(&this.state, &ScheduleState::default_path()).await
+                {
+                    Ok(schedule) => {
+                        this.show_toast(&match schedule.pending_count() {
+                            0 => "No updates found".to_string(),
+                            1 => "1 update found".to_string(),
+                            n => format!("{n} updates found"),
+                        });
+                        this.refresh_pending();
+                        this.check_release(RunTrigger::Manual);
+                    }
+                    Err(e) => {
+                        error!("Failed to check for updates: {e}");
+                        this.show_toast("Failed to check for updates");
+                    }
+                }
+                action.set_enabled(true);
+            });
+        });
+        self.window.add_action(&check_now);
+
+        let cleanup = gio::SimpleAction::new("cleanup", None);
+        let this = self.clone();
+        cleanup.connect_activate(move |_, _| {
+            this.view_stack.set_visible_child_name("sources");
+            this.start_request(RunRequest {
+                sources: Self::collect_enabled_sources(&this.sources_list),
+                dry_run: this.dry_run_switch.is_active(),
+                mode: RunMode::Cleanup,
+                trigger: RunTrigger::Manual,
+                ..RunRequest::default()
+            });
+        });
+        self.window.add_action(&cleanup);
+
+        let undo = gio::SimpleAction::new("undo-last-update", None);
+        let this = self.clone();
+        undo.connect_activate(move |_, _| {
+            if this.state.updater.is_running() {
+                this.show_toast("Wait for the current run to finish");
+                return;
+            }
+            rollback::show_undo_dialog(&this.window, &this.toast_overlay, &this.state);
+        });
+        self.window.add_action(&undo);
+    }
+
+    /// Reloads the pending page from the cached check results.
+    pub fn refresh_pending(&self) {
+        let this = self.clone();
+        glib::spawn_future_local(async move {
+            let schedule = ScheduleState::load(&ScheduleState::default_path())
+                .await
+                .unwrap_or_default();
+            this.show_pending(&schedule);
+        });
+    }
+
+    fn show_pending(&self, schedule: &ScheduleState) {
+        self.pending_view.show(schedule, &self.state);
+        if let Some(action) = self
+            .window
+            .lookup_action("update-pending")
+            .and_then(|action| action.downcast::<gio::SimpleAction>().ok())
+        {
+            action.set_enabled(schedule.pending_count() > 0);
+        }
+        if let Some(action) = self
+            .window
+            .lookup_action("update-security")
+            .and_then(|action| action.downcast::<gio::SimpleAction>().ok())
+        {
+            action.set_enabled(schedule.security().count > 0);
+        }
+    }
+
+    fn show_toast(&self, message: &str) {
+        self.toast_overlay.add_toast(Toast::new(message));
+    }
+
+    fn load_sources(&self) {
         let state = self.state.clone();
         let sources_list = self.sources_list.clone();
 
@@ -303,18 +674,27 @@ impl MainWindow {
         sources_list.append(&row_container);
     }
 
-    async fn handle_updates(
-        receiver: Receiver<UpdateEvent>,
-        sources_list: ListBox,
-        start_button: Button,
-        stop_button: Button,
-    ) {
+    async fn handle_updates(&self, receiver: Receiver<UpdateEvent>, mut recorder: RunRecorder) {
+        let sources_list = &self.sources_list;
+        let app = self.window.application();
         let mut completed_count = 0;
         let mut failed_count = 0;
+        let mut reboot_required = false;
+        let mut reclaimed = None;
         while let Ok(event) = receiver.recv().await {
+            recorder.observe(&event);
             match event {
-                UpdateEvent::Started => {}
+                UpdateEvent::Started => {
+                    self.inhibit();
+                    if let Some(app) = &app {
+                        notifications::withdraw_stale(app);
+                    }
+                }
                 UpdateEvent::SourceStarted(name) => {
+                    self.clear_diagnoses(&name);
+                    if self.state.config.read().await.runs_in_terminal(&name) {
+                        self.attach_terminal(&name);
+                    }
                     Self::update_source_status(
                         sources_list.clone(),
                         name,
@@ -322,7 +702,8 @@ impl MainWindow {
                         true,
                     );
                 }
-                UpdateEvent::SourceProgress(name, _msg) => {
+                UpdateEvent::SourceProgress(name, msg) => {
+                    self.append_terminal(&name, &msg);
                     Self::update_source_status(
                         sources_list.clone(),
                         name,
@@ -345,28 +726,85 @@ impl MainWindow {
                         failed_count += 1;
                     }
                 }
-                UpdateEvent::SourceError(name, _msg) => {
+                // Sources often print warnings and progress to stderr and
+                // still succeed, so only SourceCompleted decides their status
+                UpdateEvent::SourceMessage(name, _, msg) => {
+                    self.append_terminal(&name, &msg);
+                }
+                UpdateEvent::SourcePrompt(name, prompt) => {
+                    self.append_terminal(&name, &prompt.question);
+                    self.ask(&name, prompt);
+                }
+                UpdateEvent::SourceDiagnosed(name, diagnosis) => {
+                    self.show_diagnosis(&name, diagnosis);
+                }
+                UpdateEvent::SourceBlocked(name, reason) => {
+                    self.show_toast(&format!("{name} was skipped: {reason}"));
                     Self::update_source_status(
                         sources_list.clone(),
                         name,
-                        "Error".to_string(),
+                        "Blocked".to_string(),
                         false,
                     );
-                    failed_count += 1;
+                }
+                UpdateEvent::Interrupted => {
+                    self.show_toast("Updates were stopped");
+                }
+                UpdateEvent::SourceCleanedUp(_, bytes) => {
+                    *reclaimed.get_or_insert(0) += bytes;
+                }
+                UpdateEvent::RebootRequired(reasons) => {
+                    reboot_required = true;
+                    self.show_reboot_banner(&reasons);
+                }
+                // A reboot restarts everything anyway
+                UpdateEvent::RestartNeeded(services) if !reboot_required => {
+                    self.show_restart_banner(services);
                 }
                 UpdateEvent::Completed(_success) => {
-                    start_button.set_sensitive(true);
-                    stop_button.set_sensitive(false);
+                    self.uninhibit();
+                    self.start_button.set_sensitive(true);
+                    self.stop_button.set_sensitive(false);
+
+                    if let Some(bytes) = reclaimed {
+                        self.show_toast(&format!("Cleanup reclaimed {}", format_bytes(bytes)));
+                    }
 
-                    // Show completion notification
-                    Self::show_completion_notification(completed_count, failed_count);
+                    // Show completion notification; automatic runs only report failures
+                    let record = recorder.finish();
+                    if let Some(app) = &app
+                        && self.state.config.read().await.show_notifications
+                    {
+                        if record.trigger != RunTrigger::Scheduled {
+                            notifications::show_run_completed(app, completed_count, failed_count);
+                        } else if !record.success() {
+                            notifications::show_auto_update_failed(app, &record.failed_sources());
+                        }
+                    }
 
-                    // TODO: Show banner - need to pass window reference for this
+                    // Updated sources are no longer pending
+                    match scheduler::apply_run(&ScheduleState::default_path(), &record).await {
+                        Ok(schedule) => self.show_pending(&schedule),
+                        Err(e) => error!("Failed to update pending list: {e}"),
+                    }
+                    // Updates may have left new versions of configuration files
+                    self.conffile_view.refresh();
                     break;
                 }
                 _ => {}
             }
         }
+        // The run may also end without completing, e.g. if the updater fails
+        self.uninhibit();
+
+        if self.quit_after_run.take() {
+            if let Some(app) = &app {
+                app.quit();
+            }
+        } else if !self.window.is_visible() {
+            // The user chose to let the run finish in the background
+            self.window.destroy();
+        }
     }
 
     fn update_source_status(
@@ -464,6 +902,7 @@ impl MainWindow {
                 (false, s) if s.contains("Failed") || s.contains("Error") || s.contains("✗") => {
                     ("dialog-error-symbolic", "error")
                 }
+                (false, s) if s.contains("Blocked") => ("changes-prevent-symbolic", "warning"),
                 _ => ("emblem-default-symbolic", ""),
             };
 
@@ -472,22 +911,276 @@ impl MainWindow {
         }
     }
 
-    fn show_completion_notification(completed: i32, failed: i32) {
-        let notification = gio::Notification::new("Updates Complete");
+    fn diagnosis_box(sources_list: &ListBox, source: &str) -> Option<Box> {
+        let (action_row, _) = Self::find_source_row(sources_list, source)?;
+        action_row
+            .last_child()
+            .and_then(|suffix_box| suffix_box.downcast::<Box>().ok())
+    }
 
-        let message = match (completed, failed) {
-            (0, 0) => "No updates were performed".to_string(),
-            (c, 0) => format!("Successfully updated {c} package manager(s)"),
-            (0, f) => format!("Failed to update {f} package manager(s)"),
-            (c, f) => format!("Updated {c} package manager(s), {f} failed"),
+    /// Shows a recognized failure next to the status icon of `source`.
+    fn show_diagnosis(&self, source: &str, diagnosis: Diagnosis) {
+        let Some(status_box) = Self::diagnosis_box(&self.sources_list, source) else {
+            return;
         };
 
-        notification.set_body(Some(&message));
-        notification.set_icon(&gio::ThemedIcon::new("system-software-update"));
+        let button = Button::builder()
+            .icon_name("dialog-question-symbolic")
+            .tooltip_text(diagnosis.title())
+            .valign(Align::Center)
+            .build();
+        button.add_css_class("flat");
+        button.add_css_class("diagnosis");
 
-        if let Some(app) = gio::Application::default() {
-            app.send_notification(Some("update-complete"), &notification);
+        let this = self.clone();
+        let source = source.to_string();
+        button.connect_clicked(move |_| this.explain_diagnosis(&source, diagnosis));
+
+        // The status icon stays first, where the status updates look for it
+        status_box.insert_child_after(&button, status_box.first_child().as_ref());
+    }
+
+    /// Removes the diagnoses of the previous run of `source`.
+    fn clear_diagnoses(&self, source: &str) {
+        let Some(status_box) = Self::diagnosis_box(&self.sources_list, source) else {
+            return;
+        };
+
+        let mut child = status_box.first_child();
+        while let Some(widget) = child {
+            child = widget.next_sibling();
+            if widget.has_css_class("diagnosis") {
+                status_box.remove(&widget);
+            }
+        }
+    }
+
+    /// Explains a recognized failure and offers its fix, if there is one.
+    fn explain_diagnosis(&self, source: &str, diagnosis: Diagnosis) {
+        let dialog = AlertDialog::new(Some(diagnosis.title()), Some(diagnosis.explanation()));
+        dialog.add_response("close", "Close");
+        dialog.set_close_response("close");
+
+        let can_clean_up = self
+            .state
+            .updater
+            .get_manager_info(source)
+            .is_some_and(|manager| manager.supports_cleanup());
+        if let Some(fix) = diagnosis
+            .fix()
+            .filter(|fix| *fix != Fix::Cleanup || can_clean_up)
+        {
+            dialog.add_response("fix", fix.label());
+            dialog.set_response_appearance("fix", ResponseAppearance::Suggested);
+            dialog.set_default_response(Some("fix"));
+
+            let this = self.clone();
+            let source = source.to_string();
+            dialog.connect_response(Some("fix"), move |_, _| this.apply_fix(&source, &fix));
         }
+        dialog.present(Some(&self.window));
+    }
+
+    fn apply_fix(&self, source: &str, fix: &Fix) {
+        if *fix == Fix::Cleanup {
+            self.start_request(RunRequest {
+                sources: vec![source.to_string()],
+                mode: RunMode::Cleanup,
+                trigger: RunTrigger::Manual,
+                ..RunRequest::default()
+            });
+            return;
+        }
+
+        let (this, source, fix) = (self.clone(), source.to_string(), fix.clone());
+        glib::spawn_future_local(async move {
+            let environment = this.state.config.read().await.child_environment.clone();
+            match fix.apply(&environment).await {
+                Ok(()) => this.show_toast(&format!("Fixed, {source} can be updated again")),
+                Err(e) => {
+                    error!("{e}");
+                    this.show_toast(&format!("Failed to fix {source}"));
+                }
+            }
+        });
+    }
+
+    /// Starts collecting the output of `source` and offers to show it.
+    fn attach_terminal(&self, source: &str) {
+        self.terminals
+            .borrow_mut()
+            .insert(source.to_string(), gtk::TextBuffer::new(None));
+
+        let Some(status_box) = Self::diagnosis_box(&self.sources_list, source) else {
+            return;
+        };
+        let mut child = status_box.first_child();
+        while let Some(widget) = child {
+            if widget.has_css_class("terminal") {
+                return;
+            }
+            child = widget.next_sibling();
+        }
+
+        let button = Button::builder()
+            .icon_name("utilities-terminal-symbolic")
+            .tooltip_text("Show Terminal")
+            .valign(Align::Center)
+            .build();
+        button.add_css_class("flat");
+        button.add_css_class("terminal");
+
+        let this = self.clone();
+        let source = source.to_string();
+        button.connect_clicked(move |_| this.open_terminal(&source));
+        status_box.insert_child_after(&button, status_box.first_child().as_ref());
+    }
+
+    fn append_terminal(&self, source: &str, line: &str) {
+        if let Some(buffer) = self.terminals.borrow().get(source) {
+            buffer.insert(&mut buffer.end_iter(), &format!("{line}\n"));
+        }
+    }
+
+    /// Asks the user a question of `source` and writes the answer to its terminal.
+    ///
+    /// Closing the dialog leaves the question unanswered; it can still be
+    /// answered in the terminal view.
+    fn ask(&self, source: &str, prompt: Prompt) {
+        let body = if prompt.recognized {
+            prompt.question.clone()
+        } else {
+            format!("{source} seems to wait for input:\n{}", prompt.question)
+        };
+        let dialog = AlertDialog::new(Some(&format!("{source} Asks")), Some(&body));
+        dialog.add_response("terminal", "Open Terminal");
+
+        let content = Box::new(Orientation::Vertical, 12);
+        if !prompt.context.is_empty() {
+            let context = gtk::Label::builder()
+                .label(prompt.context.join("\n"))
+                .xalign(0.0)
+                .wrap(true)
+                .selectable(true)
+                .build();
+            context.add_css_class("monospace");
+            content.append(&context);
+        }
+
+        let entry = gtk::Entry::new();
+        match &prompt.kind {
+            PromptKind::Confirm => {
+                dialog.add_response("n", "No");
+                dialog.add_response("y", "Yes");
+            }
+            PromptKind::Choice(choices) => {
+                for choice in choices {
+                    dialog.add_response(choice, choice);
+                }
+            }
+            PromptKind::Text => {
+                entry.set_text(prompt.default.as_deref().unwrap_or_default());
+                content.append(&entry);
+                dialog.add_response("answer", "Answer");
+                dialog.set_response_appearance("answer", ResponseAppearance::Suggested);
+                dialog.set_default_response(Some("answer"));
+            }
+        }
+        if let Some(default) = &prompt.default
+            && prompt.kind != PromptKind::Text
+            && dialog.has_response(default)
+        {
+            dialog.set_response_appearance(default, ResponseAppearance::Suggested);
+            dialog.set_default_response(Some(default));
+        }
+        if content.first_child().is_some() {
+            dialog.set_extra_child(Some(&content));
+        }
+
+        let this = self.clone();
+        let source = source.to_string();
+        dialog.connect_response(None, move |_, response| match response {
+            "close" => {}
+            "terminal" => this.open_terminal(&source),
+            "answer" => this.send_answer(entry.text().to_string()),
+            // Confirmations and choices use the answer as the response id
+            answer => this.send_answer(answer.to_string()),
+        });
+        dialog.present(Some(&self.window));
+    }
+
+    fn send_answer(&self, answer: String) {
+        let this = self.clone();
+        glib::spawn_future_local(async move {
+            if let Err(e) = this.state.updater.answer(&answer).await {
+                error!("{e}");
+                this.show_toast("The answer could not be sent");
+            }
+        });
+    }
+
+    /// Shows the output of `source` like a terminal, with a line to type input into.
+    fn open_terminal(&self, source: &str) {
+        let Some(buffer) = self.terminals.borrow().get(source).cloned() else {
+            return;
+        };
+
+        let view = gtk::TextView::builder()
+            .buffer(&buffer)
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .wrap_mode(gtk::WrapMode::WordChar)
+            .top_margin(6)
+            .bottom_margin(6)
+            .left_margin(6)
+            .right_margin(6)
+            .build();
+        let end = buffer.create_mark(None, &buffer.end_iter(), false);
+        view.scroll_to_mark(&end, 0.0, false, 0.0, 1.0);
+        buffer.connect_changed(glib::clone!(
+            #[weak]
+            view,
+            #[weak]
+            end,
+            move |_| view.scroll_to_mark(&end, 0.0, false, 0.0, 1.0)
+        ));
+
+        let scrolled = gtk::ScrolledWindow::builder()
+            .child(&view)
+            .vexpand(true)
+            .build();
+        scrolled.add_css_class("card");
+
+        let entry = gtk::Entry::builder()
+            .placeholder_text("Type an answer and press Enter")
+            .build();
+        let this = self.clone();
+        entry.connect_activate(move |entry| {
+            this.send_answer(entry.text().to_string());
+            entry.set_text("");
+        });
+
+        let content = Box::new(Orientation::Vertical, 12);
+        content.set_margin_top(12);
+        content.set_margin_bottom(12);
+        content.set_margin_start(12);
+        content.set_margin_end(12);
+        content.append(&scrolled);
+        content.append(&entry);
+
+        let toolbar = libadwaita::ToolbarView::new();
+        toolbar.add_top_bar(&libadwaita::HeaderBar::new());
+        toolbar.set_content(Some(&content));
+
+        let dialog = libadwaita::Dialog::builder()
+            .title(format!("{source} Terminal"))
+            .content_width(720)
+            .content_height(480)
+            .child(&toolbar)
+            .build();
+        dialog.present(Some(&self.window));
+        entry.grab_focus();
     }
 
     /// Shows a banner with the specified message and type.
@@ -504,9 +1197,9 @@ impl MainWindow {
     /// ```ignore
     /// window.show_banner("Updates completed successfully!", BannerType::Success);
     /// ```
-    pub fn show_banner(&mut self, message: &str, banner_type: BannerType) {
+    pub fn show_banner(&self, message: &str, banner_type: BannerType) -> Banner {
         // Remove the existing banner if present
-        if let Some(ref current_banner) = self.current_banner {
+        if let Some(ref current_banner) = *self.current_banner.borrow() {
             self.main_box.remove(current_banner);
         }
 
@@ -521,7 +1214,7 @@ impl MainWindow {
 
         // Add a banner at the top of the main box
         self.main_box.prepend(&banner);
-        self.current_banner = Some(banner);
+        self.current_banner.replace(Some(banner.clone()));
 
         tracing::debug!(
             "Showed {} banner: {}",
@@ -533,11 +1226,282 @@ impl MainWindow {
             },
             message
         );
+        banner
+    }
+
+    /// Tells the user that the updates need a reboot and offers to restart.
+    fn show_reboot_banner(&self, reasons: &[String]) {
+        let banner = self.show_banner(
+            "A restart is required to finish applying updates",
+            BannerType::Warning,
+        );
+        banner.set_button_label(Some("Restart…"));
+        banner.set_tooltip_text(Some(&reasons.join("\n")));
+
+        let window = self.window.clone();
+        banner.connect_button_clicked(move |_| {
+            Self::confirm_reboot(&window);
+        });
+    }
+
+    fn confirm_reboot(window: &ApplicationWindow) {
+        let dialog = AlertDialog::new(
+            Some("Restart Now?"),
+            Some("Unsaved work in other applications will be lost."),
+        );
+        dialog.add_responses(&[("cancel", "Cancel"), ("restart", "Restart")]);
+        dialog.set_response_appearance("restart", ResponseAppearance::Destructive);
+        dialog.set_default_response(Some("cancel"));
+        dialog.set_close_response("cancel");
+
+        dialog.connect_response(Some("restart"), |_, _| {
+            glib::spawn_future_local(async {
+                if let Err(e) = Self::request_reboot().await {
+                    error!("Failed to restart: {e}");
+                }
+            });
+        });
+        dialog.present(Some(window));
+    }
+
+    /// Tells the user that running services still use outdated libraries.
+    fn show_restart_banner(&self, services: Vec<StaleService>) {
+        let message = match services.len() {
+            1 => format!("{} still uses outdated libraries", services[0].name()),
+            n => format!("{n} services and applications still use outdated libraries"),
+        };
+        let banner = self.show_banner(&message, BannerType::Info);
+        banner.set_button_label(Some("Review…"));
+
+        let this = self.clone();
+        banner.connect_button_clicked(move |_| {
+            this.show_stale_services(&services);
+        });
+    }
+
+    /// Lists the services using outdated libraries and offers to restart them.
+    fn show_stale_services(&self, services: &[StaleService]) {
+        let dialog = AlertDialog::new(
+            Some("Restart Services"),
+            Some("These programs were started before the update and still use the old libraries."),
+        );
+        dialog.add_response("close", "Close");
+        dialog.set_close_response("close");
+
+        let list = ListBox::new();
+        list.add_css_class("boxed-list");
+        list.set_selection_mode(gtk::SelectionMode::None);
+
+        for service in services {
+            let row = ActionRow::builder()
+                .title(service.name())
+                .subtitle(service.libraries().join(", "))
+                .build();
+            row.set_subtitle_lines(2);
+
+            if service.can_restart() {
+                let button = Button::builder()
+                    .label("Restart")
+                    .valign(Align::Center)
+                    .build();
+                let service = service.clone();
+                let this = self.clone();
+                button.connect_clicked(move |button| {
+                    let (service, this, button) = (service.clone(), this.clone(), button.clone());
+                    button.set_sensitive(false);
+                    glib::spawn_future_local(async move {
+                        match service.restart().await {
+                            Ok(()) => button.set_label("Restarted"),
+                            Err(e) => {
+                                error!("{e}");
+                                this.show_toast(&format!("Failed to restart {}", service.name()));
+                                button.set_sensitive(true);
+                            }
+                        }
+                    });
+                });
+                row.add_suffix(&button);
+            } else {
+                let hint = match service.unit {
+                    Some(_) => "Log out or restart",
+                    None => "Restart the application",
+                };
+                let label = gtk::Label::new(Some(hint));
+                label.add_css_class("dim-label");
+                row.add_suffix(&label);
+            }
+            list.append(&row);
+        }
+
+        let scrolled = gtk::ScrolledWindow::builder()
+            .child(&list)
+            .propagate_natural_height(true)
+            .max_content_height(360)
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .build();
+        dialog.set_extra_child(Some(&scrolled));
+        dialog.present(Some(&self.window));
+    }
+
+    /// Looks for a run that never finished, e.g. because the system lost power,
+    /// and offers to repair what it left behind.
+    fn check_interrupted_run(&self) {
+        let this = self.clone();
+        glib::spawn_future_local(async move {
+            let marker =
+                match RunMarker::take_stale(&RunMarker::default_path(), &History::default_path())
+                    .await
+                {
+                    Ok(Some(marker)) => marker,
+                    Ok(None) => return,
+                    Err(e) => {
+                        error!("Failed to check for an interrupted run: {e}");
+                        return;
+                    }
+                };
+
+            let mut leftovers = marker.leftovers();
+            leftovers.extend(LeftoverScan::new().scan(&marker.sources).await);
+            if leftovers.is_empty() {
+                this.show_banner(
+                    "The last update did not finish, but no problems were found",
+                    BannerType::Info,
+                );
+                return;
+            }
+
+            let banner = this.show_banner(
+                "The last update did not finish and needs to be repaired",
+                BannerType::Warning,
+            );
+            banner.set_button_label(Some("Review…"));
+            let this = this.clone();
+            banner.connect_button_clicked(move |_| {
+                this.show_leftovers(&leftovers);
+            });
+        });
+    }
+
+    /// Lists what an interrupted run left behind and offers to repair it.
+    fn show_leftovers(&self, leftovers: &[Leftover]) {
+        let dialog = AlertDialog::new(
+            Some("Repair Interrupted Update"),
+            Some(
+                "Repair these problems before updating again. Repairs of system packages \
+                 run as administrator.",
+            ),
+        );
+        dialog.add_response("close", "Close");
+        dialog.set_close_response("close");
+
+        let list = ListBox::new();
+        list.add_css_class("boxed-list");
+        list.set_selection_mode(gtk::SelectionMode::None);
+
+        for leftover in leftovers {
+            let row = ActionRow::builder()
+                .title(&leftover.problem)
+                .subtitle(leftover.repair_command())
+                .build();
+            row.set_subtitle_lines(2);
+
+            let button = Button::builder()
+                .label("Repair")
+                .valign(Align::Center)
+                .build();
+            let leftover = leftover.clone();
+            let this = self.clone();
+            button.connect_clicked(move |button| {
+                let (leftover, this, button) = (leftover.clone(), this.clone(), button.clone());
+                button.set_sensitive(false);
+                glib::spawn_future_local(async move {
+                    let environment = this.state.config.read().await.child_environment.clone();
+                    match leftover.repair(&environment).await {
+                        Ok(()) => button.set_label("Repaired"),
+                        Err(e) => {
+                            error!("{e}");
+                            this.show_toast(&format!("Failed to repair {}", leftover.source));
+                            button.set_sensitive(true);
+                        }
+                    }
+                });
+            });
+            row.add_suffix(&button);
+            list.append(&row);
+        }
+
+        let scrolled = gtk::ScrolledWindow::builder()
+            .child(&list)
+            .propagate_natural_height(true)
+            .max_content_height(360)
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .build();
+        dialog.set_extra_child(Some(&scrolled));
+        dialog.present(Some(&self.window));
+    }
+
+    fn setup_release_banner(&self) {
+        let this = self.clone();
+        self.release_banner.connect_button_clicked(move |_| {
+            let Some(upgrade) = this.release_upgrade.borrow().clone() else {
+                return;
+            };
+            if this.state.updater.is_running() {
+                this.show_toast("Wait for the current run to finish");
+                return;
+            }
+            release::show_upgrade(&this, &upgrade);
+        });
+    }
+
+    /// Looks for a new release of the distribution and offers the upgrade.
+    /// Unless asked for, the check waits for the conditions of a scheduled one.
+    fn check_release(&self, trigger: RunTrigger) {
+        let this = self.clone();
+        glib::spawn_future_local(async move {
+            let check = ReleaseCheck::new();
+            let distribution = check.os_release().and_then(|os| os.distribution());
+            if let (RunTrigger::Scheduled, Some(distribution)) = (trigger, distribution) {
+                let conditions = background::probe_conditions().await;
+                let config = this.state.config.read().await.clone();
+                if let Some(reason) =
+                    conditions.deferral_reason(&config, distribution.source(), Phase::Check)
+                {
+                    info!("Not looking for a new release: {reason}");
+                    return;
+                }
+            }
+            let upgrade = check.run().await;
+            if let Some(upgrade) = &upgrade {
+                this.release_banner
+                    .set_title(&format!("{} is available", upgrade.target_name()));
+            }
+            this.release_banner.set_revealed(upgrade.is_some());
+            this.release_upgrade.replace(upgrade);
+        });
+    }
+
+    /// Asks logind to reboot, letting polkit ask for authorization if needed.
+    pub(crate) async fn request_reboot() -> Result<(), glib::Error> {
+        let connection = gio::bus_get_future(gio::BusType::System).await?;
+        connection
+            .call_future(
+                Some("org.freedesktop.login1"),
+                "/org/freedesktop/login1",
+                "org.freedesktop.login1.Manager",
+                "Reboot",
+                Some(&(true,).to_variant()),
+                None,
+                gio::DBusCallFlags::ALLOW_INTERACTIVE_AUTHORIZATION,
+                -1,
+            )
+            .await?;
+        Ok(())
     }
 
     /// Hides the current banner if one is visible.
-    pub fn hide_banner(&mut self) {
-        if let Some(ref current_banner) = self.current_banner {
+    pub fn hide_banner(&self) {
+        if let Some(current_banner) = self.current_banner.take() {
             current_banner.set_revealed(false);
 
             // Remove banner after animation completes
@@ -548,7 +1512,6 @@ impl MainWindow {
                 main_box_clone.remove(&banner_clone);
             });
 
-            self.current_banner = None;
             tracing::debug!("Hidden banner");
         }
     }
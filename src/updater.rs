@@ -8,15 +8,34 @@ use async_std::{
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
     process::Stdio,
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
+    time::{Duration, Instant},
 };
 use tracing::{error, info, warn};
 
+use crate::{
+    advisories::{annotate, parse_advisories},
+    cleanup,
+    diagnosis::Diagnosis,
+    environment::{ChildEnv, Pass},
+    history::{History, RunRecorder, RunTrigger},
+    pending::{PendingUpdate, parse_check_output},
+    preflight::{Blocker, LOCK_POLL_INTERVAL, LOCK_WAIT_TIMEOUT, Lock, Preflight, Requirements},
+    pty::{PROMPT_IDLE, Prompt, Pty, Transcript},
+    reboot::RebootCheck,
+    recovery::RunMarker,
+    restart::{StaleLibraryScan, StaleService},
+    rollback::{Inventory, Rollback},
+    snapshot::{Snapshot, SnapshotKind, Snapshotter},
+};
+
 #[derive(Debug, Clone)]
 pub enum UpdateEvent {
     Started,
@@ -24,11 +43,95 @@ pub enum UpdateEvent {
     SourceStarted(String),
     SourceProgress(String, String), // (source_name, message)
     SourceCompleted(String, bool),
-    SourceError(String, String), // (source_name, error_message)
+    /// A line the source printed to stderr, or a failure reported by the run itself.
+    SourceMessage(String, Severity, String), // (source_name, severity, message)
+    /// An error line of the source was recognized as a known failure; sent once per run.
+    SourceDiagnosed(String, Diagnosis),
+    SourceSnapshot(String, Snapshot),
+    /// The source asked a question on its terminal; see [`Updater::answer`].
+    SourcePrompt(String, Prompt),
+    /// The source was skipped because the preflight failed; it does not complete.
+    SourceBlocked(String, String), // (source_name, reason)
+    RebootRequired(Vec<String>), // reasons
+    RestartNeeded(Vec<StaleService>),
+    SourceCleanedUp(String, u64), // (source_name, reclaimed_bytes)
+    /// The update of the source can be undone as described.
+    SourceRollback(String, Rollback),
+    /// The run was stopped before all sources were processed; `Completed` follows.
+    Interrupted,
     Completed(bool),
     Error(String),
 }
 
+/// How severe a line printed by a package manager is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Classifies the lines a package manager prints to stderr by their prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineClassifier {
+    /// Lowercase line prefixes, checked in order after leading whitespace.
+    pub prefixes: Vec<(String, Severity)>,
+    /// Severity of lines without a known prefix, e.g. progress output.
+    pub fallback: Severity,
+}
+
+impl Default for LineClassifier {
+    /// Most tools prefix their diagnostics with `error:` and `warning:`.
+    fn default() -> Self {
+        Self::new(
+            &[("error", Severity::Error), ("warning", Severity::Warning)],
+            Severity::Info,
+        )
+    }
+}
+
+impl LineClassifier {
+    pub fn new(prefixes: &[(&str, Severity)], fallback: Severity) -> Self {
+        Self {
+            prefixes: prefixes
+                .iter()
+                .map(|(prefix, severity)| (prefix.to_string(), *severity))
+                .collect(),
+            fallback,
+        }
+    }
+
+    /// Returns the severity of `line`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::updater::{LineClassifier, Severity};
+    ///
+    /// let classifier = LineClassifier::default();
+    /// assert_eq!(classifier.classify("error: failed to commit transaction"), Severity::Error);
+    /// assert_eq!(classifier.classify("Downloading 3 updates"), Severity::Info);
+    /// ```
+    pub fn classify(&self, line: &str) -> Severity {
+        let line = line.trim_start().to_lowercase();
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| line.starts_with(prefix.as_str()))
+            .map_or(self.fallback, |(_, severity)| *severity)
+    }
+}
+
+/// How [`Updater::stop`] ends a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The processes of the run were interrupted and have exited.
+    Stopped,
+    /// The current step runs as root and cannot be interrupted; the run ends
+    /// once it finishes.
+    AfterCurrentStep,
+}
+
 #[derive(Debug, Clone)]
 pub enum SourceState {
     Idle,
@@ -37,6 +140,45 @@ pub enum SourceState {
     Failed,
 }
 
+/// How a package manager keeps excluded packages from being updated.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExclusionMethod {
+    /// The manager cannot skip individual packages.
+    #[default]
+    Unsupported,
+    /// A flag followed by a comma-separated list, e.g. `--ignore a,b`.
+    ListFlag(String),
+    /// A flag followed by one argument per package, e.g. `--skip a b`.
+    ArgsFlag(String),
+    /// Packages are held for the duration of the update and released afterwards,
+    /// e.g. `apt-mark hold` / `apt-mark unhold`. Only installed packages can be
+    /// held, and those the user held already are left alone; `installed` and
+    /// `held` list them, one name at the start of each line.
+    Hold {
+        hold: String,
+        release: String,
+        #[serde(default)]
+        installed: Vec<String>,
+        #[serde(default)]
+        held: Vec<String>,
+    },
+}
+
+/// How a package manager updates selected packages without a full upgrade.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TargetedUpdate {
+    /// The selected packages are appended to `cmd`, each followed by `suffix`.
+    Command { cmd: Vec<String>, suffix: String },
+    /// Updating single packages is unsafe with this manager; the reason is shown to the user.
+    Blocked(String),
+}
+
+impl Default for TargetedUpdate {
+    fn default() -> Self {
+        Self::Blocked("Updating single packages is not supported".to_string())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageManager {
     pub description: String,
@@ -44,6 +186,36 @@ pub struct PackageManager {
     pub update_cmd: Vec<String>,
     pub needs_sudo: bool,
     pub name: String,
+    #[serde(default)]
+    pub exclusion: ExclusionMethod,
+    #[serde(default)]
+    pub targeted: TargetedUpdate,
+    /// Removes orphans, caches and unused runtimes; empty if the manager has no cleanup.
+    #[serde(default)]
+    pub cleanup_cmd: Vec<String>,
+    /// Locks and free space checked before the manager runs.
+    #[serde(default)]
+    pub preflight: Requirements,
+    /// Decides which lines printed to stderr are errors, warnings or progress.
+    #[serde(default)]
+    pub stderr: LineClassifier,
+    /// Options that turn off coloured output, inserted after the program name.
+    #[serde(default)]
+    pub color_args: Vec<String>,
+    /// Variables the manager needs on top of the default child environment.
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Options that answer questions with their defaults; dropped when the
+    /// user can answer them on a terminal.
+    #[serde(default)]
+    pub unattended_args: Vec<String>,
+    /// Lists the security advisories of pending updates; empty if the
+    /// manager's check output already tells them apart or it has none.
+    #[serde(default)]
+    pub advisory_cmd: Vec<String>,
+    /// Installs only the updates that fix security issues; empty if unsupported.
+    #[serde(default)]
+    pub security_cmd: Vec<String>,
 }
 
 impl PackageManager {
@@ -54,15 +226,385 @@ impl PackageManager {
             update_cmd: update.iter().map(|s| s.to_string()).collect(),
             needs_sudo: sudo,
             name: name.to_string(),
+            exclusion: ExclusionMethod::Unsupported,
+            targeted: TargetedUpdate::default(),
+            cleanup_cmd: Vec::new(),
+            preflight: Requirements::default(),
+            stderr: LineClassifier::default(),
+            color_args: Vec::new(),
+            env: Vec::new(),
+            unattended_args: Vec::new(),
+            advisory_cmd: Vec::new(),
+            security_cmd: Vec::new(),
+        }
+    }
+
+    fn with_exclusion(mut self, exclusion: ExclusionMethod) -> Self {
+        self.exclusion = exclusion;
+        self
+    }
+
+    fn with_targeted(mut self, targeted: TargetedUpdate) -> Self {
+        self.targeted = targeted;
+        self
+    }
+
+    fn with_cleanup(mut self, cleanup: &[&str]) -> Self {
+        self.cleanup_cmd = cleanup.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn with_preflight(mut self, preflight: Requirements) -> Self {
+        self.preflight = preflight;
+        self
+    }
+
+    fn with_stderr(mut self, prefixes: &[(&str, Severity)]) -> Self {
+        self.stderr = LineClassifier::new(prefixes, Severity::Info);
+        self
+    }
+
+    fn with_color_args(mut self, args: &[&str]) -> Self {
+        self.color_args = args.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn with_env(mut self, env: &[(&str, &str)]) -> Self {
+        self.env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self
+    }
+
+    fn with_unattended_args(mut self, args: &[&str]) -> Self {
+        self.unattended_args = args.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn with_advisories(mut self, cmd: &[&str]) -> Self {
+        self.advisory_cmd = cmd.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn with_security_update(mut self, cmd: &[&str]) -> Self {
+        self.security_cmd = cmd.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Returns `true` if the manager can install only the updates that fix
+    /// security issues.
+    pub fn supports_security_only(&self) -> bool {
+        !self.security_cmd.is_empty()
+    }
+
+    /// Returns `cmd` without the options that answer questions on the user's behalf.
+    pub fn interactive(&self, cmd: &[String]) -> Vec<String> {
+        cmd.iter()
+            .filter(|arg| !self.unattended_args.contains(arg))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns `cmd` with colours turned off if it runs the manager's own program.
+    ///
+    /// Shell scripts are left alone; they rely on the environment instead.
+    pub fn colorless(&self, cmd: &[String]) -> Vec<String> {
+        let mut cmd = cmd.to_vec();
+        if !cmd.is_empty() && cmd.first() == self.check_cmd.first() {
+            cmd.splice(1..1, self.color_args.iter().cloned());
+        }
+        cmd
+    }
+
+    /// Returns `true` if the manager has a cleanup operation.
+    pub fn supports_cleanup(&self) -> bool {
+        !self.cleanup_cmd.is_empty()
+    }
+
+    /// Returns why single packages cannot be updated, or `None` if they can.
+    pub fn targeted_blocked_reason(&self) -> Option<&str> {
+        match &self.targeted {
+            TargetedUpdate::Command { .. } => None,
+            TargetedUpdate::Blocked(reason) => Some(reason),
+        }
+    }
+
+    /// Builds the command that updates only the given `packages`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no package is given, a package name is invalid or
+    /// the manager does not allow updating single packages.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::updater::Updater;
+    ///
+    /// let updater = Updater::new();
+    /// let npm = updater.get_manager_info("npm").unwrap();
+    /// let cmd = npm.targeted_command(&["typescript".to_string()]).unwrap();
+    /// assert_eq!(cmd, ["npm", "install", "-g", "typescript@latest"]);
+    ///
+    /// let paru = updater.get_manager_info("paru").unwrap();
+    /// assert!(paru.targeted_command(&["linux".to_string()]).is_err());
+    /// ```
+    pub fn targeted_command(&self, packages: &[String]) -> Result<Vec<String>> {
+        let (cmd, suffix) = match &self.targeted {
+            TargetedUpdate::Command { cmd, suffix } => (cmd, suffix),
+            TargetedUpdate::Blocked(reason) => {
+                return Err(anyhow::anyhow!("{}: {}", self.name, reason));
+            }
+        };
+
+        if packages.is_empty() {
+            return Err(anyhow::anyhow!("No packages selected for {}", self.name));
+        }
+
+        let mut cmd = cmd.clone();
+        for package in packages {
+            validate_package_name(package)?;
+            cmd.push(format!("{package}{suffix}"));
+        }
+        Ok(cmd)
+    }
+
+    /// Returns `true` if the manager can skip individual packages during an update.
+    pub fn supports_exclusions(&self) -> bool {
+        self.exclusion != ExclusionMethod::Unsupported
+    }
+
+    /// Builds the update command that leaves the `excluded` packages untouched.
+    ///
+    /// Package names are never spliced into shell scripts; they are passed as
+    /// separate arguments. Managers without an exclusion mechanism get their
+    /// plain update command.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a package name is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::updater::Updater;
+    ///
+    /// let updater = Updater::new();
+    /// let paru = updater.get_manager_info("paru").unwrap();
+    /// let cmd = paru.update_command(&["linux".to_string()]).unwrap();
+    /// assert_eq!(cmd, ["paru", "-Syu", "--noconfirm", "--ignore", "linux"]);
+    /// ```
+    pub fn update_command(&self, excluded: &[String]) -> Result<Vec<String>> {
+        self.command_excluding(&self.update_cmd, excluded)
+    }
+
+    /// Builds the command that installs only security fixes, leaving the
+    /// `excluded` packages untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manager cannot tell security fixes apart or a
+    /// package name is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::updater::Updater;
+    ///
+    /// let updater = Updater::new();
+    /// let dnf = updater.get_manager_info("dnf").unwrap();
+    /// let cmd = dnf.security_command(&[]).unwrap();
+    /// assert_eq!(cmd, ["dnf", "upgrade", "--security", "-y"]);
+    ///
+    /// let paru = updater.get_manager_info("paru").unwrap();
+    /// assert!(paru.security_command(&[]).is_err());
+    /// ```
+    pub fn security_command(&self, excluded: &[String]) -> Result<Vec<String>> {
+        if !self.supports_security_only() {
+            return Err(anyhow::anyhow!(
+                "{} cannot install only security updates",
+                self.name
+            ));
+        }
+        self.command_excluding(&self.security_cmd, excluded)
+    }
+
+    fn command_excluding(&self, base: &[String], excluded: &[String]) -> Result<Vec<String>> {
+        for package in excluded {
+            validate_package_name(package)?;
+        }
+
+        if excluded.is_empty() {
+            return Ok(base.to_vec());
+        }
+
+        let mut cmd = base.to_vec();
+        match &self.exclusion {
+            ExclusionMethod::Unsupported => {}
+            ExclusionMethod::ListFlag(flag) => {
+                cmd.push(flag.clone());
+                cmd.push(excluded.join(","));
+            }
+            ExclusionMethod::ArgsFlag(flag) => {
+                cmd.push(flag.clone());
+                cmd.extend(excluded.iter().cloned());
+            }
+            ExclusionMethod::Hold { hold, release, .. } => {
+                let update = match base {
+                    [shell, flag, script] if shell == "sh" && flag == "-c" => script.clone(),
+                    other => other.join(" "),
+                };
+                cmd = vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    format!(
+                        "{hold} \"$@\" && {{ {update}; status=$?; {release} \"$@\"; exit $status; }}"
+                    ),
+                    "sh".to_string(),
+                ];
+                cmd.extend(excluded.iter().cloned());
+            }
         }
+        Ok(cmd)
+    }
+
+    /// Returns the command that releases the `packages` held by
+    /// [`update_command`](Self::update_command), or `None` if the manager does
+    /// not hold packages.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use uptodate::updater::Updater;
+    ///
+    /// let updater = Updater::new();
+    /// let apt = updater.get_manager_info("apt").unwrap();
+    /// let cmd = apt.release_command(&["firefox".to_string()]).unwrap();
+    /// assert_eq!(cmd, ["apt-mark", "unhold", "firefox"]);
+    /// ```
+    pub fn release_command(&self, packages: &[String]) -> Option<Vec<String>> {
+        let ExclusionMethod::Hold { release, .. } = &self.exclusion else {
+            return None;
+        };
+        let mut cmd: Vec<String> = release.split_whitespace().map(str::to_string).collect();
+        cmd.extend(packages.iter().cloned());
+        Some(cmd)
     }
 }
 
+/// Returns the `excluded` packages a run has to hold: those listed in
+/// `installed` that are not listed in `held` already. Both are the output of
+/// the manager's listing commands, with a package name at the start of each line.
+fn new_holds(excluded: &[String], installed: &str, held: &str) -> Vec<String> {
+    let names = |output: &str| -> HashSet<String> {
+        output
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect()
+    };
+    let (installed, held) = (names(installed), names(held));
+    excluded
+        .iter()
+        .filter(|package| installed.contains(*package) && !held.contains(*package))
+        .cloned()
+        .collect()
+}
+
+/// What a run does with each source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunMode {
+    #[default]
+    Update,
+    /// Update, then clean up the sources that were updated successfully.
+    UpdateAndCleanup,
+    /// Only remove orphans, caches and unused runtimes.
+    Cleanup,
+    /// Only install updates that fix security issues, where the manager
+    /// supports it.
+    SecurityOnly,
+    /// Upgrade the distribution to a new release, one guided step at a time.
+    ReleaseUpgrade,
+}
+
+impl RunMode {
+    pub fn updates(&self) -> bool {
+        *self != Self::Cleanup
+    }
+
+    pub fn cleans_up(&self) -> bool {
+        matches!(self, Self::UpdateAndCleanup | Self::Cleanup)
+    }
+}
+
+/// Parameters of a single run.
+#[derive(Debug, Clone, Default)]
+pub struct RunRequest {
+    pub sources: Vec<String>,
+    pub dry_run: bool,
+    pub trigger: RunTrigger,
+    pub mode: RunMode,
+    /// Packages to leave untouched, keyed by source.
+    pub exclusions: HashMap<String, Vec<String>>,
+    /// Sources listed here only update the given packages instead of everything.
+    pub packages: HashMap<String, Vec<String>>,
+    /// Snapshot the root filesystem before and after updating system packages.
+    pub snapshots: bool,
+    /// Critical packages the run is about to update, keyed by source. Once
+    /// updated they are reported as needing a reboot.
+    pub critical: HashMap<String, Vec<String>>,
+    /// Snapshot system sources with critical updates if a snapshot tool is
+    /// available, even without `snapshots`.
+    pub critical_snapshots: bool,
+    /// Wait for package manager locks held by other programs instead of
+    /// reporting the source as blocked right away.
+    pub wait_for_locks: bool,
+    /// Overrides of the environment package managers run in; an empty value
+    /// removes the variable.
+    pub environment: HashMap<String, String>,
+    /// Sources run under a pseudo-terminal, so that their questions can be answered.
+    pub terminal: Vec<String>,
+}
+
+/// Keeps the [`Updater`] busy while it is held; see [`Updater::reserve`].
+#[derive(Debug)]
+pub struct Reservation {
+    running: Arc<AtomicBool>,
+    marker_path: PathBuf,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.marker_path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove run marker: {}", e);
+        }
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// How the commands of a source are started.
+#[derive(Debug)]
+struct Launch {
+    env: ChildEnv,
+    /// Run under a pseudo-terminal and report the questions asked on it.
+    terminal: bool,
+    /// Where answers to the questions are written.
+    input: Arc<Mutex<Option<File>>>,
+}
+
 #[derive(Debug)]
 pub struct Updater {
     running: Arc<AtomicBool>,
     child_pids: Arc<Mutex<Vec<u32>>>,
     managers: HashMap<String, PackageManager>,
+    history_path: PathBuf,
+    marker_path: PathBuf,
+    /// The terminal of the source that is running under a pseudo-terminal.
+    terminal_input: Arc<Mutex<Option<File>>>,
 }
 
 impl Default for Updater {
@@ -71,9 +613,31 @@ impl Default for Updater {
     }
 }
 
+/// How long the output of a source that ran under a pseudo-terminal is
+/// still read after it exited.
+const TERMINAL_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long interrupted processes get to exit before they are killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often [`Updater::stop`] checks whether interrupted processes exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Managers of the distribution's system packages; at most one is used.
+const SYSTEM_MANAGERS: &[&str] = &["paru", "apt", "dnf", "zypper", "apk"];
+
+/// Managers that run as root through pkexec.
+pub const PRIVILEGED_MANAGERS: &[&str] = &["paru", "apt", "dnf", "zypper", "apk", "snap"];
+
+/// Managers that need someone to authorize them: those running as root, and
+/// fwupd, which asks polkit before flashing firmware. Nobody is there during
+/// scheduled runs, so they are never updated automatically.
+pub const ATTENDED_MANAGERS: &[&str] = &["paru", "apt", "dnf", "zypper", "apk", "snap", "fwupd"];
+
 /// List of allowed package managers for security validation
 const ALLOWED_MANAGERS: &[&str] = &[
-    "paru", "apt", "dnf", "zypper", "apk", "flatpak", "snap", "pipx", "npm", "rustup", "brew",
+    "paru", "apt", "dnf", "zypper", "apk", "flatpak", "snap", "fwupd", "pipx", "npm", "rustup",
+    "brew",
 ];
 
 /// Validates that a package manager is allowed to execute commands.
@@ -107,6 +671,13 @@ fn validate_manager_security(manager: &PackageManager) -> Result<()> {
 ///
 /// Returns an error if dangerous patterns are detected.
 fn validate_command_args(args: &[String]) -> Result<()> {
+    // The script of a predefined `sh -c` command is trusted; dynamic values are
+    // passed to it as positional parameters and validated like any other argument.
+    let args = match args {
+        [shell, flag, _script, rest @ ..] if shell == "sh" && flag == "-c" => rest,
+        args => args,
+    };
+
     for arg in args {
         // Check for command injection patterns
         if arg.contains("&&") || arg.contains("||") || arg.contains(";") || arg.contains("`") {
@@ -135,12 +706,87 @@ fn validate_command_args(args: &[String]) -> Result<()> {
     Ok(())
 }
 
+/// Validates a package name before it is passed to a package manager.
+///
+/// # Errors
+///
+/// Returns an error if the name is empty, too long, starts with `-` (and could
+/// be mistaken for an option) or contains characters that no supported manager
+/// uses in package names.
+///
+/// # Examples
+///
+/// ```
+/// use uptodate::updater::validate_package_name;
+///
+/// assert!(validate_package_name("linux-lts").is_ok());
+/// assert!(validate_package_name("org.mozilla.firefox").is_ok());
+/// assert!(validate_package_name("@angular/cli").is_ok());
+/// assert!(validate_package_name("--all").is_err());
+/// assert!(validate_package_name("vim; reboot").is_err());
+/// ```
+pub fn validate_package_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(anyhow::anyhow!("Package name cannot be empty"));
+    }
+
+    if name.len() > 200 {
+        return Err(anyhow::anyhow!(
+            "Package name too long (max 200 characters): {}",
+            name
+        ));
+    }
+
+    if name.starts_with('-') {
+        return Err(anyhow::anyhow!(
+            "Package name cannot start with '-': '{}'",
+            name
+        ));
+    }
+
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_.+@/:".contains(c))
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid characters in package name: '{}'",
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+fn targeted(cmd: &[&str]) -> TargetedUpdate {
+    TargetedUpdate::Command {
+        cmd: cmd.iter().map(|s| s.to_string()).collect(),
+        suffix: String::new(),
+    }
+}
+
+fn advisory(path: &str) -> Lock {
+    Lock::Advisory(PathBuf::from(path))
+}
+
+fn hold(hold: &str, release: &str, installed: &[&str], held: &[&str]) -> ExclusionMethod {
+    let command = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+    ExclusionMethod::Hold {
+        hold: hold.to_string(),
+        release: release.to_string(),
+        installed: command(installed),
+        held: command(held),
+    }
+}
+
 impl Updater {
     pub fn new() -> Self {
         let mut updater = Self {
             running: Arc::new(AtomicBool::new(false)),
             child_pids: Arc::new(Mutex::new(Vec::new())),
             managers: HashMap::new(),
+            history_path: History::default_path(),
+            marker_path: RunMarker::default_path(),
+            terminal_input: Arc::new(Mutex::new(None)),
         };
         updater.init_managers();
         updater
@@ -155,62 +801,212 @@ impl Updater {
                 &["paru", "-Syu", "--noconfirm"],
                 true,
                 "System packages",
-            ),
+            )
+            .with_exclusion(ExclusionMethod::ListFlag("--ignore".to_string()))
+            .with_targeted(TargetedUpdate::Blocked(
+                "Partial upgrades are not supported on Arch Linux and can break the system. \
+                 Update all system packages instead."
+                    .to_string(),
+            ))
+            .with_cleanup(&[
+                "sh",
+                "-c",
+                "paccache -rk2 && { orphans=$(pacman -Qdtq); [ -z \"$orphans\" ] || pacman -Rns --noconfirm $orphans; }",
+            ])
+            .with_preflight(Requirements::new(
+                vec![Lock::File(PathBuf::from("/var/lib/pacman/db.lck"))],
+                &["/", "/var/cache/pacman/pkg"],
+                1024,
+            ))
+            .with_stderr(&[
+                ("error", Severity::Error),
+                ("==> error", Severity::Error),
+                ("warning", Severity::Warning),
+                ("==> warning", Severity::Warning),
+            ])
+            .with_color_args(&["--color", "never"])
+            .with_unattended_args(&["--noconfirm"])
+            .with_advisories(&["arch-audit", "--upgradable", "--format", "%n|%s|%c"]),
             PackageManager::new(
                 "apt",
                 &["apt", "list", "--upgradable"],
                 &["sh", "-c", "apt update && apt upgrade -y"],
                 true,
                 "System packages",
-            ),
+            )
+            .with_exclusion(hold(
+                "apt-mark hold",
+                "apt-mark unhold",
+                &["dpkg-query", "--show", "--showformat=${Package}\\n"],
+                &["apt-mark", "showhold"],
+            ))
+            .with_targeted(targeted(&["apt", "install", "--only-upgrade", "-y"]))
+            .with_cleanup(&["sh", "-c", "apt-get autoremove -y && apt-get autoclean"])
+            .with_preflight(Requirements::new(
+                vec![
+                    advisory("/var/lib/dpkg/lock-frontend"),
+                    advisory("/var/lib/dpkg/lock"),
+                    advisory("/var/lib/apt/lists/lock"),
+                ],
+                &["/", "/var/cache/apt/archives"],
+                500,
+            ))
+            .with_stderr(&[
+                ("e:", Severity::Error),
+                ("err:", Severity::Error),
+                ("dpkg: error", Severity::Error),
+                ("w:", Severity::Warning),
+                // `WARNING: apt does not have a stable CLI interface` is printed on every run
+                ("warning: apt does not have a stable cli", Severity::Info),
+                ("warning", Severity::Warning),
+            ])
+            .with_color_args(&["-o", "APT::Color=0"]),
             PackageManager::new(
                 "dnf",
                 &["dnf", "check-update"],
                 &["dnf", "upgrade", "-y"],
                 true,
                 "System packages",
-            ),
+            )
+            .with_exclusion(ExclusionMethod::ListFlag("--exclude".to_string()))
+            .with_targeted(targeted(&["dnf", "upgrade", "-y"]))
+            .with_unattended_args(&["-y"])
+            .with_advisories(&["dnf", "updateinfo", "list", "--security"])
+            .with_security_update(&["dnf", "upgrade", "--security", "-y"])
+            .with_cleanup(&["dnf", "autoremove", "-y"])
+            .with_preflight(Requirements::new(
+                vec![advisory("/var/lib/rpm/.rpm.lock")],
+                &["/", "/var/cache/dnf"],
+                1024,
+            )),
             PackageManager::new(
                 "zypper",
                 &["zypper", "list-updates"],
                 &["zypper", "update", "-y"],
                 true,
                 "System packages",
-            ),
+            )
+            .with_exclusion(hold(
+                "zypper addlock",
+                "zypper removelock",
+                &["rpm", "--query", "--all", "--queryformat", "%{NAME}\\n"],
+                &[
+                    "sh",
+                    "-c",
+                    "zypper --quiet locks | awk -F '|' 'NR > 2 { gsub(/ /, \"\", $2); print $2 }'",
+                ],
+            ))
+            .with_targeted(targeted(&["zypper", "update", "-y"]))
+            .with_advisories(&["zypper", "list-patches", "--category", "security"])
+            .with_security_update(&[
+                "zypper",
+                "--non-interactive",
+                "patch",
+                "--category",
+                "security",
+            ])
+            .with_preflight(Requirements::new(
+                vec![
+                    Lock::Pid(PathBuf::from("/run/zypp.pid")),
+                    advisory("/var/lib/rpm/.rpm.lock"),
+                ],
+                &["/", "/var/cache/zypp"],
+                1024,
+            ))
+            .with_stderr(&[
+                ("error", Severity::Error),
+                ("problem:", Severity::Error),
+                ("warning", Severity::Warning),
+            ]),
             PackageManager::new(
                 "apk",
                 &["apk", "list", "--upgradable"],
                 &["sh", "-c", "apk update && apk upgrade"],
                 true,
                 "System packages",
-            ),
+            )
+            .with_targeted(targeted(&["apk", "upgrade"]))
+            .with_preflight(Requirements::new(
+                vec![advisory("/lib/apk/db/lock")],
+                &["/"],
+                200,
+            )),
             // Universal managers
             PackageManager::new(
                 "flatpak",
-                &["flatpak", "remote-ls", "--updates"],
+                &[
+                    "flatpak",
+                    "remote-ls",
+                    "--updates",
+                    "--columns=application,version",
+                ],
                 &["flatpak", "update", "-y"],
                 false,
                 "Flatpak applications",
-            ),
+            )
+            .with_exclusion(hold(
+                "flatpak mask",
+                "flatpak mask --remove",
+                &["flatpak", "list", "--columns=application"],
+                &["flatpak", "mask"],
+            ))
+            .with_targeted(targeted(&["flatpak", "update", "-y"]))
+            .with_cleanup(&["flatpak", "uninstall", "--unused", "-y"])
+            .with_preflight(Requirements::new(
+                Vec::new(),
+                &["/var/lib/flatpak", "~/.local/share/flatpak"],
+                1024,
+            )),
             PackageManager::new(
                 "snap",
                 &["snap", "refresh", "--list"],
                 &["snap", "refresh"],
                 true,
                 "Snap packages",
-            ),
+            )
+            .with_exclusion(hold(
+                "snap refresh --hold",
+                "snap refresh --unhold",
+                &["snap", "list"],
+                &["sh", "-c", "snap list | awk 'NR > 1 && $NF ~ /held/ { print $1 }'"],
+            ))
+            .with_targeted(targeted(&["snap", "refresh"]))
+            .with_cleanup(&[
+                "sh",
+                "-c",
+                "snap list --all | awk '$NF ~ /disabled/ {print $1, $3}' | while read -r name revision; do snap remove \"$name\" --revision=\"$revision\" || exit 1; done",
+            ])
+            .with_preflight(Requirements::new(Vec::new(), &["/var/lib/snapd"], 500)),
+            // Firmware; the fwupd daemon asks polkit for authorization itself
+            PackageManager::new(
+                "fwupd",
+                &["fwupdmgr", "get-updates", "--json"],
+                &["fwupdmgr", "update", "--assume-yes", "--no-reboot-check"],
+                false,
+                "Device firmware",
+            )
+            .with_targeted(TargetedUpdate::Blocked(
+                "Firmware is updated for all devices at once.".to_string(),
+            ))
+            .with_preflight(Requirements::new(Vec::new(), &["/var/lib/fwupd"], 100))
+            .with_unattended_args(&["--assume-yes"]),
             // Development tools
             PackageManager::new(
                 "pipx",
                 &["pipx", "list", "--outdated"],
-                &[
-                    "sh",
-                    "-c",
-                    "if command -v pipx >/dev/null 2>&1; then pipx upgrade-all; else pipx list --outdated --format=freeze | cut -d= -f1 | xargs -r pipx install --user --upgrade; fi",
-                ],
+                &["pipx", "upgrade-all"],
                 false,
                 "Python packages",
-            ),
+            )
+            .with_exclusion(ExclusionMethod::ArgsFlag("--skip".to_string()))
+            .with_targeted(targeted(&["pipx", "upgrade"]))
+            .with_preflight(Requirements::new(Vec::new(), &["~/.local"], 200))
+            .with_stderr(&[
+                ("error", Severity::Error),
+                ("⚠️", Severity::Warning),
+                ("warning", Severity::Warning),
+            ])
+            .with_env(&[("PIP_NO_COLOR", "1")]),
             PackageManager::new(
                 "npm",
                 &["npm", "outdated", "-g"],
@@ -221,21 +1017,50 @@ impl Updater {
                 ],
                 false,
                 "Node.js packages",
-            ),
+            )
+            .with_targeted(TargetedUpdate::Command {
+                cmd: vec!["npm".to_string(), "install".to_string(), "-g".to_string()],
+                suffix: "@latest".to_string(),
+            })
+            .with_cleanup(&["npm", "cache", "verify"])
+            .with_preflight(Requirements::new(Vec::new(), &["~"], 200))
+            .with_stderr(&[
+                ("npm err!", Severity::Error),
+                ("npm error", Severity::Error),
+                ("npm warn", Severity::Warning),
+            ])
+            .with_env(&[("npm_config_color", "false"), ("npm_config_progress", "false")]),
             PackageManager::new(
                 "rustup",
                 &["rustup", "check"],
                 &["rustup", "update"],
                 false,
                 "Rust toolchain",
-            ),
+            )
+            .with_targeted(targeted(&["rustup", "update"]))
+            .with_preflight(Requirements::new(Vec::new(), &["~/.rustup"], 1024))
+            .with_stderr(&[
+                ("error", Severity::Error),
+                ("warning", Severity::Warning),
+                ("info", Severity::Info),
+            ]),
             PackageManager::new(
                 "brew",
                 &["brew", "outdated"],
                 &["sh", "-c", "brew update && brew upgrade"],
                 false,
                 "Homebrew packages",
-            ),
+            )
+            .with_exclusion(hold(
+                "brew pin",
+                "brew unpin",
+                &["brew", "list", "--formula", "-1"],
+                &["brew", "list", "--pinned"],
+            ))
+            .with_targeted(targeted(&["brew", "upgrade"]))
+            .with_cleanup(&["brew", "cleanup"])
+            .with_preflight(Requirements::new(Vec::new(), &["~"], 500))
+            .with_env(&[("HOMEBREW_NO_COLOR", "1"), ("HOMEBREW_NO_ENV_HINTS", "1")]),
         ];
 
         for manager in managers {
@@ -251,8 +1076,7 @@ impl Updater {
         let mut available = Vec::new();
 
         // Check system managers first (only one)
-        let system_managers = ["paru", "apt", "dnf", "zypper", "apk"];
-        for manager in &system_managers {
+        for manager in SYSTEM_MANAGERS {
             if self.command_exists(manager).await {
                 available.push(manager.to_string());
                 break;
@@ -267,6 +1091,11 @@ impl Updater {
             }
         }
 
+        // Firmware is updated through the client of the fwupd daemon
+        if self.command_exists("fwupdmgr").await {
+            available.push("fwupd".to_string());
+        }
+
         info!("Detected {} package managers", available.len());
         Ok(available)
     }
@@ -282,27 +1111,191 @@ impl Updater {
             .unwrap_or(false)
     }
 
+    /// Runs the check phase for the given sources and collects pending updates.
+    ///
+    /// Unlike a dry run this does not stream events; the output of each check
+    /// command is parsed into [`PendingUpdate`]s. Sources whose check command
+    /// cannot be spawned are skipped with a warning.
+    ///
+    /// The check commands run in a neutral locale, with `environment`
+    /// overriding the child environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an update run is already in progress.
+    pub async fn check_pending(
+        &self,
+        sources: &[String],
+        environment: &HashMap<String, String>,
+    ) -> Result<Vec<PendingUpdate>> {
+        if self.is_running() {
+            return Err(anyhow::anyhow!("Updates already running"));
+        }
+
+        let mut pending = Vec::new();
+        for source in sources {
+            let Some(manager) = self.managers.get(source) else {
+                continue;
+            };
+
+            if let Err(e) = validate_manager_security(manager)
+                .and_then(|_| validate_command_args(&manager.check_cmd))
+            {
+                warn!("Skipping check for {}: {}", manager.name, e);
+                continue;
+            }
+
+            // Check commands signal pending updates through their exit code
+            // (e.g. `dnf check-update` exits with 100), so only stdout matters.
+            let cmd = manager.colorless(&manager.check_cmd);
+            let mut command = Command::new(&cmd[0]);
+            command
+                .args(&cmd[1..])
+                .stdin(Stdio::null())
+                .stderr(Stdio::null());
+            ChildEnv::new(Pass::Parse, &manager.env, environment).apply(&mut command);
+            let mut found = match command.output().await {
+                Ok(output) => {
+                    parse_check_output(&manager.name, &String::from_utf8_lossy(&output.stdout))
+                }
+                Err(e) => {
+                    warn!("Failed to check {}: {}", manager.name, e);
+                    continue;
+                }
+            };
+
+            if !found.is_empty() && !manager.advisory_cmd.is_empty() {
+                Self::find_advisories(manager, &mut found, environment).await;
+            }
+            pending.extend(found);
+        }
+
+        info!("Found {} pending updates", pending.len());
+        Ok(pending)
+    }
+
+    /// Marks the updates in `pending` that fix security issues.
+    ///
+    /// Advisory tools such as `arch-audit` are optional, so a missing tool
+    /// only leaves the updates unmarked.
+    async fn find_advisories(
+        manager: &PackageManager,
+        pending: &mut [PendingUpdate],
+        environment: &HashMap<String, String>,
+    ) {
+        let cmd = &manager.advisory_cmd;
+        if let Err(e) = validate_command_args(cmd) {
+            warn!("Skipping advisories for {}: {}", manager.name, e);
+            return;
+        }
+
+        let mut command = Command::new(&cmd[0]);
+        command
+            .args(&cmd[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null());
+        ChildEnv::new(Pass::Parse, &manager.env, environment).apply(&mut command);
+        match command.output().await {
+            Ok(output) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                annotate(
+                    pending,
+                    &manager.name,
+                    parse_advisories(&manager.name, &stdout),
+                );
+            }
+            Err(e) => info!("No advisories for {} ({}): {}", manager.name, cmd[0], e),
+        }
+    }
+
     pub async fn run_updates(
         &self,
         sources: &[String],
         dry_run: bool,
     ) -> Result<Receiver<UpdateEvent>> {
+        self.run(RunRequest {
+            sources: sources.to_vec(),
+            dry_run,
+            ..RunRequest::default()
+        })
+        .await
+    }
+
+    /// Starts a run described by `request` and returns its event stream.
+    ///
+    /// The finished run is appended to the history file before
+    /// [`UpdateEvent::Completed`] is delivered. Until then a [`RunMarker`]
+    /// records that the run is in progress; a stale marker left by a run that
+    /// never finished is moved to the history first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if updates are already running, in this or another process.
+    pub async fn run(&self, request: RunRequest) -> Result<Receiver<UpdateEvent>> {
         if self.is_running() {
             return Err(anyhow::anyhow!("Updates already running"));
         }
 
+        // Mark the run as in progress until it is recorded, so that a crash is
+        // noticed and another instance, e.g. the timer's, does not update at
+        // the same time
+        let marker_path = (!request.dry_run).then(|| self.marker_path.clone());
+        if marker_path.is_some() {
+            self.claim_marker(&request).await?;
+        }
+
         self.running.store(true, Ordering::Relaxed);
         let running = self.running.clone();
-        let (tx, rx) = unbounded();
+        let (tx, events) = unbounded();
+        let (forward_tx, rx) = unbounded();
+
+        let holds_marker = marker_path.clone();
+
+        // Record the run while forwarding its events to the caller
+        let history_path = self.history_path.clone();
+        let mut recorder = RunRecorder::for_request(&request);
+        async_std::task::spawn(async move {
+            while let Ok(event) = events.recv().await {
+                recorder.observe(&event);
+                if matches!(event, UpdateEvent::Completed(_)) {
+                    if let Err(e) = History::append(&history_path, recorder.finish()).await {
+                        warn!("Failed to record run history: {}", e);
+                    }
+                    if let Some(path) = &marker_path
+                        && let Err(e) = RunMarker::remove(path).await
+                    {
+                        warn!("Failed to remove run marker: {}", e);
+                    }
+                    forward_tx.send(event).await.ok();
+                    break;
+                }
+                forward_tx.send(event).await.ok();
+            }
+        });
 
         tx.send(UpdateEvent::Started).await.ok();
 
-        let sources = sources.to_vec();
+        let RunRequest {
+            sources,
+            dry_run,
+            mode,
+            exclusions,
+            packages,
+            snapshots,
+            critical,
+            critical_snapshots,
+            wait_for_locks,
+            environment,
+            terminal,
+            ..
+        } = request;
         let managers = self.managers.clone();
         let child_pids = self.child_pids.clone();
+        let terminal_input = self.terminal_input.clone();
 
         async_std::task::spawn(async move {
             let mut success = true;
+            let mut critical_updated: Vec<String> = Vec::new();
 
             for source in sources {
                 if !running.load(Ordering::Relaxed) {
@@ -314,14 +1307,146 @@ impl Updater {
                         .await
                         .ok();
 
-                    let result = if dry_run {
-                        Self::check_updates(manager, &tx, &child_pids).await
+                    if !dry_run
+                        && let Some(blocker) =
+                            Self::preflight(manager, wait_for_locks, &running, &tx).await
+                    {
+                        warn!("{} is blocked: {}", manager.name, blocker);
+                        tx.send(UpdateEvent::SourceBlocked(
+                            manager.name.clone(),
+                            blocker.to_string(),
+                        ))
+                        .await
+                        .ok();
+                        continue;
+                    }
+
+                    // Fail safe: a system update without its pre-update snapshot is not started
+                    let system = !dry_run && SYSTEM_MANAGERS.contains(&manager.name.as_str());
+                    let has_critical = critical.get(&source).is_some_and(|p| !p.is_empty());
+                    let snapshot = system
+                        && (snapshots
+                            || (critical_snapshots
+                                && has_critical
+                                && Snapshotter::detect().await.is_some()));
+                    let pre_snapshot = if snapshot {
+                        let Ok(pre) =
+                            Self::take_snapshot(manager, SnapshotKind::Pre, None, &tx).await
+                        else {
+                            success = false;
+                            tx.send(UpdateEvent::SourceCompleted(manager.name.clone(), false))
+                                .await
+                                .ok();
+                            continue;
+                        };
+                        Some(pre)
                     } else {
-                        Self::run_update(manager, &tx, &child_pids).await
+                        None
+                    };
+
+                    // A dry run only checks, and check output is parsed
+                    let on_terminal = !dry_run && terminal.contains(&source);
+                    let pass = if dry_run {
+                        Pass::Parse
+                    } else if on_terminal {
+                        Pass::Terminal
+                    } else {
+                        Pass::Display
+                    };
+                    let launch = Launch {
+                        env: ChildEnv::new(pass, &manager.env, &environment),
+                        terminal: on_terminal,
+                        input: terminal_input.clone(),
+                    };
+                    let excluded = exclusions.get(&source).map_or(&[][..], Vec::as_slice);
+                    // What was installed before, so that the update can be undone
+                    let inventory = if !dry_run && mode.updates() {
+                        Inventory::capture(&source, &environment).await
+                    } else {
+                        None
+                    };
+                    // Packages held for this run, released when its update ends
+                    let mut holds = Vec::new();
+                    let mut result = match packages.get(&source) {
+                        _ if !mode.updates() => true,
+                        _ if dry_run => {
+                            Self::check_updates(manager, &launch, &tx, &child_pids).await
+                        }
+                        Some(selected) => {
+                            Self::run_targeted_update(
+                                manager,
+                                &launch,
+                                selected,
+                                excluded,
+                                &tx,
+                                &child_pids,
+                            )
+                            .await
+                        }
+                        None => match Self::hold_for_run(
+                            manager,
+                            excluded,
+                            &environment,
+                            holds_marker.as_deref(),
+                            &tx,
+                        )
+                        .await
+                        {
+                            None => false,
+                            Some(held) => {
+                                holds = held;
+                                if mode == RunMode::SecurityOnly {
+                                    Self::run_security_update(
+                                        manager,
+                                        &launch,
+                                        &holds,
+                                        &tx,
+                                        &child_pids,
+                                    )
+                                    .await
+                                } else {
+                                    Self::run_update(manager, &launch, &holds, &tx, &child_pids)
+                                        .await
+                                }
+                            }
+                        },
                     };
 
+                    // The update command released its holds when it ended
+                    if matches!(manager.exclusion, ExclusionMethod::Hold { .. })
+                        && !holds.is_empty()
+                        && let Some(path) = &holds_marker
+                        && let Err(e) = RunMarker::set_holds(path, &source, None).await
+                    {
+                        warn!("Failed to record released packages: {}", e);
+                    }
+
+                    if let Some(before) = &inventory
+                        && let Some(after) = Inventory::capture(&source, &environment).await
+                        && let Some(rollback) = Rollback::between(before, &after)
+                    {
+                        tx.send(UpdateEvent::SourceRollback(manager.name.clone(), rollback))
+                            .await
+                            .ok();
+                    }
+
+                    if result && mode.cleans_up() {
+                        result =
+                            Self::run_cleanup(manager, &launch, dry_run, &tx, &child_pids).await;
+                    }
+
+                    if let Some(pre) = &pre_snapshot {
+                        // The update already ran, so a missing post snapshot is only reported
+                        Self::take_snapshot(manager, SnapshotKind::Post, Some(pre), &tx)
+                            .await
+                            .ok();
+                    }
+
                     if !result {
                         success = false;
+                    } else if !dry_run && mode.updates() {
+                        critical_updated
+                            .extend(critical.get(&source).into_iter().flatten().cloned());
                     }
 
                     tx.send(UpdateEvent::SourceCompleted(manager.name.clone(), result))
@@ -330,34 +1455,418 @@ impl Updater {
                 }
             }
 
+            // Only `stop` clears the flag before the run ends
+            let interrupted = !running.load(Ordering::Relaxed);
+
+            // Post-run analysis: kernel, glibc or systemd updates need a reboot.
+            // A stopped run is left alone; the user wants it to end
+            if !dry_run && mode.updates() && !interrupted {
+                let mut reboot = RebootCheck::new().run().await;
+                for package in critical_updated {
+                    reboot.require(format!("{package} was updated"));
+                }
+                if reboot.required {
+                    tx.send(UpdateEvent::RebootRequired(reboot.reasons))
+                        .await
+                        .ok();
+                }
+
+                let stale = StaleLibraryScan::new().scan();
+                if !stale.is_empty() {
+                    tx.send(UpdateEvent::RestartNeeded(stale)).await.ok();
+                }
+            }
+
             running.store(false, Ordering::Relaxed);
-            tx.send(UpdateEvent::Completed(success)).await.ok();
+            if interrupted {
+                tx.send(UpdateEvent::Interrupted).await.ok();
+            }
+            tx.send(UpdateEvent::Completed(success && !interrupted))
+                .await
+                .ok();
         });
 
         Ok(rx)
     }
 
+    /// Keeps runs from starting while the caller uses the package managers
+    /// itself, e.g. for a release upgrade described by `request`, until the
+    /// returned [`Reservation`] is dropped. Like a run, the reservation is
+    /// marked in progress, so that other processes wait and a crash is noticed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if updates are already running, in this or another process.
+    pub async fn reserve(&self, request: &RunRequest) -> Result<Reservation> {
+        if self.is_running() {
+            return Err(anyhow::anyhow!("Updates already running"));
+        }
+        self.claim_marker(request).await?;
+        self.running.store(true, Ordering::Relaxed);
+        Ok(Reservation {
+            running: self.running.clone(),
+            marker_path: self.marker_path.clone(),
+        })
+    }
+
+    /// Records `request` as in progress in the run marker after moving a
+    /// stale marker to the history.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if another process has a run in progress. A marker
+    /// that cannot be written is only logged.
+    async fn claim_marker(&self, request: &RunRequest) -> Result<()> {
+        if let Err(e) = RunMarker::take_stale(&self.marker_path, &self.history_path).await {
+            warn!("Failed to check for an interrupted run: {}", e);
+        }
+        match RunMarker::for_request(request)
+            .claim(&self.marker_path)
+            .await
+        {
+            Ok(None) => Ok(()),
+            Ok(Some(blocker)) => Err(anyhow::anyhow!("Cannot update: {}", blocker)),
+            Err(e) => {
+                warn!("Failed to mark run as in progress: {}", e);
+                Ok(())
+            }
+        }
+    }
+
     async fn check_updates(
         manager: &PackageManager,
+        launch: &Launch,
         tx: &Sender<UpdateEvent>,
         child_pids: &Arc<Mutex<Vec<u32>>>,
     ) -> bool {
-        Self::run_command(&manager.check_cmd, false, manager, tx, child_pids).await
+        Self::run_command(&manager.check_cmd, false, manager, launch, tx, child_pids).await
     }
 
     async fn run_update(
         manager: &PackageManager,
+        launch: &Launch,
+        excluded: &[String],
         tx: &Sender<UpdateEvent>,
         child_pids: &Arc<Mutex<Vec<u32>>>,
     ) -> bool {
-        Self::run_command(
-            &manager.update_cmd,
+        let cmd = match manager.update_command(excluded) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                error!("Invalid exclusions for {}: {}", manager.name, e);
+                tx.send(UpdateEvent::SourceMessage(
+                    manager.name.clone(),
+                    Severity::Error,
+                    e.to_string(),
+                ))
+                .await
+                .ok();
+                return false;
+            }
+        };
+
+        if !excluded.is_empty() {
+            let note = if manager.supports_exclusions() {
+                format!("Holding back: {}", excluded.join(", "))
+            } else {
+                format!(
+                    "{} cannot hold back packages; excluded packages may be updated: {}",
+                    manager.name,
+                    excluded.join(", ")
+                )
+            };
+            tx.send(UpdateEvent::SourceProgress(manager.name.clone(), note))
+                .await
+                .ok();
+        }
+
+        Self::run_command(&cmd, manager.needs_sudo, manager, launch, tx, child_pids).await
+    }
+
+    /// Narrows `excluded` down to the packages `manager` has to hold for this
+    /// run: those it has installed and that are not held already. Packages of
+    /// other sources would fail the hold, and holds the user set outside the
+    /// app are never released. The new holds are recorded in the marker at
+    /// `marker_path`, so that they can be released after a crash.
+    ///
+    /// Managers that do not hold packages get `excluded` unchanged. Returns
+    /// `None` if the packages cannot be listed.
+    async fn hold_for_run(
+        manager: &PackageManager,
+        excluded: &[String],
+        environment: &HashMap<String, String>,
+        marker_path: Option<&Path>,
+        tx: &Sender<UpdateEvent>,
+    ) -> Option<Vec<String>> {
+        let ExclusionMethod::Hold {
+            installed, held, ..
+        } = &manager.exclusion
+        else {
+            return Some(excluded.to_vec());
+        };
+        if excluded.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let env = ChildEnv::new(Pass::Parse, &manager.env, environment);
+        let listed = match (
+            Self::list(installed, &env).await,
+            Self::list(held, &env).await,
+        ) {
+            (Ok(installed), Ok(held)) => new_holds(excluded, &installed, &held),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Cannot hold back packages of {}: {}", manager.name, e);
+                tx.send(UpdateEvent::SourceMessage(
+                    manager.name.clone(),
+                    Severity::Error,
+                    format!("Cannot tell which packages to hold back: {e}"),
+                ))
+                .await
+                .ok();
+                return None;
+            }
+        };
+
+        if let Some(path) = marker_path
+            && !listed.is_empty()
+            && let Err(e) =
+                RunMarker::set_holds(path, &manager.name, manager.release_command(&listed)).await
+        {
+            warn!("Failed to record held packages: {}", e);
+        }
+        Some(listed)
+    }
+
+    /// Runs the listing command `cmd` and returns its output.
+    async fn list(cmd: &[String], env: &ChildEnv) -> Result<String> {
+        validate_command_args(cmd)?;
+        let mut command = Command::new(&cmd[0]);
+        command
+            .args(&cmd[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null());
+        env.apply(&mut command);
+        let output = command
+            .output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", cmd[0], e))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "{} failed: {}",
+                cmd.join(" "),
+                output.status
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Installs only the security fixes of `manager`.
+    ///
+    /// Managers without a security-only command fail; callers update their
+    /// flagged packages with a targeted update instead.
+    async fn run_security_update(
+        manager: &PackageManager,
+        launch: &Launch,
+        excluded: &[String],
+        tx: &Sender<UpdateEvent>,
+        child_pids: &Arc<Mutex<Vec<u32>>>,
+    ) -> bool {
+        let cmd = match manager.security_command(excluded) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                warn!("Cannot update security fixes: {}", e);
+                tx.send(UpdateEvent::SourceMessage(
+                    manager.name.clone(),
+                    Severity::Error,
+                    e.to_string(),
+                ))
+                .await
+                .ok();
+                return false;
+            }
+        };
+
+        tx.send(UpdateEvent::SourceProgress(
+            manager.name.clone(),
+            "Installing security updates only".to_string(),
+        ))
+        .await
+        .ok();
+
+        Self::run_command(&cmd, manager.needs_sudo, manager, launch, tx, child_pids).await
+    }
+
+    /// Checks the locks and free space `manager` needs and returns what blocks it.
+    ///
+    /// With `wait` set, held locks are polled until they are released, the
+    /// run is stopped or [`LOCK_WAIT_TIMEOUT`] passes.
+    async fn preflight(
+        manager: &PackageManager,
+        wait: bool,
+        running: &AtomicBool,
+        tx: &Sender<UpdateEvent>,
+    ) -> Option<Blocker> {
+        let preflight = Preflight::new();
+        let mut blocker = preflight.check(&manager.preflight).await?;
+        if !wait || !blocker.is_lock() {
+            return Some(blocker);
+        }
+
+        tx.send(UpdateEvent::SourceProgress(
+            manager.name.clone(),
+            format!("Waiting: {blocker}"),
+        ))
+        .await
+        .ok();
+        let started = Instant::now();
+        while blocker.is_lock()
+            && started.elapsed() < LOCK_WAIT_TIMEOUT
+            && running.load(Ordering::Relaxed)
+        {
+            async_std::task::sleep(LOCK_POLL_INTERVAL).await;
+            blocker = preflight.check(&manager.preflight).await?;
+        }
+        Some(blocker)
+    }
+
+    /// Runs the cleanup of `manager` and reports the disk space it freed.
+    async fn run_cleanup(
+        manager: &PackageManager,
+        launch: &Launch,
+        dry_run: bool,
+        tx: &Sender<UpdateEvent>,
+        child_pids: &Arc<Mutex<Vec<u32>>>,
+    ) -> bool {
+        let note = if !manager.supports_cleanup() {
+            format!("{} has nothing to clean up", manager.name)
+        } else if dry_run {
+            format!("Would clean up: {}", manager.cleanup_cmd.join(" "))
+        } else {
+            "Cleaning up".to_string()
+        };
+        tx.send(UpdateEvent::SourceProgress(manager.name.clone(), note))
+            .await
+            .ok();
+        if !manager.supports_cleanup() || dry_run {
+            return true;
+        }
+
+        let paths = cleanup::measured_paths();
+        let before = cleanup::free_space(&paths).await;
+        let result = Self::run_command(
+            &manager.cleanup_cmd,
             manager.needs_sudo,
             manager,
+            launch,
             tx,
             child_pids,
         )
+        .await;
+
+        if result {
+            // Other programs may write in the meantime, so the difference is only an estimate
+            let reclaimed = match (before, cleanup::free_space(&paths).await) {
+                (Ok(before), Ok(after)) => after.saturating_sub(before),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Failed to measure reclaimed space: {}", e);
+                    0
+                }
+            };
+            tx.send(UpdateEvent::SourceCleanedUp(
+                manager.name.clone(),
+                reclaimed,
+            ))
+            .await
+            .ok();
+        }
+        result
+    }
+
+    /// Creates a snapshot around an update of `manager` and reports it as an event.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, after reporting it as a [`UpdateEvent::SourceMessage`],
+    /// if no snapshot tool is available or the snapshot cannot be created.
+    async fn take_snapshot(
+        manager: &PackageManager,
+        kind: SnapshotKind,
+        pre: Option<&Snapshot>,
+        tx: &Sender<UpdateEvent>,
+    ) -> Result<Snapshot> {
+        let label = match kind {
+            SnapshotKind::Pre => format!("uptodate: before {} update", manager.name),
+            SnapshotKind::Post => format!("uptodate: after {} update", manager.name),
+        };
+
+        let result = match Snapshotter::detect().await {
+            Some(snapshotter) => snapshotter.create(kind, &label, pre).await,
+            None => Err(anyhow::anyhow!(
+                "No snapshot tool found (snapper, timeshift or btrfs with {})",
+                crate::snapshot::BTRFS_SNAPSHOT_DIR
+            )),
+        };
+
+        match result {
+            Ok(snapshot) => {
+                tx.send(UpdateEvent::SourceSnapshot(
+                    manager.name.clone(),
+                    snapshot.clone(),
+                ))
+                .await
+                .ok();
+                Ok(snapshot)
+            }
+            Err(e) => {
+                error!("Snapshot for {} failed: {}", manager.name, e);
+                tx.send(UpdateEvent::SourceMessage(
+                    manager.name.clone(),
+                    Severity::Error,
+                    format!("Snapshot failed: {e}"),
+                ))
+                .await
+                .ok();
+                Err(e)
+            }
+        }
+    }
+
+    async fn run_targeted_update(
+        manager: &PackageManager,
+        launch: &Launch,
+        selected: &[String],
+        excluded: &[String],
+        tx: &Sender<UpdateEvent>,
+        child_pids: &Arc<Mutex<Vec<u32>>>,
+    ) -> bool {
+        let selected: Vec<String> = selected
+            .iter()
+            .filter(|package| !excluded.contains(package))
+            .cloned()
+            .collect();
+
+        let cmd = match manager.targeted_command(&selected) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                warn!("Cannot update selected packages: {}", e);
+                tx.send(UpdateEvent::SourceMessage(
+                    manager.name.clone(),
+                    Severity::Error,
+                    e.to_string(),
+                ))
+                .await
+                .ok();
+                return false;
+            }
+        };
+
+        tx.send(UpdateEvent::SourceProgress(
+            manager.name.clone(),
+            format!("Updating selected packages: {}", selected.join(", ")),
+        ))
         .await
+        .ok();
+
+        Self::run_command(&cmd, manager.needs_sudo, manager, launch, tx, child_pids).await
     }
 
     /// Safely executes a command with proper validation and escaping.
@@ -373,6 +1882,7 @@ impl Updater {
     /// * `cmd` - The command and arguments to execute
     /// * `needs_sudo` - Whether the command requires elevated privileges
     /// * `manager` - The package manager information for validation
+    /// * `launch` - The environment and terminal the command runs in
     /// * `tx` - Channel sender for progress updates
     /// * `child_pids` - Shared list of child process IDs for cleanup
     ///
@@ -386,14 +1896,16 @@ impl Updater {
         cmd: &[String],
         needs_sudo: bool,
         manager: &PackageManager,
+        launch: &Launch,
         tx: &Sender<UpdateEvent>,
         child_pids: &Arc<Mutex<Vec<u32>>>,
     ) -> bool {
         // Validate security before executing
         if let Err(e) = validate_manager_security(manager) {
             error!("Security validation failed: {}", e);
-            tx.send(UpdateEvent::SourceError(
+            tx.send(UpdateEvent::SourceMessage(
                 manager.name.clone(),
+                Severity::Error,
                 e.to_string(),
             ))
             .await
@@ -403,8 +1915,9 @@ impl Updater {
 
         if let Err(e) = validate_command_args(cmd) {
             error!("Command validation failed: {}", e);
-            tx.send(UpdateEvent::SourceError(
+            tx.send(UpdateEvent::SourceMessage(
                 manager.name.clone(),
+                Severity::Error,
                 e.to_string(),
             ))
             .await
@@ -412,17 +1925,31 @@ impl Updater {
             return false;
         }
 
-        let mut command = if needs_sudo {
-            let mut sudo_cmd = Command::new("pkexec");
-            sudo_cmd.args(["--user", "root", "sh", "-c", &cmd.join(" ")]);
-            sudo_cmd
+        let cmd = if launch.terminal {
+            manager.colorless(&manager.interactive(cmd))
         } else {
-            let mut regular_cmd = Command::new(&cmd[0]);
-            regular_cmd.args(&cmd[1..]);
-            regular_cmd
+            manager.colorless(cmd)
         };
+        let cmd = if needs_sudo {
+            // pkexec drops the environment, so `env` sets it again
+            launch.env.elevate(&cmd)
+        } else {
+            cmd
+        };
+
+        if launch.terminal {
+            return Self::run_in_terminal(&cmd, manager, launch, tx, child_pids).await;
+        }
 
-        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut command = Command::new(&cmd[0]);
+        command.args(&cmd[1..]);
+        launch.env.apply(&mut command);
+
+        // Nothing may wait for input that never comes
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
 
         match command.spawn() {
             Ok(mut child) => {
@@ -453,24 +1980,27 @@ impl Updater {
                 if let Some(stderr) = child.stderr.take() {
                     let tx = tx.clone();
                     let name = manager.name.clone();
+                    let classifier = manager.stderr.clone();
                     async_std::task::spawn(async move {
                         let reader = BufReader::new(stderr);
                         let mut lines = reader.lines();
+                        let mut diagnoses = Vec::new();
                         while let Some(Ok(line)) = lines.next().await {
+                            if let Some(diagnosis) = Diagnosis::recognize(&name, &line)
+                                && !diagnoses.contains(&diagnosis)
+                            {
+                                diagnoses.push(diagnosis);
+                                tx.send(UpdateEvent::SourceDiagnosed(name.clone(), diagnosis))
+                                    .await
+                                    .ok();
+                            }
                             if !line.trim().is_empty() && !line.contains("password") {
-                                // Don't treat informational messages as errors
-                                if line.contains("up to date")
-                                    || line.contains("Nothing to do")
-                                    || line.contains("info:")
-                                {
-                                    tx.send(UpdateEvent::SourceProgress(name.clone(), line))
-                                        .await
-                                        .ok();
-                                } else {
-                                    tx.send(UpdateEvent::SourceError(name.clone(), line))
-                                        .await
-                                        .ok();
-                                }
+                                // Many tools log progress to stderr; the exit status
+                                // alone decides whether the source failed
+                                let severity = classifier.classify(&line);
+                                tx.send(UpdateEvent::SourceMessage(name.clone(), severity, line))
+                                    .await
+                                    .ok();
                             }
                         }
                     });
@@ -498,21 +2028,181 @@ impl Updater {
         }
     }
 
-    pub async fn stop(&self) -> Result<()> {
-        if self.is_running() {
-            self.running.store(false, Ordering::Relaxed);
-
-            let pids = self.child_pids.lock().await;
-            for &pid in pids.iter() {
-                warn!("Stopping process {}", pid);
-                Command::new("kill")
-                    .args(["-INT", &pid.to_string()])
-                    .output()
-                    .await
-                    .ok();
+    /// Runs `cmd` under a pseudo-terminal and reports the questions it asks,
+    /// which are answered through [`Updater::answer`].
+    ///
+    /// A terminal does not separate stdout from stderr, so every line is
+    /// classified; lines that are not warnings or errors are reported as progress.
+    async fn run_in_terminal(
+        cmd: &[String],
+        manager: &PackageManager,
+        launch: &Launch,
+        tx: &Sender<UpdateEvent>,
+        child_pids: &Arc<Mutex<Vec<u32>>>,
+    ) -> bool {
+        let spawned = Pty::open().and_then(|pty| {
+            let mut command = pty.command(cmd)?;
+            launch.env.apply(&mut command);
+            let child = command
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("Failed to start {:?}: {}", cmd[0], e))?;
+            Ok((child, pty.into_master()))
+        });
+        let (mut child, master) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                error!("Failed to run command for {}: {}", manager.name, e);
+                tx.send(UpdateEvent::Error(format!(
+                    "Failed to run {}: {}",
+                    manager.name, e
+                )))
+                .await
+                .ok();
+                return false;
             }
+        };
+
+        let pid = child.id();
+        child_pids.lock().await.push(pid);
+        match master.try_clone() {
+            Ok(input) => *launch.input.lock().await = Some(input),
+            Err(e) => warn!("Questions of {} cannot be answered: {}", manager.name, e),
+        }
+
+        // Reading blocks, so it happens on a thread of its own; it fails with
+        // EIO once the child has exited
+        let (chunks_tx, chunks) = unbounded();
+        std::thread::spawn(move || {
+            let mut master = master;
+            let mut buffer = [0; 4096];
+            while let Ok(read @ 1..) = std::io::Read::read(&mut master, &mut buffer) {
+                if async_std::task::block_on(chunks_tx.send(buffer[..read].to_vec())).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let tx_output = tx.clone();
+        let name = manager.name.clone();
+        let classifier = manager.stderr.clone();
+        let output = async_std::task::spawn(async move {
+            let tx = tx_output;
+            let mut transcript = Transcript::default();
+            let mut diagnoses = Vec::new();
+            loop {
+                let idle = match async_std::future::timeout(PROMPT_IDLE, chunks.recv()).await {
+                    Ok(Ok(chunk)) => {
+                        for line in transcript.feed(&chunk) {
+                            if let Some(diagnosis) = Diagnosis::recognize(&name, &line)
+                                && !diagnoses.contains(&diagnosis)
+                            {
+                                diagnoses.push(diagnosis);
+                                tx.send(UpdateEvent::SourceDiagnosed(name.clone(), diagnosis))
+                                    .await
+                                    .ok();
+                            }
+                            let event = match classifier.classify(&line) {
+                                Severity::Info => UpdateEvent::SourceProgress(name.clone(), line),
+                                severity => {
+                                    UpdateEvent::SourceMessage(name.clone(), severity, line)
+                                }
+                            };
+                            tx.send(event).await.ok();
+                        }
+                        false
+                    }
+                    Ok(Err(_)) => break,
+                    Err(_) => true,
+                };
+                if let Some(prompt) = transcript.question(idle) {
+                    tx.send(UpdateEvent::SourcePrompt(name.clone(), prompt))
+                        .await
+                        .ok();
+                }
+            }
+        });
+
+        let success = child.status().await.map(|s| s.success()).unwrap_or(false);
+        launch.input.lock().await.take();
+        child_pids.lock().await.retain(|&p| p != pid);
+
+        // The last lines belong to this source, unless a daemon it started keeps
+        // the terminal open
+        async_std::future::timeout(TERMINAL_DRAIN_TIMEOUT, output)
+            .await
+            .ok();
+        success
+    }
+
+    /// Writes `input` and Enter to the source running under a pseudo-terminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no source is running under a pseudo-terminal or
+    /// the input cannot be written.
+    pub async fn answer(&self, input: &str) -> Result<()> {
+        let mut terminal = self.terminal_input.lock().await;
+        let terminal = terminal
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("No source is waiting for an answer"))?;
+        std::io::Write::write_all(terminal, format!("{input}\n").as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to send answer: {}", e))
+    }
+
+    /// Stops the current run: no further source is started, and the
+    /// processes it started and everything they spawned are interrupted and
+    /// waited for. Processes that do not exit within [`STOP_TIMEOUT`] are killed.
+    ///
+    /// Sources running as root through pkexec cannot be interrupted; the run
+    /// then ends once their current step finishes, which is reported as
+    /// [`Stop::AfterCurrentStep`]. The run ends with [`UpdateEvent::Interrupted`]
+    /// and [`UpdateEvent::Completed`] either way.
+    pub async fn stop(&self) -> Stop {
+        if !self.is_running() {
+            return Stop::Stopped;
+        }
+        self.running.store(false, Ordering::Relaxed);
+
+        let pids = self.child_pids.lock().await.clone();
+        let (privileged, own): (Vec<u32>, Vec<u32>) = process_tree(Path::new("/proc"), &pids)
+            .into_iter()
+            .partition(|pid| signal(*pid, 0).is_err_and(|e| e.raw_os_error() == Some(libc::EPERM)));
+        if !privileged.is_empty() {
+            info!(
+                "Processes {:?} run as root; stopping after the current step",
+                privileged
+            );
+        }
+
+        for &pid in &own {
+            warn!("Stopping process {}", pid);
+            // Processes that exited meanwhile are gone already
+            if let Err(e) = signal(pid, libc::SIGINT)
+                && e.raw_os_error() != Some(libc::ESRCH)
+            {
+                warn!("Failed to stop process {}: {}", pid, e);
+            }
+        }
+
+        let started = Instant::now();
+        let mut remaining = own;
+        loop {
+            remaining.retain(|pid| is_alive(Path::new("/proc"), *pid));
+            if remaining.is_empty() || started.elapsed() >= STOP_TIMEOUT {
+                break;
+            }
+            async_std::task::sleep(STOP_POLL_INTERVAL).await;
+        }
+        for pid in remaining {
+            warn!("Process {} did not stop; killing it", pid);
+            signal(pid, libc::SIGKILL).ok();
+        }
+
+        if privileged.is_empty() {
+            Stop::Stopped
+        } else {
+            Stop::AfterCurrentStep
         }
-        Ok(())
     }
 
     pub fn get_manager_info(&self, name: &str) -> Option<&PackageManager> {
@@ -520,6 +2210,63 @@ impl Updater {
     }
 }
 
+/// Sends `signal` to `pid`; signal 0 only checks that it could be sent.
+fn signal(pid: u32, signal: i32) -> std::io::Result<()> {
+    // SAFETY: kill takes no pointers and only acts on the given process.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Returns `true` if process `pid` exists below `proc_root` and has not
+/// exited; a zombie only waits for its parent to collect it.
+fn is_alive(proc_root: &Path, pid: u32) -> bool {
+    std::fs::read_to_string(proc_root.join(pid.to_string()).join("stat")).is_ok_and(|stat| {
+        stat.rsplit_once(')')
+            .and_then(|(_, fields)| fields.split_whitespace().next())
+            .is_some_and(|state| state != "Z")
+    })
+}
+
+/// Returns `roots` followed by all of their descendants, read from `proc_root`.
+fn process_tree(proc_root: &Path, roots: &[u32]) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    if let Ok(entries) = std::fs::read_dir(proc_root) {
+        for entry in entries.flatten() {
+            let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+                continue;
+            };
+            let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+            // The command name may contain spaces and parentheses, the fields after it do not
+            let parent = stat
+                .rsplit_once(')')
+                .and_then(|(_, fields)| fields.split_whitespace().nth(1))
+                .and_then(|ppid| ppid.parse::<u32>().ok());
+            if let Some(parent) = parent {
+                children.entry(parent).or_default().push(pid);
+            }
+        }
+    }
+
+    let mut tree = roots.to_vec();
+    let mut index = 0;
+    while index < tree.len() {
+        if let Some(pids) = children.get(&tree[index]) {
+            for &pid in pids {
+                if !tree.contains(&pid) {
+                    tree.push(pid);
+                }
+            }
+        }
+        index += 1;
+    }
+    tree
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -615,6 +2362,345 @@ mod tests {
         assert!(validate_command_args(&args6).is_err());
     }
 
+    #[test]
+    fn test_validate_command_args_trusts_predefined_scripts() {
+        let args = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "apt update && apt upgrade -y".to_string(),
+        ];
+        assert!(validate_command_args(&args).is_ok());
+
+        let args = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "apt-mark hold \"$@\"".to_string(),
+            "sh".to_string(),
+            "vim; reboot".to_string(),
+        ];
+        assert!(validate_command_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_validate_package_name() {
+        assert!(validate_package_name("linux").is_ok());
+        assert!(validate_package_name("python3.12").is_ok());
+        assert!(validate_package_name("g++").is_ok());
+        assert!(validate_package_name("stable-x86_64-unknown-linux-gnu").is_ok());
+
+        assert!(validate_package_name("").is_err());
+        assert!(validate_package_name("-y").is_err());
+        assert!(validate_package_name("a b").is_err());
+        assert!(validate_package_name("$(reboot)").is_err());
+        assert!(validate_package_name(&"a".repeat(201)).is_err());
+    }
+
+    #[test]
+    fn test_update_command_with_flags() {
+        let updater = Updater::new();
+
+        let excluded = vec!["kernel".to_string(), "mesa".to_string()];
+        let dnf = updater.get_manager_info("dnf").unwrap();
+        assert_eq!(
+            dnf.update_command(&excluded).unwrap(),
+            vec!["dnf", "upgrade", "-y", "--exclude", "kernel,mesa"]
+        );
+
+        let pipx = updater.get_manager_info("pipx").unwrap();
+        assert_eq!(
+            pipx.update_command(&excluded).unwrap(),
+            vec!["pipx", "upgrade-all", "--skip", "kernel", "mesa"]
+        );
+
+        assert_eq!(dnf.update_command(&[]).unwrap(), dnf.update_cmd);
+    }
+
+    #[test]
+    fn test_update_command_with_hold() {
+        let updater = Updater::new();
+        let apt = updater.get_manager_info("apt").unwrap();
+
+        let cmd = apt
+            .update_command(&["firefox".to_string(), "linux-image-generic".to_string()])
+            .unwrap();
+        assert_eq!(cmd[..2], ["sh", "-c"]);
+        assert!(cmd[2].starts_with("apt-mark hold \"$@\" && { apt update && apt upgrade -y;"));
+        assert!(cmd[2].contains("apt-mark unhold \"$@\""));
+        assert_eq!(cmd[3..], ["sh", "firefox", "linux-image-generic"]);
+        assert!(validate_command_args(&cmd).is_ok());
+
+        let flatpak = updater.get_manager_info("flatpak").unwrap();
+        let cmd = flatpak
+            .update_command(&["org.gimp.GIMP".to_string()])
+            .unwrap();
+        assert!(cmd[2].contains("{ flatpak update -y;"));
+    }
+
+    #[test]
+    fn test_new_holds() {
+        let excluded = ["firefox", "node", "vim"].map(str::to_string);
+        // `snap list` starts with a header, `flatpak mask` indents its patterns
+        let installed = "Name  Version\nfirefox  128.0\nvim  9.1\n";
+        assert_eq!(new_holds(&excluded, installed, ""), ["firefox", "vim"]);
+        assert_eq!(new_holds(&excluded, installed, "  vim\n"), ["firefox"]);
+        assert!(new_holds(&excluded, "", "").is_empty());
+
+        let updater = Updater::new();
+        let brew = updater.get_manager_info("brew").unwrap();
+        assert_eq!(
+            brew.release_command(&["node".to_string()]).unwrap(),
+            ["brew", "unpin", "node"]
+        );
+        let paru = updater.get_manager_info("paru").unwrap();
+        assert!(paru.release_command(&["linux".to_string()]).is_none());
+    }
+
+    #[test]
+    fn test_update_command_rejects_invalid_names() {
+        let updater = Updater::new();
+        let snap = updater.get_manager_info("snap").unwrap();
+        assert!(snap.update_command(&["--devmode".to_string()]).is_err());
+
+        // Names are validated even when the manager cannot exclude packages
+        let npm = updater.get_manager_info("npm").unwrap();
+        assert!(!npm.supports_exclusions());
+        assert!(npm.update_command(&["a`b`".to_string()]).is_err());
+        assert_eq!(
+            npm.update_command(&["typescript".to_string()]).unwrap(),
+            npm.update_cmd
+        );
+    }
+
+    #[test]
+    fn test_targeted_command() {
+        let updater = Updater::new();
+        let packages = vec!["org.gimp.GIMP".to_string(), "org.gnome.Maps".to_string()];
+
+        let flatpak = updater.get_manager_info("flatpak").unwrap();
+        assert!(flatpak.targeted_blocked_reason().is_none());
+        assert_eq!(
+            flatpak.targeted_command(&packages).unwrap(),
+            vec!["flatpak", "update", "-y", "org.gimp.GIMP", "org.gnome.Maps"]
+        );
+
+        let rustup = updater.get_manager_info("rustup").unwrap();
+        assert_eq!(
+            rustup.targeted_command(&["stable".to_string()]).unwrap(),
+            vec!["rustup", "update", "stable"]
+        );
+
+        assert!(flatpak.targeted_command(&[]).is_err());
+        assert!(flatpak.targeted_command(&["-y".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_security_command() {
+        let updater = Updater::new();
+        let kernel = vec!["kernel".to_string()];
+
+        let dnf = updater.get_manager_info("dnf").unwrap();
+        assert!(dnf.supports_security_only());
+        assert_eq!(
+            dnf.security_command(&kernel).unwrap(),
+            vec!["dnf", "upgrade", "--security", "-y", "--exclude", "kernel"]
+        );
+
+        let zypper = updater.get_manager_info("zypper").unwrap();
+        let cmd = zypper.security_command(&kernel).unwrap();
+        assert_eq!(cmd[..2], ["sh", "-c"]);
+        assert!(cmd[2].contains("zypper --non-interactive patch --category security"));
+        assert_eq!(cmd[cmd.len() - 1], "kernel");
+
+        let apt = updater.get_manager_info("apt").unwrap();
+        assert!(!apt.supports_security_only());
+        assert!(apt.security_command(&[]).is_err());
+        assert!(dnf.security_command(&["-y".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_targeted_command_blocked_for_arch() {
+        let updater = Updater::new();
+        let paru = updater.get_manager_info("paru").unwrap();
+
+        let reason = paru.targeted_blocked_reason().unwrap();
+        assert!(reason.contains("Partial upgrades"));
+        assert!(paru.targeted_command(&["linux".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_cleanup_commands() {
+        let updater = Updater::new();
+
+        let dnf = updater.get_manager_info("dnf").unwrap();
+        assert!(dnf.supports_cleanup());
+        assert_eq!(dnf.cleanup_cmd, vec!["dnf", "autoremove", "-y"]);
+
+        let flatpak = updater.get_manager_info("flatpak").unwrap();
+        assert_eq!(
+            flatpak.cleanup_cmd,
+            vec!["flatpak", "uninstall", "--unused", "-y"]
+        );
+
+        let rustup = updater.get_manager_info("rustup").unwrap();
+        assert!(!rustup.supports_cleanup());
+
+        // Predefined cleanup scripts pass the same validation as update commands
+        for name in ALLOWED_MANAGERS {
+            let manager = updater.get_manager_info(name).unwrap();
+            assert!(validate_command_args(&manager.cleanup_cmd).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_privileged_managers_need_sudo() {
+        let updater = Updater::new();
+        for name in ALLOWED_MANAGERS {
+            let manager = updater.get_manager_info(name).unwrap();
+            assert_eq!(
+                manager.needs_sudo,
+                PRIVILEGED_MANAGERS.contains(name),
+                "{name}"
+            );
+        }
+        assert!(
+            PRIVILEGED_MANAGERS
+                .iter()
+                .all(|name| ATTENDED_MANAGERS.contains(name))
+        );
+    }
+
+    #[test]
+    fn test_stderr_classifiers() {
+        let updater = Updater::new();
+        let classify = |name: &str, line: &str| updater.managers[name].stderr.classify(line);
+
+        assert_eq!(
+            classify("paru", "error: failed to commit transaction"),
+            Severity::Error
+        );
+        assert_eq!(
+            classify("paru", "==> WARNING: skipping verification"),
+            Severity::Warning
+        );
+        assert_eq!(
+            classify("paru", ":: Synchronizing package databases..."),
+            Severity::Info
+        );
+        assert_eq!(
+            classify("apt", "E: Unable to locate package foo"),
+            Severity::Error
+        );
+        assert_eq!(
+            classify(
+                "apt",
+                "WARNING: apt does not have a stable CLI interface. Use with caution in scripts."
+            ),
+            Severity::Info
+        );
+        assert_eq!(
+            classify("npm", "npm WARN deprecated inflight@1.0.6"),
+            Severity::Warning
+        );
+        assert_eq!(classify("npm", "npm ERR! code EACCES"), Severity::Error);
+        assert_eq!(
+            classify("rustup", "info: syncing channel updates for 'stable'"),
+            Severity::Info
+        );
+        assert_eq!(
+            classify("flatpak", "  Error: No remote refs found"),
+            Severity::Error
+        );
+    }
+
+    #[test]
+    fn test_colorless_commands() {
+        let updater = Updater::new();
+        let to_strings = |cmd: &[&str]| cmd.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let paru = &updater.managers["paru"];
+        assert_eq!(
+            paru.colorless(&paru.check_cmd),
+            to_strings(&["paru", "--color", "never", "-Qu"])
+        );
+        // Scripts and other programs are not changed
+        let apt = &updater.managers["apt"];
+        assert_eq!(apt.colorless(&apt.update_cmd), apt.update_cmd);
+        assert_eq!(
+            apt.colorless(&to_strings(&[
+                "apt",
+                "install",
+                "--only-upgrade",
+                "-y",
+                "vim"
+            ])),
+            to_strings(&[
+                "apt",
+                "-o",
+                "APT::Color=0",
+                "install",
+                "--only-upgrade",
+                "-y",
+                "vim"
+            ])
+        );
+        let flatpak = &updater.managers["flatpak"];
+        assert_eq!(flatpak.colorless(&flatpak.update_cmd), flatpak.update_cmd);
+    }
+
+    #[test]
+    fn test_interactive_commands() {
+        let updater = Updater::new();
+        let paru = &updater.managers["paru"];
+        assert_eq!(paru.interactive(&paru.update_cmd), vec!["paru", "-Syu"]);
+        let flatpak = &updater.managers["flatpak"];
+        assert_eq!(flatpak.interactive(&flatpak.update_cmd), flatpak.update_cmd);
+    }
+
+    #[async_std::test]
+    async fn test_answer_without_terminal_fails() {
+        assert!(Updater::new().answer("y").await.is_err());
+    }
+
+    #[test]
+    fn test_preflight_requirements() {
+        let updater = Updater::new();
+
+        let paru = updater.get_manager_info("paru").unwrap();
+        assert_eq!(
+            paru.preflight.locks,
+            vec![Lock::File(PathBuf::from("/var/lib/pacman/db.lck"))]
+        );
+
+        let apt = updater.get_manager_info("apt").unwrap();
+        assert!(
+            apt.preflight
+                .locks
+                .contains(&advisory("/var/lib/dpkg/lock-frontend"))
+        );
+
+        // Every manager needs some free space to download updates
+        for name in ALLOWED_MANAGERS {
+            let manager = updater.get_manager_info(name).unwrap();
+            assert!(manager.preflight.min_free_mb > 0, "{name}");
+            assert!(!manager.preflight.space.is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_run_mode() {
+        assert!(RunMode::Update.updates());
+        assert!(!RunMode::Update.cleans_up());
+        assert!(RunMode::UpdateAndCleanup.updates());
+        assert!(RunMode::UpdateAndCleanup.cleans_up());
+        assert!(!RunMode::Cleanup.updates());
+        assert!(RunMode::Cleanup.cleans_up());
+        assert!(RunMode::SecurityOnly.updates());
+        assert!(!RunMode::SecurityOnly.cleans_up());
+        assert!(RunMode::ReleaseUpgrade.updates());
+        assert!(!RunMode::ReleaseUpgrade.cleans_up());
+        assert_eq!(RunMode::default(), RunMode::Update);
+    }
+
     #[test]
     fn test_update_event_variants() {
         let events = vec![
@@ -623,7 +2709,32 @@ mod tests {
             UpdateEvent::SourceStarted("flatpak".to_string()),
             UpdateEvent::SourceProgress("flatpak".to_string(), "Updating...".to_string()),
             UpdateEvent::SourceCompleted("flatpak".to_string(), true),
-            UpdateEvent::SourceError("flatpak".to_string(), "Error occurred".to_string()),
+            UpdateEvent::SourceMessage(
+                "flatpak".to_string(),
+                Severity::Error,
+                "Error occurred".to_string(),
+            ),
+            UpdateEvent::SourceDiagnosed("flatpak".to_string(), Diagnosis::DiskFull),
+            UpdateEvent::SourceSnapshot(
+                "dnf".to_string(),
+                Snapshot {
+                    tool: crate::snapshot::SnapshotTool::Snapper,
+                    kind: SnapshotKind::Pre,
+                    id: "42".to_string(),
+                },
+            ),
+            UpdateEvent::RebootRequired(vec!["linux was updated".to_string()]),
+            UpdateEvent::RestartNeeded(Vec::new()),
+            UpdateEvent::SourceCleanedUp("flatpak".to_string(), 1024),
+            UpdateEvent::SourceBlocked("apt".to_string(), "locked".to_string()),
+            UpdateEvent::SourcePrompt("dnf".to_string(), Prompt::unrecognized("Overwrite? ", &[])),
+            UpdateEvent::SourceRollback(
+                "rustup".to_string(),
+                Rollback::RustupToolchain {
+                    version: "1.79.0".to_string(),
+                },
+            ),
+            UpdateEvent::Interrupted,
             UpdateEvent::Completed(true),
             UpdateEvent::Error("General error".to_string()),
         ];
@@ -636,13 +2747,110 @@ mod tests {
                 UpdateEvent::SourceStarted(_) => {}
                 UpdateEvent::SourceProgress(_, _) => {}
                 UpdateEvent::SourceCompleted(_, _) => {}
-                UpdateEvent::SourceError(_, _) => {}
+                UpdateEvent::SourceMessage(_, _, _) => {}
+                UpdateEvent::SourceDiagnosed(_, _) => {}
+                UpdateEvent::SourceSnapshot(_, _) => {}
+                UpdateEvent::RebootRequired(_) => {}
+                UpdateEvent::RestartNeeded(_) => {}
+                UpdateEvent::SourceCleanedUp(_, _) => {}
+                UpdateEvent::SourceBlocked(_, _) => {}
+                UpdateEvent::SourcePrompt(_, _) => {}
+                UpdateEvent::SourceRollback(_, _) => {}
+                UpdateEvent::Interrupted => {}
                 UpdateEvent::Completed(_) => {}
                 UpdateEvent::Error(_) => {}
             }
         }
     }
 
+    #[test]
+    fn test_signal() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+
+        assert!(signal(pid, 0).is_ok());
+        signal(pid, libc::SIGINT).unwrap();
+        assert!(!child.wait().unwrap().success());
+        assert_eq!(
+            signal(pid, 0).unwrap_err().raw_os_error(),
+            Some(libc::ESRCH)
+        );
+    }
+
+    #[test]
+    fn test_process_tree() {
+        let proc_root = tempfile::tempdir().unwrap();
+        let process = |pid: u32, stat: &str| {
+            let dir = proc_root.path().join(pid.to_string());
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join("stat"), stat).unwrap();
+        };
+        process(100, "100 (pkexec) S 1 100 100 0 -1");
+        process(101, "101 (paru) S 100 100 100 0 -1");
+        process(102, "102 (pacman (1)) S 101 100 100 0 -1");
+        process(200, "200 (bash) S 1 200 200 0 -1");
+        std::fs::create_dir(proc_root.path().join("self")).unwrap();
+
+        assert_eq!(process_tree(proc_root.path(), &[100]), vec![100, 101, 102]);
+        assert_eq!(
+            process_tree(proc_root.path(), &[101, 200]),
+            vec![101, 200, 102]
+        );
+        // Processes that already exited are still signalled, which is harmless
+        assert_eq!(process_tree(proc_root.path(), &[300]), vec![300]);
+
+        process(103, "103 (apt) Z 101 100 100 0 -1");
+        assert!(is_alive(proc_root.path(), 102));
+        assert!(!is_alive(proc_root.path(), 103));
+        assert!(!is_alive(proc_root.path(), 300));
+    }
+
+    #[async_std::test]
+    async fn test_reservation_blocks_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut updater = Updater::new();
+        updater.marker_path = dir.path().join("run-in-progress.toml");
+        updater.history_path = dir.path().join("history.toml");
+        let request = RunRequest {
+            sources: vec!["dnf".to_string()],
+            mode: RunMode::ReleaseUpgrade,
+            ..RunRequest::default()
+        };
+
+        let reservation = updater.reserve(&request).await.unwrap();
+        assert!(updater.is_running());
+        assert!(updater.marker_path.exists());
+        assert!(updater.reserve(&request).await.is_err());
+        assert!(updater.run(request.clone()).await.is_err());
+
+        drop(reservation);
+        assert!(!updater.is_running());
+        assert!(!updater.marker_path.exists());
+        drop(updater.reserve(&request).await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_stop_waits_for_processes() {
+        let updater = Updater::new();
+        assert_eq!(updater.stop().await, Stop::Stopped);
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        updater.running.store(true, Ordering::Relaxed);
+        updater.child_pids.lock().await.push(child.id());
+
+        let started = Instant::now();
+        assert_eq!(updater.stop().await, Stop::Stopped);
+        assert!(!updater.is_running());
+        assert!(started.elapsed() < STOP_TIMEOUT);
+        assert!(!child.wait().unwrap().success());
+    }
+
     #[test]
     fn test_source_state_variants() {
         let states = vec![
@@ -683,6 +2891,49 @@ mod tests {
         assert!(!updater.is_running());
     }
 
+    #[async_std::test]
+    async fn test_check_fwupd_with_stub() {
+        let bin = tempfile::tempdir().unwrap();
+        crate::test_support::stub(
+            bin.path(),
+            "fwupdmgr",
+            "[ \"$1 $2\" = \"get-updates --json\" ] || exit 1\n\
+             echo '{\"Devices\": [{\"Name\": \"System Firmware\", \"Plugin\": \"uefi_capsule\", \
+             \"Version\": \"0.1.31\", \"Releases\": [{\"Version\": \"0.1.33\"}]}]}'\n\
+             exit 0",
+        );
+
+        let path = format!(
+            "{}:{}",
+            bin.path().display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+        let environment = HashMap::from([("PATH".to_string(), path)]);
+        let pending = Updater::new()
+            .check_pending(&["fwupd".to_string()], &environment)
+            .await
+            .unwrap();
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].source, "fwupd");
+        assert_eq!(pending[0].package, "System Firmware");
+        assert_eq!(pending[0].current_version.as_deref(), Some("0.1.31"));
+        assert!(pending[0].needs_reboot);
+    }
+
+    #[test]
+    fn test_fwupd_update_is_unattended() {
+        let updater = Updater::new();
+        let fwupd = updater.get_manager_info("fwupd").unwrap();
+
+        assert!(!fwupd.needs_sudo);
+        assert!(fwupd.targeted_blocked_reason().is_some());
+        // Pending firmware is named after devices, which cannot be held
+        assert!(!fwupd.supports_exclusions());
+        let cmd = fwupd.update_command(&[]).unwrap();
+        assert!(cmd.contains(&"--no-reboot-check".to_string()));
+    }
+
     #[test]
     fn test_allowed_managers_constant() {
         assert!(ALLOWED_MANAGERS.contains(&"flatpak"));